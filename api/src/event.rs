@@ -5,6 +5,20 @@ use borsh::{BorshDeserialize, BorshSerialize};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
+/// The channel of a chat message.
+#[derive(Debug, Default, Clone, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub enum MessageChannel {
+    /// Visible to everyone at the table.
+    #[default]
+    Table,
+    /// Visible to the members of the team only.
+    Team(String),
+    /// Visible to the sender and the receiver only.
+    Direct(String),
+}
+
 /// A message sent by player
 /// Used to express unimportant game events that
 /// can be sent at any time without the server checking
@@ -17,6 +31,27 @@ pub struct Message {
     pub content: String,
 }
 
+/// A chat message sent to a channel.  The messages to
+/// [MessageChannel::Table] are delivered as [Message], for the
+/// clients which don't know the channels.
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct ChatMessage {
+    pub sender: String,
+    pub content: String,
+    pub channel: MessageChannel,
+}
+
+impl From<ChatMessage> for Message {
+    fn from(message: ChatMessage) -> Self {
+        Self {
+            sender: message.sender,
+            content: message.content,
+        }
+    }
+}
+
 /// Game event structure
 #[derive(Debug, Clone, BorshDeserialize, BorshSerialize, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
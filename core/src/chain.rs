@@ -1,4 +1,4 @@
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ChainType {
     Bnb,
     Facade,
@@ -6,15 +6,22 @@ pub enum ChainType {
     Sui,
 }
 
+impl ChainType {
+    /// Parse a chain name, return None if it's unknown.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bnb" => Some(Self::Bnb),
+            "facade" => Some(Self::Facade),
+            "solana" => Some(Self::Solana),
+            "sui" => Some(Self::Sui),
+            _ => None,
+        }
+    }
+}
+
 impl From<&str> for ChainType {
     fn from(value: &str) -> Self {
-        match value {
-            "bnb" => Self::Bnb,
-            "facade" => Self::Facade,
-            "solana" => Self::Solana,
-            "sui" => Self::Sui,
-            _ => panic!("Invalid chain specified: {}", value),
-        }
+        Self::from_name(value).unwrap_or_else(|| panic!("Invalid chain specified: {}", value))
     }
 }
 
@@ -135,6 +135,19 @@ impl ContextCheckpoint {
         &self.shared_data
     }
 
+    /// Make `transactor_addr` the transactor node, the previous
+    /// transactor becomes a validator.  Used when a validator takes
+    /// over the game.
+    pub fn set_transactor(&mut self, transactor_addr: &str) {
+        for node in self.shared_data.nodes.iter_mut() {
+            if node.addr == transactor_addr {
+                node.mode = ClientMode::Transactor;
+            } else if node.mode == ClientMode::Transactor {
+                node.mode = ClientMode::Validator;
+            }
+        }
+    }
+
     pub fn root_data_mut(&mut self) -> &mut VersionedData {
         &mut self.root_data
     }
@@ -247,6 +247,12 @@ pub enum Error {
     #[error("Wasm memory overflow")]
     WasmMemoryOverflow,
 
+    #[error("Wasm fuel exhausted")]
+    WasmFuelExhausted,
+
+    #[error("Wasm memory limit exceeded")]
+    WasmMemoryLimitExceeded,
+
     #[error("Invalid checkpoint")]
     InvalidCheckpoint,
 
@@ -339,6 +345,48 @@ pub enum Error {
 
     #[error("Math overflow")]
     MathOverflow,
+
+    #[error("Transactor is shutting down")]
+    TransactorShuttingDown,
+
+    #[error("Rate limited")]
+    RateLimited,
+
+    #[error("Payload too large: {0} bytes")]
+    PayloadTooLarge(usize),
+
+    #[error("Sender is temporarily banned")]
+    SenderBanned,
+
+    #[error("Player is muted")]
+    PlayerMuted,
+
+    #[error("Not the game owner")]
+    NotGameOwner,
+
+    #[error("Not a member of the team")]
+    NotTeamMember,
+
+    #[error("Not a participant of the game")]
+    NotGameParticipant,
+
+    #[error("Invalid session")]
+    InvalidSession,
+
+    #[error("Session expired")]
+    SessionExpired,
+
+    #[error("Too many spectators")]
+    TooManySpectators,
+
+    #[error("Duplicated chain: {0}")]
+    DuplicatedChain(String),
+
+    #[error("Chain not served: {0}")]
+    ChainNotServed(String),
+
+    #[error("Game address on more than one chain, qualify it with the chain: {0}")]
+    AmbiguousGameAddr(String),
 }
 
 #[cfg(feature = "serde")]
@@ -356,6 +404,32 @@ impl From<std::io::Error> for Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+    /// Return true if the request is rejected by the rate limiter.
+    pub fn is_rate_limited(&self) -> bool {
+        matches!(
+            self,
+            Error::RateLimited | Error::PayloadTooLarge(_) | Error::SenderBanned
+        )
+    }
+
+    /// Return true if the game handler exceeded one of its execution
+    /// limits: fuel or memory.
+    pub fn is_wasm_limit_exceeded(&self) -> bool {
+        matches!(
+            self,
+            Error::WasmFuelExhausted
+                | Error::WasmMemoryLimitExceeded
+        )
+    }
+
+    /// Return true if the transaction may succeed when it's sent
+    /// again, e.g. RPC timeouts and expired blockhashes.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Error::RpcError(_) | Error::TransactionExpired)
+    }
+}
+
 
 impl From<crate::error::Error> for HandleError {
     fn from(value: crate::error::Error) -> Self {
@@ -1,7 +1,17 @@
 use async_trait::async_trait;
 use crate::error::Result;
 
-use crate::{checkpoint::CheckpointOffChain, types::{GetCheckpointParams, SaveCheckpointParams}};
+use crate::{
+    checkpoint::CheckpointOffChain,
+    types::{
+        AppendDivergenceLogParams, AppendJournalParams, ChatMember, ConfirmSettleParams,
+        DivergenceReport, GetBacklogsParams, GetChatMembersParams, GetCheckpointParams,
+        GetDivergenceLogParams, GetJournalParams, GetPendingRefundsParams,
+        GetPendingSettlesParams, PruneBacklogsParams, RemovePendingRefundsParams, SaveBacklogParams,
+        SaveChatMemberParams, SaveCheckpointParams, SavePendingRefundsParams,
+        SavePendingSettleParams, SettleParams, TruncateJournalParams, CutJournalParams,
+    },
+};
 
 #[async_trait]
 pub trait StorageT: Send + Sync {
@@ -10,4 +20,62 @@ pub trait StorageT: Send + Sync {
 
     /// Get data by key from storage.
     async fn get_checkpoint(&self, params: GetCheckpointParams) -> Result<Option<CheckpointOffChain>>;
+
+    /// Save a settlement before it's sent to the chain, and the
+    /// checkpoint at its `next_settle_version`, in one transaction.
+    async fn save_pending_settle(&self, params: SavePendingSettleParams) -> Result<()>;
+
+    /// Remove the settlements landed on the chain.
+    async fn confirm_settle(&self, params: ConfirmSettleParams) -> Result<()>;
+
+    /// Get the unconfirmed settlements, ordered by settle version.
+    async fn get_pending_settles(&self, params: GetPendingSettlesParams) -> Result<Vec<SettleParams>>;
+
+    /// Save the deposits to refund.
+    async fn save_pending_refunds(&self, params: SavePendingRefundsParams) -> Result<()>;
+
+    /// Remove the deposits which are refunded.
+    async fn remove_pending_refunds(&self, params: RemovePendingRefundsParams) -> Result<()>;
+
+    /// Get the access versions of the deposits to refund, in order.
+    async fn get_pending_refunds(&self, params: GetPendingRefundsParams) -> Result<Vec<u64>>;
+
+    /// Save a backlog group spilled from memory.
+    async fn save_backlog(&self, params: SaveBacklogParams) -> Result<()>;
+
+    /// Get the backlog groups, ordered by settle version.
+    async fn get_backlogs(&self, params: GetBacklogsParams) -> Result<Vec<Vec<u8>>>;
+
+    /// Remove the backlog groups no longer served.
+    async fn prune_backlogs(&self, params: PruneBacklogsParams) -> Result<()>;
+
+    /// Append an entry to the journal of a game.  Appending the same
+    /// entry again is idempotent.
+    async fn append_journal(&self, params: AppendJournalParams) -> Result<()>;
+
+    /// Get the journal entries after a checkpoint, in order.
+    async fn get_journal(&self, params: GetJournalParams) -> Result<Vec<Vec<u8>>>;
+
+    /// Remove the journal entries before a checkpoint.
+    async fn truncate_journal(&self, params: TruncateJournalParams) -> Result<()>;
+
+    /// Remove the journal entries after a checkpoint from a seq on.
+    async fn cut_journal(&self, params: CutJournalParams) -> Result<()>;
+
+    /// Save the chat settings of a player.  A member neither muted nor
+    /// in a team is removed.
+    async fn save_chat_member(&self, params: SaveChatMemberParams) -> Result<()>;
+
+    /// Get the chat settings of the players in a game.
+    async fn get_chat_members(&self, params: GetChatMembersParams) -> Result<Vec<ChatMember>>;
+
+    /// Append a divergence report, and drop the oldest ones over the
+    /// limits.
+    async fn append_divergence_log(&self, params: AppendDivergenceLogParams) -> Result<()>;
+
+    /// Get the divergence reports of a game, oldest first.
+    async fn get_divergence_log(&self, params: GetDivergenceLogParams) -> Result<Vec<DivergenceReport>>;
+
+    /// Check the storage is writable, used by the readiness check.
+    async fn health_check(&self) -> Result<()>;
 }
@@ -5,6 +5,7 @@ mod accounts;
 mod broadcast_frame;
 mod tx_state;
 mod storage_params;
+mod divergence;
 
 pub use storage_params::*;
 pub use transport_params::*;
@@ -13,3 +14,4 @@ pub use common::*;
 pub use accounts::*;
 pub use broadcast_frame::*;
 pub use tx_state::*;
+pub use divergence::*;
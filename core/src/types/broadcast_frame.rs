@@ -1,7 +1,7 @@
 use crate::checkpoint::CheckpointOffChain;
 use crate::types::{PlayerJoin, ServerJoin, PlayerDeposit, TxState};
 use borsh::{BorshDeserialize, BorshSerialize};
-use race_api::event::{Event, Message};
+use race_api::event::{ChatMessage, Event, Message};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
@@ -62,6 +62,78 @@ pub enum BroadcastFrame {
         backlogs: Box<Vec<BroadcastFrame>>,
         state_sha: String,
     },
+    // The first frame when a subscription is resumed, with the frames
+    // missed since the cursor.  Messages and tx states are not kept,
+    // so they are not included.
+    Resume {
+        backlogs: Box<Vec<BroadcastFrame>>,
+    },
+    // The game is taken over by another transactor, clients should
+    // reconnect to `endpoint`.  This is the last frame.
+    Reconnect {
+        transactor_addr: String,
+        endpoint: String,
+    },
+    // A chat message to a team or a direct message, only sent to the
+    // subscribers who can see it.
+    ChatMessage {
+        message: ChatMessage,
+    },
+    // The recent chat messages visible to the subscriber, sent after
+    // `Backlogs` to the subscribers with a credential.
+    ChatHistory {
+        messages: Vec<ChatMessage>,
+    },
+}
+
+/// Select the kinds of frames in a subscription.  The `Backlogs`,
+/// `Resume` and `Reconnect` frames are always sent, with the frames
+/// inside filtered.
+#[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize, BorshSerialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase", default))]
+pub struct FrameFilter {
+    pub events: bool,
+    pub messages: bool,
+    pub tx_states: bool,
+    pub sync: bool,
+}
+
+impl Default for FrameFilter {
+    fn default() -> Self {
+        Self {
+            events: true,
+            messages: true,
+            tx_states: true,
+            sync: true,
+        }
+    }
+}
+
+impl FrameFilter {
+    pub fn accepts(&self, frame: &BroadcastFrame) -> bool {
+        match frame {
+            BroadcastFrame::Event { .. } => self.events,
+            BroadcastFrame::Message { .. }
+            | BroadcastFrame::ChatMessage { .. }
+            | BroadcastFrame::ChatHistory { .. } => self.messages,
+            BroadcastFrame::TxState { .. } => self.tx_states,
+            BroadcastFrame::Sync { .. } => self.sync,
+            BroadcastFrame::Backlogs { .. }
+            | BroadcastFrame::Resume { .. }
+            | BroadcastFrame::Reconnect { .. } => true,
+        }
+    }
+
+    /// Drop the frames not accepted from a `Backlogs` or `Resume` frame.
+    pub fn apply_to_backlogs(&self, frame: &mut BroadcastFrame) {
+        match frame {
+            BroadcastFrame::Backlogs { backlogs, .. } | BroadcastFrame::Resume { backlogs } => {
+                backlogs.retain(|f| self.accepts(f));
+            }
+            _ => (),
+        }
+    }
 }
 
 impl Display for BroadcastFrame {
@@ -86,6 +158,18 @@ impl Display for BroadcastFrame {
             BroadcastFrame::Backlogs { backlogs, .. } => {
                 write!(f, "BroadcastFrame::EventHistories, len: {}", backlogs.len())
             }
+            BroadcastFrame::Resume { backlogs } => {
+                write!(f, "BroadcastFrame::Resume, len: {}", backlogs.len())
+            }
+            BroadcastFrame::Reconnect { endpoint, .. } => {
+                write!(f, "BroadcastFrame::Reconnect: {}", endpoint)
+            }
+            BroadcastFrame::ChatMessage { message } => {
+                write!(f, "BroadcastFrame::ChatMessage: {}", message.sender)
+            }
+            BroadcastFrame::ChatHistory { messages } => {
+                write!(f, "BroadcastFrame::ChatHistory, len: {}", messages.len())
+            }
         }
     }
 }
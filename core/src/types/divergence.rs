@@ -0,0 +1,40 @@
+//! Reports of validators on the states different from the transactor's
+
+use crate::error::Result;
+use crate::types::Signature;
+use borsh::{BorshDeserialize, BorshSerialize};
+use race_api::event::Event;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Made by a validator when its state after replaying `event` doesn't
+/// match the `state_sha` broadcast by the transactor.  The signature
+/// is made by the validator on [DivergenceReport::signing_bytes].
+#[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize, BorshSerialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct DivergenceReport {
+    pub game_addr: String,
+    pub transactor_addr: String,
+    pub validator_addr: String,
+    pub access_version: u64,
+    pub settle_version: u64,
+    pub event: Event,
+    pub timestamp: u64,
+    /// The state SHA broadcast by the transactor.
+    pub expected_state_sha: String,
+    /// The state SHA computed by the validator.
+    pub actual_state_sha: String,
+    pub signature: Option<Signature>,
+}
+
+impl DivergenceReport {
+    /// The borsh bytes of the report without the signature.
+    pub fn signing_bytes(&self) -> Result<Vec<u8>> {
+        let unsigned = DivergenceReport {
+            signature: None,
+            ..self.clone()
+        };
+        Ok(borsh::to_vec(&unsigned)?)
+    }
+}
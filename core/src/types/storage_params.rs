@@ -3,6 +3,7 @@ use borsh::{BorshDeserialize, BorshSerialize};
 use serde::{Deserialize, Serialize};
 
 use crate::checkpoint::CheckpointOffChain;
+use crate::types::{DivergenceReport, SettleParams};
 
 #[derive(Debug, BorshSerialize, BorshDeserialize)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -27,3 +28,177 @@ pub struct GetCheckpointParams {
 pub struct SaveResult {
     pub proof: String,
 }
+
+/// Save a settlement before it's sent, with the off-chain part of
+/// the checkpoint it makes.  There is one pending settlement for each
+/// `settle_version` of a game, the later one replaces the earlier.
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct SavePendingSettleParams {
+    pub game_addr: String,
+    pub checkpoint: CheckpointOffChain,
+    pub settle_params: SettleParams,
+}
+
+/// Confirm the pending settlements up to `next_settle_version`.
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct ConfirmSettleParams {
+    pub game_addr: String,
+    pub next_settle_version: u64,
+}
+
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct GetPendingSettlesParams {
+    pub game_addr: String,
+}
+
+/// Save the deposits to refund, identified by their access versions.
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct SavePendingRefundsParams {
+    pub game_addr: String,
+    pub access_versions: Vec<u64>,
+}
+
+/// Remove the deposits which are refunded.
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct RemovePendingRefundsParams {
+    pub game_addr: String,
+    pub access_versions: Vec<u64>,
+}
+
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct GetPendingRefundsParams {
+    pub game_addr: String,
+}
+
+/// Save a group of the event backlogs, which is dropped from memory.
+/// The data is opaque to storage.
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct SaveBacklogParams {
+    pub game_addr: String,
+    pub settle_version: u64,
+    pub data: Vec<u8>,
+}
+
+/// Get the latest `limit` backlog groups from `settle_version`, and
+/// before `before` if it's provided.
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct GetBacklogsParams {
+    pub game_addr: String,
+    pub settle_version: u64,
+    pub before: Option<u64>,
+    pub limit: usize,
+}
+
+/// Remove the backlog groups before `settle_version`.
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct PruneBacklogsParams {
+    pub game_addr: String,
+    pub settle_version: u64,
+}
+
+/// Append an entry to the journal of a game.  The entries are keyed by
+/// the settle version of the checkpoint they follow, and their order
+/// after it.  The data is opaque to storage.
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct AppendJournalParams {
+    pub game_addr: String,
+    pub settle_version: u64,
+    pub seq: u64,
+    pub data: Vec<u8>,
+}
+
+/// Get the journal entries after the checkpoint at `settle_version`.
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct GetJournalParams {
+    pub game_addr: String,
+    pub settle_version: u64,
+}
+
+/// Remove the journal entries before the checkpoint at `settle_version`.
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct TruncateJournalParams {
+    pub game_addr: String,
+    pub settle_version: u64,
+}
+
+/// Remove the journal entries after the checkpoint at
+/// `settle_version`, from `seq` on.
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct CutJournalParams {
+    pub game_addr: String,
+    pub settle_version: u64,
+    pub seq: u64,
+}
+
+/// The chat settings of a player in a game, set by the game owner.
+#[derive(Debug, Clone, Default, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct ChatMember {
+    pub player_addr: String,
+    pub muted: bool,
+    pub team: Option<String>,
+}
+
+/// Save the chat settings of a player, replacing the previous ones.
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct SaveChatMemberParams {
+    pub game_addr: String,
+    pub member: ChatMember,
+}
+
+/// Get the chat settings of all players in a game.
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct GetChatMembersParams {
+    pub game_addr: String,
+}
+
+/// Append a divergence report to the log.  Only the latest
+/// `max_per_game` reports of a game, and the latest `max_total` of all
+/// games are kept.
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct AppendDivergenceLogParams {
+    pub report: DivergenceReport,
+    pub max_per_game: u64,
+    pub max_total: u64,
+}
+
+/// Get the divergence reports of a game, oldest first.
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct GetDivergenceLogParams {
+    pub game_addr: String,
+}
@@ -1,10 +1,13 @@
 //! Parameters for interacting with transactor
 
 use crate::encryptor::NodePublicKeyRaw;
+use crate::types::FrameFilter;
 use borsh::{BorshDeserialize, BorshSerialize};
-use race_api::event::Event;
+use hmac::{Hmac, Mac};
+use race_api::event::{Event, MessageChannel};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 use std::fmt::Display;
 
 #[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize, BorshSerialize)]
@@ -61,6 +64,141 @@ impl Display for SubmitMessageParams {
     }
 }
 
+/// Submit a chat message to a channel.  [SubmitMessageParams] is for
+/// the table channel.
+#[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize, BorshSerialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct SubmitChatMessageParams {
+    pub content: String,
+    pub channel: MessageChannel,
+}
+
+impl Display for SubmitChatMessageParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SubmitChatMessageParams")
+    }
+}
+
+/// Sent by the game owner to put a player in a chat team, or remove
+/// the player from its team with `None`.
+#[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize, BorshSerialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct SetPlayerTeamParams {
+    pub player_addr: String,
+    pub team: Option<String>,
+}
+
+impl Display for SetPlayerTeamParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SetPlayerTeamParams")
+    }
+}
+
+/// Sent by the game owner to mute or unmute a player in chat.
+#[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize, BorshSerialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct MutePlayerParams {
+    pub player_addr: String,
+    pub muted: bool,
+}
+
+impl Display for MutePlayerParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MutePlayerParams")
+    }
+}
+
+/// The prefix of the session credential, which replaces the
+/// signature in a signed request.  The credential is
+/// `session:<token>:<nonce>:<proof>`, see [SessionInfo::credential].
+pub const SESSION_PREFIX: &str = "session:";
+
+/// The error code for the requests rejected because their session is
+/// invalid or expired.  Such a request is not processed, so it's safe
+/// to resend it with a new session.
+pub const SESSION_ERROR_CODE: i32 = -32030;
+
+/// The purpose of [CreateSessionParams], so its signature can't be
+/// taken for another request.
+pub const CREATE_SESSION_PURPOSE: &str = "race:create_session";
+
+/// Sent with a signature to create a session in game `game_addr`.
+/// The session lasts until the subscription is closed or it expires.
+#[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize, BorshSerialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct CreateSessionParams {
+    pub purpose: String,
+    pub game_addr: String,
+}
+
+impl CreateSessionParams {
+    pub fn new(game_addr: impl Into<String>) -> Self {
+        Self {
+            purpose: CREATE_SESSION_PURPOSE.to_string(),
+            game_addr: game_addr.into(),
+        }
+    }
+}
+
+impl Display for CreateSessionParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CreateSessionParams, game_addr: {}", self.game_addr)
+    }
+}
+
+/// The session issued by transactor.  It's only sent through the
+/// session subscription, the `key` never leaves the subscriber.
+#[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize, BorshSerialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct SessionInfo {
+    pub token: String,
+    /// The secret to prove the ownership of the session.
+    pub key: String,
+    /// Seconds before the session expires.
+    pub ttl: u64,
+}
+
+fn session_mac(key: &str, nonce: u64, arg: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC takes any key size");
+    mac.update(&nonce.to_le_bytes());
+    mac.update(arg);
+    mac
+}
+
+/// The proof of a session request, the hex HMAC-SHA256 of the nonce
+/// and the borsh bytes of the argument, keyed by the session key.
+pub fn session_proof(key: &str, nonce: u64, arg: &[u8]) -> String {
+    hex::encode(session_mac(key, nonce, arg).finalize().into_bytes())
+}
+
+/// Verify a proof made by [session_proof], in constant time.
+pub fn verify_session_proof(key: &str, nonce: u64, arg: &[u8], proof: &str) -> bool {
+    match hex::decode(proof) {
+        Ok(proof) => session_mac(key, nonce, arg).verify_slice(&proof).is_ok(),
+        Err(_) => false,
+    }
+}
+
+impl SessionInfo {
+    /// The credential to put in the place of signature, for the
+    /// argument with borsh bytes `arg`.  Every request must use a new
+    /// `nonce`, so a credential can't be replayed.
+    pub fn credential(&self, nonce: u64, arg: &[u8]) -> String {
+        format!(
+            "{}{}:{}:{}",
+            SESSION_PREFIX,
+            self.token,
+            nonce,
+            session_proof(&self.key, nonce, arg)
+        )
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize, BorshSerialize)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
@@ -79,14 +217,74 @@ pub struct SubscribeEventParams {
     pub settle_version: u64,
 }
 
+/// The parameters of `subscribe_event_v2`, which can resume a
+/// subscription and filter the frames.  It's a separate type to keep
+/// the borsh layout of [SubscribeEventParams].
+#[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize, BorshSerialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct SubscribeEventParamsV2 {
+    pub settle_version: u64,
+    /// Resume from this position instead of the checkpoint.
+    pub resume: Option<EventCursor>,
+    /// The kinds of frames to receive, all by default.
+    pub filter: Option<FrameFilter>,
+}
+
+impl From<SubscribeEventParams> for SubscribeEventParamsV2 {
+    fn from(params: SubscribeEventParams) -> Self {
+        Self {
+            settle_version: params.settle_version,
+            resume: None,
+            filter: None,
+        }
+    }
+}
+
+/// A position in the event stream: the settle version of the
+/// checkpoint in the `Backlogs` frame, and the number of events
+/// received after it.
+#[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize, BorshSerialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct EventCursor {
+    pub settle_version: u64,
+    pub event_index: u64,
+}
+
 impl Display for SubscribeEventParams {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "SubscribeEventParams")
     }
 }
 
+impl Display for SubscribeEventParamsV2 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SubscribeEventParamsV2")
+    }
+}
+
+/// Subscribe the delayed event stream as a spectator.
+#[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize, BorshSerialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct SubscribeSpectateParams {}
+
+impl Display for SubscribeSpectateParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SubscribeSpectateParams")
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize, BorshSerialize)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
 pub struct SubscribeCheckpointParams {
 }
+
+/// Get the divergence reports made by the validator of a game.
+#[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize, BorshSerialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct GetDivergenceReportsParams {
+}
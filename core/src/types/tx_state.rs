@@ -37,4 +37,31 @@ pub enum TxState {
         settle_version: u64,
         signature: Option<String>,
     },
+
+    /// The settlement failed and will be sent again.
+    SettleRetrying {
+        settle_version: u64,
+        attempts: u32,
+        error: String,
+    },
+
+    /// The settlement has been retried for too many times, it's
+    /// still being retried.
+    SettleStuck {
+        settle_version: u64,
+        attempts: u32,
+    },
+
+    /// The refund of the rejected deposits failed and will be sent
+    /// again.  Deposits are identified by their access versions.
+    RefundRetrying {
+        access_versions: Vec<u64>,
+        attempts: u32,
+        error: String,
+    },
+
+    /// The rejected deposits are refunded on chain.
+    DepositsRefunded {
+        access_versions: Vec<u64>,
+    },
 }
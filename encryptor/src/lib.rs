@@ -149,7 +149,6 @@ where
     Ok(base64_encode(&der))
 }
 
-#[allow(unused)]
 fn import_rsa_private(raw: &str) -> EncryptorResult<Rsa<Private>> {
     let der = base64_decode(raw)?;
     let pkey =
@@ -182,7 +181,6 @@ where
     Ok(base64_encode(&der))
 }
 
-#[allow(unused)]
 fn import_ec_private(raw: &str) -> EncryptorResult<EcKey<Private>> {
     let der = base64_decode(raw)?;
     let pkey =
@@ -225,8 +223,15 @@ impl TryInto<NodePublicKeyRaw> for &NodePublicKey {
 pub fn generate_credentials(
     original_secret: Vec<u8>,
 ) -> EncryptorResult<Credentials> {
-    let rsa = rsa_generate()?;
-    let ec = ec_generate()?;
+    encrypt_credentials(&NodePrivateKey::generate()?, original_secret)
+}
+
+/// Build the credentials to register on chain from the keys of a node.
+pub fn encrypt_credentials(
+    private: &NodePrivateKey,
+    original_secret: Vec<u8>,
+) -> EncryptorResult<Credentials> {
+    let NodePrivateKey { rsa, ec } = private;
 
     let mut salt = [0u8; 16];
     let mut iv = [0u8; 12];
@@ -266,6 +271,34 @@ pub struct NodePrivateKey {
     ec: EcKey<Private>,
 }
 
+impl NodePrivateKey {
+    pub fn generate() -> EncryptorResult<Self> {
+        Ok(Self {
+            rsa: rsa_generate()?,
+            ec: ec_generate()?,
+        })
+    }
+
+    /// Import the keys exported by [NodePrivateKey::export].
+    pub fn import(rsa: &str, ec: &str) -> EncryptorResult<Self> {
+        Ok(Self {
+            rsa: import_rsa_private(rsa)?,
+            ec: import_ec_private(ec)?,
+        })
+    }
+
+    /// Export the RSA and the ECDSA keys, as base64 encoded PKCS#8 DER.
+    pub fn export(&self) -> EncryptorResult<(String, String)> {
+        let rsa = PKey::from_rsa(self.rsa.clone())
+            .and_then(|k| k.private_key_to_pkcs8())
+            .map_err(|_| EncryptorError::ExportPrivateKeyError)?;
+        let ec = PKey::from_ec_key(self.ec.clone())
+            .and_then(|k| k.private_key_to_pkcs8())
+            .map_err(|_| EncryptorError::ExportPrivateKeyError)?;
+        Ok((base64_encode(&rsa), base64_encode(&ec)))
+    }
+}
+
 impl TryInto<NodePublicKeyRaw> for &NodePrivateKey {
     type Error = EncryptorError;
 
@@ -479,6 +512,21 @@ mod tests {
         assert_eq!(decrypted, plain);
     }
 
+    #[test]
+    fn test_export_import_private_key() -> anyhow::Result<()> {
+        let e = Encryptor::default();
+        let (rsa, ec) = e.private.export()?;
+        let imported = Encryptor::try_new(NodePrivateKey::import(&rsa, &ec)?)?;
+
+        let plain = e.gen_secret();
+        let encrypted = e.encrypt(None, &plain)?;
+        assert_eq!(imported.decrypt(&encrypted)?, plain);
+
+        let signature = imported.sign_raw(b"hello")?;
+        e.verify_raw(None, b"hello", &signature)?;
+        Ok(())
+    }
+
     #[test]
     fn test_wrap_unwrap_secret() {
         let secret = vec![
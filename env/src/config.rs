@@ -1,67 +1,268 @@
 //! Configuration of application
 
-use std::{fs::File, io::Read, path::PathBuf};
+use std::{collections::HashMap, fs::File, io::Read, path::PathBuf};
 
 use serde::Deserialize;
 use tracing::info;
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, PartialEq)]
 pub struct FacadeConfig {
     pub host: String,
     pub address: String,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, PartialEq)]
 pub struct SolanaConfig {
     pub rpc: String,
     pub keyfile: PathBuf,
     pub skip_preflight: Option<bool>,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, PartialEq)]
 pub struct BnbConfig {
     pub rpc: String,
     pub keyfile: PathBuf,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, PartialEq)]
 pub struct SuiConfig {
     pub rpc: String,
     pub keyfile: PathBuf,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, PartialEq)]
 pub struct SubmitterConfig {
     pub squash_time_window: Option<u64>,
     pub squash_limit: Option<usize>,
     pub tx_queue_size: Option<usize>,
 }
 
-#[derive(Deserialize, Clone)]
+/// The execution limits of game handlers.  Validators must use the
+/// same limits as the transactor, otherwise they may diverge.
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct HandlerConfig {
+    /// The fuel budget for each call into the game bundle.
+    pub fuel: Option<u64>,
+    /// The maximum memory of the game bundle, in 64KiB pages.  It
+    /// requires a restart to change.
+    pub max_memory_pages: Option<u32>,
+}
+
+/// The limits for the events and messages submitted by clients.
+/// Rates are in requests per second, and bursts are the capacities
+/// of the token buckets.
+#[derive(Deserialize, Clone, PartialEq, Default)]
+pub struct RateLimitConfig {
+    pub signer_rate: Option<u32>,
+    pub signer_burst: Option<u32>,
+    pub game_rate: Option<u32>,
+    pub game_burst: Option<u32>,
+    /// The maximum size of the raw data in a custom event, in bytes.
+    pub max_event_size: Option<usize>,
+    /// The maximum size of the content in a message, in bytes.
+    pub max_message_size: Option<usize>,
+    /// The number of rejections in a minute to ban a signer.
+    pub ban_threshold: Option<u32>,
+    /// Seconds a signer is banned.
+    pub ban_duration: Option<u64>,
+}
+
+/// The chat moderation settings.
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct ChatConfig {
+    /// The words masked in chat messages, case-insensitive.
+    pub profanity_words: Option<Vec<String>>,
+}
+
+/// The settings of spectator subscriptions.
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct SpectatorConfig {
+    /// Seconds the frames are delayed for spectators.
+    pub delay: Option<u64>,
+    /// Hold the frames until the next checkpoint instead, that is
+    /// usually the end of a hand.
+    pub delay_until_checkpoint: Option<bool>,
+    pub max_spectators_per_game: Option<usize>,
+    pub max_spectators: Option<usize>,
+    /// Require a participant's credential for `subscribe_event` too.
+    /// It's open to anyone by default, for the existing clients.
+    pub participants_only: Option<bool>,
+}
+
+/// How the registration task picks the games to serve when its
+/// capacity is limited.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionPolicy {
+    /// In the order found in the registrations.
+    #[default]
+    FirstCome,
+    /// Only the games with bundles in `bundle_allowlist`.
+    BundleAllowlist,
+    /// The games with larger stakes first.
+    Stake,
+}
+
+/// The capacity of a transactor.  Once any limit is reached, no more
+/// games are served until some are closed.
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct CapacityConfig {
+    pub max_games: Option<usize>,
+    pub max_sub_games: Option<usize>,
+    /// The maximum number of games running WASM bundles.
+    pub max_wasm_instances: Option<usize>,
+    pub selection: Option<SelectionPolicy>,
+    pub bundle_allowlist: Option<Vec<String>>,
+}
+
+/// The retention of the event backlogs kept in memory, which are
+/// served to new clients.  The oldest groups beyond any limit are
+/// dropped, and optionally spilled to the local DB.  The latest group
+/// is always kept.
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct BacklogConfig {
+    /// The maximum number of checkpoint groups of a game.
+    pub max_groups: Option<usize>,
+    /// Seconds a group is kept after its checkpoint.
+    pub max_age: Option<u64>,
+    /// The maximum bytes of a game.
+    pub max_bytes: Option<usize>,
+    /// The maximum bytes of all games.
+    pub max_total_bytes: Option<usize>,
+    /// Save the dropped groups to the local DB, default to false.
+    pub spill: Option<bool>,
+    /// The number of settle versions the spilled groups are kept for,
+    /// behind the latest checkpoint.  They are all removed when the
+    /// game is closed.
+    pub max_spilled_groups: Option<u64>,
+}
+
+/// The recordings of the served games, used to settle disputes.
+/// Each game is recorded in its own directory, the files are rotated
+/// by size and by time.
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct RecorderConfig {
+    /// Record the games, default to false.
+    pub enabled: Option<bool>,
+    /// The directory to save the recordings.
+    pub dir: String,
+    /// Start a new file when the current one has this many bytes
+    /// before compression.
+    pub max_file_bytes: Option<u64>,
+    /// Start a new file after this many seconds.
+    pub rotate_interval: Option<u64>,
+    /// The zstd compression level, 0 to disable compression.
+    pub compression_level: Option<i32>,
+}
+
+/// The OpenTelemetry tracing of the games.  The spans are exported to
+/// an OTLP collector, or to a file for offline use, or both.
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct TelemetryConfig {
+    /// The OTLP endpoint over gRPC, e.g. `http://localhost:4317`.
+    pub otlp_endpoint: Option<String>,
+    /// The file to write the spans as JSON.
+    pub file: Option<String>,
+    /// Default to `race-transactor`.
+    pub service_name: Option<String>,
+    /// The ratio of the traces to sample, default to 1.0.
+    pub sample_ratio: Option<f64>,
+}
+
+/// A chain served by the transactor besides the one in
+/// [TransactorConfig::chain].  The transport reads the RPC and the
+/// keyfile from the section of the chain, e.g. `[sui]`.
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct ChainConfig {
+    pub chain: String,
+    /// The server account on this chain.
+    pub address: String,
+    pub reg_addresses: Vec<String>,
+    /// The file of the encryptor keys of the server on this chain,
+    /// created with new keys if it doesn't exist.  Without it, new
+    /// keys are generated on every start.
+    pub credentials_file: Option<String>,
+}
+
+/// The thresholds of the readiness check at `/readyz`.
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct HealthConfig {
+    /// Seconds to wait for each subsystem to respond, default to 5.
+    pub check_timeout: Option<u64>,
+    /// The registration task is considered dead when it hasn't
+    /// finished a scan in this many seconds, default to 60.
+    pub reg_timeout: Option<u64>,
+    /// A game is considered stuck when its settlement has been
+    /// retried for this many seconds, default to 600.
+    pub settle_stuck_threshold: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Default)]
 pub struct TransactorConfig {
     pub port: u32,
     pub endpoint: String,
     pub chain: String,
     pub address: String,
     pub reg_addresses: Vec<String>,
+    /// The file of the encryptor keys on `chain`, see
+    /// [ChainConfig::credentials_file].
+    pub credentials_file: Option<String>,
+    /// More chains to serve from the same process.
+    pub chains: Option<Vec<ChainConfig>>,
     pub disable_blacklist: Option<bool>,
     pub debug_mode: Option<bool>,
     pub log_dir: Option<String>,
+    /// The directory to save the compiled game bundles.
     pub bundle_dir: Option<String>,
     pub submitter: Option<SubmitterConfig>,
+    pub handler: Option<HandlerConfig>,
+    /// A map from bundle address to the name of a native handler
+    /// built into the transactor.  Only used in debug mode.
+    pub native_handlers: Option<HashMap<String, String>>,
+    pub rate_limit: Option<RateLimitConfig>,
+    pub chat: Option<ChatConfig>,
+    /// Seconds a client session lasts.
+    pub session_ttl: Option<u64>,
+    pub spectator: Option<SpectatorConfig>,
+    pub capacity: Option<CapacityConfig>,
+    pub backlog: Option<BacklogConfig>,
+    pub recorder: Option<RecorderConfig>,
+    pub telemetry: Option<TelemetryConfig>,
+    pub health: Option<HealthConfig>,
+    /// Seconds a game can stay without players and events before it's
+    /// unloaded, it's reloaded on demand.  Never unloaded if not set.
+    pub idle_timeout: Option<u64>,
+    /// Seconds to wait for games to finish when shutting down.
+    pub shutdown_timeout: Option<u64>,
+}
+
+impl TransactorConfig {
+    /// Return all chains to serve, the one in `chain` goes first.
+    pub fn chain_configs(&self) -> Vec<ChainConfig> {
+        let mut chains = vec![ChainConfig {
+            chain: self.chain.clone(),
+            address: self.address.clone(),
+            reg_addresses: self.reg_addresses.clone(),
+            credentials_file: self.credentials_file.clone(),
+        }];
+        if let Some(more) = self.chains.as_ref() {
+            chains.extend(more.iter().cloned());
+        }
+        chains
+    }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, PartialEq)]
 pub struct ReplayerConfig {
     pub port: u32,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, PartialEq)]
 pub struct StorageConfig {
     pub db_file_name: String,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, PartialEq, Default)]
 pub struct Config {
     pub transactor: Option<TransactorConfig>,
     pub replayer: Option<ReplayerConfig>,
@@ -85,4 +286,15 @@ impl Config {
             }
         }
     }
+
+    /// Like [Config::from_path], but return an error instead of
+    /// panicking.  Used when reloading the configuration at runtime.
+    pub fn try_from_path(path: &PathBuf) -> Result<Config, String> {
+        info!("Reload configuration from {:?}", path);
+        let mut buf = Vec::with_capacity(1024);
+        let mut f = File::open(path).map_err(|e| format!("Config file not found: {}", e))?;
+        f.read_to_end(&mut buf)
+            .map_err(|e| format!("Failed to read config file: {}", e))?;
+        toml::from_slice(&buf).map_err(|e| format!("Invalid config file: {}", e))
+    }
 }
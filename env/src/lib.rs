@@ -1,6 +1,8 @@
 mod config;
 
-pub use config::{Config, TransactorConfig, SubmitterConfig};
+pub use config::{Config, TransactorConfig, SubmitterConfig, HandlerConfig, RateLimitConfig, ChatConfig,
+    SpectatorConfig, CapacityConfig, SelectionPolicy, BacklogConfig, RecorderConfig, TelemetryConfig,
+    HealthConfig, ChainConfig, ReplayerConfig};
 
 pub fn parse_with_default_rpc<'a>(chain: &'a str, rpc: &'a str) -> &'a str {
     match (chain, rpc) {
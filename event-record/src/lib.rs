@@ -3,43 +3,132 @@
 //! [RecordsHeader] - the game address and bundle address
 //! [Record]*       - One record on each line
 //!
-//! All content are serialized with borsh & base64.
+//! All content are serialized with borsh & base64.  The file can be
+//! compressed with zstd as a whole, see [open_records_file].
+//!
+//! A recording is rotated into several files, each file starts with
+//! its own header.  The files after the first one start with the
+//! latest [Record::Checkpoint], so each file can be replayed alone.
 
 use borsh::{BorshSerialize, BorshDeserialize};
+use base64::Engine;
 use race_core::node::Node;
-use race_core::types::PlayerBalance;
+use race_core::types::{PlayerBalance, TxState};
 use race_core::game_spec::GameSpec;
 use race_core::entry_type::EntryType;
-use race_api::event::Event;
+use race_api::event::{Event, Message};
+use race_api::types::{Award, Settle, Transfer};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+/// The version of the records format.
+///
+/// - 1, the header without version, only events and checkpoints.
+/// - 2, the header with version and segment, broadcasts, messages,
+///   settlements, transaction states and bridge events.
+pub const RECORDS_FORMAT_VERSION: u16 = 2;
 
-#[derive(Default, Debug, BorshSerialize, BorshDeserialize)]
+/// The first bytes of a zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Default, Debug, BorshSerialize)]
 pub struct RecordsHeader {
     pub spec: GameSpec,
     pub chain: String,        // solana, sui, facade
     pub init_data: Vec<u8>,
     pub entry_type: EntryType,
+    /// The version of the format, see [RECORDS_FORMAT_VERSION].
+    pub version: u16,
+    /// The index of the file in a rotated recording.
+    pub segment: u32,
+    /// The timestamp when the recording was started.
+    pub started_at: u64,
 }
 
 impl RecordsHeader {
     pub fn new(spec: GameSpec, init_data: Vec<u8>, entry_type: EntryType, chain: String) -> Self {
-        Self { spec, init_data, entry_type, chain }
+        Self {
+            spec,
+            init_data,
+            entry_type,
+            chain,
+            version: RECORDS_FORMAT_VERSION,
+            segment: 0,
+            started_at: 0,
+        }
+    }
+}
+
+// The header is always read from its own line, so a header without
+// the trailing fields is a version 1 header.
+impl BorshDeserialize for RecordsHeader {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let spec = GameSpec::deserialize_reader(reader)?;
+        let chain = String::deserialize_reader(reader)?;
+        let init_data = Vec::<u8>::deserialize_reader(reader)?;
+        let entry_type = EntryType::deserialize_reader(reader)?;
+
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest)?;
+        if rest.is_empty() {
+            return Ok(Self {
+                spec,
+                chain,
+                init_data,
+                entry_type,
+                version: 1,
+                segment: 0,
+                started_at: 0,
+            });
+        }
+
+        let mut rest = rest.as_slice();
+        let version = u16::deserialize(&mut rest)?;
+        let segment = u32::deserialize(&mut rest)?;
+        let started_at = u64::deserialize(&mut rest)?;
+        Ok(Self {
+            spec,
+            chain,
+            init_data,
+            entry_type,
+            version,
+            segment,
+            started_at,
+        })
     }
 }
 
 impl std::fmt::Display for Record {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
-            Record::Checkpoint { .. } => {
-                write!(f, "<Checkpoint>")
+            Record::Checkpoint { settle_version, .. } => {
+                write!(f, "<Checkpoint {}>", settle_version)
             }
             Record::Event { event, .. } => {
                 write!(f, "{}", event)
             }
+            Record::Broadcast { event, .. } => {
+                write!(f, "{}", event)
+            }
+            Record::Message { message } => {
+                write!(f, "<Message from {}>", message.sender)
+            }
+            Record::Settle { settle_version, .. } => {
+                write!(f, "<Settle {}>", settle_version)
+            }
+            Record::TxState { tx_state } => {
+                write!(f, "<TxState {:?}>", tx_state)
+            }
+            Record::BridgeEvent { from, dest, event, .. } => {
+                write!(f, "<Bridge {} -> {}> {}", from, dest, event)
+            }
         }
     }
 }
 
-#[derive(Debug, BorshSerialize, BorshDeserialize)]
+// New variants must be appended to keep the existing records readable.
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
 pub enum Record {
     Checkpoint {
         state: Vec<u8>,
@@ -48,10 +137,44 @@ pub enum Record {
         access_version: u64,
         settle_version: u64,
     },
+    /// An event handled by the game.  Used by version 1, replaced by
+    /// [Record::Broadcast].
     Event {
         event: Event,
         timestamp: u64,
-    }
+    },
+    /// An event broadcast by the transactor, `state_sha` is the state
+    /// after the event.
+    Broadcast {
+        event: Event,
+        timestamp: u64,
+        state_sha: String,
+    },
+    Message {
+        message: Message,
+    },
+    /// A settlement made by the game.
+    Settle {
+        settles: Vec<Settle>,
+        transfer: Option<Transfer>,
+        awards: Vec<Award>,
+        accept_deposits: Vec<u64>,
+        access_version: u64,
+        settle_version: u64,
+        previous_settle_version: u64,
+    },
+    /// The result of a transaction sent for the game.
+    TxState {
+        tx_state: TxState,
+    },
+    /// A bridge event between the game and its sub games.  `incoming`
+    /// is true when the event is received by this game.
+    BridgeEvent {
+        from: usize,
+        dest: usize,
+        event: Event,
+        incoming: bool,
+    },
 }
 
 impl Record {
@@ -73,3 +196,111 @@ impl Record {
         }
     }
 }
+
+/// Serialize a header or a record as a line, without the line break.
+pub fn encode_line<B: BorshSerialize>(b: &B) -> io::Result<String> {
+    let bs = borsh::to_vec(b)?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(bs))
+}
+
+fn decode_line(line: &str) -> io::Result<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD
+        .decode(line)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Open a records file, the file is decompressed if it's compressed
+/// with zstd.
+pub fn open_records_file(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let mut file = BufReader::new(File::open(path)?);
+    if file.fill_buf()?.starts_with(&ZSTD_MAGIC) {
+        let decoder = zstd::stream::read::Decoder::with_buffer(file)?;
+        Ok(Box::new(BufReader::new(decoder)))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// Read the header and the records.  A file which is not finished,
+/// e.g. the transactor was killed, is read until where it's broken.
+pub fn read_records<R: BufRead>(reader: R) -> io::Result<(RecordsHeader, Vec<Record>)> {
+    let mut lines = reader.lines();
+    let Some(header_line) = lines.next() else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Missing header"));
+    };
+    let header = RecordsHeader::try_from_slice(&decode_line(&header_line?)?)?;
+
+    let mut records = vec![];
+    for line in lines {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        };
+        records.push(Record::try_from_slice(&decode_line(&line)?)?);
+    }
+
+    Ok((header, records))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[derive(BorshSerialize)]
+    struct RecordsHeaderV1 {
+        spec: GameSpec,
+        chain: String,
+        init_data: Vec<u8>,
+        entry_type: EntryType,
+    }
+
+    #[test]
+    fn test_read_v1_header() {
+        let v1 = RecordsHeaderV1 {
+            spec: GameSpec::default(),
+            chain: "facade".into(),
+            init_data: vec![1, 2, 3],
+            entry_type: EntryType::default(),
+        };
+        let header = RecordsHeader::try_from_slice(&borsh::to_vec(&v1).unwrap()).unwrap();
+        assert_eq!(header.version, 1);
+        assert_eq!(header.chain, "facade");
+        assert_eq!(header.init_data, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_read_compressed_records() {
+        let mut header = RecordsHeader::new(GameSpec::default(), vec![], EntryType::default(), "facade".into());
+        header.segment = 2;
+        let records = vec![
+            Record::Broadcast {
+                event: Event::GameStart,
+                timestamp: 100,
+                state_sha: "sha".into(),
+            },
+            Record::TxState {
+                tx_state: TxState::SettleSucceed {
+                    signature: None,
+                    settle_version: 3,
+                },
+            },
+        ];
+
+        let mut encoder = zstd::stream::write::Encoder::new(vec![], 3).unwrap();
+        writeln!(encoder, "{}", encode_line(&header).unwrap()).unwrap();
+        for record in records.iter() {
+            writeln!(encoder, "{}", encode_line(record).unwrap()).unwrap();
+        }
+        let compressed = encoder.finish().unwrap();
+        assert!(compressed.starts_with(&ZSTD_MAGIC));
+
+        let decoder = zstd::stream::read::Decoder::new(compressed.as_slice()).unwrap();
+        let (header, read) = read_records(BufReader::new(decoder)).unwrap();
+        assert_eq!(header.version, RECORDS_FORMAT_VERSION);
+        assert_eq!(header.segment, 2);
+        assert_eq!(read.len(), 2);
+        assert!(matches!(read[1], Record::TxState { .. }));
+    }
+}
@@ -17,7 +17,7 @@ struct MinimalAccountData { }
 
 #[derive(BorshDeserialize, BorshSerialize, Default)]
 #[game_handler]
-struct Minimal {
+pub struct Minimal {
     /// A map from player ID to its balance.
     ///
     /// Note: Although we don't care about balances in this example,
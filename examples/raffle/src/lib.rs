@@ -52,7 +52,7 @@ impl From<GamePlayer> for Player {
 
 #[derive(BorshSerialize, BorshDeserialize)]
 #[game_handler]
-struct Raffle {
+pub struct Raffle {
     winner_player_id: Option<u64>,
     players: Vec<Player>,
     random_id: usize,  // We save random id, and we use it to get randomness information in the game progress.
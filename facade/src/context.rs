@@ -104,6 +104,20 @@ impl Context {
         Ok(())
     }
 
+    /// Add a placeholder bundle, the game code is built into the
+    /// transactor as a native handler.
+    pub fn add_native_bundle(&self, bundle_addr: &str) -> anyhow::Result<()> {
+        let bundle = GameBundle {
+            addr: bundle_addr.to_owned(),
+            name: bundle_addr.to_owned(),
+            uri: "native".into(),
+            data: vec![],
+        };
+        create_game_bundle(&self.conn, &bundle)?;
+        println!("+ Native bundle: {}", bundle_addr);
+        Ok(())
+    }
+
     pub fn add_game(&self, spec_path: &str) -> anyhow::Result<()> {
         let f = File::open(spec_path).expect("Spec file not found");
         let GameSpec {
@@ -944,6 +944,8 @@ fn cli() -> Command {
         .about("A mock server for local development with Race")
         .arg(arg!(-g <game> ... "The path to a game spec json file"))
         .arg(arg!(-b <bundle> ... "The path to a wasm bundle"))
+        .arg(arg!(-n <native> ... "The address of a bundle served by a native handler, requires --dev"))
+        .arg(arg!(--dev "Enable the features for local development"))
 }
 
 #[tokio::main]
@@ -958,6 +960,15 @@ async fn main() -> anyhow::Result<()> {
     if let Some(bundle_paths) = matches.get_many::<String>("bundle") {
         context.load_bundles(&bundle_paths.map(String::as_str).collect::<Vec<&str>>())?;
     }
+    if let Some(bundle_addrs) = matches.get_many::<String>("native") {
+        if matches.get_flag("dev") {
+            for bundle_addr in bundle_addrs {
+                context.add_native_bundle(bundle_addr)?;
+            }
+        } else {
+            println!("! Native bundles are ignored, they require --dev");
+        }
+    }
     let server_handle = run_server(context).await?;
     server_handle.stopped().await;
     Ok(())
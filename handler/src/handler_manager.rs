@@ -1,48 +1,46 @@
-/// This HandlerManager caches the downloaded bundles, to avoid
-/// unnecessary networking.
+/// This HandlerManager loads the game handlers.  The compiled modules
+/// are cached and shared through [ModuleCache].
 
 use crate::handler::HandlerT;
+use crate::limits::WasmLimits;
+use crate::module_cache::ModuleCache;
 use crate::wasm_handler::WasmHandler;
-use std::collections::HashMap;
 use race_core::error::{Error, Result};
-use std::collections::hash_map::Entry;
 use race_core::transport::TransportT;
-use race_core::types::GameBundle;
 
 use std::sync::Arc;
-use tokio::sync::Mutex;
 
 pub struct HandlerManager {
-    bundles: Mutex<HashMap<String, GameBundle>>,
     transport: Arc<dyn TransportT>,
+    module_cache: Arc<ModuleCache>,
+    limits: WasmLimits,
 }
 
 impl HandlerManager {
-    pub fn new(transport: Arc<dyn TransportT>) -> Self {
-        let bundles = Mutex::new(HashMap::default());
-
+    pub fn new(
+        transport: Arc<dyn TransportT>,
+        module_cache: Arc<ModuleCache>,
+        limits: WasmLimits,
+    ) -> Self {
         Self {
-            bundles,
             transport,
+            module_cache,
+            limits,
         }
     }
 
     pub async fn get_handler(&self, bundle_addr: &str) -> Result<Box<dyn HandlerT>> {
-        let mut bundles = self.bundles.lock().await;
-
-        match bundles.entry(bundle_addr.to_string()) {
-            Entry::Occupied(e) => {
-                Ok(Box::new(WasmHandler::load_by_bundle(e.get()).await?))
-            }
-            Entry::Vacant(e) => {
-                let bundle = self.transport
-                    .get_game_bundle(bundle_addr)
-                    .await?
-                    .ok_or(Error::GameBundleNotFound)?;
-                let handler = WasmHandler::load_by_bundle(&bundle).await?;
-                e.insert(bundle);
-                Ok(Box::new(handler))
-            }
+        if let Some(handler) = self.module_cache.get_native_handler(bundle_addr) {
+            return Ok(handler);
         }
+
+        let bundle = self.transport
+            .get_game_bundle(bundle_addr)
+            .await?
+            .ok_or(Error::GameBundleNotFound)?;
+        let compiled = self.module_cache.get_module(&bundle).await?;
+
+        let handler = WasmHandler::load_by_module(&compiled.engine, &compiled.module, self.limits)?;
+        Ok(Box::new(handler))
     }
 }
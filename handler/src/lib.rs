@@ -1,6 +1,12 @@
 mod handler_manager;
 mod wasm_handler;
+mod native_handler;
 mod handler;
+mod limits;
+mod module_cache;
 
 pub use handler_manager::HandlerManager;
 pub use handler::HandlerT;
+pub use limits::WasmLimits;
+pub use module_cache::ModuleCache;
+pub use native_handler::{NativeHandler, NativeHandlers};
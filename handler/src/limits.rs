@@ -0,0 +1,120 @@
+//! Execution limits of WASM game handlers.
+//!
+//! A game bundle is untrusted code, every call into it is metered
+//! with a fuel budget and its linear memory is capped.  Fuel is counted
+//! per operator, so a call stops at the same point on every node,
+//! unlike a wall-clock limit.
+
+use std::ptr::NonNull;
+
+use wasmer::vm::{self, MemoryError, MemoryStyle, TableStyle, VMMemoryDefinition, VMTableDefinition};
+use wasmer::wasmparser::Operator;
+use wasmer::{MemoryType, Pages, TableType, Tunables};
+
+pub const DEFAULT_FUEL: u64 = 1_000_000_000;
+pub const DEFAULT_MAX_MEMORY_PAGES: u32 = 1024; // 64 MiB
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WasmLimits {
+    /// The fuel budget for each call.
+    pub fuel: u64,
+    /// The maximum memory size, in 64KiB pages.
+    pub max_memory_pages: u32,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            fuel: DEFAULT_FUEL,
+            max_memory_pages: DEFAULT_MAX_MEMORY_PAGES,
+        }
+    }
+}
+
+/// The cost of each operator, one unit for all.
+pub(crate) fn operator_cost(_operator: &Operator) -> u64 {
+    1
+}
+
+/// Tunables to cap the memory of the instance.  The memories
+/// without a maximum are given the limit as their maximum, so
+/// `memory.grow` fails in the guest when the limit is reached.
+pub(crate) struct LimitingTunables<T: Tunables> {
+    limit: Pages,
+    base: T,
+}
+
+impl<T: Tunables> LimitingTunables<T> {
+    pub(crate) fn new(base: T, limit: Pages) -> Self {
+        Self { limit, base }
+    }
+
+    fn adjust_memory(&self, requested: &MemoryType) -> MemoryType {
+        let mut adjusted = *requested;
+        if requested.maximum.is_none() {
+            adjusted.maximum = Some(self.limit);
+        }
+        adjusted
+    }
+
+    fn validate_memory(&self, ty: &MemoryType) -> Result<(), MemoryError> {
+        if ty.minimum > self.limit {
+            return Err(MemoryError::Generic(
+                "Minimum exceeds the allowed memory limit".to_string(),
+            ));
+        }
+        match ty.maximum {
+            Some(max) if max > self.limit => Err(MemoryError::Generic(
+                "Maximum exceeds the allowed memory limit".to_string(),
+            )),
+            Some(_) => Ok(()),
+            None => Err(MemoryError::Generic("Maximum unset".to_string())),
+        }
+    }
+}
+
+impl<T: Tunables> Tunables for LimitingTunables<T> {
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        let adjusted = self.adjust_memory(memory);
+        self.base.memory_style(&adjusted)
+    }
+
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.base.table_style(table)
+    }
+
+    fn create_host_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+    ) -> Result<vm::VMMemory, MemoryError> {
+        let adjusted = self.adjust_memory(ty);
+        self.validate_memory(&adjusted)?;
+        self.base.create_host_memory(&adjusted, style)
+    }
+
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<vm::VMMemory, MemoryError> {
+        let adjusted = self.adjust_memory(ty);
+        self.validate_memory(&adjusted)?;
+        self.base
+            .create_vm_memory(&adjusted, style, vm_definition_location)
+    }
+
+    fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<vm::VMTable, String> {
+        self.base.create_host_table(ty, style)
+    }
+
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<vm::VMTable, String> {
+        self.base.create_vm_table(ty, style, vm_definition_location)
+    }
+}
@@ -0,0 +1,230 @@
+//! The cache of compiled game bundles, shared by all games.
+//!
+//! Modules are keyed by the SHA256 of the bundle data.  Each module is
+//! compiled with its own engine, since the metering middleware can't be
+//! shared between modules, and it's instantiated with that engine.
+//! When a bundle
+//! directory is given, the compiled artifacts are saved to
+//! `<bundle_dir>/<sha>.wasmu`, so a restart doesn't compile them
+//! again.  An artifact file is laid out as:
+//!
+//! - 64 bytes, the hex SHA256 of the bundle data
+//! - 64 bytes, the hex SHA256 of the artifact
+//! - the serialized artifact
+//!
+//! Both hashes are verified before an artifact is loaded, otherwise
+//! the bundle is compiled again and the file is overwritten.
+//!
+//! For local development, bundles can be served by [NativeHandlers]
+//! instead, they take precedence over the WASM bundles.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use race_core::error::{Error, Result};
+use race_core::types::GameBundle;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use wasmer::{Engine, Module};
+
+use crate::handler::HandlerT;
+use crate::native_handler::NativeHandlers;
+use crate::wasm_handler::create_engine;
+
+const ARTIFACT_EXT: &str = "wasmu";
+const SHA_LEN: usize = 64;
+
+/// A compiled module, with the engine it's compiled by.
+#[derive(Clone)]
+pub struct CompiledModule {
+    pub engine: Engine,
+    pub module: Module,
+}
+
+pub struct ModuleCache {
+    max_memory_pages: u32,
+    modules: Mutex<HashMap<String, CompiledModule>>,
+    bundle_dir: Option<PathBuf>,
+    native_handlers: NativeHandlers,
+}
+
+impl ModuleCache {
+    pub fn new(max_memory_pages: u32, bundle_dir: Option<PathBuf>) -> Self {
+        if let Some(ref dir) = bundle_dir {
+            if let Err(e) = fs::create_dir_all(dir) {
+                warn!("Failed to create bundle directory {:?}: {}", dir, e);
+            }
+        }
+        Self {
+            max_memory_pages,
+            modules: Mutex::new(HashMap::default()),
+            bundle_dir,
+            native_handlers: NativeHandlers::default(),
+        }
+    }
+
+    pub fn with_native_handlers(mut self, native_handlers: NativeHandlers) -> Self {
+        self.native_handlers = native_handlers;
+        self
+    }
+
+    /// Get the native handler for `bundle_addr` if it's registered.
+    pub fn get_native_handler(&self, bundle_addr: &str) -> Option<Box<dyn HandlerT>> {
+        self.native_handlers.get_handler(bundle_addr)
+    }
+
+    /// Whether `bundle_addr` is served by a native handler.
+    pub fn is_native(&self, bundle_addr: &str) -> bool {
+        self.native_handlers.contains(bundle_addr)
+    }
+
+    /// Get the compiled module of `bundle`.  The lock is held during
+    /// compilation, so a bundle is compiled only once when many games
+    /// are launched with it at the same time.
+    pub async fn get_module(&self, bundle: &GameBundle) -> Result<CompiledModule> {
+        let sha = sha256::digest(&bundle.data);
+        let mut modules = self.modules.lock().await;
+
+        if let Some(module) = modules.get(&sha) {
+            return Ok(module.clone());
+        }
+
+        let module = match self.load_artifact(&sha) {
+            Some(module) => module,
+            None => {
+                info!("Compile bundle {}, sha: {}", bundle.addr, sha);
+                let engine = create_engine(self.max_memory_pages);
+                let module = Module::from_binary(&engine, &bundle.data)
+                    .or(Err(Error::MalformedGameBundle))?;
+                self.save_artifact(&sha, &module);
+                CompiledModule { engine, module }
+            }
+        };
+
+        modules.insert(sha, module.clone());
+        Ok(module)
+    }
+
+    fn artifact_path(&self, sha: &str) -> Option<PathBuf> {
+        self.bundle_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.{}", sha, ARTIFACT_EXT)))
+    }
+
+    fn load_artifact(&self, sha: &str) -> Option<CompiledModule> {
+        let path = self.artifact_path(sha)?;
+        let data = fs::read(&path).ok()?;
+
+        if data.len() < SHA_LEN * 2 || &data[..SHA_LEN] != sha.as_bytes() {
+            warn!("Artifact {:?} doesn't match the bundle, ignored", path);
+            return None;
+        }
+        let artifact = &data[SHA_LEN * 2..];
+        if data[SHA_LEN..SHA_LEN * 2] != *sha256::digest(artifact).as_bytes() {
+            warn!("Artifact {:?} is corrupted, ignored", path);
+            return None;
+        }
+
+        // Safety: the artifact is written by `save_artifact` and its
+        // hash is verified above.
+        let engine = create_engine(self.max_memory_pages);
+        match unsafe { Module::deserialize(&engine, artifact.to_vec()) } {
+            Ok(module) => {
+                info!("Load compiled bundle from {:?}", path);
+                Some(CompiledModule { engine, module })
+            }
+            Err(e) => {
+                warn!("Failed to deserialize artifact {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    fn save_artifact(&self, sha: &str, module: &Module) {
+        let Some(path) = self.artifact_path(sha) else {
+            return;
+        };
+        let artifact = match module.serialize() {
+            Ok(artifact) => artifact,
+            Err(e) => {
+                warn!("Failed to serialize module {}: {}", sha, e);
+                return;
+            }
+        };
+        let mut data = Vec::with_capacity(SHA_LEN * 2 + artifact.len());
+        data.extend_from_slice(sha.as_bytes());
+        data.extend_from_slice(sha256::digest(&artifact[..]).as_bytes());
+        data.extend_from_slice(&artifact);
+
+        // Write to a temporary file first, so a crash won't leave a
+        // partial artifact.
+        let tmp_path = path.with_extension("tmp");
+        if let Err(e) = fs::write(&tmp_path, &data).and_then(|_| fs::rename(&tmp_path, &path)) {
+            warn!("Failed to save artifact {:?}: {}", path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_bundle() -> GameBundle {
+        make_bundle_from_wat("bundle", r#"(module (memory (export "memory") 1))"#)
+    }
+
+    fn make_bundle_from_wat(addr: &str, wat: &str) -> GameBundle {
+        GameBundle {
+            addr: addr.into(),
+            uri: "".into(),
+            name: "test".into(),
+            data: wasmer::wat2wasm(wat.as_bytes()).unwrap().to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_artifact_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("race-bundles-{}", std::process::id()));
+        let bundle = make_bundle();
+        let sha = sha256::digest(&bundle.data);
+
+        let cache = ModuleCache::new(16, Some(dir.clone()));
+        cache.get_module(&bundle).await.unwrap();
+        assert!(cache.artifact_path(&sha).unwrap().exists());
+
+        let cache = ModuleCache::new(16, Some(dir.clone()));
+        assert!(cache.load_artifact(&sha).is_some());
+
+        // A tampered artifact is rejected
+        let path = cache.artifact_path(&sha).unwrap();
+        let mut data = fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        fs::write(&path, data).unwrap();
+        assert!(cache.load_artifact(&sha).is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_compile_many_modules() {
+        let cache = ModuleCache::new(16, None);
+        let bundle1 = make_bundle();
+        let bundle2 = make_bundle_from_wat(
+            "bundle2",
+            r#"(module (memory (export "memory") 1) (func (export "f") (result i32) (i32.const 1)))"#,
+        );
+
+        let compiled1 = cache.get_module(&bundle1).await.unwrap();
+        let compiled2 = cache.get_module(&bundle2).await.unwrap();
+        for compiled in [compiled1, compiled2] {
+            let mut store = wasmer::Store::new(compiled.engine.clone());
+            wasmer::Instance::new(&mut store, &compiled.module, &wasmer::imports![]).unwrap();
+        }
+        // Cached modules are reused
+        assert_eq!(cache.modules.lock().await.len(), 2);
+        cache.get_module(&bundle1).await.unwrap();
+        assert_eq!(cache.modules.lock().await.len(), 2);
+    }
+}
@@ -0,0 +1,135 @@
+//! Run game handlers as native Rust code, for local development.
+//!
+//! A [NativeHandler] calls the [GameHandler] directly, the same way
+//! as the glue code generated by `#[game_handler]` does in WASM.  So
+//! the game can be debugged with gdb or lldb, and panics come with
+//! stack traces.
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use borsh::BorshDeserialize;
+use race_api::effect::Effect;
+use race_api::engine::GameHandler;
+use race_api::event::Event;
+use race_api::init_account::InitAccount;
+use race_core::error::{Error, Result};
+
+use crate::handler::HandlerT;
+use crate::wasm_handler::init_effect;
+
+pub struct NativeHandler<H: GameHandler> {
+    _handler: PhantomData<fn() -> H>,
+}
+
+impl<H: GameHandler> Default for NativeHandler<H> {
+    fn default() -> Self {
+        Self {
+            _handler: PhantomData,
+        }
+    }
+}
+
+/// Copy the effect through serialization, as it's passed into WASM.
+fn copy_effect(effect: &Effect) -> Result<Effect> {
+    let effect_bs = borsh::to_vec(effect).or(Err(Error::SerializationError))?;
+    Effect::try_from_slice(&effect_bs).or(Err(Error::SerializationError))
+}
+
+impl<H: GameHandler> HandlerT for NativeHandler<H> {
+    fn handle_event(&mut self, effect: &Effect, event: &Event) -> Result<Effect> {
+        let mut effect = copy_effect(effect)?;
+        let mut handler: H = effect.__handler_state();
+        match handler.handle_event(&mut effect, event.clone()) {
+            Ok(_) => effect.__set_handler_result(handler),
+            Err(e) => effect.__set_error(e),
+        }
+        Ok(effect)
+    }
+
+    fn init_state(&mut self, init_account: &InitAccount) -> Result<Effect> {
+        let mut effect = init_effect();
+        match H::init_state(&mut effect, init_account.clone()) {
+            Ok(handler) => effect.__set_handler_result(handler),
+            Err(e) => effect.__set_error(e),
+        }
+        Ok(effect)
+    }
+}
+
+pub type NativeHandlerFactory = fn() -> Box<dyn HandlerT>;
+
+/// Native handlers registered by bundle address.
+#[derive(Default, Clone)]
+pub struct NativeHandlers {
+    factories: HashMap<String, NativeHandlerFactory>,
+}
+
+impl NativeHandlers {
+    /// Serve the bundle at `bundle_addr` with game handler `H`.
+    pub fn register<H: GameHandler + 'static>(&mut self, bundle_addr: impl Into<String>) {
+        let factory: NativeHandlerFactory =
+            || -> Box<dyn HandlerT> { Box::new(NativeHandler::<H>::default()) };
+        self.factories.insert(bundle_addr.into(), factory);
+    }
+
+    pub fn get_handler(&self, bundle_addr: &str) -> Option<Box<dyn HandlerT>> {
+        self.factories.get(bundle_addr).map(|factory| factory())
+    }
+
+    pub fn contains(&self, bundle_addr: &str) -> bool {
+        self.factories.contains_key(bundle_addr)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.factories.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use borsh::BorshSerialize;
+    use race_api::prelude::*;
+
+    use super::*;
+
+    #[derive(Default, BorshSerialize, BorshDeserialize)]
+    struct Counter {
+        n: u64,
+    }
+
+    impl GameHandler for Counter {
+        fn init_state(_effect: &mut Effect, _init_account: InitAccount) -> HandleResult<Self> {
+            Ok(Self::default())
+        }
+
+        fn handle_event(&mut self, _effect: &mut Effect, event: Event) -> HandleResult<()> {
+            match event {
+                Event::GameStart => self.n += 1,
+                _ => return Err(HandleError::Custom("Unexpected event".into())),
+            }
+            Ok(())
+        }
+
+        fn balances(&self) -> Vec<PlayerBalance> {
+            vec![]
+        }
+    }
+
+    #[test]
+    fn test_native_handler() {
+        let mut handlers = NativeHandlers::default();
+        handlers.register::<Counter>("counter");
+        assert!(handlers.get_handler("other").is_none());
+
+        let mut hdlr = handlers.get_handler("counter").unwrap();
+        let effect = hdlr.init_state(&InitAccount::default()).unwrap();
+        assert_eq!(effect.handler_state, Some(vec![0, 0, 0, 0, 0, 0, 0, 0]));
+
+        let effect = hdlr.handle_event(&effect, &Event::GameStart).unwrap();
+        assert_eq!(effect.handler_state, Some(vec![1, 0, 0, 0, 0, 0, 0, 0]));
+
+        let mut effect = hdlr.handle_event(&effect, &Event::WaitingTimeout).unwrap();
+        assert!(effect.__take_error().is_some());
+    }
+}
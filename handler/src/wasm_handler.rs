@@ -1,15 +1,25 @@
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use borsh::BorshDeserialize;
 use race_api::effect::Effect;
 use race_api::event::Event;
 use race_api::init_account::InitAccount;
 use race_core::error::{Error, Result};
-use race_core::types::GameBundle;
-use tracing::{info, error};
-use wasmer::{imports, Instance, Module, Store, TypedFunction};
+use tracing::{info, error, warn};
+use wasmer::{
+    imports, BaseTunables, CompilerConfig, Cranelift, Engine, Instance, Memory, Module,
+    NativeEngineExt, Pages, Store, Target, TypedFunction,
+};
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
+use wasmer_middlewares::Metering;
 
 use crate::handler::HandlerT;
+use crate::limits::{operator_cost, LimitingTunables, WasmLimits, DEFAULT_FUEL};
+
+/// The pages to grow before the first call, they are used to pass
+/// the effect and the event to the bundle.
+const INIT_MEMORY_GROW_PAGES: u32 = 4;
 
 fn log_execution_context(effect_bs: &Vec<u8>, event_bs: &Vec<u8>) {
     info!("Execution context");
@@ -21,7 +31,7 @@ fn log_execution_context(effect_bs: &Vec<u8>, event_bs: &Vec<u8>) {
 }
 
 /// Create a new empty Effect for initialization.
-fn init_effect() -> Effect {
+pub(crate) fn init_effect() -> Effect {
     Effect {
         is_init: true,
         curr_sub_game_id: 1,
@@ -31,9 +41,25 @@ fn init_effect() -> Effect {
     }
 }
 
+/// Create an engine with fuel metering and memory limit.  The fuel
+/// budget is reset before each call, see [WasmHandler::call_with_limits].
+///
+/// A [Metering] middleware can only be used by one module, so an
+/// engine must be created for each compilation.
+pub(crate) fn create_engine(max_memory_pages: u32) -> Engine {
+    let metering = Arc::new(Metering::new(DEFAULT_FUEL, operator_cost));
+    let mut compiler = Cranelift::default();
+    compiler.push_middleware(metering);
+    let mut engine: Engine = compiler.into();
+    let base = BaseTunables::for_target(&Target::default());
+    engine.set_tunables(LimitingTunables::new(base, Pages(max_memory_pages)));
+    engine
+}
+
 pub struct WasmHandler {
     store: Store,
     instance: Instance,
+    limits: WasmLimits,
 }
 
 impl HandlerT for WasmHandler {
@@ -57,18 +83,21 @@ impl HandlerT for WasmHandler {
 }
 
 impl WasmHandler {
-    /// Load WASM bundle
-    pub async fn load_by_bundle(
-        bundle: &GameBundle,
+    /// Instantiate a compiled module.  The `engine` must be the one
+    /// used to compile the module.
+    pub fn load_by_module(
+        engine: &Engine,
+        module: &Module,
+        limits: WasmLimits,
     ) -> Result<Self> {
-        let mut store = Store::default();
-        let module =
-            Module::from_binary(&store, &bundle.data).or(Err(Error::MalformedGameBundle))?;
+        let mut store = Store::new(engine.clone());
         let import_object = imports![];
-        let instance = Instance::new(&mut store, &module, &import_object).expect("Init failed");
+        let instance = Instance::new(&mut store, module, &import_object)
+            .map_err(|e| Error::WasmInitializationError(e.to_string()))?;
         Ok(Self {
             store,
             instance,
+            limits,
         })
     }
 
@@ -76,16 +105,61 @@ impl WasmHandler {
     /// This function is used for testing.
     #[allow(dead_code)]
     pub fn load_by_path(path: PathBuf) -> Result<Self> {
-        let mut store = Store::default();
+        let limits = WasmLimits::default();
+        let mut store = Store::new(create_engine(limits.max_memory_pages));
         let module = Module::from_file(&store, path).expect("Fail to load module");
         let import_object = imports![];
         let instance = Instance::new(&mut store, &module, &import_object).expect("Init failed");
         Ok(Self {
             store,
             instance,
+            limits,
         })
     }
 
+    /// Grow the memory, fail if it exceeds the limit.
+    fn grow_memory(&mut self, memory: &Memory, delta: u32) -> Result<()> {
+        let pages = memory.view(&self.store).size().0;
+        if pages.saturating_add(delta) > self.limits.max_memory_pages {
+            return Err(Error::WasmMemoryLimitExceeded);
+        }
+        memory
+            .grow(&mut self.store, delta)
+            .map_err(|e| Error::WasmInitializationError(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Call a function of the bundle with a fresh fuel budget.  The
+    /// call fails when the fuel is exhausted or the memory limit is
+    /// reached.
+    fn call_with_limits(
+        &mut self,
+        f: &TypedFunction<(u32, u32), u32>,
+        memory: &Memory,
+        arg0: u32,
+        arg1: u32,
+        map_err: fn(String) -> Error,
+    ) -> Result<u32> {
+        set_remaining_points(&mut self.store, &self.instance, self.limits.fuel);
+        let ret = f.call(&mut self.store, arg0, arg1);
+
+        if let MeteringPoints::Exhausted = get_remaining_points(&mut self.store, &self.instance) {
+            warn!("Wasm fuel exhausted, budget: {}", self.limits.fuel);
+            return Err(Error::WasmFuelExhausted);
+        }
+        match ret {
+            Ok(len) => Ok(len),
+            Err(e) => {
+                if memory.view(&self.store).size().0 >= self.limits.max_memory_pages {
+                    warn!("Wasm memory limit reached: {} pages", self.limits.max_memory_pages);
+                    Err(Error::WasmMemoryLimitExceeded)
+                } else {
+                    Err(map_err(e.to_string()))
+                }
+            }
+        }
+    }
+
     pub fn custom_init_state(
         &mut self,
         init_account: &InitAccount,
@@ -95,11 +169,10 @@ impl WasmHandler {
             .instance
             .exports
             .get_memory("memory")
+            .cloned()
             .map_err(|e| Error::WasmInitializationError(e.to_string()))?;
 
-        memory
-            .grow(&mut self.store, 4)
-            .map_err(|e| Error::WasmInitializationError(e.to_string()))?;
+        self.grow_memory(&memory, INIT_MEMORY_GROW_PAGES)?;
         let init_state: TypedFunction<(u32, u32), u32> = self
             .instance
             .exports
@@ -121,13 +194,13 @@ impl WasmHandler {
         mem_view
             .write(offset as _, &init_account_bs)
             .map_err(|e| Error::WasmInitializationError(e.to_string()))?;
-        let len = init_state
-            .call(
-                &mut self.store,
-                effect_bs.len() as _,
-                init_account_bs.len() as _,
-            )
-            .map_err(|e| Error::WasmInitializationError(e.to_string()))?;
+        let len = self.call_with_limits(
+            &init_state,
+            &memory,
+            effect_bs.len() as _,
+            init_account_bs.len() as _,
+            Error::WasmInitializationError,
+        )?;
 
         match len {
             0 => {
@@ -168,6 +241,7 @@ impl WasmHandler {
             .instance
             .exports
             .get_memory("memory")
+            .cloned()
             .map_err(|e| Error::WasmExecutionError(e.to_string()))?;
         let handle_event: TypedFunction<(u32, u32), u32> = self
             .instance
@@ -189,11 +263,17 @@ impl WasmHandler {
         mem_view
             .write(offset as _, &event_bs)
             .map_err(|e| Error::WasmExecutionError(e.to_string()))?;
-        let len = handle_event
-            .call(&mut self.store, effect_bs.len() as _, event_bs.len() as _)
+        let len = self
+            .call_with_limits(
+                &handle_event,
+                &memory,
+                effect_bs.len() as _,
+                event_bs.len() as _,
+                Error::WasmExecutionError,
+            )
             .map_err(|e| {
                 log_execution_context(&effect_bs, &event_bs);
-                Error::WasmExecutionError(e.to_string())
+                e
             })?;
 
         match len {
@@ -273,6 +353,52 @@ mod tests {
         WasmHandler::load_by_path(bundle_path).unwrap()
     }
 
+    fn make_handler_from_wat(wat: &str, limits: WasmLimits) -> WasmHandler {
+        let mut store = Store::new(create_engine(limits.max_memory_pages));
+        let module = Module::new(&store, wat).unwrap();
+        let instance = Instance::new(&mut store, &module, &imports![]).unwrap();
+        WasmHandler {
+            store,
+            instance,
+            limits,
+        }
+    }
+
+    #[test]
+    fn test_fuel_exhausted() {
+        let wat = r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "handle_event") (param i32 i32) (result i32)
+                (loop $l (br $l))
+                (i32.const 3)))
+        "#;
+        let limits = WasmLimits {
+            fuel: 10_000,
+            ..Default::default()
+        };
+        let mut hdlr = make_handler_from_wat(wat, limits);
+        let ret = hdlr.handle_event(&Effect::default(), &Event::GameStart);
+        assert_eq!(ret, Err(Error::WasmFuelExhausted));
+    }
+
+    #[test]
+    fn test_memory_limit_exceeded() {
+        let wat = r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "init_state") (param i32 i32) (result i32)
+                (i32.const 3)))
+        "#;
+        let limits = WasmLimits {
+            max_memory_pages: 2,
+            ..Default::default()
+        };
+        let mut hdlr = make_handler_from_wat(wat, limits);
+        let ret = hdlr.init_state(&InitAccount::default());
+        assert_eq!(ret, Err(Error::WasmMemoryLimitExceeded));
+    }
+
     #[ignore]
     #[test]
     fn test_handle_event() {
@@ -6,7 +6,14 @@ use race_core::{
     error::{Error, Result},
     checkpoint::CheckpointOffChain,
     storage::StorageT,
-    types::{GetCheckpointParams, SaveCheckpointParams},
+    types::{
+        AppendDivergenceLogParams, AppendJournalParams, ChatMember, ConfirmSettleParams,
+        DivergenceReport, GetBacklogsParams, GetChatMembersParams, GetCheckpointParams,
+        GetDivergenceLogParams, GetJournalParams, GetPendingRefundsParams,
+        GetPendingSettlesParams, PruneBacklogsParams, RemovePendingRefundsParams, SaveBacklogParams,
+        SaveChatMemberParams, SaveCheckpointParams, SavePendingRefundsParams,
+        SavePendingSettleParams, SettleParams, TruncateJournalParams, CutJournalParams,
+    },
 };
 use rusqlite::{params, Connection, OptionalExtension};
 use tokio::sync::Mutex;
@@ -66,6 +73,289 @@ impl StorageT for LocalDbStorage {
             Ok(None)
         }
     }
+
+    async fn save_pending_settle(&self, params: SavePendingSettleParams) -> Result<()> {
+        let mut conn = self.conn.lock().await;
+        let SavePendingSettleParams { game_addr, checkpoint, settle_params } = params;
+        let checkpoint_bs = borsh::to_vec(&checkpoint).or(Err(Error::MalformedCheckpoint))?;
+        let sha = digest(&checkpoint_bs);
+        let settle_params_bs = borsh::to_vec(&settle_params).map_err(|e| Error::StorageError(e.to_string()))?;
+        let tx = conn
+            .transaction()
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+        tx.execute(
+            "INSERT OR REPLACE INTO game_checkpoints (game_addr, settle_version, checkpoint, sha) VALUES (?1, ?2, ?3, ?4)",
+            params![game_addr, settle_params.next_settle_version, checkpoint_bs, sha],
+        )
+        .map_err(|e| Error::StorageError(e.to_string()))?;
+        tx.execute(
+            "INSERT OR REPLACE INTO pending_settles (game_addr, settle_version, next_settle_version, settle_params) VALUES (?1, ?2, ?3, ?4)",
+            params![game_addr, settle_params.settle_version, settle_params.next_settle_version, settle_params_bs],
+        )
+        .map_err(|e| Error::StorageError(e.to_string()))?;
+        tx.commit().map_err(|e| Error::StorageError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn confirm_settle(&self, params: ConfirmSettleParams) -> Result<()> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "DELETE FROM pending_settles WHERE game_addr = ?1 and next_settle_version <= ?2",
+            params![params.game_addr, params.next_settle_version],
+        )
+        .map_err(|e| Error::StorageError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn get_pending_settles(&self, params: GetPendingSettlesParams) -> Result<Vec<SettleParams>> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn
+            .prepare("SELECT settle_params FROM pending_settles WHERE game_addr = ?1 ORDER BY settle_version")
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+        let rows = stmt
+            .query_map(params![params.game_addr], |row| row.get::<_, Vec<u8>>(0))
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+
+        let mut settles = vec![];
+        for row in rows {
+            let settle_params_bs = row.map_err(|e| Error::StorageError(e.to_string()))?;
+            let settle_params = SettleParams::try_from_slice(&settle_params_bs)
+                .map_err(|e| Error::StorageError(e.to_string()))?;
+            settles.push(settle_params);
+        }
+
+        Ok(settles)
+    }
+
+    async fn save_pending_refunds(&self, params: SavePendingRefundsParams) -> Result<()> {
+        let conn = self.conn.lock().await;
+        for access_version in params.access_versions {
+            conn.execute(
+                "INSERT OR IGNORE INTO pending_refunds (game_addr, access_version) VALUES (?1, ?2)",
+                params![params.game_addr, access_version],
+            )
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+        }
+
+        Ok(())
+    }
+
+    async fn remove_pending_refunds(&self, params: RemovePendingRefundsParams) -> Result<()> {
+        let conn = self.conn.lock().await;
+        for access_version in params.access_versions {
+            conn.execute(
+                "DELETE FROM pending_refunds WHERE game_addr = ?1 and access_version = ?2",
+                params![params.game_addr, access_version],
+            )
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+        }
+
+        Ok(())
+    }
+
+    async fn get_pending_refunds(&self, params: GetPendingRefundsParams) -> Result<Vec<u64>> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn
+            .prepare("SELECT access_version FROM pending_refunds WHERE game_addr = ?1 ORDER BY access_version")
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+        let rows = stmt
+            .query_map(params![params.game_addr], |row| row.get::<_, u64>(0))
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+
+        rows.collect::<std::result::Result<Vec<u64>, _>>()
+            .map_err(|e| Error::StorageError(e.to_string()))
+    }
+
+    async fn save_backlog(&self, params: SaveBacklogParams) -> Result<()> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "INSERT OR REPLACE INTO game_backlogs (game_addr, settle_version, data) VALUES (?1, ?2, ?3)",
+            params![params.game_addr, params.settle_version, params.data],
+        )
+        .map_err(|e| Error::StorageError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn get_backlogs(&self, params: GetBacklogsParams) -> Result<Vec<Vec<u8>>> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn
+            .prepare(
+                "SELECT data FROM (
+                    SELECT settle_version, data FROM game_backlogs
+                    WHERE game_addr = ?1 and settle_version >= ?2 and (?3 IS NULL or settle_version < ?3)
+                    ORDER BY settle_version DESC LIMIT ?4
+                ) ORDER BY settle_version",
+            )
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+        let rows = stmt
+            .query_map(
+                params![params.game_addr, params.settle_version, params.before, params.limit],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+
+        rows.collect::<std::result::Result<Vec<Vec<u8>>, _>>()
+            .map_err(|e| Error::StorageError(e.to_string()))
+    }
+
+    async fn prune_backlogs(&self, params: PruneBacklogsParams) -> Result<()> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "DELETE FROM game_backlogs WHERE game_addr = ?1 and settle_version < ?2",
+            params![params.game_addr, params.settle_version],
+        )
+        .map_err(|e| Error::StorageError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn append_journal(&self, params: AppendJournalParams) -> Result<()> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "INSERT OR REPLACE INTO game_journals (game_addr, settle_version, seq, data) VALUES (?1, ?2, ?3, ?4)",
+            params![params.game_addr, params.settle_version, params.seq, params.data],
+        )
+        .map_err(|e| Error::StorageError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn get_journal(&self, params: GetJournalParams) -> Result<Vec<Vec<u8>>> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn
+            .prepare("SELECT data FROM game_journals WHERE game_addr = ?1 and settle_version = ?2 ORDER BY seq")
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+        let rows = stmt
+            .query_map(params![params.game_addr, params.settle_version], |row| row.get::<_, Vec<u8>>(0))
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+
+        rows.collect::<std::result::Result<Vec<Vec<u8>>, _>>()
+            .map_err(|e| Error::StorageError(e.to_string()))
+    }
+
+    async fn truncate_journal(&self, params: TruncateJournalParams) -> Result<()> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "DELETE FROM game_journals WHERE game_addr = ?1 and settle_version < ?2",
+            params![params.game_addr, params.settle_version],
+        )
+        .map_err(|e| Error::StorageError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn cut_journal(&self, params: CutJournalParams) -> Result<()> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "DELETE FROM game_journals WHERE game_addr = ?1 and settle_version = ?2 and seq >= ?3",
+            params![params.game_addr, params.settle_version, params.seq],
+        )
+        .map_err(|e| Error::StorageError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn save_chat_member(&self, params: SaveChatMemberParams) -> Result<()> {
+        let conn = self.conn.lock().await;
+        let SaveChatMemberParams { game_addr, member } = params;
+        let r = if !member.muted && member.team.is_none() {
+            conn.execute(
+                "DELETE FROM chat_members WHERE game_addr = ?1 and player_addr = ?2",
+                params![game_addr, member.player_addr],
+            )
+        } else {
+            conn.execute(
+                "INSERT OR REPLACE INTO chat_members (game_addr, player_addr, muted, team) VALUES (?1, ?2, ?3, ?4)",
+                params![game_addr, member.player_addr, member.muted, member.team],
+            )
+        };
+        r.map_err(|e| Error::StorageError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn get_chat_members(&self, params: GetChatMembersParams) -> Result<Vec<ChatMember>> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn
+            .prepare("SELECT player_addr, muted, team FROM chat_members WHERE game_addr = ?1")
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+        let rows = stmt
+            .query_map(params![params.game_addr], |row| {
+                Ok(ChatMember {
+                    player_addr: row.get(0)?,
+                    muted: row.get(1)?,
+                    team: row.get(2)?,
+                })
+            })
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+
+        rows.collect::<std::result::Result<Vec<ChatMember>, _>>()
+            .map_err(|e| Error::StorageError(e.to_string()))
+    }
+
+    async fn append_divergence_log(&self, params: AppendDivergenceLogParams) -> Result<()> {
+        let mut conn = self.conn.lock().await;
+        let AppendDivergenceLogParams { report, max_per_game, max_total } = params;
+        let report_bs = borsh::to_vec(&report).map_err(|e| Error::StorageError(e.to_string()))?;
+        let tx = conn
+            .transaction()
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+        tx.execute(
+            "INSERT INTO divergence_reports (game_addr, report) VALUES (?1, ?2)",
+            params![report.game_addr, report_bs],
+        )
+        .map_err(|e| Error::StorageError(e.to_string()))?;
+        tx.execute(
+            "DELETE FROM divergence_reports WHERE game_addr = ?1 AND id NOT IN
+               (SELECT id FROM divergence_reports WHERE game_addr = ?1 ORDER BY id DESC LIMIT ?2)",
+            params![report.game_addr, max_per_game],
+        )
+        .map_err(|e| Error::StorageError(e.to_string()))?;
+        tx.execute(
+            "DELETE FROM divergence_reports WHERE id NOT IN
+               (SELECT id FROM divergence_reports ORDER BY id DESC LIMIT ?1)",
+            params![max_total],
+        )
+        .map_err(|e| Error::StorageError(e.to_string()))?;
+        tx.commit().map_err(|e| Error::StorageError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn get_divergence_log(&self, params: GetDivergenceLogParams) -> Result<Vec<DivergenceReport>> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn
+            .prepare("SELECT report FROM divergence_reports WHERE game_addr = ?1 ORDER BY id")
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+        let rows = stmt
+            .query_map(params![params.game_addr], |row| row.get::<_, Vec<u8>>(0))
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+
+        let mut reports = vec![];
+        for row in rows {
+            let bs = row.map_err(|e| Error::StorageError(e.to_string()))?;
+            reports.push(
+                DivergenceReport::try_from_slice(&bs)
+                    .map_err(|e| Error::StorageError(e.to_string()))?,
+            );
+        }
+        Ok(reports)
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        let conn = self.conn.lock().await;
+        // A single row is kept, so the table never grows
+        conn.execute(
+            "INSERT OR REPLACE INTO health_check (id, checked_at) VALUES (0, strftime('%s', 'now'))",
+            (),
+        )
+        .map_err(|e| Error::StorageError(e.to_string()))?;
+
+        Ok(())
+    }
 }
 
 pub fn init_table(conn: &Connection) -> Result<()> {
@@ -80,6 +370,75 @@ pub fn init_table(conn: &Connection) -> Result<()> {
         (),
     )
     .map_err(|e| Error::StorageError(e.to_string()))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS pending_settles (
+          game_addr TEXT NOT NULL,
+          settle_version INTEGER NOT NULL,
+          next_settle_version INTEGER NOT NULL,
+          settle_params BLOB NOT NULL,
+          PRIMARY KEY(game_addr, settle_version)
+        )",
+        (),
+    )
+    .map_err(|e| Error::StorageError(e.to_string()))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS pending_refunds (
+          game_addr TEXT NOT NULL,
+          access_version INTEGER NOT NULL,
+          PRIMARY KEY(game_addr, access_version)
+        )",
+        (),
+    )
+    .map_err(|e| Error::StorageError(e.to_string()))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS game_backlogs (
+          game_addr TEXT NOT NULL,
+          settle_version INTEGER NOT NULL,
+          data BLOB NOT NULL,
+          PRIMARY KEY(game_addr, settle_version)
+        )",
+        (),
+    )
+    .map_err(|e| Error::StorageError(e.to_string()))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS game_journals (
+          game_addr TEXT NOT NULL,
+          settle_version INTEGER NOT NULL,
+          seq INTEGER NOT NULL,
+          data BLOB NOT NULL,
+          PRIMARY KEY(game_addr, settle_version, seq)
+        )",
+        (),
+    )
+    .map_err(|e| Error::StorageError(e.to_string()))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS chat_members (
+          game_addr TEXT NOT NULL,
+          player_addr TEXT NOT NULL,
+          muted INTEGER NOT NULL,
+          team TEXT,
+          PRIMARY KEY(game_addr, player_addr)
+        )",
+        (),
+    )
+    .map_err(|e| Error::StorageError(e.to_string()))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS divergence_reports (
+          id INTEGER PRIMARY KEY AUTOINCREMENT,
+          game_addr TEXT NOT NULL,
+          report BLOB NOT NULL
+        )",
+        (),
+    )
+    .map_err(|e| Error::StorageError(e.to_string()))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS health_check (
+          id INTEGER PRIMARY KEY,
+          checked_at INTEGER NOT NULL
+        )",
+        (),
+    )
+    .map_err(|e| Error::StorageError(e.to_string()))?;
     Ok(())
 }
 
@@ -148,4 +507,260 @@ mod tests {
 
         assert_eq!(checkpoint_from_db, Some(checkpoint));
     }
+
+    fn make_settle_params(game_addr: &str, settle_version: u64) -> SettleParams {
+        SettleParams {
+            addr: game_addr.to_string(),
+            settles: vec![],
+            transfer: None,
+            awards: vec![],
+            checkpoint: Default::default(),
+            access_version: 1,
+            settle_version,
+            next_settle_version: settle_version + 1,
+            entry_lock: None,
+            accept_deposits: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pending_settles() {
+        let game_addr = "testaddr1".to_string();
+        let storage = LocalDbStorage::try_new_mem().unwrap();
+
+        for settle_version in [1, 2, 3] {
+            storage
+                .save_pending_settle(SavePendingSettleParams {
+                    game_addr: game_addr.clone(),
+                    checkpoint: CheckpointOffChain::default(),
+                    settle_params: make_settle_params(&game_addr, settle_version),
+                })
+                .await
+                .unwrap();
+        }
+        // Saving the same settle version again is idempotent
+        storage
+            .save_pending_settle(SavePendingSettleParams {
+                game_addr: game_addr.clone(),
+                checkpoint: CheckpointOffChain::default(),
+                settle_params: make_settle_params(&game_addr, 2),
+            })
+            .await
+            .unwrap();
+
+        // The checkpoint is saved at the next settle version
+        let checkpoint = storage
+            .get_checkpoint(GetCheckpointParams {
+                game_addr: game_addr.clone(),
+                settle_version: 4,
+            })
+            .await
+            .unwrap();
+        assert_eq!(checkpoint, Some(CheckpointOffChain::default()));
+
+        storage
+            .confirm_settle(ConfirmSettleParams {
+                game_addr: game_addr.clone(),
+                next_settle_version: 3,
+            })
+            .await
+            .unwrap();
+
+        let pending = storage
+            .get_pending_settles(GetPendingSettlesParams {
+                game_addr: game_addr.clone(),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(pending, vec![make_settle_params(&game_addr, 3)]);
+
+        // The confirmed rows are deleted
+        let conn = storage.conn.lock().await;
+        let count: u64 = conn
+            .query_row("SELECT COUNT(*) FROM pending_settles", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_journal() {
+        let game_addr = "testaddr1".to_string();
+        let storage = LocalDbStorage::try_new_mem().unwrap();
+
+        for (settle_version, seq) in [(1, 0), (1, 1), (2, 0), (2, 1), (2, 2)] {
+            storage
+                .append_journal(AppendJournalParams {
+                    game_addr: game_addr.clone(),
+                    settle_version,
+                    seq,
+                    data: vec![settle_version as u8, seq as u8],
+                })
+                .await
+                .unwrap();
+        }
+        // Appending the same entry again is idempotent
+        storage
+            .append_journal(AppendJournalParams {
+                game_addr: game_addr.clone(),
+                settle_version: 2,
+                seq: 1,
+                data: vec![2, 1],
+            })
+            .await
+            .unwrap();
+
+        let get_journal = |settle_version| {
+            storage.get_journal(GetJournalParams {
+                game_addr: game_addr.clone(),
+                settle_version,
+            })
+        };
+        assert_eq!(get_journal(2).await.unwrap(), vec![vec![2, 0], vec![2, 1], vec![2, 2]]);
+
+        storage
+            .truncate_journal(TruncateJournalParams {
+                game_addr: game_addr.clone(),
+                settle_version: 2,
+            })
+            .await
+            .unwrap();
+        assert!(get_journal(1).await.unwrap().is_empty());
+        assert_eq!(get_journal(2).await.unwrap().len(), 3);
+
+        storage
+            .cut_journal(CutJournalParams {
+                game_addr: game_addr.clone(),
+                settle_version: 2,
+                seq: 1,
+            })
+            .await
+            .unwrap();
+        assert_eq!(get_journal(2).await.unwrap(), vec![vec![2, 0]]);
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let storage = LocalDbStorage::try_new_mem().unwrap();
+        storage.health_check().await.unwrap();
+        storage.health_check().await.unwrap();
+
+        let conn = storage.conn.lock().await;
+        let count: u64 = conn
+            .query_row("SELECT COUNT(*) FROM health_check", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_chat_members() {
+        let storage = LocalDbStorage::try_new_mem().unwrap();
+        let member = ChatMember {
+            player_addr: "alice".into(),
+            muted: true,
+            team: Some("red".into()),
+        };
+        storage
+            .save_chat_member(SaveChatMemberParams {
+                game_addr: "game".into(),
+                member: member.clone(),
+            })
+            .await
+            .unwrap();
+        let get = |game_addr: &str| GetChatMembersParams { game_addr: game_addr.into() };
+        assert_eq!(storage.get_chat_members(get("game")).await.unwrap(), vec![member]);
+        assert!(storage.get_chat_members(get("other")).await.unwrap().is_empty());
+
+        // A member with nothing set is removed
+        storage
+            .save_chat_member(SaveChatMemberParams {
+                game_addr: "game".into(),
+                member: ChatMember {
+                    player_addr: "alice".into(),
+                    ..Default::default()
+                },
+            })
+            .await
+            .unwrap();
+        assert!(storage.get_chat_members(get("game")).await.unwrap().is_empty());
+    }
+
+    fn make_report(game_addr: &str, settle_version: u64) -> DivergenceReport {
+        DivergenceReport {
+            game_addr: game_addr.into(),
+            transactor_addr: "transactor".into(),
+            validator_addr: "validator".into(),
+            access_version: 0,
+            settle_version,
+            event: race_api::event::Event::GameStart,
+            timestamp: 0,
+            expected_state_sha: "a".into(),
+            actual_state_sha: "b".into(),
+            signature: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_divergence_log() {
+        let storage = LocalDbStorage::try_new_mem().unwrap();
+        let append = |game_addr: &str, settle_version| AppendDivergenceLogParams {
+            report: make_report(game_addr, settle_version),
+            max_per_game: 2,
+            max_total: 3,
+        };
+        let get = |game_addr: &str| GetDivergenceLogParams { game_addr: game_addr.into() };
+
+        for v in [1, 2, 3] {
+            storage.append_divergence_log(append("game1", v)).await.unwrap();
+        }
+        // The oldest report of the game is dropped
+        assert_eq!(
+            storage.get_divergence_log(get("game1")).await.unwrap(),
+            vec![make_report("game1", 2), make_report("game1", 3)]
+        );
+
+        storage.append_divergence_log(append("game2", 1)).await.unwrap();
+        storage.append_divergence_log(append("game2", 2)).await.unwrap();
+        // The oldest reports of all games are dropped
+        assert_eq!(
+            storage.get_divergence_log(get("game1")).await.unwrap(),
+            vec![make_report("game1", 3)]
+        );
+        assert_eq!(storage.get_divergence_log(get("game2")).await.unwrap().len(), 2);
+        assert!(storage.get_divergence_log(get("other")).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_backlogs() {
+        let storage = LocalDbStorage::try_new_mem().unwrap();
+        for v in 1..=5u8 {
+            storage
+                .save_backlog(SaveBacklogParams {
+                    game_addr: "game".into(),
+                    settle_version: v as u64,
+                    data: vec![v],
+                })
+                .await
+                .unwrap();
+        }
+        let get = |settle_version, before, limit| GetBacklogsParams {
+            game_addr: "game".into(),
+            settle_version,
+            before,
+            limit,
+        };
+
+        assert_eq!(storage.get_backlogs(get(2, None, 10)).await.unwrap(), vec![vec![2], vec![3], vec![4], vec![5]]);
+        // The latest ones within the limit
+        assert_eq!(storage.get_backlogs(get(1, Some(5), 2)).await.unwrap(), vec![vec![3], vec![4]]);
+
+        storage
+            .prune_backlogs(PruneBacklogsParams {
+                game_addr: "game".into(),
+                settle_version: 4,
+            })
+            .await
+            .unwrap();
+        assert_eq!(storage.get_backlogs(get(0, None, 10)).await.unwrap(), vec![vec![4], vec![5]]);
+    }
 }
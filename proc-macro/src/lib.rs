@@ -5,7 +5,9 @@ use proc_macro::TokenStream;
 use quote::quote;
 use syn::{parse_macro_input, ItemStruct};
 
-/// A macro to generate boilerplate code for using in wasm.
+/// A macro to generate boilerplate code for using in wasm.  The
+/// code is only generated for `wasm32` target, so the game can be
+/// linked as a native library, see `race_handler::NativeHandler`.
 ///
 /// ```
 /// use race_api::prelude::*;
@@ -39,6 +41,7 @@ pub fn game_handler(_metadata: TokenStream, input: TokenStream) -> TokenStream {
 
         #s
 
+        #[cfg(target_arch = "wasm32")]
         pub fn read_ptr<T: BorshDeserialize>(ptr: &mut *mut u8, size: u32) -> Option<T> {
             let slice = unsafe { core::slice::from_raw_parts_mut(*ptr, size as _) };
             if let Ok(parsed) = T::try_from_slice(&slice) {
@@ -49,6 +52,7 @@ pub fn game_handler(_metadata: TokenStream, input: TokenStream) -> TokenStream {
             }
         }
 
+        #[cfg(target_arch = "wasm32")]
         pub fn write_ptr<T: BorshSerialize>(ptr: &mut *mut u8, data: T) -> u32 {
             if let Ok(vec) = borsh::to_vec(&data) {
                 unsafe { std::ptr::copy(vec.as_ptr(), *ptr, vec.len()) }
@@ -58,6 +62,7 @@ pub fn game_handler(_metadata: TokenStream, input: TokenStream) -> TokenStream {
             }
         }
 
+        #[cfg(target_arch = "wasm32")]
         #[no_mangle]
         pub extern "C" fn handle_event(effect_size: u32, event_size: u32) -> u32 {
             let mut ptr = 1 as *mut u8;
@@ -82,6 +87,7 @@ pub fn game_handler(_metadata: TokenStream, input: TokenStream) -> TokenStream {
             write_ptr(&mut ptr, effect)
         }
 
+        #[cfg(target_arch = "wasm32")]
         #[no_mangle]
         pub extern "C" fn init_state(effect_size: u32, init_account_size: u32) -> u32 {
             let mut ptr = 1 as *mut u8;
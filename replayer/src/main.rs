@@ -4,18 +4,15 @@ mod context;
 mod server;
 mod ui;
 
-use borsh::BorshDeserialize;
 use clap::{arg, Command};
 use crate::context::ReplayerContext;
 use crate::error::ReplayerError;
 use crate::server::run_server;
 use crate::ui::render_controller_ui;
-use crate::utils::base64_decode;
 use race_env::Config;
-use race_event_record::{Record, RecordsHeader};
+use race_event_record::{open_records_file, read_records};
 use race_transport::builder::TransportBuilder;
-use std::fs::File;
-use std::io::{self, BufRead};
+use std::path::Path;
 use std::sync::Arc;
 
 fn cli() -> Command {
@@ -34,26 +31,13 @@ async fn play(context: ReplayerContext, file: String) -> Result<(), ReplayerErro
 
     let context = Arc::new(context);
 
-    let mut lines = io::BufReader::new(File::open(file)?).lines();
-    let Some(Ok(header_line)) = lines.next() else {
-        return Err(ReplayerError::MissingHeader);
-    };
-
-    let header = RecordsHeader::try_from_slice(&base64_decode(&header_line)?)?;
+    let (header, records) = read_records(open_records_file(Path::new(&file))?)?;
 
     let transport = TransportBuilder::default()
         .with_chain(header.chain.as_str().into())
         .try_with_config(&context.config)?
         .build();
 
-    let mut records = vec![];
-    for ln in lines{
-        let ln = ln?;
-        let v = base64_decode(&ln)?;
-        let r = Record::try_from_slice(&v)?;
-        records.push(r);
-    }
-
     let server_handle = run_server(context.clone()).await?;
 
     render_controller_ui(context, header, records)?;
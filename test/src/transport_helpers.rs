@@ -20,10 +20,13 @@ use race_core::{
     },
 };
 
+#[derive(Clone)]
 pub struct DummyTransport {
     settles: Arc<Mutex<Vec<Settle>>>,
     states: Arc<Mutex<Vec<GameAccount>>>,
     fail_next_settle: Arc<Mutex<bool>>,
+    votes: Arc<Mutex<Vec<VoteParams>>>,
+    reject_deposits: Arc<Mutex<Vec<RejectDepositsParams>>>,
 }
 
 impl DummyTransport {
@@ -32,6 +35,16 @@ impl DummyTransport {
         self.settles.lock().unwrap()
     }
 
+    #[allow(dead_code)]
+    pub fn get_votes(&self) -> impl Deref<Target = Vec<VoteParams>> + '_ {
+        self.votes.lock().unwrap()
+    }
+
+    #[allow(dead_code)]
+    pub fn get_reject_deposits(&self) -> impl Deref<Target = Vec<RejectDepositsParams>> + '_ {
+        self.reject_deposits.lock().unwrap()
+    }
+
     #[allow(dead_code)]
     pub fn simulate_states(&self, mut states: Vec<GameAccount>) {
         self.states.lock().unwrap().append(&mut states);
@@ -55,6 +68,8 @@ impl Default for DummyTransport {
             settles: Arc::new(Mutex::new(vec![])),
             states: Arc::new(Mutex::new(vec![])),
             fail_next_settle: Arc::new(Mutex::new(false)),
+            votes: Arc::new(Mutex::new(vec![])),
+            reject_deposits: Arc::new(Mutex::new(vec![])),
         }
     }
 }
@@ -87,6 +102,7 @@ impl TransportT for DummyTransport {
     }
 
     async fn vote(&self, params: VoteParams) -> Result<()> {
+        self.votes.lock().unwrap().push(params);
         Ok(())
     }
 
@@ -136,7 +152,7 @@ impl TransportT for DummyTransport {
         let mut fail_next_settle = self.fail_next_settle.lock().unwrap();
         if *fail_next_settle {
             *fail_next_settle = false;
-            Err(Error::TransportError("Mock failure".into()))
+            Err(Error::RpcError("Mock failure".into()))
         } else if params.addr.eq("TEST") {
             let mut settles = self.settles.lock().unwrap();
             settles.append(&mut params.settles);
@@ -198,6 +214,7 @@ impl TransportT for DummyTransport {
     }
 
     async fn reject_deposits(&self, params: RejectDepositsParams) -> Result<RejectDepositsResult> {
+        self.reject_deposits.lock().unwrap().push(params);
         Ok(RejectDepositsResult {
             signature: "".to_string()
         })
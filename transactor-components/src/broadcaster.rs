@@ -1,16 +1,33 @@
 //! The broadcaster will broadcast events to all connected participants
 //! The broadcast should also save
-
-use std::collections::LinkedList;
-use std::sync::Arc;
+//!
+//! Each subscriber has a bounded queue.  When the queue of a slow
+//! subscriber is full, the frames are no longer queued for it.  Once
+//! there's room, a `Backlogs` frame from the latest checkpoint is sent
+//! to resync it.  The subscribers which stay slow are disconnected.
+//!
+//! The backlogs are kept in memory within the limits in
+//! [BacklogConfig].  The older groups are spilled to storage if
+//! enabled, and loaded back for the clients starting from them.  The
+//! spilled groups are pruned at settlements, and removed when the game
+//! is closed.
+
+use std::collections::{LinkedList, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use borsh::{BorshSerialize, BorshDeserialize};
 use async_trait::async_trait;
-use race_api::event::Event;
+use race_api::event::{ChatMessage, Event, MessageChannel};
 use race_core::checkpoint::CheckpointOffChain;
-use race_core::types::{BroadcastFrame, BroadcastSync, TxState};
+use race_core::storage::StorageT;
+use race_core::types::{BroadcastFrame, BroadcastSync, ClientMode, EventCursor, GetBacklogsParams, PruneBacklogsParams, SaveBacklogParams};
 use race_core::node::Node;
-use tokio::sync::{broadcast, RwLock};
+use race_env::BacklogConfig;
+use serde::Serialize;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{broadcast, mpsc, RwLock};
 use tracing::{debug, error, info, warn};
 
 use crate::common::{Component, ConsumerPorts};
@@ -18,11 +35,39 @@ use race_transactor_frames::EventFrame;
 
 use super::{CloseReason, ComponentEnv};
 
+/// The number of chat messages kept for new connected clients.
+const MESSAGE_BACKLOG_SIZE: usize = 100;
+
+/// The number of frames queued for each subscriber.
+const SUBSCRIBER_QUEUE_SIZE: usize = 100;
+
+/// A subscriber lagged for longer than this is disconnected.
+const SLOW_SUBSCRIBER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A subscriber is disconnected when it lags again after this number
+/// of resyncs.
+const MAX_SUBSCRIBER_RESYNCS: u32 = 3;
+
+/// The default for maximum number of backlog groups of a game.
+const DEFAULT_MAX_BACKLOG_GROUPS: usize = 200;
+
+/// The default for number of settle versions the spilled groups are
+/// kept for.
+const DEFAULT_MAX_SPILLED_GROUPS: u64 = 1000;
+
+/// The maximum number of groups in a `Backlogs` or `Resume` frame.
+/// A client starting from an older settle version gets the backlogs
+/// from a later checkpoint.
+const MAX_SERVED_BACKLOG_GROUPS: usize = 20;
+
+/// The bytes of the backlogs in memory, of all games.
+static TOTAL_BACKLOG_BYTES: AtomicUsize = AtomicUsize::new(0);
+
 /// Backup events in memeory, for new connected clients.  The
 /// `settle_version` and `access_version` are the values at the time
 /// we handle the events. The backups always start with a checkpoint
 /// event which contains the initial handler state
-#[derive(Debug)]
+#[derive(Debug, BorshSerialize, BorshDeserialize)]
 pub struct EventBackup {
     pub event: Event,
     pub timestamp: u64,
@@ -37,26 +82,114 @@ pub struct EventBackupGroup {
     pub settle_version: u64,
     pub checkpoint_off_chain: Option<CheckpointOffChain>,
     pub nodes: Vec<Node>,
+    /// The estimated size in memory.
+    pub bytes: usize,
+    pub created_at: Instant,
+}
+
+/// An [EventBackupGroup] spilled to storage.
+#[derive(BorshSerialize, BorshDeserialize)]
+struct SpilledGroup {
+    state_sha: String,
+    sync: BroadcastSync,
+    events: Vec<EventBackup>,
+    settle_version: u64,
+    checkpoint_off_chain: Option<CheckpointOffChain>,
+    nodes: Vec<Node>,
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+fn estimate_bytes<T: BorshSerialize>(value: &T) -> usize {
+    borsh::object_length(value).unwrap_or_default()
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct CheckpointBroadcastFrame {
     pub data: Vec<u8>,
     pub nodes: Vec<Node>,
 }
 
+impl EventBackup {
+    pub fn to_frame(&self) -> BroadcastFrame {
+        BroadcastFrame::Event {
+            event: self.event.clone(),
+            timestamp: self.timestamp,
+            state_sha: self.state_sha.clone(),
+        }
+    }
+}
+
 impl EventBackupGroup {
+    pub fn new(
+        state_sha: String,
+        sync: BroadcastSync,
+        settle_version: u64,
+        checkpoint_off_chain: Option<CheckpointOffChain>,
+        nodes: Vec<Node>,
+    ) -> Self {
+        let bytes = estimate_bytes(&checkpoint_off_chain) + estimate_bytes(&nodes);
+        Self {
+            state_sha,
+            sync,
+            events: LinkedList::new(),
+            settle_version,
+            checkpoint_off_chain,
+            nodes,
+            bytes,
+            created_at: Instant::now(),
+        }
+    }
+
+    pub fn push_event(&mut self, event: EventBackup) {
+        self.bytes += estimate_bytes(&event);
+        self.events.push_back(event);
+    }
+
+    fn spill(&self) -> SpilledGroup {
+        SpilledGroup {
+            state_sha: self.state_sha.clone(),
+            sync: self.sync.clone(),
+            events: self
+                .events
+                .iter()
+                .map(|e| EventBackup {
+                    event: e.event.clone(),
+                    timestamp: e.timestamp,
+                    state_sha: e.state_sha.clone(),
+                })
+                .collect(),
+            settle_version: self.settle_version,
+            checkpoint_off_chain: self.checkpoint_off_chain.clone(),
+            nodes: self.nodes.clone(),
+        }
+    }
+
+    fn load(spilled: SpilledGroup) -> Self {
+        let mut group = Self::new(
+            spilled.state_sha,
+            spilled.sync,
+            spilled.settle_version,
+            spilled.checkpoint_off_chain,
+            spilled.nodes,
+        );
+        for event in spilled.events {
+            group.push_event(event);
+        }
+        group
+    }
+
     pub fn to_frames(&self) -> Vec<BroadcastFrame> {
+        self.to_frames_from(0)
+    }
+
+    /// The sync frame and the events from index `start`.
+    pub fn to_frames_from(&self, start: usize) -> Vec<BroadcastFrame> {
         let mut frames = vec![];
         frames.push(BroadcastFrame::Sync {
             sync: self.sync.clone(),
         });
-        for event in self.events.iter() {
-            frames.push(BroadcastFrame::Event {
-                event: event.event.clone(),
-                timestamp: event.timestamp,
-                state_sha: event.state_sha.clone(),
-            });
+        for event in self.events.iter().skip(start) {
+            frames.push(event.to_frame());
         }
         frames
     }
@@ -66,45 +199,392 @@ impl EventBackupGroup {
     }
 }
 
+/// The counters of the subscribers of a game.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriberStats {
+    pub active: usize,
+    pub lagged: u64,
+    pub resynced: u64,
+    pub disconnected: u64,
+}
+
+struct SubscriberSlot {
+    tx: mpsc::Sender<BroadcastFrame>,
+    lagged_since: Option<Instant>,
+    resyncs: u32,
+}
+
+#[derive(Default)]
+struct Subscribers {
+    slots: Vec<SubscriberSlot>,
+    stats: SubscriberStats,
+}
+
+/// Return true if the frame is included in the `Backlogs` built after
+/// it, so it's not sent again after a resync.
+fn is_in_backlogs(frame: &BroadcastFrame) -> bool {
+    matches!(frame, BroadcastFrame::Event { .. } | BroadcastFrame::Sync { .. })
+}
+
+impl Subscribers {
+    fn subscribe(&mut self) -> mpsc::Receiver<BroadcastFrame> {
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_QUEUE_SIZE);
+        self.slots.push(SubscriberSlot {
+            tx,
+            lagged_since: None,
+            resyncs: 0,
+        });
+        rx
+    }
+
+    fn has_lagged(&self) -> bool {
+        self.slots.iter().any(|s| s.lagged_since.is_some())
+    }
+
+    /// Queue the frame for all subscribers.  The lagged subscribers
+    /// get `resync` instead, if there's room.
+    fn send(&mut self, frame: BroadcastFrame, resync: Option<&BroadcastFrame>) {
+        let now = Instant::now();
+        let stats = &mut self.stats;
+
+        self.slots.retain_mut(|slot| {
+            if let Some(lagged_since) = slot.lagged_since {
+                if slot.resyncs >= MAX_SUBSCRIBER_RESYNCS
+                    || now.duration_since(lagged_since) > SLOW_SUBSCRIBER_TIMEOUT
+                {
+                    stats.disconnected += 1;
+                    return false;
+                }
+                let Some(resync) = resync else {
+                    return true;
+                };
+                match slot.tx.try_send(resync.clone()) {
+                    Ok(_) => {
+                        slot.lagged_since = None;
+                        slot.resyncs += 1;
+                        stats.resynced += 1;
+                    }
+                    Err(TrySendError::Full(_)) => return true,
+                    Err(TrySendError::Closed(_)) => return false,
+                }
+                if is_in_backlogs(&frame) {
+                    return true;
+                }
+            }
+
+            match slot.tx.try_send(frame.clone()) {
+                Ok(_) => true,
+                Err(TrySendError::Full(_)) => {
+                    slot.lagged_since = Some(now);
+                    stats.lagged += 1;
+                    true
+                }
+                Err(TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+}
+
+/// Build the `Backlogs` frame.  See [Broadcaster::get_backlogs].
+fn make_backlogs(
+    event_backup_groups: &[&EventBackupGroup],
+    settle_version: u64,
+) -> BroadcastFrame {
+    let mut checkpoint_off_chain: Option<CheckpointOffChain> = None;
+    let mut backlogs: Vec<BroadcastFrame> = vec![];
+    let mut state_sha = "".to_string();
+
+    // By default, returns the histories with settle_version
+    // greater than the given one
+    if settle_version > 0 {
+        let groups: Vec<&&EventBackupGroup> = event_backup_groups
+            .iter()
+            .filter(|g| g.settle_version >= settle_version)
+            .collect();
+        let skip = groups.len().saturating_sub(MAX_SERVED_BACKLOG_GROUPS);
+        for (i, group) in groups.into_iter().skip(skip).enumerate() {
+            // Start from the first group served if the older ones
+            // are left out
+            if group.settle_version == settle_version || (skip > 0 && i == 0) {
+                checkpoint_off_chain = group.checkpoint_off_chain.clone();
+                state_sha = group.state_sha.clone();
+            }
+            backlogs.append(&mut group.to_frames());
+        }
+    }
+
+    if backlogs.is_empty() {
+        if let Some(group) = event_backup_groups.iter().last() {
+            checkpoint_off_chain = group.checkpoint_off_chain.clone();
+            state_sha = group.state_sha.clone();
+            backlogs.append(&mut group.to_frames());
+        }
+    }
+
+    BroadcastFrame::Backlogs {
+        checkpoint_off_chain,
+        backlogs: Box::new(backlogs),
+        state_sha,
+    }
+}
+
+/// The limits of the backlogs in memory.
+struct Retention {
+    max_groups: usize,
+    max_age: Option<Duration>,
+    max_bytes: Option<usize>,
+    max_total_bytes: Option<usize>,
+    spill: bool,
+    max_spilled_groups: u64,
+}
+
+impl Retention {
+    fn from_config(config: Option<&BacklogConfig>) -> Self {
+        Self {
+            max_groups: config
+                .and_then(|c| c.max_groups)
+                .unwrap_or(DEFAULT_MAX_BACKLOG_GROUPS),
+            max_age: config.and_then(|c| c.max_age).map(Duration::from_secs),
+            max_bytes: config.and_then(|c| c.max_bytes),
+            max_total_bytes: config.and_then(|c| c.max_total_bytes),
+            spill: config.and_then(|c| c.spill).unwrap_or(false),
+            max_spilled_groups: config
+                .and_then(|c| c.max_spilled_groups)
+                .unwrap_or(DEFAULT_MAX_SPILLED_GROUPS),
+        }
+    }
+
+    /// Pop the oldest groups beyond the limits, the latest group is
+    /// always kept.  `bytes` is the bytes of `groups`, and
+    /// `total_bytes` is the bytes of all games.
+    fn evict(
+        &self,
+        groups: &mut LinkedList<EventBackupGroup>,
+        now: Instant,
+        mut bytes: usize,
+        mut total_bytes: usize,
+    ) -> Vec<EventBackupGroup> {
+        let mut evicted = vec![];
+
+        while groups.len() > 1 {
+            let Some(oldest) = groups.front() else {
+                break;
+            };
+            let over = groups.len() > self.max_groups
+                || self
+                    .max_age
+                    .map_or(false, |age| now.duration_since(oldest.created_at) > age)
+                || self.max_bytes.map_or(false, |max| bytes > max)
+                || self.max_total_bytes.map_or(false, |max| total_bytes > max);
+            if !over {
+                break;
+            }
+            if let Some(group) = groups.pop_front() {
+                bytes = bytes.saturating_sub(group.bytes);
+                total_bytes = total_bytes.saturating_sub(group.bytes);
+                evicted.push(group);
+            }
+        }
+
+        evicted
+    }
+}
+
+/// The activity of a game, to tell if it's idle.  Players and
+/// validators are counted from the nodes in checkpoints, plus the
+/// joins since then.
+struct Activity {
+    last_active: Instant,
+    players: usize,
+    validators: usize,
+    access_version: u64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            last_active: Instant::now(),
+            players: 0,
+            validators: 0,
+            access_version: 0,
+        }
+    }
+
+    fn touch(&mut self) {
+        self.last_active = Instant::now();
+    }
+
+    fn reset_nodes(&mut self, nodes: &[Node], access_version: u64) {
+        self.players = nodes.iter().filter(|n| n.mode == ClientMode::Player).count();
+        self.validators = nodes.iter().filter(|n| n.mode == ClientMode::Validator).count();
+        self.access_version = access_version;
+        self.touch();
+    }
+
+    // A game with validators is never idle, they would take an
+    // unloaded game for a dropped transactor.
+    fn idle_for(&self) -> Option<Duration> {
+        (self.players == 0 && self.validators == 0).then(|| self.last_active.elapsed())
+    }
+}
+
 pub struct BroadcasterContext {
-    #[allow(unused)]
     id: String,
     event_backup_groups: Arc<RwLock<LinkedList<EventBackupGroup>>>,
-    broadcast_tx: broadcast::Sender<BroadcastFrame>,
+    messages: Arc<RwLock<VecDeque<ChatMessage>>>,
+    subscribers: Arc<Mutex<Subscribers>>,
     checkpoint_tx: broadcast::Sender<CheckpointBroadcastFrame>,
+    storage: Option<Arc<dyn StorageT>>,
+    retention: Retention,
+    /// The bytes of this game counted in [TOTAL_BACKLOG_BYTES].
+    bytes: AtomicUsize,
+    activity: Arc<Mutex<Activity>>,
+}
+
+/// Count the bytes added to the backlogs of the game.
+fn add_backlog_bytes(ctx: &BroadcasterContext, bytes: usize) {
+    ctx.bytes.fetch_add(bytes, Ordering::SeqCst);
+    TOTAL_BACKLOG_BYTES.fetch_add(bytes, Ordering::SeqCst);
+}
+
+/// Start a new group from a checkpoint.
+fn push_backlog_group(
+    ctx: &BroadcasterContext,
+    event_backup_groups: &mut LinkedList<EventBackupGroup>,
+    group: EventBackupGroup,
+) {
+    add_backlog_bytes(ctx, group.bytes);
+    event_backup_groups.push_back(group);
+}
+
+/// Apply the retention after the backlogs grow.  The dropped groups
+/// are saved to storage before they are removed from memory, if
+/// spilling is enabled.
+async fn retain_backlogs(ctx: &BroadcasterContext, env: &ComponentEnv) {
+    let mut event_backup_groups = ctx.event_backup_groups.write().await;
+
+    let mut evicted = ctx.retention.evict(
+        &mut event_backup_groups,
+        Instant::now(),
+        ctx.bytes.load(Ordering::SeqCst),
+        TOTAL_BACKLOG_BYTES.load(Ordering::SeqCst),
+    );
+    if evicted.is_empty() {
+        return;
+    }
+
+    if let (true, Some(storage)) = (ctx.retention.spill, ctx.storage.as_ref()) {
+        for group in evicted.iter() {
+            let data = match borsh::to_vec(&group.spill()) {
+                Ok(data) => data,
+                Err(e) => {
+                    error!("{} Failed to serialize backlog group: {}", env.log_prefix, e);
+                    continue;
+                }
+            };
+            let r = storage
+                .save_backlog(SaveBacklogParams {
+                    game_addr: ctx.id.clone(),
+                    settle_version: group.settle_version,
+                    data,
+                })
+                .await;
+            if let Err(e) = r {
+                error!("{} Failed to spill backlog group: {}", env.log_prefix, e);
+            }
+        }
+    }
+
+    let evicted_bytes: usize = evicted.iter().map(|g| g.bytes).sum();
+    ctx.bytes.fetch_sub(evicted_bytes, Ordering::SeqCst);
+    TOTAL_BACKLOG_BYTES.fetch_sub(evicted_bytes, Ordering::SeqCst);
+    debug!(
+        "{} Drop {} backlog groups from memory, settle_version up to {:?}",
+        env.log_prefix,
+        evicted.len(),
+        evicted.pop().map(|g| g.settle_version)
+    );
+}
+
+/// Remove the spilled groups before `settle_version`.
+async fn prune_spilled(ctx: &BroadcasterContext, env: &ComponentEnv, settle_version: u64) {
+    let (true, Some(storage)) = (ctx.retention.spill, ctx.storage.as_ref()) else {
+        return;
+    };
+    let r = storage
+        .prune_backlogs(PruneBacklogsParams {
+            game_addr: ctx.id.clone(),
+            settle_version,
+        })
+        .await;
+    if let Err(e) = r {
+        error!("{} Failed to prune spilled backlogs: {}", env.log_prefix, e);
+    }
+}
+
+/// Send the frame to all subscribers, with the resync frame built
+/// from the current backups when any of them lagged.
+async fn broadcast(ctx: &BroadcasterContext, frame: BroadcastFrame) {
+    let lagged = ctx.subscribers.lock().unwrap().has_lagged();
+    let resync = if lagged {
+        let event_backup_groups = ctx.event_backup_groups.read().await;
+        let groups: Vec<&EventBackupGroup> = event_backup_groups.iter().collect();
+        Some(make_backlogs(&groups, 0))
+    } else {
+        None
+    };
+    ctx.subscribers.lock().unwrap().send(frame, resync.as_ref());
 }
 
 /// A component that pushes event to clients.
 pub struct Broadcaster {
-    #[allow(unused)]
     id: String,
     #[allow(unused)]
     game_id: usize,
     event_backup_groups: Arc<RwLock<LinkedList<EventBackupGroup>>>,
-    broadcast_tx: broadcast::Sender<BroadcastFrame>,
+    messages: Arc<RwLock<VecDeque<ChatMessage>>>,
+    subscribers: Arc<Mutex<Subscribers>>,
     checkpoint_tx: broadcast::Sender<CheckpointBroadcastFrame>,
+    storage: Option<Arc<dyn StorageT>>,
+    activity: Arc<Mutex<Activity>>,
 }
 
 impl Broadcaster {
-    pub fn init(id: String, game_id: usize) -> (Self, BroadcasterContext) {
+    /// The backlogs are spilled to `storage` if it's provided.
+    pub fn init(
+        id: String,
+        game_id: usize,
+        storage: Option<Arc<dyn StorageT>>,
+        config: Option<&BacklogConfig>,
+    ) -> (Self, BroadcasterContext) {
         let event_backup_groups = Arc::new(RwLock::new(LinkedList::new()));
-        let (broadcast_tx, broadcast_rx) = broadcast::channel(10);
+        let messages = Arc::new(RwLock::new(VecDeque::new()));
+        let subscribers = Arc::new(Mutex::new(Subscribers::default()));
         let (checkpoint_tx, checkpoint_rx) = broadcast::channel(10);
-        drop(broadcast_rx);
         drop(checkpoint_rx);
+        let activity = Arc::new(Mutex::new(Activity::new()));
         (
             Self {
                 id: id.clone(),
                 game_id,
                 event_backup_groups: event_backup_groups.clone(),
-                broadcast_tx: broadcast_tx.clone(),
+                messages: messages.clone(),
+                subscribers: subscribers.clone(),
                 checkpoint_tx: checkpoint_tx.clone(),
+                storage: storage.clone(),
+                activity: activity.clone(),
             },
             BroadcasterContext {
                 id,
                 event_backup_groups,
-                broadcast_tx,
+                messages,
+                subscribers,
                 checkpoint_tx,
+                storage,
+                retention: Retention::from_config(config),
+                bytes: AtomicUsize::new(0),
+                activity,
             },
         )
     }
@@ -113,8 +593,28 @@ impl Broadcaster {
         self.checkpoint_tx.subscribe()
     }
 
-    pub fn get_broadcast_rx(&self) -> broadcast::Receiver<BroadcastFrame> {
-        self.broadcast_tx.subscribe()
+    /// Subscribe the broadcast frames, with a bounded queue.
+    pub fn subscribe(&self) -> mpsc::Receiver<BroadcastFrame> {
+        self.subscribers.lock().unwrap().subscribe()
+    }
+
+    pub fn subscriber_stats(&self) -> SubscriberStats {
+        let subscribers = self.subscribers.lock().unwrap();
+        SubscriberStats {
+            active: subscribers.slots.len(),
+            ..subscribers.stats.clone()
+        }
+    }
+
+    /// Return how long the game has had no players and no events,
+    /// None if any player or validator is in the game.
+    pub fn idle_for(&self) -> Option<Duration> {
+        self.activity.lock().unwrap().idle_for()
+    }
+
+    /// Return the latest access version seen by the game.
+    pub fn access_version(&self) -> u64 {
+        self.activity.lock().unwrap().access_version
     }
 
     pub async fn get_latest_checkpoint_broadcast_frame(&self) -> Option<CheckpointBroadcastFrame> {
@@ -138,6 +638,41 @@ impl Broadcaster {
         None
     }
 
+    /// Load the latest `limit` groups spilled to storage, from
+    /// `settle_version` and before the groups in memory.
+    async fn load_spilled(
+        &self,
+        settle_version: u64,
+        before: Option<u64>,
+        limit: usize,
+    ) -> Vec<EventBackupGroup> {
+        let Some(storage) = self.storage.as_ref() else {
+            return vec![];
+        };
+        if limit == 0 {
+            return vec![];
+        }
+        let r = storage
+            .get_backlogs(GetBacklogsParams {
+                game_addr: self.id.clone(),
+                settle_version,
+                before,
+                limit,
+            })
+            .await;
+        match r {
+            Ok(groups) => groups
+                .into_iter()
+                .filter_map(|data| SpilledGroup::try_from_slice(&data).ok())
+                .map(EventBackupGroup::load)
+                .collect(),
+            Err(e) => {
+                error!("Failed to load spilled backlogs: {}", e);
+                vec![]
+            }
+        }
+    }
+
     pub async fn get_checkpoint(&self, settle_version: u64) -> Option<CheckpointOffChain> {
         let event_backup_groups = self.event_backup_groups.read().await;
         info!("Get checkpoint with settle_version = {}", settle_version);
@@ -148,6 +683,14 @@ impl Broadcaster {
             }
         }
 
+        let before = event_backup_groups.front().map(|g| g.settle_version);
+        if before.map_or(true, |v| settle_version < v) {
+            let spilled = self.load_spilled(settle_version, Some(settle_version + 1), 1).await;
+            if let Some(group) = spilled.into_iter().find(|g| g.settle_version == settle_version) {
+                return group.checkpoint_off_chain;
+            }
+        }
+
         warn!("Missing the checkpoint for settle_version = {}, the client won't be able to join this game.", settle_version);
         let available_settle_versions: Vec<u64> = event_backup_groups
             .iter()
@@ -159,43 +702,79 @@ impl Broadcaster {
 
     /// Retrieve a list of event histories with a given
     /// `settle_version`.  All events happened after the
-    /// `settle_version` will be returned.  If a zero `settle_version`
+    /// `settle_version` will be returned, from at most
+    /// [MAX_SERVED_BACKLOG_GROUPS] groups.  If a zero `settle_version`
     /// is provided, just return the events after the latest
     /// checkpoint.
     pub async fn get_backlogs(&self, settle_version: u64) -> BroadcastFrame {
         let event_backup_groups = self.event_backup_groups.read().await;
 
-        let mut checkpoint_off_chain: Option<CheckpointOffChain> = None;
-        let mut backlogs: Vec<BroadcastFrame> = vec![];
-        let mut state_sha = "".to_string();
-
-        // By default, returns the histories with settle_version
-        // greater than the given one
-        if settle_version > 0 {
-            for group in event_backup_groups.iter() {
-                if group.settle_version == settle_version {
-                    checkpoint_off_chain = group.checkpoint_off_chain.clone();
-                    state_sha = group.state_sha.clone();
-                }
-                if group.settle_version >= settle_version {
-                    backlogs.append(&mut group.to_frames());
-                }
-            }
+        // The groups before the ones in memory are loaded from storage
+        let before = event_backup_groups.front().map(|g| g.settle_version);
+        let spilled = if settle_version > 0 && before.map_or(false, |v| settle_version < v) {
+            let limit = MAX_SERVED_BACKLOG_GROUPS.saturating_sub(event_backup_groups.len());
+            self.load_spilled(settle_version, before, limit).await
+        } else {
+            vec![]
+        };
+
+        let groups: Vec<&EventBackupGroup> = spilled.iter().chain(event_backup_groups.iter()).collect();
+        make_backlogs(&groups, settle_version)
+    }
+
+    /// The recent chat messages of all channels, the server filters
+    /// them for each subscriber.
+    pub async fn get_messages(&self) -> Vec<ChatMessage> {
+        self.messages.read().await.iter().cloned().collect()
+    }
+
+    /// Retrieve the frames missed after `cursor`.  A group's sync
+    /// frame is included when any event of the group is missed, and
+    /// the latest one is always included, as syncs are merged in the
+    /// group.  Return None if the cursor has aged out or is invalid,
+    /// then a full resync is required.  An index is only carried into
+    /// the next group from the exact end of a group.
+    pub async fn get_resume(&self, cursor: &EventCursor) -> Option<BroadcastFrame> {
+        let event_backup_groups = self.event_backup_groups.read().await;
+
+        // The cursor in the groups spilled to storage
+        let before = event_backup_groups.front().map(|g| g.settle_version);
+        let spilled = if before.map_or(false, |v| cursor.settle_version < v) {
+            let limit = MAX_SERVED_BACKLOG_GROUPS.saturating_sub(event_backup_groups.len());
+            self.load_spilled(cursor.settle_version, before, limit).await
+        } else {
+            vec![]
+        };
+
+        let groups: Vec<&EventBackupGroup> = spilled
+            .iter()
+            .chain(event_backup_groups.iter())
+            .skip_while(|g| g.settle_version != cursor.settle_version)
+            .collect();
+        if groups.is_empty() {
+            return None;
         }
 
-        if backlogs.is_empty() {
-            if let Some(group) = event_backup_groups.iter().last() {
-                checkpoint_off_chain = group.checkpoint_off_chain.clone();
-                state_sha = group.state_sha.clone();
-                backlogs.append(&mut group.to_frames());
+        let mut skip = cursor.event_index as usize;
+        let mut backlogs: Vec<BroadcastFrame> = vec![];
+        let last = groups.len() - 1;
+
+        for (i, group) in groups.into_iter().enumerate() {
+            let len = group.events.len();
+            if skip == len && i < last {
+                skip = 0;
+                continue;
+            }
+            if skip > len {
+                return None;
             }
+            backlogs.append(&mut group.to_frames_from(skip));
+            skip = 0;
         }
 
-        BroadcastFrame::Backlogs {
-            checkpoint_off_chain,
+        Some(BroadcastFrame::Resume {
             backlogs: Box::new(backlogs),
-            state_sha,
-        }
+        })
     }
 }
 
@@ -213,12 +792,19 @@ impl Component<ConsumerPorts, BroadcasterContext> for Broadcaster {
         while let Some(event) = ports.recv().await {
             match event {
                 EventFrame::SendMessage { message } => {
-                    let r = ctx.broadcast_tx.send(BroadcastFrame::Message { message });
-
-                    if let Err(e) = r {
-                        // Usually it means no receivers
-                        debug!("{} Failed to broadcast event: {:?}", env.log_prefix, e);
+                    let mut messages = ctx.messages.write().await;
+                    messages.push_back(message.clone());
+                    if messages.len() > MESSAGE_BACKLOG_SIZE {
+                        messages.pop_front();
                     }
+                    drop(messages);
+
+                    let frame = if message.channel == MessageChannel::Table {
+                        BroadcastFrame::Message { message: message.into() }
+                    } else {
+                        BroadcastFrame::ChatMessage { message }
+                    };
+                    broadcast(&ctx, frame).await;
                 }
 
                 EventFrame::Checkpoint {
@@ -238,6 +824,7 @@ impl Component<ConsumerPorts, BroadcasterContext> for Broadcaster {
                     let checkpoint_off_chain = checkpoint.build_checkpoint().derive_offchain_part();
 
                     let nodes = checkpoint.shared_data().nodes.clone();
+                    ctx.activity.lock().unwrap().reset_nodes(&nodes, access_version);
 
                     let r = ctx.checkpoint_tx.send(CheckpointBroadcastFrame {
                         nodes: nodes.clone(),
@@ -248,14 +835,35 @@ impl Component<ConsumerPorts, BroadcasterContext> for Broadcaster {
                         debug!("{} Failed to broadcast checkpoint: {:?}", env.log_prefix, e);
                     }
 
-                    event_backup_groups.push_back(EventBackupGroup {
-                        sync: BroadcastSync::new(access_version),
-                        state_sha: sha256::digest(&versioned_data.handler_state),
-                        events: LinkedList::new(),
+                    push_backlog_group(&ctx, &mut event_backup_groups, EventBackupGroup::new(
+                        sha256::digest(&versioned_data.handler_state),
+                        BroadcastSync::new(access_version),
                         settle_version,
-                        checkpoint_off_chain: Some(checkpoint_off_chain),
+                        Some(checkpoint_off_chain),
                         nodes,
+                    ));
+                    drop(event_backup_groups);
+                    retain_backlogs(&ctx, &env).await;
+                    prune_spilled(
+                        &ctx,
+                        &env,
+                        settle_version.saturating_sub(ctx.retention.max_spilled_groups),
+                    )
+                    .await;
+                }
+
+                // The final checkpoint is only pushed to checkpoint subscribers, it
+                // doesn't start a new history group, because its settle_version is
+                // not bumped.
+                EventFrame::FinalCheckpoint { checkpoint } => {
+                    info!("{} Broadcast final checkpoint", env.log_prefix);
+                    let r = ctx.checkpoint_tx.send(CheckpointBroadcastFrame {
+                        nodes: checkpoint.shared_data().nodes.clone(),
+                        data: checkpoint.root_data().handler_state.clone(),
                     });
+                    if let Err(e) = r {
+                        debug!("{} Failed to broadcast final checkpoint: {:?}", env.log_prefix, e);
+                    }
                 }
 
                 // XXX we probably don't need this, we broadcast only the checkpoint
@@ -293,42 +901,24 @@ impl Component<ConsumerPorts, BroadcasterContext> for Broadcaster {
                     let access_version = checkpoint.root_data.versions.access_version;
                     let checkpoint_off_chain = checkpoint.build_checkpoint().derive_offchain_part();
                     let state_sha = sha256::digest(&checkpoint.root_data.handler_state);
+                    ctx.activity.lock().unwrap().reset_nodes(&nodes, access_version);
 
                     info!("{} Create new history group (via RecoverCheckpoint). access_version = {}", env.log_prefix, access_version);
 
-                    event_backup_groups.push_back(EventBackupGroup {
-                        sync: BroadcastSync::new(access_version),
-                        events: LinkedList::new(),
-                        checkpoint_off_chain: Some(checkpoint_off_chain),
+                    push_backlog_group(&ctx, &mut event_backup_groups, EventBackupGroup::new(
                         state_sha,
+                        BroadcastSync::new(access_version),
                         settle_version,
+                        Some(checkpoint_off_chain),
                         nodes,
-                    });
+                    ));
+                    drop(event_backup_groups);
+                    retain_backlogs(&ctx, &env).await;
                 }
 
-                EventFrame::TxState { tx_state } => match tx_state {
-                    TxState::SettleSucceed { .. } => {
-                        let r = ctx.broadcast_tx.send(BroadcastFrame::TxState { tx_state });
-
-                        if let Err(e) = r {
-                            debug!("{} Failed to broadcast event: {:?}", env.log_prefix, e);
-                        }
-                    }
-                    TxState::PlayerConfirming { .. } => {
-                        let r = ctx.broadcast_tx.send(BroadcastFrame::TxState { tx_state });
-
-                        if let Err(e) = r {
-                            debug!("{} Failed to broadcast event: {:?}", env.log_prefix, e);
-                        }
-                    }
-                    TxState::PlayerConfirmingFailed(_) => {
-                        let r = ctx.broadcast_tx.send(BroadcastFrame::TxState { tx_state });
-
-                        if let Err(e) = r {
-                            debug!("{} Failed to broadcast event: {:?}", env.log_prefix, e);
-                        }
-                    }
-                },
+                EventFrame::TxState { tx_state } => {
+                    broadcast(&ctx, BroadcastFrame::TxState { tx_state }).await;
+                }
 
                 EventFrame::Broadcast {
                     event,
@@ -337,33 +927,28 @@ impl Component<ConsumerPorts, BroadcasterContext> for Broadcaster {
                     ..
                 } => {
                     info!("{} Broadcaster receive event: {}", env.log_prefix, event);
+                    ctx.activity.lock().unwrap().touch();
                     let mut event_backup_groups = ctx.event_backup_groups.write().await;
 
                     if let Some(current) = event_backup_groups.back_mut() {
-                        current.events.push_back(EventBackup {
+                        let bytes = current.bytes;
+                        current.push_event(EventBackup {
                             event: event.clone(),
                             timestamp,
                             state_sha: state_sha.clone(),
                         });
+                        add_backlog_bytes(&ctx, current.bytes - bytes);
                     } else {
                         error!("{} Received event without checkpoint", env.log_prefix);
                     }
-                    // Keep 200 groups at most
-                    if event_backup_groups.len() > 200 {
-                        event_backup_groups.pop_front();
-                    }
                     drop(event_backup_groups);
+                    retain_backlogs(&ctx, &env).await;
 
-                    let r = ctx.broadcast_tx.send(BroadcastFrame::Event {
+                    broadcast(&ctx, BroadcastFrame::Event {
                         event,
                         timestamp,
                         state_sha,
-                    });
-
-                    if let Err(e) = r {
-                        // Usually it means no receivers
-                        debug!("{} Failed to broadcast event: {:?}", env.log_prefix, e);
-                    }
+                    }).await;
                 }
 
                 EventFrame::SyncWithCredentials {
@@ -373,6 +958,16 @@ impl Component<ConsumerPorts, BroadcasterContext> for Broadcaster {
                     access_version,
                     transactor_addr,
                 } => {
+                    {
+                        let mut activity = ctx.activity.lock().unwrap();
+                        activity.access_version = access_version;
+                        activity.validators += new_servers.len();
+                        if !new_players.is_empty() || !new_deposits.is_empty() {
+                            activity.players += new_players.len();
+                            activity.touch();
+                        }
+                    }
+
                     let sync = BroadcastSync {
                         new_players: new_players.into_iter().map(|p| p.into()).collect(),
                         new_servers: new_servers.into_iter().map(|s| s.into()).collect(),
@@ -387,15 +982,7 @@ impl Component<ConsumerPorts, BroadcasterContext> for Broadcaster {
                         .back_mut()
                         .map(|g| g.merge_sync(&sync));
 
-                    let broadcast_frame = BroadcastFrame::Sync { sync };
-                    let r = ctx.broadcast_tx.send(broadcast_frame);
-
-                    if let Err(e) = r {
-                        debug!(
-                            "{} Failed to broadcast node updates: {:?}",
-                            env.log_prefix, e
-                        );
-                    }
+                    broadcast(&ctx, BroadcastFrame::Sync { sync }).await;
                 }
                 EventFrame::SubSync {
                     access_version,
@@ -417,15 +1004,11 @@ impl Component<ConsumerPorts, BroadcasterContext> for Broadcaster {
                         .back_mut()
                         .map(|g| g.merge_sync(&sync));
 
-                    let broadcast_frame = BroadcastFrame::Sync { sync };
-                    let r = ctx.broadcast_tx.send(broadcast_frame);
-
-                    if let Err(e) = r {
-                        debug!(
-                            "{} Failed to broadcast node updates: {:?}",
-                            env.log_prefix, e
-                        );
-                    }
+                    broadcast(&ctx, BroadcastFrame::Sync { sync }).await;
+                }
+                EventFrame::TransactorChanged { transactor_addr, endpoint } => {
+                    info!("{} Game taken over by {}, clients reconnect to {}", env.log_prefix, transactor_addr, endpoint);
+                    broadcast(&ctx, BroadcastFrame::Reconnect { transactor_addr, endpoint }).await;
                 }
                 EventFrame::Shutdown => {
                     info!("{} Stopped", env.log_prefix);
@@ -435,6 +1018,15 @@ impl Component<ConsumerPorts, BroadcasterContext> for Broadcaster {
             }
         }
 
+        // The backlogs are no longer counted once the game is closed
+        TOTAL_BACKLOG_BYTES.fetch_sub(ctx.bytes.load(Ordering::SeqCst), Ordering::SeqCst);
+
+        // Nor served
+        let latest = ctx.event_backup_groups.read().await.back().map(|g| g.settle_version);
+        if let Some(settle_version) = latest {
+            prune_spilled(&ctx, &env, settle_version + 1).await;
+        }
+
         CloseReason::Complete
     }
 }
@@ -442,7 +1034,7 @@ impl Component<ConsumerPorts, BroadcasterContext> for Broadcaster {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use race_core::types::PlayerJoin;
+    use race_core::types::{PlayerJoin, ServerJoin, TxState};
     use race_test::prelude::*;
 
     #[tokio::test]
@@ -454,9 +1046,9 @@ mod tests {
             .add_player(&mut bob, 100)
             .build();
 
-        let (broadcaster, ctx) = Broadcaster::init(game_account.addr.clone(), 0);
+        let (broadcaster, ctx) = Broadcaster::init(game_account.addr.clone(), 0, None, None);
         let handle = broadcaster.start("", ctx);
-        let mut rx = broadcaster.get_broadcast_rx();
+        let mut rx = broadcaster.subscribe();
 
         // BroadcastFrame::Event
         {
@@ -505,4 +1097,244 @@ mod tests {
             assert_eq!(received, broadcast_frame);
         }
     }
+
+    #[tokio::test]
+    async fn test_idle_for() {
+        let (broadcaster, ctx) = Broadcaster::init("game".into(), 0, None, None);
+        let handle = broadcaster.start("", ctx);
+        let mut rx = broadcaster.subscribe();
+        assert!(broadcaster.idle_for().is_some());
+
+        handle
+            .send_unchecked(EventFrame::SyncWithCredentials {
+                new_players: vec![PlayerJoin {
+                    addr: "Alice".into(),
+                    position: 0,
+                    access_version: 3,
+                    verify_key: "alice".into(),
+                }],
+                new_servers: vec![],
+                new_deposits: vec![],
+                transactor_addr: "".into(),
+                access_version: 3,
+            })
+            .await;
+        rx.recv().await.unwrap();
+        assert_eq!(broadcaster.idle_for(), None);
+        assert_eq!(broadcaster.access_version(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_not_idle_with_validators() {
+        let (broadcaster, ctx) = Broadcaster::init("game".into(), 0, None, None);
+        let handle = broadcaster.start("", ctx);
+        let mut rx = broadcaster.subscribe();
+
+        handle
+            .send_unchecked(EventFrame::SyncWithCredentials {
+                new_players: vec![],
+                new_servers: vec![ServerJoin {
+                    addr: "validator".into(),
+                    endpoint: "".into(),
+                    access_version: 2,
+                }],
+                new_deposits: vec![],
+                transactor_addr: "".into(),
+                access_version: 2,
+            })
+            .await;
+        rx.recv().await.unwrap();
+        assert_eq!(broadcaster.idle_for(), None);
+    }
+
+    fn make_group(settle_version: u64, num_events: u8) -> EventBackupGroup {
+        let mut group = EventBackupGroup::new(
+            "".into(),
+            BroadcastSync::new(0),
+            settle_version,
+            None,
+            vec![],
+        );
+        for i in 0..num_events {
+            group.push_event(EventBackup {
+                event: Event::Custom { sender: 1, raw: vec![i] },
+                timestamp: i as u64,
+                state_sha: "".into(),
+            });
+        }
+        group
+    }
+
+    #[tokio::test]
+    async fn test_get_resume() {
+        let (broadcaster, _) = Broadcaster::init("game".into(), 0, None, None);
+        {
+            let mut groups = broadcaster.event_backup_groups.write().await;
+            groups.push_back(make_group(1, 3));
+            groups.push_back(make_group(2, 2));
+        }
+
+        let resume_len = |settle_version, event_index| {
+            let broadcaster = &broadcaster;
+            async move {
+                let cursor = EventCursor { settle_version, event_index };
+                match broadcaster.get_resume(&cursor).await {
+                    Some(BroadcastFrame::Resume { backlogs }) => Some(backlogs.len()),
+                    _ => None,
+                }
+            }
+        };
+
+        // Missed one event in the first group, and the second group
+        assert_eq!(resume_len(1, 2).await, Some(5));
+        // Missed the second group
+        assert_eq!(resume_len(1, 3).await, Some(3));
+        // Up to date, only the latest sync
+        assert_eq!(resume_len(2, 2).await, Some(1));
+        assert_eq!(resume_len(2, 1).await, Some(2));
+        // Invalid or aged out
+        assert_eq!(resume_len(1, 4).await, None);
+        assert_eq!(resume_len(1, 6).await, None);
+        assert_eq!(resume_len(0, 0).await, None);
+    }
+
+    fn make_event_frame(i: u8) -> BroadcastFrame {
+        BroadcastFrame::Event {
+            event: Event::Custom { sender: 1, raw: vec![i] },
+            timestamp: i as u64,
+            state_sha: "".into(),
+        }
+    }
+
+    #[test]
+    fn test_subscriber_resync() {
+        let mut subscribers = Subscribers::default();
+        let mut rx = subscribers.subscribe();
+        let resync = BroadcastFrame::Resume { backlogs: Box::new(vec![]) };
+
+        for i in 0..=SUBSCRIBER_QUEUE_SIZE {
+            subscribers.send(make_event_frame(i as u8), None);
+        }
+        assert_eq!(subscribers.stats.lagged, 1);
+        assert!(subscribers.has_lagged());
+
+        // No room yet, the frame is not queued
+        subscribers.send(make_event_frame(0), Some(&resync));
+        assert_eq!(subscribers.stats.resynced, 0);
+
+        while rx.try_recv().is_ok() {}
+
+        // The event is included in the resync frame
+        subscribers.send(make_event_frame(1), Some(&resync));
+        assert_eq!(subscribers.stats.resynced, 1);
+        assert!(!subscribers.has_lagged());
+        assert_eq!(rx.try_recv().unwrap(), resync);
+        assert!(rx.try_recv().is_err());
+
+        // Closed subscribers are removed
+        drop(rx);
+        subscribers.send(make_event_frame(2), None);
+        assert!(subscribers.slots.is_empty());
+    }
+
+    #[test]
+    fn test_retention_evict() {
+        let retention = Retention::from_config(Some(&BacklogConfig {
+            max_groups: Some(3),
+            max_age: None,
+            max_bytes: None,
+            max_total_bytes: None,
+            spill: None,
+            max_spilled_groups: None,
+        }));
+        let mut groups: LinkedList<EventBackupGroup> = (1..=5).map(|v| make_group(v, 1)).collect();
+        let evicted = retention.evict(&mut groups, Instant::now(), 0, 0);
+        assert_eq!(evicted.iter().map(|g| g.settle_version).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(groups.len(), 3);
+
+        // The latest group is always kept
+        let retention = Retention::from_config(Some(&BacklogConfig {
+            max_groups: None,
+            max_age: None,
+            max_bytes: Some(0),
+            max_total_bytes: None,
+            spill: None,
+            max_spilled_groups: None,
+        }));
+        let bytes = groups.iter().map(|g| g.bytes).sum();
+        let evicted = retention.evict(&mut groups, Instant::now(), bytes, 0);
+        assert_eq!(evicted.len(), 2);
+        assert_eq!(groups.front().map(|g| g.settle_version), Some(5));
+    }
+
+    fn make_spill_config(max_groups: usize) -> BacklogConfig {
+        BacklogConfig {
+            max_groups: Some(max_groups),
+            max_age: None,
+            max_bytes: None,
+            max_total_bytes: None,
+            spill: Some(true),
+            max_spilled_groups: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spill_backlogs() {
+        let storage: Arc<dyn StorageT> = Arc::new(race_local_db::LocalDbStorage::try_new_mem().unwrap());
+        let config = make_spill_config(1);
+        let (broadcaster, ctx) = Broadcaster::init("game".into(), 0, Some(storage), Some(&config));
+        let env = ComponentEnv::new("game", "Broadcaster");
+        {
+            let mut groups = ctx.event_backup_groups.write().await;
+            push_backlog_group(&ctx, &mut groups, make_group(1, 3));
+            push_backlog_group(&ctx, &mut groups, make_group(2, 2));
+        }
+        retain_backlogs(&ctx, &env).await;
+        assert_eq!(ctx.event_backup_groups.read().await.len(), 1);
+        assert_eq!(
+            ctx.bytes.load(Ordering::SeqCst),
+            ctx.event_backup_groups.read().await.iter().map(|g| g.bytes).sum::<usize>()
+        );
+
+        // The spilled group is served from storage
+        match broadcaster.get_backlogs(1).await {
+            BroadcastFrame::Backlogs { backlogs, .. } => assert_eq!(backlogs.len(), 7),
+            _ => panic!("Expect backlogs"),
+        }
+        let cursor = EventCursor { settle_version: 1, event_index: 2 };
+        match broadcaster.get_resume(&cursor).await {
+            Some(BroadcastFrame::Resume { backlogs }) => assert_eq!(backlogs.len(), 5),
+            _ => panic!("Expect resume"),
+        }
+
+        // Removed once the game is closed
+        prune_spilled(&ctx, &env, 3).await;
+        match broadcaster.get_backlogs(1).await {
+            BroadcastFrame::Backlogs { backlogs, .. } => assert_eq!(backlogs.len(), 3),
+            _ => panic!("Expect backlogs"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_backlogs_capped() {
+        let (broadcaster, _) = Broadcaster::init("game".into(), 0, None, None);
+        let latest = MAX_SERVED_BACKLOG_GROUPS as u64 + 5;
+        {
+            let mut groups = broadcaster.event_backup_groups.write().await;
+            for v in 1..=latest {
+                let mut group = make_group(v, 1);
+                group.state_sha = v.to_string();
+                groups.push_back(group);
+            }
+        }
+
+        // Start from the checkpoint of the first group served
+        match broadcaster.get_backlogs(1).await {
+            BroadcastFrame::Backlogs { backlogs, state_sha, .. } => {
+                assert_eq!(backlogs.len(), MAX_SERVED_BACKLOG_GROUPS * 2);
+                assert_eq!(state_sha, "6");
+            }
+            _ => panic!("Expect backlogs"),
+        }
+    }
 }
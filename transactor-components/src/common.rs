@@ -1,12 +1,17 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
 use async_trait::async_trait;
 use tokio::{
     sync::mpsc::{self, error::SendError},
     task::JoinHandle,
 };
-use tracing::{info, warn};
+use tracing::{field, info, info_span, warn, Span};
 
 use crate::utils::addr_shorthand;
-use race_transactor_frames::EventFrame;
+use race_transactor_frames::{EventFrame, TracedFrame};
 
 use super::event_bus::CloseReason;
 
@@ -16,25 +21,25 @@ pub trait Attachable {
 
     /// Return the input channel of current component.
     /// Return `None` when the component does not accept input.
-    fn input(&mut self) -> Option<mpsc::Sender<EventFrame>>;
+    fn input(&mut self) -> Option<mpsc::Sender<TracedFrame>>;
 
     /// Return the output channel of this component.
     /// A component must return an output channel, even though it doesn't produce an output.
     /// A closed output channel means that this component has stopped.
-    fn output(&mut self) -> Option<mpsc::Receiver<EventFrame>>;
+    fn output(&mut self) -> Option<mpsc::Receiver<TracedFrame>>;
 }
 
 /// Represent the input/output of the ports
 pub struct PortsIO {
-    input_tx: Option<mpsc::Sender<EventFrame>>,
-    output_rx: Option<mpsc::Receiver<EventFrame>>,
+    input_tx: Option<mpsc::Sender<TracedFrame>>,
+    output_rx: Option<mpsc::Receiver<TracedFrame>>,
 }
 
 impl PortsIO {
     #[allow(unused)]
-    pub async fn send(&self, frame: EventFrame) -> Result<(), SendError<EventFrame>> {
+    pub async fn send(&self, frame: EventFrame) -> Result<(), SendError<TracedFrame>> {
         if let Some(ref input_tx) = self.input_tx {
-            input_tx.send(frame).await
+            input_tx.send(TracedFrame::new(frame)).await
         } else {
             panic!("Input is not supported");
         }
@@ -43,7 +48,7 @@ impl PortsIO {
     #[allow(unused)]
     pub async fn recv(&mut self) -> Option<EventFrame> {
         if let Some(ref mut output_rx) = self.output_rx {
-            output_rx.recv().await
+            output_rx.recv().await.map(|f| f.frame)
         } else {
             panic!("Output is not supported");
         }
@@ -52,8 +57,8 @@ impl PortsIO {
 
 pub struct PortsHandle {
     pub id: String,
-    input_tx: Option<mpsc::Sender<EventFrame>>,
-    output_rx: Option<mpsc::Receiver<EventFrame>>,
+    input_tx: Option<mpsc::Sender<TracedFrame>>,
+    output_rx: Option<mpsc::Receiver<TracedFrame>>,
     join_handle: JoinHandle<CloseReason>,
 }
 
@@ -86,7 +91,7 @@ impl PortsHandle {
     #[allow(dead_code)]
     pub async fn send_unchecked(&self, frame: EventFrame) {
         if let Some(ref input_tx) = self.input_tx {
-            input_tx.send(frame).await.expect("Failed to send");
+            input_tx.send(TracedFrame::new(frame)).await.expect("Failed to send");
         } else {
             panic!("Sender is not available");
         }
@@ -95,7 +100,7 @@ impl PortsHandle {
     #[allow(dead_code)]
     pub async fn recv_unchecked(&mut self) -> Option<EventFrame> {
         if let Some(ref mut output_rx) = self.output_rx {
-            output_rx.recv().await
+            output_rx.recv().await.map(|f| f.frame)
         } else {
             panic!("Receiver is not available");
         }
@@ -107,14 +112,14 @@ impl Attachable for PortsHandle {
         self.id.as_str()
     }
 
-    fn input(&mut self) -> Option<mpsc::Sender<EventFrame>> {
+    fn input(&mut self) -> Option<mpsc::Sender<TracedFrame>> {
         if self.input_tx.is_some() {
             self.input_tx.clone()
         } else {
             None
         }
     }
-    fn output(&mut self) -> Option<mpsc::Receiver<EventFrame>> {
+    fn output(&mut self) -> Option<mpsc::Receiver<TracedFrame>> {
         if self.output_rx.is_some() {
             self.output_rx.take()
         } else {
@@ -124,29 +129,177 @@ impl Attachable for PortsHandle {
 }
 
 pub trait Ports: Send {
-    fn create() -> (Self, PortsIO)
+    fn create(env: &ComponentEnv) -> (Self, PortsIO)
     where
         Self: Sized;
 }
 
+fn enter_span(span: &Span) {
+    span.with_subscriber(|(id, dispatch)| dispatch.enter(id));
+}
+
+fn exit_span(span: &Span) {
+    span.with_subscriber(|(id, dispatch)| dispatch.exit(id));
+}
+
+struct ScopeState {
+    span: Span,
+    entered: bool,
+}
+
+/// The span of the frame being handled by a component.  It's entered
+/// whenever the component is polled, see [InFrameScope], so the logs
+/// of the component are attached to it.
+#[derive(Clone)]
+pub(crate) struct FrameScope {
+    state: Arc<Mutex<ScopeState>>,
+}
+
+impl FrameScope {
+    fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ScopeState {
+                span: Span::none(),
+                entered: false,
+            })),
+        }
+    }
+
+    /// Switch to the span of another frame, in the middle of a poll.
+    fn set(&self, span: Span) {
+        let mut state = self.state.lock().unwrap();
+        if state.entered {
+            exit_span(&state.span);
+            enter_span(&span);
+        }
+        state.span = span;
+    }
+}
+
+/// Poll the future of a component in the span of the frame being
+/// handled.  It works like [tracing::Instrument], with a span changed
+/// by the component for each frame.
+struct InFrameScope<F> {
+    inner: Pin<Box<F>>,
+    scope: FrameScope,
+}
+
+impl<F: Future> Future for InFrameScope<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        {
+            let mut state = self.scope.state.lock().unwrap();
+            enter_span(&state.span);
+            state.entered = true;
+        }
+        let r = self.inner.as_mut().poll(cx);
+        let mut state = self.scope.state.lock().unwrap();
+        exit_span(&state.span);
+        state.entered = false;
+        r
+    }
+}
+
+/// Open a span for each received frame, as a child of the span the
+/// frame was sent in.  The span is closed once the frame is handled,
+/// when the component waits for the next one.  The frames sent in
+/// between are sent in it.
+struct FrameTracer {
+    component: String,
+    addr: String,
+    span: Span,
+    scope: FrameScope,
+}
+
+impl FrameTracer {
+    fn new(env: &ComponentEnv) -> Self {
+        Self {
+            component: env.component_name.clone(),
+            addr: env.addr.clone(),
+            span: Span::none(),
+            scope: env.scope.clone(),
+        }
+    }
+
+    fn enter(&mut self, traced: TracedFrame) -> EventFrame {
+        let TracedFrame { frame, span: parent } = traced;
+        let span = info_span!(
+            parent: &parent,
+            "frame",
+            otel.name = %format!("{} {}", self.component, frame.kind()),
+            component = %self.component,
+            game_addr = %self.addr,
+            frame = frame.kind(),
+            event = field::Empty,
+            access_version = field::Empty,
+            settle_version = field::Empty,
+        );
+        if let EventFrame::SendEvent { event, .. }
+        | EventFrame::SendServerEvent { event, .. }
+        | EventFrame::Broadcast { event, .. } = &frame
+        {
+            span.record("event", field::display(event));
+        }
+        let (access_version, settle_version) = frame.versions();
+        if let Some(access_version) = access_version {
+            span.record("access_version", access_version);
+        }
+        if let Some(settle_version) = settle_version {
+            span.record("settle_version", settle_version);
+        }
+        self.scope.set(span.clone());
+        self.span = span;
+        frame
+    }
+
+    fn leave(&mut self) {
+        self.scope.set(Span::none());
+        self.span = Span::none();
+    }
+}
+
+/// Wrap a frame to send.  A frame sent in a span opened by the
+/// component itself, e.g. a settlement, is sent in that span.
+fn wrap_frame(frame: EventFrame, span: &Span) -> TracedFrame {
+    let current = Span::current();
+    if current.is_none() {
+        TracedFrame::with_span(frame, span.clone())
+    } else {
+        TracedFrame::with_span(frame, current)
+    }
+}
+
 pub struct ConsumerPorts {
-    rx: mpsc::Receiver<EventFrame>,
+    rx: mpsc::Receiver<TracedFrame>,
+    tracer: FrameTracer,
 }
 
 impl ConsumerPorts {
     pub async fn recv(&mut self) -> Option<EventFrame> {
-        self.rx.recv().await
+        self.tracer.leave();
+        let traced = self.rx.recv().await?;
+        Some(self.tracer.enter(traced))
+    }
+
+    /// The span of the frame being handled.
+    #[allow(unused)]
+    pub fn span(&self) -> &Span {
+        &self.tracer.span
     }
 }
 
 impl Ports for ConsumerPorts {
-    fn create() -> (Self, PortsIO)
+    fn create(env: &ComponentEnv) -> (Self, PortsIO)
     where
         Self: Sized,
     {
         let (input_tx, input_rx) = mpsc::channel(100);
         (
-            Self { rx: input_rx },
+            Self {
+                rx: input_rx,
+                tracer: FrameTracer::new(env),
+            },
             PortsIO {
                 input_tx: Some(input_tx),
                 output_rx: None,
@@ -156,13 +309,14 @@ impl Ports for ConsumerPorts {
 }
 
 pub struct ProducerPorts {
-    tx: mpsc::Sender<EventFrame>,
+    tx: mpsc::Sender<TracedFrame>,
+    span: Span,
 }
 
 impl ProducerPorts {
     #[allow(dead_code)]
-    pub async fn try_send(&self, frame: EventFrame) -> Result<(), SendError<EventFrame>> {
-        self.tx.send(frame).await
+    pub async fn try_send(&self, frame: EventFrame) -> Result<(), SendError<TracedFrame>> {
+        self.tx.send(wrap_frame(frame, &self.span)).await
     }
 
     #[allow(dead_code)]
@@ -171,7 +325,7 @@ impl ProducerPorts {
     }
 
     pub async fn send(&self, frame: EventFrame) {
-        match self.tx.send(frame).await {
+        match self.tx.send(wrap_frame(frame, &self.span)).await {
             Ok(_) => (),
             Err(e) => {
                 warn!("Send error: {:?}", e)
@@ -181,13 +335,16 @@ impl ProducerPorts {
 }
 
 impl Ports for ProducerPorts {
-    fn create() -> (Self, PortsIO)
+    fn create(_env: &ComponentEnv) -> (Self, PortsIO)
     where
         Self: Sized,
     {
         let (output_tx, output_rx) = mpsc::channel(10);
         (
-            Self { tx: output_tx },
+            Self {
+                tx: output_tx,
+                span: Span::none(),
+            },
             PortsIO {
                 input_tx: None,
                 output_rx: Some(output_rx),
@@ -197,27 +354,50 @@ impl Ports for ProducerPorts {
 }
 
 pub struct PipelinePorts {
-    rx: mpsc::Receiver<EventFrame>,
-    tx: mpsc::Sender<EventFrame>,
+    rx: mpsc::Receiver<TracedFrame>,
+    tx: mpsc::Sender<TracedFrame>,
+    tracer: FrameTracer,
 }
 
 impl PipelinePorts {
     pub async fn recv(&mut self) -> Option<EventFrame> {
-        self.rx.recv().await
+        self.tracer.leave();
+        let traced = self.rx.recv().await?;
+        Some(self.tracer.enter(traced))
+    }
+
+    /// The span of the frame being handled.
+    pub fn span(&self) -> &Span {
+        &self.tracer.span
+    }
+
+    /// Open the span for a frame made by the component itself, e.g.
+    /// a dispatched event.  It starts a new trace.
+    pub fn enter(&mut self, frame: EventFrame) -> EventFrame {
+        self.tracer.enter(TracedFrame::with_span(frame, Span::none()))
     }
 
     #[allow(unused)]
-    pub async fn try_send(&self, frame: EventFrame) -> Result<(), SendError<EventFrame>> {
-        self.tx.send(frame).await
+    pub async fn try_send(&self, frame: EventFrame) -> Result<(), SendError<TracedFrame>> {
+        self.tx.send(wrap_frame(frame, &self.tracer.span)).await
     }
 
+    /// The producer sends in the span of the frame being handled
+    /// when it's cloned.
     pub fn clone_as_producer(&self) -> ProducerPorts {
         ProducerPorts {
             tx: self.tx.clone(),
+            span: self.tracer.span.clone(),
         }
     }
 
     pub async fn send(&self, frame: EventFrame) {
+        self.send_traced(wrap_frame(frame, &self.tracer.span)).await
+    }
+
+    /// Send a frame with its own span, e.g. a frame from another
+    /// game.
+    pub async fn send_traced(&self, frame: TracedFrame) {
         match self.tx.send(frame).await {
             Ok(_) => (),
             Err(e) => {
@@ -228,7 +408,7 @@ impl PipelinePorts {
 }
 
 impl Ports for PipelinePorts {
-    fn create() -> (Self, PortsIO)
+    fn create(env: &ComponentEnv) -> (Self, PortsIO)
     where
         Self: Sized,
     {
@@ -238,6 +418,7 @@ impl Ports for PipelinePorts {
             Self {
                 rx: input_rx,
                 tx: output_tx,
+                tracer: FrameTracer::new(env),
             },
             PortsIO {
                 input_tx: Some(input_tx),
@@ -253,6 +434,7 @@ pub struct ComponentEnv {
     pub addr_shorthand: String,
     pub component_name: String,
     pub log_prefix: String,
+    pub(crate) scope: FrameScope,
 }
 
 impl ComponentEnv {
@@ -263,6 +445,7 @@ impl ComponentEnv {
             addr_shorthand: addr_short.clone(),
             log_prefix: format!("[{}|{}]", addr_short, component_name),
             component_name: component_name.into(),
+            scope: FrameScope::new(),
         }
     }
 }
@@ -276,18 +459,186 @@ where
     fn name() -> &'static str;
 
     fn prepare(&self, addr: &str) -> (P, PortsIO, ComponentEnv) {
-        let (ports, io) = P::create();
         let env = ComponentEnv::new(addr, Self::name());
+        let (ports, io) = P::create(&env);
         (ports, io, env)
     }
 
     fn start(&self, addr: &str, context: C) -> PortsHandle {
         info!("Starting component: {}", Self::name());
         let (ports, io, env) = self.prepare(addr);
-        let join_handle = tokio::spawn(async move { Self::run(ports, context, env).await });
+        let scope = env.scope.clone();
+        let join_handle = tokio::spawn(InFrameScope {
+            inner: Box::pin(async move { Self::run(ports, context, env).await }),
+            scope,
+        });
         PortsHandle::from_io(Self::name(), io, join_handle)
     }
 
 
     async fn run(ports: P, context: C, env: ComponentEnv) -> CloseReason;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::span::Id;
+    use tracing::{Instrument, Subscriber};
+    use tracing_subscriber::layer::{Context as LayerContext, SubscriberExt};
+    use tracing_subscriber::registry::LookupSpan;
+
+    struct Echo;
+
+    #[async_trait]
+    impl Component<PipelinePorts, ()> for Echo {
+        fn name() -> &'static str {
+            "Echo"
+        }
+
+        async fn run(mut ports: PipelinePorts, _ctx: (), _env: ComponentEnv) -> CloseReason {
+            while let Some(frame) = ports.recv().await {
+                if matches!(frame, EventFrame::Shutdown) {
+                    break;
+                }
+                // The handling takes more than one poll
+                tokio::task::yield_now().await;
+                info!(target: "frame_test", "handled");
+                ports.send(frame).await;
+            }
+            CloseReason::Complete
+        }
+    }
+
+    struct Sink;
+
+    #[async_trait]
+    impl Component<ConsumerPorts, ()> for Sink {
+        fn name() -> &'static str {
+            "Sink"
+        }
+
+        async fn run(mut ports: ConsumerPorts, _ctx: (), _env: ComponentEnv) -> CloseReason {
+            while let Some(frame) = ports.recv().await {
+                if matches!(frame, EventFrame::Shutdown) {
+                    break;
+                }
+                tokio::task::yield_now().await;
+                info!(target: "frame_test", "handled");
+            }
+            CloseReason::Complete
+        }
+    }
+
+    /// Record the spans of the test logs, and the closed frame spans.
+    struct SpanLog(Arc<Mutex<Vec<String>>>);
+
+    impl<S> tracing_subscriber::Layer<S> for SpanLog
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_event(&self, event: &tracing::Event<'_>, ctx: LayerContext<'_, S>) {
+            if event.metadata().target() != "frame_test" {
+                return;
+            }
+            let entry = match ctx.lookup_current() {
+                Some(span) => format!(
+                    "log in {} < {}",
+                    span.name(),
+                    span.parent().map_or("none", |p| p.name())
+                ),
+                None => "log in none".to_string(),
+            };
+            self.0.lock().unwrap().push(entry);
+        }
+
+        fn on_close(&self, id: Id, ctx: LayerContext<'_, S>) {
+            if ctx.span(&id).map_or(false, |s| s.name() == "frame") {
+                self.0.lock().unwrap().push("close frame".to_string());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_frame_span() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let subscriber = tracing_subscriber::registry().with(SpanLog(log.clone()));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut handle = Echo.start("game", ());
+        let mut output = handle.output().unwrap();
+        let root = info_span!("root");
+        handle
+            .send_unchecked(EventFrame::SettleVersionChanged { settle_version: 1 })
+            .instrument(root)
+            .await;
+
+        // Sent in the span of the frame, a child of the sender's span
+        let traced = output.recv().await.unwrap();
+        assert_eq!(traced.span.metadata().map(|m| m.name()), Some("frame"));
+        assert_eq!(*log.lock().unwrap(), vec!["log in frame < root".to_string()]);
+
+        // Closed once handled, not when the next frame is received
+        drop(traced);
+        assert_eq!(log.lock().unwrap().last().map(String::as_str), Some("close frame"));
+
+        handle.send_unchecked(EventFrame::Shutdown).await;
+        handle.wait().await;
+    }
+
+    /// Record when the frame spans are entered, exited and closed,
+    /// and the test logs.
+    struct FrameSpanLog(Arc<Mutex<Vec<&'static str>>>);
+
+    impl<S> tracing_subscriber::Layer<S> for FrameSpanLog
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_event(&self, event: &tracing::Event<'_>, _ctx: LayerContext<'_, S>) {
+            if event.metadata().target() == "frame_test" {
+                self.0.lock().unwrap().push("log");
+            }
+        }
+
+        fn on_enter(&self, id: &Id, ctx: LayerContext<'_, S>) {
+            if ctx.span(id).map_or(false, |s| s.name() == "frame") {
+                self.0.lock().unwrap().push("enter");
+            }
+        }
+
+        fn on_exit(&self, id: &Id, ctx: LayerContext<'_, S>) {
+            if ctx.span(id).map_or(false, |s| s.name() == "frame") {
+                self.0.lock().unwrap().push("exit");
+            }
+        }
+
+        fn on_close(&self, id: Id, ctx: LayerContext<'_, S>) {
+            if ctx.span(&id).map_or(false, |s| s.name() == "frame") {
+                self.0.lock().unwrap().push("close");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_frame_span_around_handling() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let subscriber = tracing_subscriber::registry().with(FrameSpanLog(log.clone()));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let handle = Sink.start("game", ());
+        for settle_version in 1..=2 {
+            handle
+                .send_unchecked(EventFrame::SettleVersionChanged { settle_version })
+                .instrument(info_span!("root"))
+                .await;
+        }
+        handle.send_unchecked(EventFrame::Shutdown).await;
+        handle.wait().await;
+
+        // Each frame span is entered on every poll while the frame is
+        // handled, and closed before the next frame is received.
+        let handling = ["enter", "exit", "enter", "log", "exit", "close"];
+        let log = log.lock().unwrap();
+        assert_eq!(log[..6], handling);
+        assert_eq!(log[6..12], handling);
+    }
+}
@@ -2,25 +2,36 @@
 //!
 //! - [`LocalConnection`], used to send event to local event bus.
 //! - [`RemoteConnection`], used to send event to remote transactor server.
+//!
+//! [`RemoteConnection`] signs once per game to create a session, the
+//! following requests are authenticated by the session, each with a
+//! new nonce.
 
 use async_stream::stream;
 use async_trait::async_trait;
 use borsh::{BorshDeserialize, BorshSerialize};
-use futures::Stream;
+use futures::{Stream, StreamExt};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::{mpsc, Mutex};
-use tracing::warn;
+use tokio::time::{Duration, Instant};
+use tracing::{info, warn};
 
 use jsonrpsee::{
     core::{
         client::{ClientT, Subscription, SubscriptionClientT},
+        error::Error as RpcError,
         params::ArrayParams,
         DeserializeOwned,
     },
     rpc_params,
+    types::error::CallError,
     ws_client::{WsClient, WsClientBuilder},
 };
-use race_core::types::{BroadcastFrame, SubscribeEventParams};
+use race_core::types::{
+    BroadcastFrame, CreateSessionParams, SessionInfo, SubscribeEventParamsV2, SESSION_ERROR_CODE,
+};
 use race_core::{
     checkpoint::CheckpointOffChain,
     error::{Error, Result},
@@ -36,14 +47,14 @@ use crate::utils::base64_decode;
 use crate::{common::Attachable, utils::base64_encode};
 use crate::utils::current_timestamp;
 
-use race_transactor_frames::EventFrame;
+use race_transactor_frames::{EventFrame, TracedFrame};
 
 /// A connection to local event bus, for transactor loopback.
 #[allow(dead_code)]
 pub struct LocalConnection {
     encryptor: Arc<dyn EncryptorT>,
-    output_tx: mpsc::Sender<EventFrame>,
-    output_rx: Option<mpsc::Receiver<EventFrame>>,
+    output_tx: mpsc::Sender<TracedFrame>,
+    output_rx: Option<mpsc::Receiver<TracedFrame>>,
 }
 
 #[async_trait]
@@ -54,10 +65,10 @@ impl ConnectionT for LocalConnection {
 
     async fn submit_event(&self, _game_addr: &str, params: SubmitEventParams) -> Result<()> {
         self.output_tx
-            .send(EventFrame::SendEvent {
+            .send(TracedFrame::new(EventFrame::SendEvent {
                 event: params.event,
                 timestamp: current_timestamp(),
-            })
+            }))
             .await
             .map_err(|e| Error::InternalError(e.to_string()))
     }
@@ -83,40 +94,56 @@ impl Attachable for LocalConnection {
         "LocalConnection"
     }
 
-    fn input(&mut self) -> Option<mpsc::Sender<EventFrame>> {
+    fn input(&mut self) -> Option<mpsc::Sender<TracedFrame>> {
         None
     }
 
-    fn output(&mut self) -> Option<mpsc::Receiver<EventFrame>> {
+    fn output(&mut self) -> Option<mpsc::Receiver<TracedFrame>> {
         let mut ret = None;
         std::mem::swap(&mut self.output_rx, &mut ret);
         ret
     }
 }
 
+// Renew a session this long before it expires.
+const SESSION_RENEW_MARGIN: Duration = Duration::from_secs(30);
+
+struct RemoteSession {
+    info: SessionInfo,
+    // The nonce of the last request
+    nonce: u64,
+    expires_at: Instant,
+    // The generation of the RPC client the session is created on.
+    generation: u64,
+    // The session is revoked by the server when this is dropped.
+    _subscription: Subscription<String>,
+}
+
 pub struct RemoteConnection {
     server_addr: String,
     endpoint: String,
     encryptor: Arc<dyn EncryptorT>,
     rpc_client: Mutex<Option<WsClient>>,
-    max_retries: u32,
+    // Increased on every reconnection, sessions of the old
+    // connections are no longer valid.
+    generation: AtomicU64,
+    sessions: Mutex<HashMap<String, RemoteSession>>,
 }
 
 #[async_trait]
 impl ConnectionT for RemoteConnection {
-    async fn attach_game(&self, game_addr: &str, params: AttachGameParams) -> Result<()> {
-        let req = self.make_request_no_sig(game_addr, &params)?;
-        self.request("attach_game", req).await
+    /// Create the session for the game.
+    async fn attach_game(&self, game_addr: &str, _params: AttachGameParams) -> Result<()> {
+        self.session_credential(game_addr, &[]).await?;
+        Ok(())
     }
 
     async fn submit_event(&self, game_addr: &str, params: SubmitEventParams) -> Result<()> {
-        let req = self.make_request(game_addr, &params)?;
-        self.request("submit_event", req).await
+        self.session_request("submit_event", game_addr, &params).await
     }
 
     async fn exit_game(&self, game_addr: &str, params: ExitGameParams) -> Result<()> {
-        let req = self.make_request(game_addr, &params)?;
-        self.request("exit-game", req).await
+        self.session_request("exit_game", game_addr, &params).await
     }
 }
 
@@ -128,19 +155,31 @@ async fn build_rpc_client(endpoint: &str) -> Result<WsClient> {
     Ok(client)
 }
 
+/// Map an RPC error.  The requests rejected for their sessions are
+/// mapped to [Error::InvalidSession], they are not processed by the
+/// server.
+fn map_rpc_error(e: RpcError) -> Error {
+    match e {
+        RpcError::Call(CallError::Custom(ref obj)) if obj.code() == SESSION_ERROR_CODE => {
+            Error::InvalidSession
+        }
+        e => Error::RpcError(e.to_string()),
+    }
+}
+
 impl RemoteConnection {
     pub async fn try_new(
         server_addr: &str,
         endpoint: &str,
         encryptor: Arc<dyn EncryptorT>,
     ) -> Result<Self> {
-        let max_retries = 3;
         Ok(Self {
             server_addr: server_addr.to_owned(),
             endpoint: endpoint.into(),
             encryptor,
             rpc_client: Mutex::new(None),
-            max_retries,
+            generation: AtomicU64::new(0),
+            sessions: Mutex::new(HashMap::default()),
         })
     }
 
@@ -158,51 +197,109 @@ impl RemoteConnection {
         Ok(rpc_params![game_addr, p, s])
     }
 
-    fn make_request_no_sig<P>(&self, game_addr: &str, params: &P) -> Result<ArrayParams>
+    /// Return the session credential of a request to `game_addr` with
+    /// argument bytes `arg`.  A new session is created if there's none
+    /// or it's about to expire.
+    async fn session_credential(&self, game_addr: &str, arg: &[u8]) -> Result<String> {
+        let mut sessions = self.sessions.lock().await;
+        let generation = self.generation.load(Ordering::SeqCst);
+
+        if let Some(session) = sessions.get_mut(game_addr) {
+            if session.generation == generation
+                && Instant::now() + SESSION_RENEW_MARGIN < session.expires_at
+            {
+                session.nonce += 1;
+                return Ok(session.info.credential(session.nonce, arg));
+            }
+        }
+
+        let req = self.make_request(game_addr, &CreateSessionParams::new(game_addr))?;
+        let mut rpc_client = self.rpc_client.lock().await;
+        let client = if let Some(client) = rpc_client.as_ref() {
+            client
+        } else {
+            *rpc_client = Some(build_rpc_client(&self.endpoint).await?);
+            rpc_client.as_ref().unwrap()
+        };
+        let generation = self.generation.load(Ordering::SeqCst);
+
+        let mut sub: Subscription<String> = client
+            .subscribe("subscribe_session", req, "unsubscribe_session")
+            .await
+            .map_err(|e| Error::RpcError(e.to_string()))?;
+        drop(rpc_client);
+
+        let s = sub
+            .next()
+            .await
+            .ok_or(Error::InvalidSession)?
+            .map_err(|e| Error::RpcError(e.to_string()))?;
+        let info = SessionInfo::try_from_slice(&base64_decode(&s)?)?;
+        info!("Session created for game {}", game_addr);
+
+        let credential = info.credential(1, arg);
+        sessions.insert(
+            game_addr.to_owned(),
+            RemoteSession {
+                expires_at: Instant::now() + Duration::from_secs(info.ttl),
+                info,
+                nonce: 1,
+                generation,
+                _subscription: sub,
+            },
+        );
+        Ok(credential)
+    }
+
+    /// Send a request authenticated by the session.  A request is
+    /// resent only if it's rejected for its session, which means it's
+    /// not processed.  Any other failure is returned, since the
+    /// request may have been applied.
+    async fn session_request<P, R>(&self, method: &str, game_addr: &str, params: &P) -> Result<R>
     where
         P: BorshSerialize,
+        R: DeserializeOwned,
     {
-        let params_bytes = borsh::to_vec(&params)?;
-        let p = base64_encode(&params_bytes);
-        Ok(rpc_params![game_addr, p])
+        let arg = borsh::to_vec(params)?;
+        let p = base64_encode(&arg);
+
+        let credential = self.session_credential(game_addr, &arg).await?;
+        match self.request(method, rpc_params![game_addr, p.clone(), credential]).await {
+            Err(Error::InvalidSession) => {
+                warn!("Session of game {} is rejected, create a new one", game_addr);
+                self.sessions.lock().await.remove(game_addr);
+                let credential = self.session_credential(game_addr, &arg).await?;
+                self.request(method, rpc_params![game_addr, p, credential]).await
+            }
+            r => r,
+        }
     }
 
+    /// Send a request once.  The client is reset if the connection is
+    /// lost, the request is not resent.
     async fn request<R>(&self, method: &str, params: ArrayParams) -> Result<R>
     where
         R: DeserializeOwned,
     {
         let mut rpc_client = self.rpc_client.lock().await;
-        let mut retries = 0;
-
-        loop {
-            let client = if let Some(rpc_client) = rpc_client.as_ref() {
-                rpc_client
-            } else {
-                *rpc_client = Some(build_rpc_client(&self.endpoint).await?);
-                rpc_client.as_ref().unwrap()
-            };
-
-            let res = client.request(method, params.clone()).await;
-            use jsonrpsee::core::error::Error::*;
-            match res {
-                Ok(ret) => return Ok(ret),
-                Err(RestartNeeded(e)) => {
-                    // For reconnecting
-                    warn!("Try reconnect due to error[{}]: {:?}", method, e);
-                    *rpc_client = None;
-                }
-                Err(e) => {
-                    warn!("Error in request[{}]: {:?}", method, e);
-                }
-            }
+        let client = if let Some(rpc_client) = rpc_client.as_ref() {
+            rpc_client
+        } else {
+            *rpc_client = Some(build_rpc_client(&self.endpoint).await?);
+            rpc_client.as_ref().unwrap()
+        };
 
-            if retries < self.max_retries {
-                retries += 1;
-                continue;
-            } else {
-                return Err(Error::RpcError("Max retries has been reached".into()));
-            }
+        let res = client.request(method, params).await;
+        if let Err(RpcError::RestartNeeded(ref e)) = res {
+            // Reconnect on the next request
+            warn!("Reset connection due to error[{}]: {:?}", method, e);
+            *rpc_client = None;
+            self.generation.fetch_add(1, Ordering::SeqCst);
         }
+        res.map_err(|e| {
+            warn!("Error in request[{}]: {:?}", method, e);
+            map_rpc_error(e)
+        })
     }
 
     pub async fn get_checkpoint_off_chain(
@@ -210,9 +307,10 @@ impl RemoteConnection {
         game_addr: &str,
         params: CheckpointParams,
     ) -> Result<Option<CheckpointOffChain>> {
-        let req = self.make_request_no_sig(game_addr, &params)?;
-
-        match self.request::<Option<Vec<u8>>>("get_checkpoint", req).await {
+        match self
+            .session_request::<_, Option<Vec<u8>>>("get_checkpoint", game_addr, &params)
+            .await
+        {
             Ok(Some(res)) => {
                 let checkpoint_off_chain = CheckpointOffChain::try_from_slice(&res)?;
                 Ok(Some(checkpoint_off_chain))
@@ -224,23 +322,18 @@ impl RemoteConnection {
     pub async fn subscribe_events(
         &self,
         game_addr: &str,
-        settle_version: u64,
+        params: SubscribeEventParamsV2,
     ) -> Result<impl Stream<Item = BroadcastFrame>> {
-        let params = SubscribeEventParams { settle_version };
+        let arg = borsh::to_vec(&params)?;
+        let p = base64_encode(&arg);
 
-        let mut rpc_client = self.rpc_client.lock().await;
-        let client = if let Some(client) = rpc_client.as_ref() {
-            client
-        } else {
-            *rpc_client = Some(build_rpc_client(&self.endpoint).await?);
-            rpc_client.as_ref().unwrap()
+        let sub = match self.subscribe_event_once(game_addr, &p, &arg).await {
+            Err(Error::InvalidSession) => {
+                self.sessions.lock().await.remove(game_addr);
+                self.subscribe_event_once(game_addr, &p, &arg).await?
+            }
+            r => r?,
         };
-        let req = self.make_request_no_sig(game_addr, &params)?;
-
-        let sub: Subscription<String> = client
-            .subscribe("subscribe_event", req, "unsubscribe_event")
-            .await
-            .map_err(|e| Error::RpcError(e.to_string()))?;
 
         Ok(stream! {
             for await s in sub {
@@ -260,4 +353,30 @@ impl RemoteConnection {
             }
         })
     }
+
+    async fn subscribe_event_once(
+        &self,
+        game_addr: &str,
+        p: &str,
+        arg: &[u8],
+    ) -> Result<Subscription<String>> {
+        let credential = self.session_credential(game_addr, arg).await?;
+
+        let mut rpc_client = self.rpc_client.lock().await;
+        let client = if let Some(client) = rpc_client.as_ref() {
+            client
+        } else {
+            *rpc_client = Some(build_rpc_client(&self.endpoint).await?);
+            rpc_client.as_ref().unwrap()
+        };
+
+        client
+            .subscribe(
+                "subscribe_event_v2",
+                rpc_params![game_addr, p, credential],
+                "unsubscribe_event_v2",
+            )
+            .await
+            .map_err(map_rpc_error)
+    }
 }
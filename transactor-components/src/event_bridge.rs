@@ -1,32 +1,49 @@
-use race_transactor_frames::EventFrame;
+use race_transactor_frames::{EventFrame, TracedFrame};
 use race_transactor_frames::{BridgeToParent, SignalFrame};
+use race_core::chain::ChainType;
 use async_trait::async_trait;
 use tokio::sync::{broadcast, mpsc};
-use tracing::{info, log::error, warn};
+use tracing::{info, log::error, warn, Span};
 
 use super::{common::PipelinePorts, CloseReason, Component, ComponentEnv};
 
+/// The frames from the event bus are sent to the other games in the
+/// span they are handled in.
+fn with_ports_span(
+    e: Option<(bool, TracedFrame)>,
+    ports: &PipelinePorts,
+) -> Option<(bool, TracedFrame)> {
+    e.map(|(from_bridge, mut traced)| {
+        if !from_bridge {
+            traced.span = ports.span().clone();
+        }
+        (from_bridge, traced)
+    })
+}
+
 #[allow(dead_code)]
 pub struct EventBridgeParentContext {
     /// The sender to send to sub games.
-    tx: broadcast::Sender<EventFrame>,
+    tx: broadcast::Sender<TracedFrame>,
     /// The receiver to receive from sub games.
-    rx: mpsc::Receiver<EventFrame>,
+    rx: mpsc::Receiver<TracedFrame>,
     /// The sender used to be cloned when launching sub games.
-    sub_tx: mpsc::Sender<EventFrame>,
+    sub_tx: mpsc::Sender<TracedFrame>,
     signal_tx: mpsc::Sender<SignalFrame>,
+    /// The chain of the game, where its sub games run.
+    chain: ChainType,
 }
 
 #[derive(Clone, Debug)]
 pub struct EventBridgeParent {
     #[allow(unused)]
-    bc: broadcast::Sender<EventFrame>,
+    bc: broadcast::Sender<TracedFrame>,
 }
 
 pub struct EventBridgeChildContext {
     pub game_id: usize,
-    tx: mpsc::Sender<EventFrame>,
-    rx: broadcast::Receiver<EventFrame>,
+    tx: mpsc::Sender<TracedFrame>,
+    rx: broadcast::Receiver<TracedFrame>,
 }
 
 pub struct EventBridgeChild {
@@ -35,7 +52,10 @@ pub struct EventBridgeChild {
 }
 
 impl EventBridgeParent {
-    pub fn init(signal_tx: mpsc::Sender<SignalFrame>) -> (Self, EventBridgeParentContext) {
+    pub fn init(
+        signal_tx: mpsc::Sender<SignalFrame>,
+        chain: ChainType,
+    ) -> (Self, EventBridgeParentContext) {
         let (mpsc_tx, mpsc_rx) = mpsc::channel(10);
         let (bc_tx, _bc_rx) = broadcast::channel(10);
         (
@@ -45,6 +65,7 @@ impl EventBridgeParent {
                 rx: mpsc_rx,
                 sub_tx: mpsc_tx.clone(),
                 signal_tx,
+                chain,
             },
         )
     }
@@ -56,9 +77,9 @@ impl EventBridgeParent {
     /// Return None when bridge is closed.
     async fn read_event(
         ports: &mut PipelinePorts,
-        rx: &mut mpsc::Receiver<EventFrame>,
-    ) -> Option<(bool, EventFrame)> {
-        tokio::select! {
+        rx: &mut mpsc::Receiver<TracedFrame>,
+    ) -> Option<(bool, TracedFrame)> {
+        let e = tokio::select! {
             e = rx.recv() => {
                 if let Some(e) = e {
                     Some((true, e))
@@ -68,12 +89,13 @@ impl EventBridgeParent {
             },
             e = ports.recv() => {
                 if let Some(e) = e {
-                    Some((false, e))
+                    Some((false, TracedFrame::with_span(e, Span::none())))
                 } else {
                     None
                 }
             },
-        }
+        };
+        with_ports_span(e, ports)
     }
 }
 
@@ -89,12 +111,13 @@ impl Component<PipelinePorts, EventBridgeParentContext> for EventBridgeParent {
         env: ComponentEnv,
     ) -> CloseReason {
         // We save the pending events here.
-        let mut pending_events: Vec<(usize, EventFrame)> = Vec::with_capacity(10);
+        let mut pending_events: Vec<(usize, TracedFrame)> = Vec::with_capacity(10);
 
         // We save the launching game IDs here.
         let mut launching_game_ids: Vec<usize> = Vec::with_capacity(10);
 
-        while let Some((from_bridge, event_frame)) = Self::read_event(&mut ports, &mut ctx.rx).await
+        while let Some((from_bridge, TracedFrame { frame: event_frame, span })) =
+            Self::read_event(&mut ports, &mut ctx.rx).await
         {
             if from_bridge {
                 // Bridge parent receives event from bridge child
@@ -107,12 +130,15 @@ impl Component<PipelinePorts, EventBridgeParentContext> for EventBridgeParent {
                     } => {
                         info!("{} Receives event: {}", env.log_prefix, event);
                         ports
-                            .send(EventFrame::RecvBridgeEvent {
-                                from,
-                                dest,
-                                event,
-                                versioned_data,
-                            })
+                            .send_traced(TracedFrame::with_span(
+                                EventFrame::RecvBridgeEvent {
+                                    from,
+                                    dest,
+                                    event,
+                                    versioned_data,
+                                },
+                                span,
+                            ))
                             .await;
                     }
 
@@ -124,9 +150,9 @@ impl Component<PipelinePorts, EventBridgeParentContext> for EventBridgeParent {
                         let mut i = 0;
                         while i < pending_events.len() {
                             if pending_events[i].0 == game_id {
-                                let (_, event_frame) = pending_events.remove(i);
-                                info!("{} Send pending event: {}", env.log_prefix, event_frame);
-                                if let Err(e) = ctx.tx.send(event_frame) {
+                                let (_, traced) = pending_events.remove(i);
+                                info!("{} Send pending event: {}", env.log_prefix, traced.frame);
+                                if let Err(e) = ctx.tx.send(traced) {
                                     error!("{} Failed to send: {}", env.log_prefix, e);
                                 }
                             } else {
@@ -134,7 +160,7 @@ impl Component<PipelinePorts, EventBridgeParentContext> for EventBridgeParent {
                             }
                         }
 
-                        ports.send(event_frame).await;
+                        ports.send_traced(TracedFrame::with_span(event_frame, span)).await;
                     }
 
                     EventFrame::SubGameLaunched { game_id } => {
@@ -145,9 +171,9 @@ impl Component<PipelinePorts, EventBridgeParentContext> for EventBridgeParent {
                         let mut i = 0;
                         while i < pending_events.len() {
                             if pending_events[i].0 == game_id {
-                                let (_, event_frame) = pending_events.remove(i);
-                                info!("{} Send pending event: {}", env.log_prefix, event_frame);
-                                if let Err(e) = ctx.tx.send(event_frame) {
+                                let (_, traced) = pending_events.remove(i);
+                                info!("{} Send pending event: {}", env.log_prefix, traced.frame);
+                                if let Err(e) = ctx.tx.send(traced) {
                                     error!("{} Failed to send: {}", env.log_prefix, e);
                                 }
                             } else {
@@ -155,12 +181,12 @@ impl Component<PipelinePorts, EventBridgeParentContext> for EventBridgeParent {
                             }
                         }
 
-                        ports.send(event_frame).await;
+                        ports.send_traced(TracedFrame::with_span(event_frame, span)).await;
                     }
 
                     EventFrame::SubGameShutdown { game_id, .. } => {
                         info!("{} Receives subgame shutdown: {}", env.log_prefix, game_id);
-                        ports.send(event_frame).await;
+                        ports.send_traced(TracedFrame::with_span(event_frame, span)).await;
                     }
 
                     _ => (),
@@ -176,6 +202,7 @@ impl Component<PipelinePorts, EventBridgeParentContext> for EventBridgeParent {
                                 rx_from_parent: ctx.tx.subscribe(),
                                 tx_to_parent: ctx.sub_tx.clone(),
                             },
+                            chain: ctx.chain,
                         };
                         // Save the launching game's ID
                         launching_game_ids.push(game_id);
@@ -185,7 +212,7 @@ impl Component<PipelinePorts, EventBridgeParentContext> for EventBridgeParent {
                     }
                     EventFrame::Shutdown => {
                         info!("{} Sends Shutdown", env.log_prefix);
-                        if let Err(e) = ctx.tx.send(event_frame) {
+                        if let Err(e) = ctx.tx.send(TracedFrame::with_span(event_frame, span)) {
                             warn!("{} Failed to send: {}", env.log_prefix, e);
                         }
                         info!("{} Stopped", env.log_prefix);
@@ -195,11 +222,11 @@ impl Component<PipelinePorts, EventBridgeParentContext> for EventBridgeParent {
                         if launching_game_ids.contains(&dest) {
                             // Subgame is not ready, add it to pending events
                             info!("{} Defer event: {}", env.log_prefix, event_frame);
-                            pending_events.push((dest, event_frame));
+                            pending_events.push((dest, TracedFrame::with_span(event_frame, span)));
                         } else {
                             // Send directly, the subgame is ready
                             info!("{} Sends event: {}", env.log_prefix, event_frame);
-                            if let Err(e) = ctx.tx.send(event_frame) {
+                            if let Err(e) = ctx.tx.send(TracedFrame::with_span(event_frame, span)) {
                                 error!("{} Failed to send: {}", env.log_prefix, e);
                             }
                         }
@@ -219,7 +246,7 @@ impl Component<PipelinePorts, EventBridgeParentContext> for EventBridgeParent {
                                 access_version,
                             };
                             info!("{} Broadcast sync: {}", env.log_prefix, sub_sync);
-                            if let Err(e) = ctx.tx.send(sub_sync) {
+                            if let Err(e) = ctx.tx.send(TracedFrame::with_span(sub_sync, span)) {
                                 error!("{} Failed to send: {}", env.log_prefix, e);
                             }
                         }
@@ -255,9 +282,9 @@ impl EventBridgeChild {
     /// Return None when bridge is closed.
     async fn read_event(
         ports: &mut PipelinePorts,
-        rx: &mut broadcast::Receiver<EventFrame>,
-    ) -> Option<(bool, EventFrame)> {
-        tokio::select! {
+        rx: &mut broadcast::Receiver<TracedFrame>,
+    ) -> Option<(bool, TracedFrame)> {
+        let e = tokio::select! {
             e = rx.recv() => {
                 if let Ok(e) = e {
                     Some((true, e))
@@ -267,12 +294,13 @@ impl EventBridgeChild {
             },
             e = ports.recv() => {
                 if let Some(e) = e {
-                    Some((false, e))
+                    Some((false, TracedFrame::with_span(e, Span::none())))
                 } else {
                     None
                 }
             }
-        }
+        };
+        with_ports_span(e, ports)
     }
 }
 
@@ -287,19 +315,20 @@ impl Component<PipelinePorts, EventBridgeChildContext> for EventBridgeChild {
         mut ctx: EventBridgeChildContext,
         env: ComponentEnv,
     ) -> CloseReason {
-        while let Some((from_bridge, event_frame)) = Self::read_event(&mut ports, &mut ctx.rx).await
+        while let Some((from_bridge, TracedFrame { frame: event_frame, span })) =
+            Self::read_event(&mut ports, &mut ctx.rx).await
         {
             if from_bridge {
                 // Bridge child receives event from event parent
                 match event_frame {
                     EventFrame::Shutdown => {
                         info!("{} Stopped", env.log_prefix);
-                        ports.send(event_frame).await;
+                        ports.send_traced(TracedFrame::with_span(event_frame, span)).await;
                         break;
                     }
                     EventFrame::SubSync { .. } => {
                         info!("{} Receives {}", env.log_prefix, event_frame);
-                        ports.send(event_frame).await;
+                        ports.send_traced(TracedFrame::with_span(event_frame, span)).await;
                     }
                     EventFrame::SendBridgeEvent {
                         from,
@@ -311,14 +340,17 @@ impl Component<PipelinePorts, EventBridgeChildContext> for EventBridgeChild {
                     } if dest == ctx.game_id => {
                         info!("{} Receives {}", env.log_prefix, event);
                         ports
-                            .send(EventFrame::RecvBridgeEvent {
-                                from,
-                                dest,
-                                event,
-                                // access_version,
-                                // settle_version,
-                                versioned_data,
-                            })
+                            .send_traced(TracedFrame::with_span(
+                                EventFrame::RecvBridgeEvent {
+                                    from,
+                                    dest,
+                                    event,
+                                    // access_version,
+                                    // settle_version,
+                                    versioned_data,
+                                },
+                                span,
+                            ))
                             .await;
                     }
                     _ => {}
@@ -330,28 +362,28 @@ impl Component<PipelinePorts, EventBridgeChildContext> for EventBridgeChild {
 
                     EventFrame::SubGameReady { .. } => {
                         info!("{} Send SubGameReady to parent", env.log_prefix);
-                        if let Err(e) = ctx.tx.send(event_frame).await {
+                        if let Err(e) = ctx.tx.send(TracedFrame::with_span(event_frame, span)).await {
                             error!("{} Failed to send: {}", env.log_prefix, e);
                         }
                     }
 
                     EventFrame::SubGameLaunched { .. } => {
                         info!("{} Send SubGameLaunched to parent", env.log_prefix);
-                        if let Err(e) = ctx.tx.send(event_frame).await {
+                        if let Err(e) = ctx.tx.send(TracedFrame::with_span(event_frame, span)).await {
                             error!("{} Failed to send: {}", env.log_prefix, e);
                         }
                     }
 
                     EventFrame::SubGameShutdown { .. } => {
                         info!("{} Send SubGameShutdown to parent", env.log_prefix);
-                        if let Err(e) = ctx.tx.send(event_frame).await {
+                        if let Err(e) = ctx.tx.send(TracedFrame::with_span(event_frame, span)).await {
                             error!("{} Failed to send: {}", env.log_prefix, e);
                         }
                     }
 
                     EventFrame::SendBridgeEvent { dest, .. } if dest != ctx.game_id => {
                         info!("{} Sends event: {}", env.log_prefix, event_frame);
-                        if let Err(e) = ctx.tx.send(event_frame).await {
+                        if let Err(e) = ctx.tx.send(TracedFrame::with_span(event_frame, span)).await {
                             error!("{} Failed to send: {}", env.log_prefix, e);
                         }
                     }
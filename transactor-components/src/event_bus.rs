@@ -5,23 +5,23 @@ use tokio::sync::{mpsc, watch, Mutex};
 use tracing::{error, warn};
 
 use crate::common::Attachable;
-use race_transactor_frames::EventFrame;
+use race_transactor_frames::{EventFrame, TracedFrame};
 use crate::utils::addr_shorthand;
 
 /// An event bus that passes the events between different components.
 pub struct EventBus {
     #[allow(unused)]
     addr: String,
-    tx: mpsc::Sender<EventFrame>,
-    attached_txs: Arc<Mutex<Vec<(String, mpsc::Sender<EventFrame>)>>>,
+    tx: mpsc::Sender<TracedFrame>,
+    attached_txs: Arc<Mutex<Vec<(String, mpsc::Sender<TracedFrame>)>>>,
     close_rx: watch::Receiver<bool>,
 }
 
 impl EventBus {
     pub fn new(addr: String) -> Self {
         let (close_tx, close_rx) = watch::channel(false);
-        let (tx, mut rx) = mpsc::channel::<EventFrame>(32);
-        let txs: Arc<Mutex<Vec<(String, mpsc::Sender<EventFrame>)>>> = Arc::new(Mutex::new(vec![]));
+        let (tx, mut rx) = mpsc::channel::<TracedFrame>(32);
+        let txs: Arc<Mutex<Vec<(String, mpsc::Sender<TracedFrame>)>>> = Arc::new(Mutex::new(vec![]));
         let attached_txs = txs.clone();
         let addr_1 = addr_shorthand(&addr);
 
@@ -34,14 +34,14 @@ impl EventBus {
                             warn!(
                                 "[{}] Failed to send message: {} to component: {} due to error: {}",
                                 addr_1,
-                                msg,
+                                msg.frame,
                                 id,
                                 e
                             );
                         }
                     }
                 }
-                if matches!(msg, EventFrame::Shutdown) {
+                if matches!(msg.frame, EventFrame::Shutdown) {
                     close_tx.send(true).unwrap();
                     break;
                 }
@@ -94,8 +94,9 @@ impl EventBus {
         }
     }
 
+    /// Send a frame in the current span.
     pub async fn send(&self, event: EventFrame) {
-        if let Err(e) = self.tx.send(event).await {
+        if let Err(e) = self.tx.send(TracedFrame::new(event)).await {
             error!("An error occurred when sending event, {}", e.to_string());
         }
     }
@@ -7,7 +7,8 @@ use race_core::context::GameContext;
 use race_core::game_spec::GameSpec;
 use race_core::encryptor::EncryptorT;
 use tracing::{error, info, warn};
-use race_handler::HandlerManager;
+use race_handler::{HandlerManager, ModuleCache, WasmLimits};
+use race_env::HandlerConfig;
 use race_core::transport::TransportT;
 
 use crate::common::{Component, PipelinePorts};
@@ -28,6 +29,8 @@ pub struct EventLoopContext {
     game_mode: GameMode,
     encryptor: Arc<dyn EncryptorT>,
     transport: Arc<dyn TransportT>,
+    module_cache: Arc<ModuleCache>,
+    wasm_limits: WasmLimits,
 }
 
 pub struct EventLoop {}
@@ -48,7 +51,7 @@ impl Component<PipelinePorts, EventLoopContext> for EventLoop {
         let game_spec = ctx.game_spec;
         let encryptor = ctx.encryptor;
 
-        let mut handler_manager = HandlerManager::new(ctx.transport);
+        let mut handler_manager = HandlerManager::new(ctx.transport, ctx.module_cache, ctx.wasm_limits);
 
         let mut handler = match handler_manager.get_handler(&game_spec.bundle_addr).await {
             Ok(handler) => handler,
@@ -435,6 +438,49 @@ impl Component<PipelinePorts, EventLoopContext> for EventLoop {
                         return close_reason;
                     }
                 }
+                EventFrame::ReplayEvent { event, timestamp, state_sha } => {
+                    if matches!(event, Event::Shutdown) {
+                        return CloseReason::Complete;
+                    }
+
+                    let settle_version = game_context.settle_version();
+                    if let Some(close_reason) = event_handler::handle_event(
+                        &mut *handler,
+                        &mut handler_manager,
+                        &mut game_context,
+                        event.clone(),
+                        &*encryptor,
+                        &ports,
+                        ctx.client_mode,
+                        ctx.game_mode,
+                        timestamp,
+                        &env,
+                    )
+                    .await
+                    {
+                        return close_reason;
+                    }
+
+                    ports
+                        .send(EventFrame::EventReplayed {
+                            event: event.clone(),
+                            timestamp,
+                            settle_version,
+                        })
+                        .await;
+                    event_handler::verify_state_sha(&game_context, event, timestamp, state_sha, &ports, &env).await;
+                }
+                EventFrame::GracefulShutdown => {
+                    event_handler::graceful_shutdown(
+                        &mut game_context,
+                        &ports,
+                        ctx.client_mode,
+                        &env,
+                    )
+                    .await;
+                    info!("{} Stopped", env.log_prefix);
+                    return CloseReason::Complete;
+                }
                 EventFrame::Shutdown => {
                     info!("{} Stopped", env.log_prefix);
                     return CloseReason::Complete;
@@ -452,9 +498,18 @@ impl EventLoop {
         game_spec: GameSpec,
         encryptor: Arc<dyn EncryptorT>,
         transport: Arc<dyn TransportT>,
+        module_cache: Arc<ModuleCache>,
         client_mode: ClientMode,
         game_mode: GameMode,
+        config: Option<&HandlerConfig>,
     ) -> (Self, EventLoopContext) {
+        let default_limits = WasmLimits::default();
+        let wasm_limits = WasmLimits {
+            fuel: config.and_then(|c| c.fuel).unwrap_or(default_limits.fuel),
+            max_memory_pages: config
+                .and_then(|c| c.max_memory_pages)
+                .unwrap_or(default_limits.max_memory_pages),
+        };
         (
             Self {},
             EventLoopContext {
@@ -463,6 +518,8 @@ impl EventLoop {
                 game_mode,
                 encryptor,
                 transport,
+                module_cache,
+                wasm_limits,
             },
         )
     }
@@ -12,11 +12,11 @@ use race_core::{
     error::Error,
     game_spec::GameSpec,
     engine::general_handle_event,
-    types::{ClientMode, GameMode},
+    types::{ClientMode, DivergenceReport, GameMode},
 };
 use race_transactor_frames::EventFrame;
 use crate::{common::PipelinePorts, CloseReason, ComponentEnv};
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, info_span, warn};
 use race_handler::{HandlerT, HandlerManager};
 use race_core::encryptor::EncryptorT;
 use race_core::entry_type::EntryType;
@@ -397,6 +397,91 @@ pub async fn recover_from_checkpoint(
     Ok(game_context)
 }
 
+/// Flush the ready settlements and emit the latest state before the
+/// game is stopped.  The settlements which are still locked by sub
+/// games can't be sent, they are reported in the logs.
+pub async fn graceful_shutdown(
+    game_context: &mut GameContext,
+    ports: &PipelinePorts,
+    client_mode: ClientMode,
+    env: &ComponentEnv,
+) {
+    if client_mode == ClientMode::Transactor && game_context.handler_is_initialized() {
+        do_send_settlements(game_context, ports, env).await;
+
+        let locked = game_context.pending_settle_details_mut().len();
+        if locked > 0 {
+            warn!(
+                "{} {} settlements are still locked by sub games, they won't be submitted",
+                env.log_prefix, locked
+            );
+        }
+
+        info!(
+            "{} Emit final checkpoint, versions: A#{} S#{}",
+            env.log_prefix,
+            game_context.access_version(),
+            game_context.settle_version()
+        );
+        ports
+            .send(EventFrame::FinalCheckpoint {
+                checkpoint: game_context.checkpoint(),
+            })
+            .await;
+    }
+
+    ports.send(EventFrame::Shutdown).await;
+}
+
+/// Compare the state after a replayed event with the transactor's.
+/// On mismatch, an unsigned divergence report is sent to the voter.
+/// The transactor always sends its SHA, so an empty one is a mismatch.
+pub async fn verify_state_sha(
+    game_context: &GameContext,
+    event: Event,
+    timestamp: u64,
+    expected_state_sha: String,
+    ports: &PipelinePorts,
+    env: &ComponentEnv,
+) {
+    let actual_state_sha = game_context.state_sha();
+    if expected_state_sha == actual_state_sha {
+        return;
+    }
+
+    error!(
+        "{} State diverged after event: {}, expected SHA: {}, actual SHA: {}",
+        env.log_prefix, event, expected_state_sha, actual_state_sha
+    );
+    log_execution_context(game_context, &event);
+
+    let transactor_addr = match game_context.get_transactor_node() {
+        Ok(node) => node.addr.clone(),
+        Err(e) => {
+            error!("{} Failed to report divergence: {}", env.log_prefix, e);
+            return;
+        }
+    };
+
+    let report = DivergenceReport {
+        game_addr: game_context.game_addr().to_string(),
+        transactor_addr,
+        validator_addr: "".into(),
+        access_version: game_context.access_version(),
+        settle_version: game_context.settle_version(),
+        event,
+        timestamp,
+        expected_state_sha,
+        actual_state_sha,
+        signature: None,
+    };
+    ports
+        .send(EventFrame::Divergence {
+            report: Box::new(report),
+        })
+        .await;
+}
+
 pub async fn handle_event(
     handler: &mut dyn HandlerT,
     handler_manager: &mut HandlerManager,
@@ -433,13 +518,17 @@ pub async fn handle_event(
 
     let effect = new_game_context.derive_effect();
 
-    let mut effect = match handler.handle_event(&effect, &event) {
+    let handler_span = info_span!(parent: ports.span(), "handler", event = %event);
+    let mut effect = match handler_span.in_scope(|| handler.handle_event(&effect, &event)) {
         Ok(eff) => eff,
         Err(e) => {
             warn!("{} Handle event error: {}", env.log_prefix, e.to_string());
             log_execution_context(&new_game_context, &event);
             match e {
-                Error::WasmExecutionError(_) | Error::WasmMemoryOverflow => {
+                Error::WasmExecutionError(_)
+                | Error::WasmMemoryOverflow
+                | Error::WasmFuelExhausted
+                | Error::WasmMemoryLimitExceeded => {
                     ports.send(EventFrame::Shutdown).await;
                     return Some(CloseReason::Fault(e));
                 }
@@ -465,6 +554,10 @@ pub async fn handle_event(
         }
     };
 
+    let versions = new_game_context.versions();
+    ports.span().record("access_version", versions.access_version);
+    ports.span().record("settle_version", versions.settle_version);
+
     let EventEffects {
         launch_sub_games,
         bridge_events,
@@ -528,3 +621,76 @@ pub async fn handle_event(
 
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use race_core::game_spec::GameSpec;
+
+    use crate::common::Ports;
+
+    use super::*;
+
+    fn make_game_context() -> GameContext {
+        let shared_data = SharedData::new(
+            vec![],
+            vec![Node::new("transactor", 0, ClientMode::Transactor)],
+        );
+        let versioned_data = VersionedData {
+            game_spec: GameSpec {
+                game_addr: "game".into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        GameContext::try_new(shared_data, versioned_data).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_verify_state_sha_mismatch() {
+        let game_context = make_game_context();
+        let env = ComponentEnv::new("game", "EventLoop");
+        let (ports, mut io) = PipelinePorts::create(&env);
+
+        verify_state_sha(&game_context, Event::GameStart, 1000, "wrong".into(), &ports, &env).await;
+
+        let Some(EventFrame::Divergence { report }) = io.recv().await else {
+            panic!("Expect a divergence report");
+        };
+        assert_eq!(report.game_addr, "game");
+        assert_eq!(report.transactor_addr, "transactor");
+        assert_eq!(report.event, Event::GameStart);
+        assert_eq!(report.timestamp, 1000);
+        assert_eq!(report.expected_state_sha, "wrong");
+        assert_eq!(report.actual_state_sha, game_context.state_sha());
+        assert_eq!(report.signature, None);
+    }
+
+    #[tokio::test]
+    async fn test_verify_state_sha_match() {
+        let game_context = make_game_context();
+        let env = ComponentEnv::new("game", "EventLoop");
+        let (ports, mut io) = PipelinePorts::create(&env);
+
+        let state_sha = game_context.state_sha();
+        verify_state_sha(&game_context, Event::GameStart, 1000, state_sha, &ports, &env).await;
+
+        drop(ports);
+        assert!(io.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_verify_empty_state_sha() {
+        let game_context = make_game_context();
+        let env = ComponentEnv::new("game", "EventLoop");
+        let (ports, mut io) = PipelinePorts::create(&env);
+
+        // A transactor can't hide its state by sending no SHA
+        verify_state_sha(&game_context, Event::GameStart, 1000, "".into(), &ports, &env).await;
+
+        let Some(EventFrame::Divergence { report }) = io.recv().await else {
+            panic!("Expect a divergence report");
+        };
+        assert_eq!(report.expected_state_sha, "");
+        assert_eq!(report.actual_state_sha, game_context.state_sha());
+    }
+}
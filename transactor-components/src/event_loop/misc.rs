@@ -34,7 +34,7 @@ pub async fn read_event(
         if dispatch.timeout <= timestamp {
             let event = dispatch.event.clone();
             game_context.cancel_dispatch();
-            return Some(EventFrame::SendServerEvent { event, timestamp });
+            return Some(ports.enter(EventFrame::SendServerEvent { event, timestamp }));
         }
         let to = tokio::time::sleep(Duration::from_millis(dispatch.timeout - timestamp));
         select! {
@@ -45,7 +45,7 @@ pub async fn read_event(
                 let event = dispatch.event.clone();
                 let timestamp = dispatch.timeout;
                 game_context.cancel_dispatch();
-                Some(EventFrame::SendServerEvent { event, timestamp })
+                Some(ports.enter(EventFrame::SendServerEvent { event, timestamp }))
             }
         }
     } else {
@@ -0,0 +1,332 @@
+//! The journal of the events handled after the latest checkpoint, to
+//! recover the in-progress state after a crash.
+//!
+//! Every event broadcasted by the event loop is appended to storage,
+//! including the randomization and decision events.  An entry is keyed
+//! by the settle version of the checkpoint it follows, and its order
+//! after that checkpoint, so replaying the journal writes the same
+//! entries again.  The entries before a checkpoint are removed when
+//! the submitter reports its settlement landed with
+//! [EventFrame::SettleConfirmed].  Until then the game may still be
+//! recovered from the previous checkpoint on chain.  The report comes
+//! after all the events before the checkpoint, so they are written by
+//! then.
+//!
+//! On recovery, the journal after the checkpoint is replayed through
+//! the handler by [crate::HistoryReplayer].  The secrets of this
+//! transactor are not journaled, so the replay stops before its first
+//! mask or lock, see [cut_at_own_secrets].
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use borsh::{BorshDeserialize, BorshSerialize};
+use race_api::event::Event;
+use race_core::error::{Error, Result};
+use race_core::storage::StorageT;
+use race_core::types::{AppendJournalParams, GetJournalParams, TruncateJournalParams};
+use race_transactor_frames::EventFrame;
+use tracing::{error, info, warn};
+
+use crate::common::{Component, ConsumerPorts};
+use crate::event_bus::CloseReason;
+use crate::ComponentEnv;
+
+#[derive(Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct JournalEntry {
+    pub event: Event,
+    pub timestamp: u64,
+}
+
+/// Load the events handled after the checkpoint at `settle_version`,
+/// with their timestamps, in order.
+pub async fn load_journal(
+    game_addr: &str,
+    settle_version: u64,
+    storage: &dyn StorageT,
+) -> Result<Vec<(Event, u64)>> {
+    let entries = storage
+        .get_journal(GetJournalParams {
+            game_addr: game_addr.to_string(),
+            settle_version,
+        })
+        .await?;
+
+    entries
+        .into_iter()
+        .map(|data| {
+            let entry = JournalEntry::try_from_slice(&data)
+                .map_err(|e| Error::StorageError(e.to_string()))?;
+            Ok((entry.event, entry.timestamp))
+        })
+        .collect()
+}
+
+/// Cut the journal before the first mask or lock of this transactor,
+/// the node `server_id`.  Its secrets are lost in the crash, so the
+/// randomness couldn't be revealed after the events are replayed.
+/// The randomization continues from the cut with new secrets.
+/// Return the number of events cut.
+pub fn cut_at_own_secrets(history: &mut Vec<(Event, u64)>, server_id: u64) -> usize {
+    let cut_at = history.iter().position(|(event, _)| match event {
+        Event::Mask { sender, .. } | Event::Lock { sender, .. } => *sender == server_id,
+        _ => false,
+    });
+    match cut_at {
+        Some(cut_at) => {
+            let cut = history.len() - cut_at;
+            history.truncate(cut_at);
+            cut
+        }
+        None => 0,
+    }
+}
+
+pub struct JournalContext {
+    addr: String,
+    storage: Arc<dyn StorageT>,
+}
+
+pub struct Journal {}
+
+impl Journal {
+    pub fn init(addr: String, storage: Arc<dyn StorageT>) -> (Self, JournalContext) {
+        (Self {}, JournalContext { addr, storage })
+    }
+}
+
+#[async_trait]
+impl Component<ConsumerPorts, JournalContext> for Journal {
+    fn name() -> &'static str {
+        "Journal"
+    }
+
+    async fn run(mut ports: ConsumerPorts, ctx: JournalContext, env: ComponentEnv) -> CloseReason {
+        // The settle version of the latest checkpoint, and the number
+        // of events handled after it.
+        let mut settle_version = 0;
+        let mut seq = 0;
+
+        while let Some(frame) = ports.recv().await {
+            match frame {
+                EventFrame::RecoverCheckpointWithCredentials { checkpoint } => {
+                    settle_version = checkpoint.root_data().versions.settle_version;
+                    seq = 0;
+                }
+                EventFrame::Checkpoint { checkpoint } => {
+                    settle_version = checkpoint.root_data().versions.settle_version;
+                    seq = 0;
+                }
+                EventFrame::Broadcast { event, timestamp, .. } => {
+                    let data = match borsh::to_vec(&JournalEntry { event, timestamp }) {
+                        Ok(data) => data,
+                        Err(e) => {
+                            error!("{} Failed to serialize journal entry: {}", env.log_prefix, e);
+                            continue;
+                        }
+                    };
+                    let r = ctx
+                        .storage
+                        .append_journal(AppendJournalParams {
+                            game_addr: ctx.addr.clone(),
+                            settle_version,
+                            seq,
+                            data,
+                        })
+                        .await;
+                    if let Err(e) = r {
+                        error!("{} Failed to append journal: {}", env.log_prefix, e);
+                    }
+                    seq += 1;
+                }
+                EventFrame::SettleConfirmed { settle_version: confirmed } => {
+                    let r = ctx
+                        .storage
+                        .truncate_journal(TruncateJournalParams {
+                            game_addr: ctx.addr.clone(),
+                            settle_version: confirmed,
+                        })
+                        .await;
+                    if let Err(e) = r {
+                        warn!("{} Failed to truncate journal: {}", env.log_prefix, e);
+                    }
+                }
+                EventFrame::Shutdown => {
+                    info!("{} Stopped", env.log_prefix);
+                    break;
+                }
+                _ => (),
+            }
+        }
+
+        CloseReason::Complete
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use race_core::checkpoint::{ContextCheckpoint, VersionedData};
+    use race_core::game_spec::GameSpec;
+    use race_core::versions::Versions;
+    use race_core::types::CutJournalParams;
+    use race_local_db::LocalDbStorage;
+
+    use crate::HistoryReplayer;
+
+    use super::*;
+
+    fn make_checkpoint(settle_version: u64) -> ContextCheckpoint {
+        let root_data = VersionedData::new(
+            GameSpec::default(),
+            Versions::new(1, settle_version),
+            vec![],
+        );
+        ContextCheckpoint::new(Default::default(), root_data)
+    }
+
+    fn make_broadcast(event: Event, timestamp: u64) -> EventFrame {
+        EventFrame::Broadcast {
+            event,
+            timestamp,
+            state_sha: "".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_journal() {
+        let storage: Arc<dyn StorageT> = Arc::new(LocalDbStorage::try_new_mem().unwrap());
+        let (journal, ctx) = Journal::init("game".into(), storage.clone());
+        let handle = journal.start("game", ctx);
+
+        handle
+            .send_unchecked(EventFrame::RecoverCheckpointWithCredentials {
+                checkpoint: make_checkpoint(1),
+            })
+            .await;
+        handle.send_unchecked(make_broadcast(Event::GameStart, 1)).await;
+        handle.send_unchecked(make_broadcast(Event::WaitingTimeout, 2)).await;
+        handle
+            .send_unchecked(EventFrame::Checkpoint {
+                checkpoint: make_checkpoint(2),
+            })
+            .await;
+        handle.send_unchecked(make_broadcast(Event::DrawTimeout, 3)).await;
+        // Replayed after a recovery, the entries are written again
+        handle
+            .send_unchecked(EventFrame::RecoverCheckpointWithCredentials {
+                checkpoint: make_checkpoint(2),
+            })
+            .await;
+        handle.send_unchecked(make_broadcast(Event::DrawTimeout, 3)).await;
+        handle.send_unchecked(make_broadcast(Event::ActionTimeout { player_id: 1 }, 4)).await;
+        handle.send_unchecked(EventFrame::Shutdown).await;
+        handle.wait().await;
+
+        assert_eq!(
+            load_journal("game", 1, storage.as_ref()).await.unwrap(),
+            vec![(Event::GameStart, 1), (Event::WaitingTimeout, 2)]
+        );
+        assert_eq!(
+            load_journal("game", 2, storage.as_ref()).await.unwrap(),
+            vec![(Event::DrawTimeout, 3), (Event::ActionTimeout { player_id: 1 }, 4)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_truncate_when_settle_confirmed() {
+        let storage: Arc<dyn StorageT> = Arc::new(LocalDbStorage::try_new_mem().unwrap());
+        let (journal, ctx) = Journal::init("game".into(), storage.clone());
+        let handle = journal.start("game", ctx);
+
+        handle
+            .send_unchecked(EventFrame::RecoverCheckpointWithCredentials {
+                checkpoint: make_checkpoint(1),
+            })
+            .await;
+        handle.send_unchecked(make_broadcast(Event::GameStart, 1)).await;
+        handle
+            .send_unchecked(EventFrame::Checkpoint {
+                checkpoint: make_checkpoint(2),
+            })
+            .await;
+        handle.send_unchecked(make_broadcast(Event::WaitingTimeout, 2)).await;
+        handle
+            .send_unchecked(EventFrame::SettleConfirmed { settle_version: 2 })
+            .await;
+        handle.send_unchecked(EventFrame::Shutdown).await;
+        handle.wait().await;
+
+        assert!(load_journal("game", 1, storage.as_ref()).await.unwrap().is_empty());
+        assert_eq!(
+            load_journal("game", 2, storage.as_ref()).await.unwrap(),
+            vec![(Event::WaitingTimeout, 2)]
+        );
+    }
+
+    #[test]
+    fn test_cut_at_own_secrets() {
+        let mask = |sender| Event::Mask {
+            sender,
+            random_id: 1,
+            ciphertexts: vec![],
+        };
+        let mut history = vec![(Event::GameStart, 1), (mask(1), 2), (mask(2), 3), (Event::DrawTimeout, 4)];
+        assert_eq!(cut_at_own_secrets(&mut history, 3), 0);
+        assert_eq!(history.len(), 4);
+        assert_eq!(cut_at_own_secrets(&mut history, 2), 2);
+        assert_eq!(history, vec![(Event::GameStart, 1), (mask(1), 2)]);
+    }
+
+    #[tokio::test]
+    async fn test_recover_from_journal() {
+        let storage: Arc<dyn StorageT> = Arc::new(LocalDbStorage::try_new_mem().unwrap());
+        let (journal, ctx) = Journal::init("game".into(), storage.clone());
+        let handle = journal.start("game", ctx);
+
+        let lock = |sender| Event::Lock {
+            sender,
+            random_id: 1,
+            ciphertexts_and_digests: vec![],
+        };
+        handle
+            .send_unchecked(EventFrame::RecoverCheckpointWithCredentials {
+                checkpoint: make_checkpoint(1),
+            })
+            .await;
+        handle.send_unchecked(make_broadcast(Event::GameStart, 1)).await;
+        handle.send_unchecked(make_broadcast(lock(1), 2)).await;
+        handle.send_unchecked(make_broadcast(lock(2), 3)).await;
+        handle.send_unchecked(make_broadcast(Event::DrawTimeout, 4)).await;
+        handle.send_unchecked(EventFrame::Shutdown).await;
+        handle.wait().await;
+
+        // Recovered by the node 2, the events from its lock are dropped
+        let mut history = load_journal("game", 1, storage.as_ref()).await.unwrap();
+        assert_eq!(cut_at_own_secrets(&mut history, 2), 2);
+        storage
+            .cut_journal(CutJournalParams {
+                game_addr: "game".into(),
+                settle_version: 1,
+                seq: history.len() as u64,
+            })
+            .await
+            .unwrap();
+        assert_eq!(load_journal("game", 1, storage.as_ref()).await.unwrap(), history);
+
+        let (replayer, ctx) = HistoryReplayer::init(history);
+        let mut handle = replayer.start("game", ctx);
+        handle
+            .send_unchecked(EventFrame::RecoverCheckpointWithCredentials {
+                checkpoint: make_checkpoint(1),
+            })
+            .await;
+        let mut replayed = vec![];
+        for _ in 0..2 {
+            match handle.recv_unchecked().await {
+                Some(EventFrame::SendServerEvent { event, timestamp }) => replayed.push((event, timestamp)),
+                frame => panic!("Unexpected frame: {:?}", frame),
+            }
+        }
+        assert_eq!(replayed, vec![(Event::GameStart, 1), (lock(1), 2)]);
+    }
+}
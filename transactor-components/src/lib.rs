@@ -14,25 +14,28 @@ mod wrapped_transport;
 mod wrapped_storage;
 mod event_bridge;
 mod recorder;
+mod journal;
+mod takeover;
 mod utils;
 
 pub use event_bus::CloseReason;
-pub use broadcaster::{Broadcaster, CheckpointBroadcastFrame};
+pub use broadcaster::{Broadcaster, CheckpointBroadcastFrame, SubscriberStats};
 pub use common::Component;
 pub use common::PortsHandle;
 pub use common::ComponentEnv;
 pub use connection::{LocalConnection, RemoteConnection};
 pub use event_bus::EventBus;
 pub use event_loop::EventLoop;
-pub use submitter::Submitter;
+pub use submitter::{replay_pending_settles, Submitter};
 pub use subscriber::Subscriber;
 pub use synchronizer::GameSynchronizer;
-#[allow(unused)]
 pub use recorder::Recorder;
+pub use journal::{cut_at_own_secrets, load_journal, Journal};
 pub use credential_consolidator::CredentialConsolidator;
-pub use voter::Voter;
+pub use voter::{DivergenceLog, Voter};
 pub use wrapped_client::WrappedClient;
-pub use wrapped_transport::WrappedTransport;
+pub use wrapped_transport::{SettleProgress, WrappedTransport};
 pub use wrapped_storage::WrappedStorage;
 pub use event_bridge::{EventBridgeChild, EventBridgeParent};
 pub use refunder::Refunder;
+pub use takeover::{HistoryReplayer, Promoter};
@@ -1,25 +1,63 @@
+//! The component to record a game, the recordings are used to settle
+//! disputes.  See [race_event_record] for the format.
+//!
+//! Each game is recorded into its own directory.  A new file is
+//! started when the current one is too large or too old, and the
+//! files are compressed with zstd.  The files are written on a
+//! dedicated thread, off the async runtime.
+
 use async_trait::async_trait;
-use borsh::BorshSerialize;
+use race_api::event::MessageChannel;
 use race_core::game_spec::GameSpec;
 use race_core::entry_type::EntryType;
 use race_core::chain::ChainType;
+use race_core::checkpoint::ContextCheckpoint;
+use race_env::RecorderConfig;
 use race_transactor_frames::EventFrame;
-use race_event_record::{RecordsHeader, Record};
-use std::fs::{File, create_dir};
-use std::io::{Write, BufWriter};
-use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use race_event_record::{encode_line, RecordsHeader, Record};
+use std::fs::{create_dir_all, File};
+use std::io::{self, Write, BufWriter};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 use super::ComponentEnv;
 use super::common::ConsumerPorts;
-use tokio::sync::Mutex;
 use tracing::{info, error};
 
 use crate::common::Component;
 use crate::event_bus::CloseReason;
-use crate::utils::base64_encode;
+use crate::utils::current_timestamp;
+
+const DEFAULT_MAX_FILE_BYTES: u64 = 64 * 1024 * 1024;
+const DEFAULT_ROTATE_INTERVAL: u64 = 3600;
+const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+
+struct RecorderOptions {
+    dir: PathBuf,
+    max_file_bytes: u64,
+    rotate_interval: Duration,
+    compression_level: i32,
+}
+
+impl RecorderOptions {
+    fn from_config(config: &RecorderConfig) -> Self {
+        Self {
+            dir: PathBuf::from(&config.dir),
+            max_file_bytes: config.max_file_bytes.unwrap_or(DEFAULT_MAX_FILE_BYTES),
+            rotate_interval: Duration::from_secs(
+                config.rotate_interval.unwrap_or(DEFAULT_ROTATE_INTERVAL),
+            ),
+            compression_level: config
+                .compression_level
+                .unwrap_or(DEFAULT_COMPRESSION_LEVEL),
+        }
+    }
+}
 
 trait RecordWriter {
     fn write(&mut self, record: Record);
+
+    fn close(&mut self) {}
 }
 
 struct InMemoryRecordWriter {
@@ -43,77 +81,245 @@ impl RecordWriter for InMemoryRecordWriter {
     }
 }
 
-struct FileRecordWriter {
-    pub writer: BufWriter<File>,
+enum RecordFile {
+    Plain(BufWriter<File>),
+    Zstd(zstd::stream::write::Encoder<'static, BufWriter<File>>),
 }
 
-impl FileRecordWriter {
-    pub fn try_new(record_file_name: PathBuf, header: RecordsHeader) -> std::io::Result<Self> {
-        let file = File::create(record_file_name)?;
-        let writer = BufWriter::new(file);
-        let mut file_writer = Self { writer };
-        if let Err(e) = file_writer.write_internal(&header) {
-            error!("Failed to write header: {}", e);
-            return Err(e);
+impl RecordFile {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            RecordFile::Plain(w) => writeln!(w, "{}", line),
+            RecordFile::Zstd(w) => writeln!(w, "{}", line),
+        }
+    }
+
+    // Flushing ends a zstd block, so it's done only at checkpoints.
+    // The file can be read up to the latest checkpoint while the game
+    // is running, or after a crash.
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            RecordFile::Plain(w) => w.flush(),
+            RecordFile::Zstd(w) => w.flush(),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            RecordFile::Plain(mut w) => w.flush(),
+            RecordFile::Zstd(w) => w.finish()?.flush(),
         }
-        Ok(file_writer)
     }
 }
 
+struct FileRecordWriter {
+    options: RecorderOptions,
+    game_dir: PathBuf,
+    header: RecordsHeader,
+    file: Option<RecordFile>,
+    bytes: u64,
+    opened_at: Instant,
+    // Written at the beginning of each file
+    last_checkpoint: Option<Record>,
+}
+
 impl FileRecordWriter {
-    fn write_internal<B: BorshSerialize>(&mut self, b: &B) -> std::io::Result<()> {
-        let s = borsh::to_vec(b)?;
-        writeln!(&mut self.writer, "{}", base64_encode(&s))?;
-        self.writer.flush()
+    fn new(options: RecorderOptions, mut header: RecordsHeader) -> Self {
+        let game_dir = if header.spec.game_id == 0 {
+            options.dir.join(&header.spec.game_addr)
+        } else {
+            options.dir.join(format!("{}_{}", header.spec.game_addr, header.spec.game_id))
+        };
+        header.started_at = current_timestamp();
+        Self {
+            options,
+            game_dir,
+            header,
+            file: None,
+            bytes: 0,
+            opened_at: Instant::now(),
+            last_checkpoint: None,
+        }
+    }
+
+    fn file_path(&self) -> PathBuf {
+        let ext = if self.options.compression_level > 0 { "rec.zst" } else { "rec" };
+        self.game_dir.join(format!("{}-{:05}.{}", self.header.started_at, self.header.segment, ext))
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        create_dir_all(&self.game_dir)?;
+        let path = self.file_path();
+        let writer = BufWriter::new(File::create(&path)?);
+        let mut file = if self.options.compression_level > 0 {
+            RecordFile::Zstd(zstd::stream::write::Encoder::new(writer, self.options.compression_level)?)
+        } else {
+            RecordFile::Plain(writer)
+        };
+        let mut bytes = 0;
+        let header_line = encode_line(&self.header)?;
+        file.write_line(&header_line)?;
+        bytes += header_line.len() as u64 + 1;
+        if let Some(checkpoint) = self.last_checkpoint.as_ref() {
+            let line = encode_line(checkpoint)?;
+            file.write_line(&line)?;
+            bytes += line.len() as u64 + 1;
+        }
+        info!("Start recording to {:?}", path);
+        self.file = Some(file);
+        self.bytes = bytes;
+        self.opened_at = Instant::now();
+        Ok(())
+    }
+
+    fn should_rotate(&self) -> bool {
+        self.bytes >= self.options.max_file_bytes
+            || self.opened_at.elapsed() >= self.options.rotate_interval
+    }
+
+    fn write_internal(&mut self, record: &Record) -> io::Result<()> {
+        if self.file.is_some() && self.should_rotate() {
+            self.close();
+            self.header.segment += 1;
+        }
+        if self.file.is_none() {
+            self.open()?;
+        }
+        let line = encode_line(record)?;
+        if let Some(file) = self.file.as_mut() {
+            file.write_line(&line)?;
+            self.bytes += line.len() as u64 + 1;
+            if matches!(record, Record::Checkpoint { .. }) {
+                file.flush()?;
+            }
+        }
+        Ok(())
     }
 }
 
 impl RecordWriter for FileRecordWriter {
     fn write(&mut self, record: Record) {
         if let Err(e) = self.write_internal(&record) {
-            error!("Failed to write record: {}", e);
+            error!("Failed to write record {}: {}", record, e);
+        }
+        if matches!(record, Record::Checkpoint { .. }) {
+            self.last_checkpoint = Some(record);
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(file) = self.file.take() {
+            if let Err(e) = file.finish() {
+                error!("Failed to finish record file: {}", e);
+            }
         }
     }
 }
 
+fn checkpoint_record(checkpoint: &ContextCheckpoint) -> Record {
+    let root_data = checkpoint.root_data();
+    let shared_data = checkpoint.shared_data();
+    Record::checkpoint(
+        root_data.handler_state.clone(),
+        shared_data.nodes.clone(),
+        shared_data.balances.clone(),
+        root_data.versions.access_version,
+        root_data.versions.settle_version,
+    )
+}
+
+/// Convert a frame to the record, return None for the frames not
+/// recorded.
+fn frame_to_record(event_frame: EventFrame) -> Option<Record> {
+    let record = match event_frame {
+        EventFrame::Broadcast { event, timestamp, state_sha } => Record::Broadcast {
+            event,
+            timestamp,
+            state_sha,
+        },
+        // Only the table messages are public
+        EventFrame::SendMessage { message } if message.channel == MessageChannel::Table => {
+            Record::Message { message: message.into() }
+        }
+        EventFrame::Checkpoint { checkpoint }
+        | EventFrame::FinalCheckpoint { checkpoint }
+        | EventFrame::RecoverCheckpoint { checkpoint }
+        | EventFrame::RecoverCheckpointWithCredentials { checkpoint } => {
+            checkpoint_record(&checkpoint)
+        }
+        EventFrame::Settle { settle_details } => Record::Settle {
+            settles: settle_details.settles,
+            transfer: settle_details.transfer,
+            awards: settle_details.awards,
+            accept_deposits: settle_details.accept_deposits,
+            access_version: settle_details.access_version,
+            settle_version: settle_details.settle_version,
+            previous_settle_version: settle_details.previous_settle_version,
+        },
+        EventFrame::TxState { tx_state } => Record::TxState { tx_state },
+        EventFrame::SendBridgeEvent { from, dest, event, .. } => Record::BridgeEvent {
+            from,
+            dest,
+            event,
+            incoming: false,
+        },
+        EventFrame::RecvBridgeEvent { from, dest, event, .. } => Record::BridgeEvent {
+            from,
+            dest,
+            event,
+            incoming: true,
+        },
+        _ => return None,
+    };
+    Some(record)
+}
+
 pub struct Recorder {
     #[allow(unused)]
-    writer: Arc<Mutex<dyn RecordWriter + Send + Sync>>,
+    writer: Arc<Mutex<dyn RecordWriter + Send>>,
 }
 
 pub struct RecorderContext {
-    writer: Arc<Mutex<dyn RecordWriter + Send + Sync>>,
+    writer: Arc<Mutex<dyn RecordWriter + Send>>,
     #[allow(unused)]
     game_id: usize,
 }
 
 impl Recorder {
+    /// Return true if the games should be recorded.
+    pub fn is_enabled(config: &RecorderConfig) -> bool {
+        config.enabled.unwrap_or(false)
+    }
+
+    /// Create a recorder for a game, the first file is created with
+    /// the first record.
     pub fn init(
         spec: GameSpec,
         init_data: Vec<u8>,
         entry_type: EntryType,
         chain: ChainType,
-        in_mem: bool,
+        config: &RecorderConfig,
     ) -> (Self, RecorderContext) {
-        let writer: Arc<Mutex<dyn RecordWriter + Send + Sync>>;
         let game_id = spec.game_id;
+        let header = RecordsHeader::new(spec, init_data, entry_type, chain.to_string());
+        let options = RecorderOptions::from_config(config);
+        let writer: Arc<Mutex<dyn RecordWriter + Send>> =
+            Arc::new(Mutex::new(FileRecordWriter::new(options, header)));
 
-        let header = RecordsHeader::new(spec.clone(), init_data, entry_type, chain.to_string());
+        (
+           Self { writer: writer.clone() },
+           RecorderContext {
+               writer,
+               game_id,
+           }
+        )
+    }
 
-        if in_mem {
-            writer = Arc::new(Mutex::new(InMemoryRecordWriter::new(header)))
-        } else {
-            let dir = Path::new("records");
-            if !dir.exists() {
-                create_dir(dir).expect("Failed to create records directory");
-            }
-            let file_path = if spec.game_id == 0 {
-                format!("records/{}", spec.game_addr)
-            } else {
-                format!("records/{}_{}", spec.game_addr, spec.game_id)
-            };
-            writer = Arc::new(Mutex::new(FileRecordWriter::try_new(file_path.into(), header).expect("Fail to create record writer")))
-        }
+    #[allow(unused)]
+    fn init_in_memory(header: RecordsHeader) -> (Self, RecorderContext) {
+        let game_id = header.spec.game_id;
+        let writer: Arc<Mutex<dyn RecordWriter + Send>> =
+            Arc::new(Mutex::new(InMemoryRecordWriter::new(header)));
 
         (
            Self { writer: writer.clone() },
@@ -139,48 +345,133 @@ impl Component<ConsumerPorts, RecorderContext> for Recorder {
 
         let RecorderContext { writer, .. } = ctx;
 
+        // The file writes and the compression are blocking
+        let (record_tx, record_rx) = mpsc::channel::<Record>();
+        let write_thread = std::thread::spawn(move || {
+            for record in record_rx {
+                writer.lock().unwrap().write(record);
+            }
+            writer.lock().unwrap().close();
+        });
+
         while let Some(event_frame) = ports.recv().await {
-            info!("{} Handle event frame: {}", env.log_prefix, event_frame);
+            if matches!(event_frame, EventFrame::Shutdown) {
+                break;
+            }
+
+            if let Some(record) = frame_to_record(event_frame) {
+                if record_tx.send(record).is_err() {
+                    error!("{} Recording thread stopped", env.log_prefix);
+                    break;
+                }
+            }
+        }
 
-            let mut writer = writer.lock().await;
-            match event_frame {
+        drop(record_tx);
+        match tokio::task::spawn_blocking(move || write_thread.join()).await {
+            Ok(Ok(())) => info!("{} Recording closed", env.log_prefix),
+            _ => error!("{} Recording thread panicked", env.log_prefix),
+        }
 
-                EventFrame::Broadcast {
-                    event,
-                    timestamp,
-                    ..
-                } => {
-                    let record = Record::Event {
-                        event, timestamp
-                    };
+        CloseReason::Complete
+    }
+}
 
-                    writer.write(record);
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use race_api::event::Event;
+    use race_event_record::{open_records_file, read_records};
 
-                EventFrame::Checkpoint {
-                    checkpoint: _,
-                } => {
-                    // XXX fix recorder
-                    //
-                    // if let Some(state) = checkpoint.versioned_data(game_id).map(|vd| vd.handler_state) {
-                    //     let nodes = checkpoint.shared_data().nodes.clone();
+    fn make_header() -> RecordsHeader {
+        let spec = GameSpec {
+            game_addr: "game".into(),
+            ..Default::default()
+        };
+        RecordsHeader::new(spec, vec![], EntryType::default(), "facade".into())
+    }
 
-                    //     let record = Record::Checkpoint {
-                    //         state, nodes, access_version, settle_version, balances,
-                    //     };
+    fn make_broadcast(timestamp: u64) -> Record {
+        Record::Broadcast {
+            event: Event::GameStart,
+            timestamp,
+            state_sha: "sha".into(),
+        }
+    }
 
-                    //     writer.write(record);
-                    // }
-                }
+    #[test]
+    fn test_rotate_records() {
+        let dir = std::env::temp_dir().join(format!("race-records-{}", uuid::Uuid::new_v4()));
+        let options = RecorderOptions {
+            dir: dir.clone(),
+            max_file_bytes: 200,
+            rotate_interval: Duration::from_secs(3600),
+            compression_level: 3,
+        };
+        let mut writer = FileRecordWriter::new(options, make_header());
+        writer.write(Record::checkpoint(vec![1], vec![], vec![], 1, 1));
+        for i in 0..10 {
+            writer.write(make_broadcast(i));
+        }
+        writer.close();
 
-                EventFrame::Shutdown => {
-                    break;
-                }
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir.join("game"))
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        paths.sort();
+        assert!(paths.len() > 1);
 
-                _ => ()
+        let mut timestamps = vec![];
+        for (i, path) in paths.iter().enumerate() {
+            let (header, records) = read_records(open_records_file(path).unwrap()).unwrap();
+            assert_eq!(header.segment, i as u32);
+            // Each file can be replayed from the checkpoint
+            assert!(matches!(records[0], Record::Checkpoint { settle_version: 1, .. }));
+            for record in records {
+                if let Record::Broadcast { timestamp, .. } = record {
+                    timestamps.push(timestamp);
+                }
             }
         }
+        assert_eq!(timestamps, (0..10).collect::<Vec<u64>>());
 
-        CloseReason::Complete
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recorder_writes_on_thread() {
+        let dir = std::env::temp_dir().join(format!("race-records-{}", uuid::Uuid::new_v4()));
+        let config = RecorderConfig {
+            enabled: Some(true),
+            dir: dir.to_string_lossy().into_owned(),
+            max_file_bytes: None,
+            rotate_interval: None,
+            compression_level: None,
+        };
+        let spec = GameSpec {
+            game_addr: "game".into(),
+            ..Default::default()
+        };
+        let (recorder, ctx) = Recorder::init(spec, vec![], EntryType::default(), ChainType::Facade, &config);
+        let handle = recorder.start("game", ctx);
+        for timestamp in 0..3 {
+            handle
+                .send_unchecked(EventFrame::Broadcast {
+                    event: Event::GameStart,
+                    timestamp,
+                    state_sha: "sha".into(),
+                })
+                .await;
+        }
+        handle.send_unchecked(EventFrame::Shutdown).await;
+        handle.wait().await;
+
+        // The file is finished when the recorder is closed
+        let path = std::fs::read_dir(dir.join("game")).unwrap().next().unwrap().unwrap().path();
+        let (_, records) = read_records(open_records_file(&path).unwrap()).unwrap();
+        assert_eq!(records.len(), 3);
+
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -1,10 +1,23 @@
 //! The component to send transactions to refund invalid deposits.
+//!
+//! The deposits to refund are saved to storage first.  They are kept
+//! until the chain shows them as refunded, and the refund is sent
+//! again after failures.  The rejections received in one squash
+//! window are refunded in one transaction.
 
+use std::collections::BTreeSet;
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
-use race_core::types::{GameAccount, RejectDepositsParams};
-use tracing::{error, info};
+use race_core::storage::StorageT;
+use race_core::types::{
+    DepositStatus, GameAccount, GetPendingRefundsParams, RejectDepositsParams,
+    RemovePendingRefundsParams, SavePendingRefundsParams, TxState,
+};
+use tokio::select;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
 
 use crate::common::Component;
 use crate::event_bus::CloseReason;
@@ -14,9 +27,18 @@ use race_core::transport::TransportT;
 use super::common::PipelinePorts;
 use super::ComponentEnv;
 
+// The time window in seconds to merge the rejections into one refund.
+const REFUND_SQUASH_TIME_WINDOW: u64 = 3;
+
+// The first and the maximum interval in seconds to check the refunds,
+// and to send them again.
+const REFUND_RETRY_INTERVAL: u64 = 5;
+const REFUND_MAX_RETRY_INTERVAL: u64 = 60;
+
 pub struct RefunderContext {
     addr: String,
     transport: Arc<dyn TransportT>,
+    storage: Arc<dyn StorageT>,
 }
 
 pub struct Refunder {}
@@ -25,17 +47,127 @@ impl Refunder {
     pub fn init(
         game_account: &GameAccount,
         transport: Arc<dyn TransportT>,
+        storage: Arc<dyn StorageT>,
     ) -> (Self, RefunderContext) {
         (
             Self {},
             RefunderContext {
                 addr: game_account.addr.clone(),
                 transport,
+                storage,
             },
         )
     }
 }
 
+/// Split the pending deposits into the refunded ones and the ones to
+/// refund.  The deposits not found on chain are in neither, they are
+/// kept until the chain shows them, since the account may be stale.
+fn split_refunded(pending: &BTreeSet<u64>, game_account: &GameAccount) -> (Vec<u64>, Vec<u64>) {
+    let mut refunded = vec![];
+    let mut to_refund = vec![];
+    for access_version in pending.iter().copied() {
+        match game_account
+            .deposits
+            .iter()
+            .find(|d| d.access_version == access_version)
+        {
+            Some(d) if d.status == DepositStatus::Refunded => refunded.push(access_version),
+            Some(_) => to_refund.push(access_version),
+            None => (),
+        }
+    }
+    (refunded, to_refund)
+}
+
+/// Check the deposits on chain, and send the refund for the ones not
+/// refunded.  Return false if the refund should be retried.
+async fn refund(
+    pending: &mut BTreeSet<u64>,
+    attempts: &mut u32,
+    ports: &PipelinePorts,
+    ctx: &RefunderContext,
+    env: &ComponentEnv,
+) -> bool {
+    let game_account = match ctx.transport.get_game_account(&ctx.addr).await {
+        Ok(Some(game_account)) => game_account,
+        Ok(None) => {
+            warn!("{} Game account not found, keep the refunds", env.log_prefix);
+            return false;
+        }
+        Err(e) => {
+            warn!("{} Failed to get game account: {}", env.log_prefix, e);
+            return false;
+        }
+    };
+
+    let (refunded, to_refund) = split_refunded(pending, &game_account);
+
+    if !refunded.is_empty() {
+        info!("{} Deposits refunded: {:?}", env.log_prefix, refunded);
+        if let Err(e) = ctx
+            .storage
+            .remove_pending_refunds(RemovePendingRefundsParams {
+                game_addr: ctx.addr.clone(),
+                access_versions: refunded.clone(),
+            })
+            .await
+        {
+            error!("{} Failed to remove pending refunds: {}", env.log_prefix, e);
+        }
+        for access_version in refunded.iter() {
+            pending.remove(access_version);
+        }
+        let tx_state = TxState::DepositsRefunded {
+            access_versions: refunded,
+        };
+        ports.send(EventFrame::TxState { tx_state }).await;
+    }
+
+    if pending.is_empty() {
+        *attempts = 0;
+        return true;
+    }
+
+    if to_refund.is_empty() {
+        warn!(
+            "{} Deposits not found on chain, check again: {:?}",
+            env.log_prefix, pending
+        );
+        return false;
+    }
+
+    *attempts += 1;
+    let r = ctx
+        .transport
+        .reject_deposits(RejectDepositsParams {
+            addr: ctx.addr.clone(),
+            reject_deposits: to_refund.clone(),
+        })
+        .await;
+
+    match r {
+        Ok(_) => {
+            info!("{} Refund sent: {:?}", env.log_prefix, to_refund);
+        }
+        Err(e) => {
+            warn!(
+                "{} Error in rejecting deposits: {}, attempts = {}",
+                env.log_prefix, e, attempts
+            );
+            let tx_state = TxState::RefundRetrying {
+                access_versions: to_refund,
+                attempts: *attempts,
+                error: e.to_string(),
+            };
+            ports.send(EventFrame::TxState { tx_state }).await;
+        }
+    }
+
+    // Check again until the chain shows them as refunded
+    false
+}
+
 #[async_trait]
 impl Component<PipelinePorts, RefunderContext> for Refunder {
     fn name() -> &'static str {
@@ -43,30 +175,106 @@ impl Component<PipelinePorts, RefunderContext> for Refunder {
     }
 
     async fn run(mut ports: PipelinePorts, ctx: RefunderContext, env: ComponentEnv) -> CloseReason {
-        while let Some(event) = ports.recv().await {
-            match event {
-                EventFrame::RejectDeposits { reject_deposits } => {
-                    if let Err(e) = ctx
-                        .transport
-                        .reject_deposits(RejectDepositsParams {
-                            addr: ctx.addr.clone(),
-                            reject_deposits,
-                        })
-                        .await
-                    {
-                        error!("{} Error in rejecting deposit", e.to_string());
+        // The refunds left by the previous run
+        let mut pending: BTreeSet<u64> = match ctx
+            .storage
+            .get_pending_refunds(GetPendingRefundsParams {
+                game_addr: ctx.addr.clone(),
+            })
+            .await
+        {
+            Ok(access_versions) => access_versions.into_iter().collect(),
+            Err(e) => {
+                error!("{} Failed to load pending refunds: {}", env.log_prefix, e);
+                BTreeSet::new()
+            }
+        };
+
+        let mut attempts = 0;
+        let mut interval = REFUND_RETRY_INTERVAL;
+        let mut next_refund = if pending.is_empty() {
+            None
+        } else {
+            Some(Instant::now())
+        };
+
+        loop {
+            select! {
+                event = ports.recv() => {
+                    match event {
+                        Some(EventFrame::RejectDeposits { reject_deposits }) => {
+                            if let Err(e) = ctx
+                                .storage
+                                .save_pending_refunds(SavePendingRefundsParams {
+                                    game_addr: ctx.addr.clone(),
+                                    access_versions: reject_deposits.clone(),
+                                })
+                                .await
+                            {
+                                error!("{} Failed to save pending refunds: {}", env.log_prefix, e);
+                            }
+                            pending.extend(reject_deposits);
+                            // Merge with the rejections in the window
+                            let squash_until = Instant::now() + Duration::from_secs(REFUND_SQUASH_TIME_WINDOW);
+                            if next_refund.map_or(true, |t| t > squash_until) {
+                                next_refund = Some(squash_until);
+                            }
+                        }
+
+                        Some(EventFrame::Shutdown) | None => {
+                            info!("{} Stopped", env.log_prefix);
+                            break;
+                        }
+
+                        _ => (),
                     }
                 }
 
-                EventFrame::Shutdown => {
-                    info!("{} Stopped", env.log_prefix);
-                    break;
+                _ = tokio::time::sleep_until(next_refund.unwrap_or_else(Instant::now)), if next_refund.is_some() => {
+                    if refund(&mut pending, &mut attempts, &ports, &ctx, &env).await {
+                        next_refund = None;
+                        interval = REFUND_RETRY_INTERVAL;
+                    } else {
+                        next_refund = Some(Instant::now() + Duration::from_secs(interval));
+                        interval = (interval * 2).min(REFUND_MAX_RETRY_INTERVAL);
+                    }
                 }
-
-                _ => (),
             }
         }
 
         CloseReason::Complete
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use race_core::types::PlayerDeposit;
+
+    use super::*;
+
+    fn make_deposit(access_version: u64, status: DepositStatus) -> PlayerDeposit {
+        PlayerDeposit {
+            addr: "alice".into(),
+            amount: 100,
+            access_version,
+            settle_version: 1,
+            status,
+        }
+    }
+
+    #[test]
+    fn test_split_refunded() {
+        let game_account = GameAccount {
+            deposits: vec![
+                make_deposit(1, DepositStatus::Refunded),
+                make_deposit(2, DepositStatus::Rejected),
+                make_deposit(3, DepositStatus::Pending),
+            ],
+            ..Default::default()
+        };
+        let pending = BTreeSet::from([1, 2, 3, 4]);
+        let (refunded, to_refund) = split_refunded(&pending, &game_account);
+        assert_eq!(refunded, vec![1]);
+        assert_eq!(to_refund, vec![2, 3]);
+    }
+}
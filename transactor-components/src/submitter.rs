@@ -4,16 +4,20 @@ use std::time::Duration;
 use async_trait::async_trait;
 use race_api::types::{Settle, Transfer};
 use race_core::context::SettleDetails;
-use race_core::error::Error;
+use race_core::error::{Error, Result};
 use race_core::storage::StorageT;
-use race_core::types::{GameAccount, SaveCheckpointParams, SettleParams, SettleResult, TxState};
+use race_core::types::{
+    ConfirmSettleParams, GameAccount, GetPendingSettlesParams, SavePendingSettleParams,
+    SettleParams, SettleResult, TxState,
+};
 use race_env::SubmitterConfig;
 use tokio::select;
-use tokio::sync::mpsc;
-use tracing::{error, info};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{error, info, info_span, warn, Instrument, Span};
 
 use crate::common::Component;
 use crate::event_bus::CloseReason;
+use crate::wrapped_transport::{SettleProgress, WrappedTransport};
 use race_transactor_frames::EventFrame;
 use race_core::transport::TransportT;
 
@@ -29,6 +33,10 @@ const DEFAULT_SUBMITTER_SQUASH_COMPLEXITY_LIMIT: usize = 50;
 // The default for size of transcation queue.
 const DEFAULT_SUBMITTER_TX_QUEUE_SIZE: usize = 100;
 
+// The attempts to send a pending settlement before the game is
+// loaded, so an outage doesn't block the loading.
+const REPLAY_SETTLE_ATTEMPTS: u32 = 3;
+
 fn merge_transfers(a: Option<Transfer>, b: Option<Transfer>) -> Option<Transfer> {
     match (a, b) {
         (Some(x1), Some(x2)) => Some(Transfer {
@@ -110,10 +118,10 @@ fn squash_settles(mut prev: SettleParams, next: SettleParams) -> SettleParams {
 // accumulating them into a vector. Stops reading on delivering a certain number
 // of settle complexity, encountering a params with non-empty `settles`, or a timeout.
 async fn read_settle_params(
-    rx: &mut mpsc::Receiver<SettleParams>,
+    rx: &mut mpsc::Receiver<(SettleParams, Span)>,
     squash_limit: usize,
     squash_time_window: u64,
-) -> Vec<SettleParams> {
+) -> Vec<(SettleParams, Span)> {
     let mut v = vec![];
     let mut cnt = 0;
 
@@ -124,13 +132,13 @@ async fn read_settle_params(
 
         select! {
             p = rx.recv() => {
-                if let Some(p) = p {
+                if let Some((p, span)) = p {
                     cnt += 1;
                     let has_payment = p.settles.iter().find(|s| s.eject || s.withdraw != 0).is_some();
                     // We terminate when there are non-empty settles/awards
                     // or we are making the first checkpoint
                     let stop_here = has_payment || (!p.awards.is_empty()) || p.next_settle_version == 1;
-                    v.push(p);
+                    v.push((p, span));
                     if stop_here {
                         break;
                     }
@@ -151,10 +159,100 @@ async fn read_settle_params(
     v
 }
 
+/// Send the settlements saved but not confirmed, which are left by a
+/// crash.  Must be called before the game is loaded, so the game
+/// starts from the checkpoint of the last settlement.
+///
+/// A settlement is sent only when its settle version matches the one
+/// on chain.  The ones before it have landed already.  A settlement
+/// is sent a few times at most.  The errors are logged, and the game
+/// starts from the checkpoint on chain.
+pub async fn replay_pending_settles(
+    game_addr: &str,
+    transport: &WrappedTransport,
+    storage: &dyn StorageT,
+) {
+    if let Err(e) = try_replay_pending_settles(game_addr, transport, storage).await {
+        error!("Failed to replay pending settles for game {}: {}", game_addr, e);
+    }
+}
+
+async fn try_replay_pending_settles(
+    game_addr: &str,
+    transport: &WrappedTransport,
+    storage: &dyn StorageT,
+) -> Result<()> {
+    let pending = storage
+        .get_pending_settles(GetPendingSettlesParams {
+            game_addr: game_addr.to_string(),
+        })
+        .await?;
+
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let Some(game_account) = transport.get_game_account(game_addr).await? else {
+        return Err(Error::GameAccountNotFound);
+    };
+    let mut settle_version = game_account.settle_version;
+
+    for params in pending {
+        let next_settle_version = params.next_settle_version;
+        if next_settle_version <= settle_version {
+            // Landed before the crash
+        } else if params.settle_version == settle_version {
+            info!(
+                "Replay pending settle for game {}, settle_version = {}",
+                game_addr, settle_version
+            );
+            transport
+                .settle_game_with_attempts(params, Some(REPLAY_SETTLE_ATTEMPTS))
+                .await?;
+            settle_version = next_settle_version;
+        } else {
+            warn!(
+                "Skip pending settle for game {}, settle_version = {}, on chain = {}",
+                game_addr, params.settle_version, settle_version
+            );
+            break;
+        }
+        storage
+            .confirm_settle(ConfirmSettleParams {
+                game_addr: game_addr.to_string(),
+                next_settle_version,
+            })
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Receive the progress of the settlements of `game_addr`.  Wait
+/// forever if there's no progress to receive.
+async fn recv_settle_progress(
+    rx: &mut Option<broadcast::Receiver<SettleProgress>>,
+    game_addr: &str,
+) -> TxState {
+    if let Some(rx) = rx {
+        loop {
+            match rx.recv().await {
+                Ok(SettleProgress { game_addr: addr, tx_state }) if addr == game_addr => {
+                    return tx_state;
+                }
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+    std::future::pending().await
+}
+
 pub struct SubmitterContext {
     addr: String,
     transport: Arc<dyn TransportT>,
     storage: Arc<dyn StorageT>,
+    settle_progress: Option<broadcast::Receiver<SettleProgress>>,
     squash_time_window: u64,
     squash_limit: usize,
     tx_queue_size: usize,
@@ -163,10 +261,13 @@ pub struct SubmitterContext {
 pub struct Submitter {}
 
 impl Submitter {
+    /// The retries of the settlements are made by the transport, its
+    /// `settle_progress` is published as [TxState].
     pub fn init(
         game_account: &GameAccount,
         transport: Arc<dyn TransportT>,
         storage: Arc<dyn StorageT>,
+        settle_progress: Option<broadcast::Receiver<SettleProgress>>,
         config: Option<&SubmitterConfig>,
     ) -> (Self, SubmitterContext) {
         let squash_time_window = config
@@ -184,6 +285,7 @@ impl Submitter {
                 addr: game_account.addr.clone(),
                 transport,
                 storage,
+                settle_progress,
                 squash_time_window,
                 squash_limit,
                 tx_queue_size,
@@ -203,9 +305,12 @@ impl Component<PipelinePorts, SubmitterContext> for Submitter {
         ctx: SubmitterContext,
         env: ComponentEnv,
     ) -> CloseReason {
-        let (queue_tx, mut queue_rx) = mpsc::channel::<SettleParams>(ctx.tx_queue_size);
+        let (queue_tx, mut queue_rx) = mpsc::channel::<(SettleParams, Span)>(ctx.tx_queue_size);
         let p = ports.clone_as_producer();
         let log_prefix = env.log_prefix.clone();
+        let game_addr = ctx.addr.clone();
+        let storage = ctx.storage.clone();
+        let mut settle_progress = ctx.settle_progress;
         // Start a task to handle settlements
         // Prevent the blocking from pending transactions
         let join_handle = tokio::spawn(async move {
@@ -214,22 +319,64 @@ impl Component<PipelinePorts, SubmitterContext> for Submitter {
                     read_settle_params(&mut queue_rx, ctx.squash_limit, ctx.squash_time_window)
                         .await;
                 info!("{} Squash {} transactions", log_prefix, ps.len());
+                let (ps, mut spans): (Vec<SettleParams>, Vec<Span>) = ps.into_iter().unzip();
                 if let Some(params) = ps.into_iter().reduce(squash_settles) {
                     let settle_version = params.settle_version;
-                    let res = ctx.transport.settle_game(params).await;
+                    let next_settle_version = params.next_settle_version;
+                    // A child of the latest settlement, linked to the
+                    // squashed ones
+                    let parent = spans.pop().unwrap_or_else(Span::none);
+                    let span = info_span!(
+                        parent: &parent,
+                        "settle_game",
+                        game_addr = %game_addr,
+                        settle_version,
+                        next_settle_version,
+                        squashed = spans.len(),
+                    );
+                    for s in spans.iter() {
+                        span.follows_from(s);
+                    }
+                    let settle = ctx.transport.settle_game(params).instrument(span.clone());
+                    tokio::pin!(settle);
+                    let res = loop {
+                        select! {
+                            res = &mut settle => break res,
+                            tx_state = recv_settle_progress(&mut settle_progress, &game_addr) => {
+                                p.send(EventFrame::TxState { tx_state })
+                                    .instrument(span.clone())
+                                    .await;
+                            }
+                        }
+                    };
                     match res {
                         Ok(SettleResult { signature, .. }) => {
+                            if let Err(e) = storage
+                                .confirm_settle(ConfirmSettleParams {
+                                    game_addr: game_addr.clone(),
+                                    next_settle_version,
+                                })
+                                .await
+                            {
+                                error!("{} Submitter failed to confirm settle: {}", log_prefix, e);
+                            }
+                            // The game is recovered from this checkpoint
+                            // from now on, the journal before it is truncated
+                            p.send(EventFrame::SettleConfirmed {
+                                settle_version: next_settle_version,
+                            })
+                            .instrument(span.clone())
+                            .await;
                             let tx_state = TxState::SettleSucceed {
-                                signature: if signature.is_empty() {
-                                    None
-                                } else {
-                                    Some(signature)
-                                },
+                                signature: (!signature.is_empty()).then_some(signature),
                                 settle_version,
                             };
-                            p.send(EventFrame::TxState { tx_state }).await;
+                            p.send(EventFrame::TxState { tx_state })
+                                .instrument(span)
+                                .await;
                         }
                         Err(e) => {
+                            error!("{} Settle failed: {}", log_prefix, e);
                             return CloseReason::Fault(e);
                         }
                     }
@@ -261,45 +408,45 @@ impl Component<PipelinePorts, SubmitterContext> for Submitter {
                     let checkpoint_onchain = checkpoint.derive_onchain_part();
                     let checkpoint_offchain = checkpoint.derive_offchain_part();
 
+                    let settle_params = SettleParams {
+                        addr: ctx.addr.clone(),
+                        settles,
+                        transfer,
+                        awards,
+                        checkpoint: checkpoint_onchain,
+                        access_version,
+                        settle_version: previous_settle_version,
+                        next_settle_version: settle_version,
+                        entry_lock,
+                        accept_deposits,
+                    };
+
                     info!(
                         "{} Submitter save checkpoint to storage, settle_version = {}",
                         env.log_prefix, settle_version
                     );
 
-                    let save_checkpoint_params = SaveCheckpointParams {
-                        game_addr: ctx.addr.clone(),
-                        settle_version,
-                        checkpoint: checkpoint_offchain,
-                    };
-
-                    let save_checkpoint_result = ctx
+                    // Write ahead with the checkpoint, the settlement
+                    // is replayed if we crash before it lands
+                    let save_settle_result = ctx
                         .storage
-                        .save_checkpoint(save_checkpoint_params)
+                        .save_pending_settle(SavePendingSettleParams {
+                            game_addr: ctx.addr.clone(),
+                            checkpoint: checkpoint_offchain,
+                            settle_params: settle_params.clone(),
+                        })
                         .await;
 
-                    if let Err(e) = save_checkpoint_result {
+                    if let Err(e) = save_settle_result {
                         error!(
-                            "{} Submitter failed to save checkpoint offchain: {}",
+                            "{} Submitter failed to save checkpoint and pending settle: {}",
                             env.log_prefix,
                             e.to_string()
                         );
                         break;
                     }
 
-                    let res = queue_tx
-                        .send(SettleParams {
-                            addr: ctx.addr.clone(),
-                            settles,
-                            transfer,
-                            awards,
-                            checkpoint: checkpoint_onchain,
-                            access_version,
-                            settle_version: previous_settle_version,
-                            next_settle_version: settle_version,
-                            entry_lock,
-                            accept_deposits,
-                        })
-                        .await;
+                    let res = queue_tx.send((settle_params, ports.span().clone())).await;
                     if let Err(e) = res {
                         error!(
                             "{} Submitter failed to send settle to task queue: {}",
@@ -329,8 +476,75 @@ impl Component<PipelinePorts, SubmitterContext> for Submitter {
 #[cfg(test)]
 mod tests {
 
+    use race_local_db::LocalDbStorage;
+    use race_test::prelude::{DummyTransport, TestGameAccountBuilder};
+
+    use crate::common::PortsHandle;
+
     use super::*;
 
+    fn start_submitter(transport: DummyTransport) -> PortsHandle {
+        let game_account = TestGameAccountBuilder::new().build();
+        let storage = Arc::new(LocalDbStorage::try_new_mem().unwrap());
+        let config = SubmitterConfig {
+            squash_time_window: Some(0),
+            squash_limit: Some(1),
+            tx_queue_size: None,
+        };
+        let (submitter, ctx) =
+            Submitter::init(&game_account, Arc::new(transport), storage, None, Some(&config));
+        submitter.start(&game_account.addr, ctx)
+    }
+
+    fn make_settle(settle_version: u64) -> EventFrame {
+        EventFrame::Settle {
+            settle_details: Box::new(SettleDetails {
+                settle_version,
+                previous_settle_version: settle_version - 1,
+                ..Default::default()
+            }),
+        }
+    }
+
+    async fn recv_all(mut handle: PortsHandle) -> Vec<EventFrame> {
+        handle.send_unchecked(EventFrame::Shutdown).await;
+        let mut frames = vec![];
+        while let Some(frame) = handle.recv_unchecked().await {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    #[tokio::test]
+    async fn test_settle_confirmed_after_landed() {
+        let handle = start_submitter(DummyTransport::default());
+        handle.send_unchecked(make_settle(2)).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let frames = recv_all(handle).await;
+        assert!(matches!(
+            frames.as_slice(),
+            [
+                EventFrame::SettleConfirmed { settle_version: 2 },
+                EventFrame::TxState { tx_state: TxState::SettleSucceed { settle_version: 1, .. } },
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_no_settle_confirmed_before_landed() {
+        // The process may crash in this window, the game is then
+        // recovered from the previous checkpoint with its journal
+        let mut transport = DummyTransport::default();
+        transport.fail_next_settle();
+        let handle = start_submitter(transport);
+        handle.send_unchecked(make_settle(2)).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let frames = recv_all(handle).await;
+        assert!(!frames.iter().any(|f| matches!(f, EventFrame::SettleConfirmed { .. })));
+    }
+
     #[test]
     fn test_merge_settle() {
         let mut settles1 = vec![Settle {
@@ -4,14 +4,15 @@ use std::pin::Pin;
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use futures::pin_mut;
 use futures::StreamExt;
 use race_core::types::BroadcastFrame;
 use race_core::types::BroadcastSync;
 use race_core::types::DepositStatus;
+use race_core::types::{EventCursor, SubscribeEventParamsV2};
 use race_core::types::VoteType;
 use race_core::types::{GameAccount, ServerAccount};
 use tokio::select;
+use tokio::time::{sleep, Duration};
 use tracing::error;
 use tracing::info;
 use tracing::warn;
@@ -23,6 +24,11 @@ use super::common::PipelinePorts;
 use super::ComponentEnv;
 use super::{event_bus::CloseReason, RemoteConnection};
 
+// The attempts in a row to resume a closed subscription.
+const MAX_RESUME_ATTEMPTS: u32 = 5;
+// The delay before the first resume attempt, doubled on each failure.
+const RESUME_BACKOFF: Duration = Duration::from_secs(1);
+
 pub struct SubscriberContext {
     game_addr: String,
     #[allow(unused)]
@@ -58,13 +64,13 @@ impl Subscriber {
 
 async fn handle_frame(frame: BroadcastFrame, ports: &mut PipelinePorts, env: &ComponentEnv) -> Pin<Box<Option<CloseReason>>> {
     let ret = match frame {
-        // Forward event to event bus
+        // Forward event to event bus, the state is verified after replay
         BroadcastFrame::Event {
-            event, timestamp, ..
+            event, timestamp, state_sha,
         } => {
             info!("{} Receive event: {}", env.log_prefix, event);
             if let Err(e) = ports
-                .try_send(EventFrame::SendServerEvent { event, timestamp })
+                .try_send(EventFrame::ReplayEvent { event, timestamp, state_sha })
                 .await
             {
                 error!("Send server event error: {}", e);
@@ -106,13 +112,24 @@ async fn handle_frame(frame: BroadcastFrame, ports: &mut PipelinePorts, env: &Co
             }
         }
 
-        BroadcastFrame::Message { .. } => {
+        BroadcastFrame::Message { .. }
+        | BroadcastFrame::ChatMessage { .. }
+        | BroadcastFrame::ChatHistory { .. } => {
             None
         }
         BroadcastFrame::TxState { .. } => {
             None
         }
-        BroadcastFrame::Backlogs { backlogs, .. } => {
+        // The game is served by another transactor now, the promoter
+        // relaunches the validator to follow it
+        BroadcastFrame::Reconnect { transactor_addr, endpoint } => {
+            warn!("{} Transactor changed to {}", env.log_prefix, transactor_addr);
+            ports
+                .send(EventFrame::TransactorChanged { transactor_addr, endpoint })
+                .await;
+            Some(CloseReason::Complete)
+        }
+        BroadcastFrame::Backlogs { backlogs, .. } | BroadcastFrame::Resume { backlogs } => {
             info!(
                 "{} Receive event backlogs: {}",
                 env.log_prefix,
@@ -130,6 +147,79 @@ async fn handle_frame(frame: BroadcastFrame, ports: &mut PipelinePorts, env: &Co
     Box::pin(ret)
 }
 
+fn count_events(frames: &[BroadcastFrame]) -> u64 {
+    frames
+        .iter()
+        .filter(|f| matches!(f, BroadcastFrame::Event { .. }))
+        .count() as u64
+}
+
+/// Track the position in the event stream, to resume the subscription.
+fn update_cursor(cursor: &mut Option<EventCursor>, frame: &BroadcastFrame) {
+    match frame {
+        BroadcastFrame::Backlogs {
+            checkpoint_off_chain,
+            backlogs,
+            ..
+        } => {
+            *cursor = checkpoint_off_chain.as_ref().map(|c| EventCursor {
+                settle_version: c.root_data.versions.settle_version,
+                event_index: count_events(backlogs),
+            });
+        }
+        BroadcastFrame::Resume { backlogs } => {
+            if let Some(c) = cursor {
+                c.event_index += count_events(backlogs);
+            }
+        }
+        BroadcastFrame::Event { .. } => {
+            if let Some(c) = cursor {
+                c.event_index += 1;
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Sleep for `delay`, return false if the subscriber is shut down
+/// meanwhile.
+async fn sleep_unless_shutdown(ports: &mut PipelinePorts, delay: Duration) -> bool {
+    let wake = sleep(delay);
+    tokio::pin!(wake);
+    loop {
+        select! {
+            _ = &mut wake => return true,
+            frame = ports.recv() => {
+                if matches!(frame, Some(EventFrame::Shutdown) | None) {
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+/// Recover the checkpoint in a `Backlogs` frame, to resync when the
+/// cursor has aged out.  Wait until it's recovered, so the backlogs
+/// are replayed on it.  Return false if the subscriber is shut down
+/// meanwhile.
+async fn recover_backlogs_checkpoint(frame: &BroadcastFrame, ports: &mut PipelinePorts) -> bool {
+    let BroadcastFrame::Backlogs { checkpoint_off_chain: Some(checkpoint), .. } = frame else {
+        return true;
+    };
+    ports
+        .send(EventFrame::RecoverCheckpoint {
+            checkpoint: checkpoint.to_context_checkpoint(),
+        })
+        .await;
+    loop {
+        match ports.recv().await {
+            Some(EventFrame::RecoverCheckpointWithCredentials { .. }) => return true,
+            Some(EventFrame::Shutdown) | None => return false,
+            _ => (),
+        }
+    }
+}
+
 #[async_trait]
 impl Component<PipelinePorts, SubscriberContext> for Subscriber {
     fn name() -> &'static str {
@@ -160,9 +250,14 @@ impl Component<PipelinePorts, SubscriberContext> for Subscriber {
         }
 
         let mut retries = 0;
+        let params = SubscribeEventParamsV2 {
+            settle_version: start_settle_version,
+            resume: None,
+            filter: None,
+        };
         let sub = loop {
             match connection
-                .subscribe_events(&game_addr, start_settle_version)
+                .subscribe_events(&game_addr, params.clone())
                 .await
             {
                 Ok(sub) => break sub,
@@ -195,7 +290,8 @@ impl Component<PipelinePorts, SubscriberContext> for Subscriber {
         };
 
         info!("{} Subscription established", env.log_prefix);
-        pin_mut!(sub);
+        let mut sub = Box::pin(sub);
+        let mut cursor: Option<EventCursor> = None;
 
         loop {
             select! {
@@ -210,10 +306,61 @@ impl Component<PipelinePorts, SubscriberContext> for Subscriber {
                 }
 
                 frame = sub.next() => {
-                    let Some(frame) = frame else {
-                        break;
+                    let frame = match frame {
+                        Some(frame) => frame,
+                        None => {
+                            // Resume with a backoff, the events already
+                            // handled must not be received again.
+                            let Some(resume) = cursor.clone() else {
+                                break;
+                            };
+                            let params = SubscribeEventParamsV2 {
+                                resume: Some(resume),
+                                ..params.clone()
+                            };
+                            let mut resumed = None;
+                            for attempt in 0..MAX_RESUME_ATTEMPTS {
+                                let delay = RESUME_BACKOFF * 2u32.pow(attempt);
+                                warn!(
+                                    "{} Subscription closed, resume from {:?} in {:?}",
+                                    env.log_prefix, params.resume, delay
+                                );
+                                if !sleep_unless_shutdown(&mut ports, delay).await {
+                                    info!("{} Stopped", env.log_prefix);
+                                    return CloseReason::Complete;
+                                }
+                                let Ok(new_sub) = connection.subscribe_events(&game_addr, params.clone()).await else {
+                                    continue;
+                                };
+                                let mut new_sub = Box::pin(new_sub);
+                                match new_sub.next().await {
+                                    Some(frame @ BroadcastFrame::Resume { .. }) => {
+                                        resumed = Some((new_sub, frame));
+                                        break;
+                                    }
+                                    // The cursor has aged out, resync from the checkpoint
+                                    Some(frame @ BroadcastFrame::Backlogs { .. }) => {
+                                        warn!("{} Resume cursor aged out, resync from the checkpoint", env.log_prefix);
+                                        if !recover_backlogs_checkpoint(&frame, &mut ports).await {
+                                            info!("{} Stopped", env.log_prefix);
+                                            return CloseReason::Complete;
+                                        }
+                                        resumed = Some((new_sub, frame));
+                                        break;
+                                    }
+                                    Some(_) | None => continue,
+                                }
+                            }
+                            let Some((new_sub, frame)) = resumed else {
+                                break;
+                            };
+                            sub = new_sub;
+                            frame
+                        }
                     };
 
+                    update_cursor(&mut cursor, &frame);
+
                     if let Some(close_reason) = *Pin::into_inner(handle_frame(frame, &mut ports, &env).await) {
                         return close_reason;
                     }
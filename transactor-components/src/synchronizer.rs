@@ -8,7 +8,7 @@ use tokio_stream::StreamExt;
 use race_transactor_frames::EventFrame;
 use race_core::{
     transport::TransportT,
-    types::{GameAccount, PlayerDeposit, PlayerJoin, ServerJoin},
+    types::{ClientMode, GameAccount, PlayerDeposit, PlayerJoin, ServerJoin},
 };
 use tracing::{error, info, warn};
 
@@ -20,6 +20,80 @@ pub struct GameSynchronizerContext {
     transport: Arc<dyn TransportT>,
     access_version: u64,
     game_addr: String,
+    server_addr: String,
+    // The transactor this node follows, in validator mode
+    transactor_addr: String,
+    client_mode: ClientMode,
+}
+
+/// Find the endpoint of a transactor, an empty string if it's not
+/// found.
+async fn get_endpoint(
+    transactor_addr: &str,
+    ctx: &GameSynchronizerContext,
+    env: &ComponentEnv,
+) -> String {
+    match ctx.transport.get_server_account(transactor_addr).await {
+        Ok(Some(server_account)) => server_account.endpoint,
+        _ => {
+            warn!("{} Endpoint of the new transactor {} not found", env.log_prefix, transactor_addr);
+            "".to_string()
+        }
+    }
+}
+
+/// Check the transactor of the game.  A transactor steps down when
+/// the game is taken over by another server.  A validator takes over
+/// when it's the new transactor, otherwise it follows the new
+/// transactor.  Return true if the synchronizer should stop.
+async fn check_transactor(
+    game_account: &GameAccount,
+    ctx: &GameSynchronizerContext,
+    ports: &mut PipelinePorts,
+    env: &ComponentEnv,
+) -> bool {
+    let Some(transactor_addr) = game_account.transactor_addr.as_ref() else {
+        return false;
+    };
+
+    match ctx.client_mode {
+        ClientMode::Transactor if transactor_addr.ne(&ctx.server_addr) => {
+            let endpoint = get_endpoint(transactor_addr, ctx, env).await;
+            warn!("{} Game is taken over by {}, shutdown", env.log_prefix, transactor_addr);
+            ports
+                .send(EventFrame::TransactorChanged {
+                    transactor_addr: transactor_addr.clone(),
+                    endpoint,
+                })
+                .await;
+            ports.send(EventFrame::Shutdown).await;
+            true
+        }
+        ClientMode::Validator if transactor_addr.eq(&ctx.server_addr) => {
+            info!(
+                "{} Become the transactor at settle_version = {}, take over the game",
+                env.log_prefix, game_account.settle_version
+            );
+            ports
+                .send(EventFrame::Takeover {
+                    settle_version: game_account.settle_version,
+                })
+                .await;
+            true
+        }
+        ClientMode::Validator if transactor_addr.ne(&ctx.transactor_addr) => {
+            let endpoint = get_endpoint(transactor_addr, ctx, env).await;
+            info!("{} Game is taken over by {}", env.log_prefix, transactor_addr);
+            ports
+                .send(EventFrame::TransactorChanged {
+                    transactor_addr: transactor_addr.clone(),
+                    endpoint,
+                })
+                .await;
+            true
+        }
+        _ => false,
+    }
 }
 
 async fn maybe_send_sync(
@@ -114,12 +188,20 @@ async fn maybe_send_sync(
 
 /// A component that reads the on-chain states and feeds the system.
 /// To construct a synchronizer, a chain adapter is required.
+///
+/// In validator mode, the node updates come from the transactor, so
+/// the synchronizer only watches the transactor address, to take over
+/// the game or follow the new transactor, and the settle version, to
+/// drop the settled checkpoints.
 pub struct GameSynchronizer {}
 
 impl GameSynchronizer {
     pub fn init(
         transport: Arc<dyn TransportT>,
         game_addr: &str,
+        server_addr: &str,
+        transactor_addr: &str,
+        client_mode: ClientMode,
         checkpoint_access_version: u64,
     ) -> (Self, GameSynchronizerContext) {
         (
@@ -127,6 +209,9 @@ impl GameSynchronizer {
             GameSynchronizerContext {
                 transport,
                 game_addr: game_addr.to_string(),
+                server_addr: server_addr.to_string(),
+                transactor_addr: transactor_addr.to_string(),
+                client_mode,
                 access_version: checkpoint_access_version,
             },
         )
@@ -146,6 +231,7 @@ impl Component<PipelinePorts, GameSynchronizerContext> for GameSynchronizer {
         env: ComponentEnv,
     ) -> CloseReason {
         let mut prev_access_version = ctx.access_version;
+        let mut prev_settle_version = 0;
 
         info!("{} Synchronizer starts with access_version = {}", env.log_prefix, prev_access_version);
 
@@ -163,7 +249,12 @@ impl Component<PipelinePorts, GameSynchronizerContext> for GameSynchronizer {
 
         // Do a first query, to handle those transactions made when our transactor is offline.
         let account = ctx.transport.get_game_account(&ctx.game_addr).await;
-        if let Ok(Some(game_account)) = account {
+        if let Ok(Some(ref game_account)) = account {
+            if check_transactor(game_account, &ctx, &mut ports, &env).await {
+                return CloseReason::Complete;
+            }
+        }
+        if let (Ok(Some(game_account)), ClientMode::Transactor) = (account, ctx.client_mode) {
             let (new_access_version, close_reason) = maybe_send_sync(
                 prev_access_version,
                 game_account,
@@ -200,6 +291,21 @@ impl Component<PipelinePorts, GameSynchronizerContext> for GameSynchronizer {
                             info!("{} Get account from subscription, access_version = {}, previous access version = {}",
                                   env.log_prefix, game_account.access_version, prev_access_version);
 
+                            if check_transactor(&game_account, &ctx, &mut ports, &env).await {
+                                return CloseReason::Complete;
+                            }
+                            if ctx.client_mode != ClientMode::Transactor {
+                                if game_account.settle_version > prev_settle_version {
+                                    prev_settle_version = game_account.settle_version;
+                                    ports
+                                        .send(EventFrame::SettleVersionChanged {
+                                            settle_version: prev_settle_version,
+                                        })
+                                        .await;
+                                }
+                                continue;
+                            }
+
                             let (new_access_version, close_reason) = maybe_send_sync(
                                 prev_access_version,
                                 game_account,
@@ -0,0 +1,363 @@
+//! The components to take over a game when the transactor is voted
+//! out and this node is the next transactor.
+//!
+//! - [Promoter] runs in validator mode.  It keeps the checkpoints
+//!   which are not settled on chain, with the events replayed after
+//!   each of them.  On [EventFrame::Takeover], it picks the checkpoint
+//!   at the on-chain settle version, asks the transactor to relaunch
+//!   the game in transactor mode through [SignalFrame::Takeover], and
+//!   stops the validator.  When another server takes over the game,
+//!   it asks to relaunch the validator with
+//!   [SignalFrame::FollowTransactor].
+//! - [HistoryReplayer] runs in the relaunched game.  It replays the
+//!   events after the checkpoint is recovered, so the game continues
+//!   where the previous transactor stopped.
+
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use race_api::event::Event;
+use race_core::chain::ChainType;
+use race_core::checkpoint::ContextCheckpoint;
+use race_transactor_frames::{EventFrame, SignalFrame};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+use crate::common::{Component, PipelinePorts};
+use crate::event_bus::CloseReason;
+use crate::ComponentEnv;
+
+/// The checkpoints which are not settled on chain, and the events
+/// replayed after them.  Both are keyed by settle version, since the
+/// checkpoints and the events arrive in an arbitrary order.
+#[derive(Default)]
+pub struct CheckpointHistory {
+    checkpoints: BTreeMap<u64, ContextCheckpoint>,
+    events: BTreeMap<u64, Vec<(Event, u64)>>,
+}
+
+impl CheckpointHistory {
+    pub fn push_checkpoint(&mut self, checkpoint: ContextCheckpoint) {
+        let settle_version = checkpoint.root_data().versions.settle_version;
+        self.checkpoints.insert(settle_version, checkpoint);
+    }
+
+    /// Add an event replayed after the checkpoint at `settle_version`.
+    pub fn push_event(&mut self, settle_version: u64, event: Event, timestamp: u64) {
+        self.events
+            .entry(settle_version)
+            .or_default()
+            .push((event, timestamp));
+    }
+
+    /// Drop the checkpoints and the events before `settle_version`,
+    /// which is settled on chain.
+    pub fn prune(&mut self, settle_version: u64) {
+        self.checkpoints = self.checkpoints.split_off(&settle_version);
+        self.events = self.events.split_off(&settle_version);
+    }
+
+    /// Take the checkpoint at `settle_version`, with all the events
+    /// after it.
+    pub fn take(&mut self, settle_version: u64) -> Option<(ContextCheckpoint, Vec<(Event, u64)>)> {
+        let checkpoint = self.checkpoints.remove(&settle_version)?;
+        let history = std::mem::take(&mut self.events)
+            .into_iter()
+            .filter(|(v, _)| *v >= settle_version)
+            .flat_map(|(_, events)| events)
+            .collect();
+        self.checkpoints.clear();
+        Some((checkpoint, history))
+    }
+}
+
+pub struct PromoterContext {
+    game_addr: String,
+    chain: ChainType,
+    server_addr: String,
+    signal_tx: mpsc::Sender<SignalFrame>,
+}
+
+pub struct Promoter {}
+
+impl Promoter {
+    pub fn init(
+        game_addr: &str,
+        chain: ChainType,
+        server_addr: &str,
+        signal_tx: mpsc::Sender<SignalFrame>,
+    ) -> (Self, PromoterContext) {
+        (
+            Self {},
+            PromoterContext {
+                game_addr: game_addr.to_string(),
+                chain,
+                server_addr: server_addr.to_string(),
+                signal_tx,
+            },
+        )
+    }
+}
+
+#[async_trait]
+impl Component<PipelinePorts, PromoterContext> for Promoter {
+    fn name() -> &'static str {
+        "Promoter"
+    }
+
+    async fn run(mut ports: PipelinePorts, ctx: PromoterContext, env: ComponentEnv) -> CloseReason {
+        let mut history = CheckpointHistory::default();
+
+        while let Some(frame) = ports.recv().await {
+            match frame {
+                EventFrame::RecoverCheckpoint { checkpoint }
+                | EventFrame::RecoverCheckpointWithCredentials { checkpoint }
+                | EventFrame::Checkpoint { checkpoint } => {
+                    history.push_checkpoint(checkpoint);
+                }
+                EventFrame::EventReplayed {
+                    event,
+                    timestamp,
+                    settle_version,
+                } => {
+                    history.push_event(settle_version, event, timestamp);
+                }
+                EventFrame::SettleVersionChanged { settle_version } => {
+                    history.prune(settle_version);
+                }
+                EventFrame::Takeover { settle_version } => {
+                    let Some((checkpoint, history)) = history.take(settle_version) else {
+                        error!(
+                            "{} No checkpoint at settle_version = {} to take over the game",
+                            env.log_prefix, settle_version
+                        );
+                        ports.send(EventFrame::Shutdown).await;
+                        break;
+                    };
+                    info!(
+                        "{} Take over the game with {} events after the checkpoint",
+                        env.log_prefix,
+                        history.len()
+                    );
+                    // Send the signal before the validator stops, so
+                    // the game is relaunched in place.
+                    if let Err(e) = ctx
+                        .signal_tx
+                        .send(SignalFrame::Takeover {
+                            game_addr: ctx.game_addr.clone(),
+                            chain: ctx.chain,
+                            checkpoint,
+                            history,
+                        })
+                        .await
+                    {
+                        error!("{} Failed to send Takeover signal: {}", env.log_prefix, e);
+                    }
+                    ports.send(EventFrame::Shutdown).await;
+                    break;
+                }
+                // This server takes over the game with the Takeover frame
+                EventFrame::TransactorChanged { transactor_addr, .. }
+                    if transactor_addr.ne(&ctx.server_addr) =>
+                {
+                    info!(
+                        "{} Game is taken over by {}, follow the new transactor",
+                        env.log_prefix, transactor_addr
+                    );
+                    if let Err(e) = ctx
+                        .signal_tx
+                        .send(SignalFrame::FollowTransactor {
+                            game_addr: ctx.game_addr.clone(),
+                            chain: ctx.chain,
+                        })
+                        .await
+                    {
+                        error!("{} Failed to send FollowTransactor signal: {}", env.log_prefix, e);
+                    }
+                    ports.send(EventFrame::Shutdown).await;
+                    break;
+                }
+                EventFrame::Shutdown => {
+                    warn!("{} Shutdown promoter", env.log_prefix);
+                    break;
+                }
+                _ => (),
+            }
+        }
+
+        CloseReason::Complete
+    }
+}
+
+pub struct HistoryReplayerContext {
+    history: Vec<(Event, u64)>,
+}
+
+pub struct HistoryReplayer {}
+
+impl HistoryReplayer {
+    pub fn init(history: Vec<(Event, u64)>) -> (Self, HistoryReplayerContext) {
+        (Self {}, HistoryReplayerContext { history })
+    }
+}
+
+#[async_trait]
+impl Component<PipelinePorts, HistoryReplayerContext> for HistoryReplayer {
+    fn name() -> &'static str {
+        "History Replayer"
+    }
+
+    async fn run(mut ports: PipelinePorts, ctx: HistoryReplayerContext, env: ComponentEnv) -> CloseReason {
+        // The events are handled after the checkpoint is recovered
+        loop {
+            match ports.recv().await {
+                Some(EventFrame::RecoverCheckpointWithCredentials { .. }) => break,
+                Some(EventFrame::Shutdown) | None => return CloseReason::Complete,
+                _ => (),
+            }
+        }
+
+        info!("{} Replay {} events", env.log_prefix, ctx.history.len());
+        for (event, timestamp) in ctx.history.into_iter() {
+            ports.send(EventFrame::SendServerEvent { event, timestamp }).await;
+        }
+
+        CloseReason::Complete
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use race_core::checkpoint::VersionedData;
+    use race_core::game_spec::GameSpec;
+    use race_core::versions::Versions;
+
+    use crate::common::PortsHandle;
+
+    use super::*;
+
+    fn make_checkpoint(settle_version: u64) -> ContextCheckpoint {
+        let root_data = VersionedData::new(
+            GameSpec::default(),
+            Versions::new(1, settle_version),
+            vec![],
+        );
+        ContextCheckpoint::new(Default::default(), root_data)
+    }
+
+    #[test]
+    fn test_checkpoint_history() {
+        let mut history = CheckpointHistory::default();
+        history.push_checkpoint(make_checkpoint(1));
+        // The events may arrive before their checkpoints
+        history.push_event(1, Event::GameStart, 1);
+        history.push_event(2, Event::WaitingTimeout, 2);
+        history.push_checkpoint(make_checkpoint(2));
+        history.push_event(3, Event::DrawTimeout, 3);
+        history.push_checkpoint(make_checkpoint(3));
+        history.push_checkpoint(make_checkpoint(4));
+
+        let (checkpoint, events) = history.take(1).unwrap();
+        assert_eq!(checkpoint.root_data().versions.settle_version, 1);
+        assert_eq!(
+            events,
+            vec![(Event::GameStart, 1), (Event::WaitingTimeout, 2), (Event::DrawTimeout, 3)]
+        );
+    }
+
+    #[test]
+    fn test_checkpoint_history_prune() {
+        let mut history = CheckpointHistory::default();
+        history.push_checkpoint(make_checkpoint(1));
+        history.push_event(1, Event::GameStart, 1);
+        history.push_checkpoint(make_checkpoint(2));
+        history.push_event(2, Event::WaitingTimeout, 2);
+        history.prune(2);
+
+        assert!(history.take(1).is_none());
+        history.push_checkpoint(make_checkpoint(2));
+        let (_, events) = history.take(2).unwrap();
+        assert_eq!(events, vec![(Event::WaitingTimeout, 2)]);
+    }
+
+    fn start_promoter() -> (PortsHandle, mpsc::Receiver<SignalFrame>) {
+        let (signal_tx, signal_rx) = mpsc::channel(10);
+        let (promoter, ctx) = Promoter::init("game", ChainType::Facade, "server", signal_tx);
+        (promoter.start("game", ctx), signal_rx)
+    }
+
+    #[tokio::test]
+    async fn test_promoter_takeover() {
+        let (mut handle, mut signal_rx) = start_promoter();
+        handle
+            .send_unchecked(EventFrame::RecoverCheckpoint {
+                checkpoint: make_checkpoint(1),
+            })
+            .await;
+        handle
+            .send_unchecked(EventFrame::EventReplayed {
+                event: Event::GameStart,
+                timestamp: 1,
+                settle_version: 1,
+            })
+            .await;
+        // The event after the checkpoint arrives first
+        handle
+            .send_unchecked(EventFrame::EventReplayed {
+                event: Event::WaitingTimeout,
+                timestamp: 2,
+                settle_version: 2,
+            })
+            .await;
+        for settle_version in 2..6 {
+            handle
+                .send_unchecked(EventFrame::Checkpoint {
+                    checkpoint: make_checkpoint(settle_version),
+                })
+                .await;
+        }
+        handle
+            .send_unchecked(EventFrame::Takeover { settle_version: 2 })
+            .await;
+
+        let Some(SignalFrame::Takeover {
+            game_addr,
+            chain,
+            checkpoint,
+            history,
+        }) = signal_rx.recv().await
+        else {
+            panic!("Expect a Takeover signal");
+        };
+        assert_eq!(game_addr, "game");
+        assert_eq!(chain, ChainType::Facade);
+        assert_eq!(checkpoint.root_data().versions.settle_version, 2);
+        assert_eq!(history, vec![(Event::WaitingTimeout, 2)]);
+        assert!(matches!(handle.recv_unchecked().await, Some(EventFrame::Shutdown)));
+    }
+
+    #[tokio::test]
+    async fn test_promoter_follow_transactor() {
+        let (mut handle, mut signal_rx) = start_promoter();
+        // This server is the new transactor, it's handled by Takeover
+        handle
+            .send_unchecked(EventFrame::TransactorChanged {
+                transactor_addr: "server".into(),
+                endpoint: "".into(),
+            })
+            .await;
+        handle
+            .send_unchecked(EventFrame::TransactorChanged {
+                transactor_addr: "other".into(),
+                endpoint: "".into(),
+            })
+            .await;
+
+        let Some(SignalFrame::FollowTransactor { game_addr, chain }) = signal_rx.recv().await else {
+            panic!("Expect a FollowTransactor signal");
+        };
+        assert_eq!(game_addr, "game");
+        assert_eq!(chain, ChainType::Facade);
+        assert!(matches!(handle.recv_unchecked().await, Some(EventFrame::Shutdown)));
+    }
+}
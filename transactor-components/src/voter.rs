@@ -1,26 +1,76 @@
 //! The component to make voting transaction.  It happens when
-//! transactor is considered dropped off.  A shutdown event will be
-//! sent when vote is sent.
+//! transactor is considered dropped off.  After the vote is sent, the
+//! voter waits for this server to take over the game.  A shutdown
+//! event will be sent if the game is not taken over in time.
+//!
+//! When the state of the validator diverges from the transactor's,
+//! the voter signs the divergence report, records it in
+//! [DivergenceLog], and votes the transactor as dropped off.
 
 use std::{sync::Arc, time::Duration};
 
 use async_trait::async_trait;
-use race_core::error::Error;
+use race_core::encryptor::EncryptorT;
+use race_core::error::{Error, Result};
+use race_core::storage::StorageT;
 use race_core::{
     transport::TransportT,
-    types::{GameAccount, ServerAccount, VoteParams},
+    types::{
+        AppendDivergenceLogParams, DivergenceReport, GameAccount, GetDivergenceLogParams,
+        ServerAccount, VoteParams, VoteType,
+    },
 };
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 
 use super::{common::{Component, PipelinePorts}, ComponentEnv};
 use race_transactor_frames::EventFrame;
 
 use super::event_bus::CloseReason;
 
+// How long to wait for the takeover after the vote is sent.
+const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(60);
+
+// The reports kept for a game, and for all games.
+const MAX_REPORTS_PER_GAME: u64 = 100;
+const MAX_REPORTS: u64 = 10_000;
+
+/// The divergence reports of all games, saved in storage so they are
+/// kept after the games are closed and across restarts.  Only the
+/// latest reports are kept.
+pub struct DivergenceLog {
+    storage: Arc<dyn StorageT>,
+}
+
+impl DivergenceLog {
+    pub fn new(storage: Arc<dyn StorageT>) -> Self {
+        Self { storage }
+    }
+
+    pub async fn add(&self, report: DivergenceReport) -> Result<()> {
+        self.storage
+            .append_divergence_log(AppendDivergenceLogParams {
+                report,
+                max_per_game: MAX_REPORTS_PER_GAME,
+                max_total: MAX_REPORTS,
+            })
+            .await
+    }
+
+    pub async fn get(&self, game_addr: &str) -> Result<Vec<DivergenceReport>> {
+        self.storage
+            .get_divergence_log(GetDivergenceLogParams {
+                game_addr: game_addr.to_owned(),
+            })
+            .await
+    }
+}
+
 pub struct VoterContext {
     game_addr: String,
     server_addr: String,
     transport: Arc<dyn TransportT>,
+    encryptor: Arc<dyn EncryptorT>,
+    divergence_log: Arc<DivergenceLog>,
 }
 
 pub struct Voter {}
@@ -30,6 +80,8 @@ impl Voter {
         game_account: &GameAccount,
         server_account: &ServerAccount,
         transport: Arc<dyn TransportT>,
+        encryptor: Arc<dyn EncryptorT>,
+        divergence_log: Arc<DivergenceLog>,
     ) -> (Self, VoterContext) {
         (
             Self {},
@@ -37,11 +89,69 @@ impl Voter {
                 game_addr: game_account.addr.clone(),
                 server_addr: server_account.addr.clone(),
                 transport,
+                encryptor,
+                divergence_log,
             },
         )
     }
 }
 
+/// Sign the report as this server and record it.  Return the votee.
+async fn record_divergence(mut report: DivergenceReport, ctx: &VoterContext, env: &ComponentEnv) -> String {
+    report.validator_addr = ctx.server_addr.clone();
+    let signature = report.signing_bytes().and_then(|bytes| {
+        ctx.encryptor
+            .sign(&bytes, ctx.server_addr.clone())
+            .map_err(Error::from)
+    });
+    match signature {
+        Ok(signature) => report.signature = Some(signature),
+        Err(e) => error!("{} Failed to sign divergence report: {:?}", env.log_prefix, e),
+    }
+    let votee = report.transactor_addr.clone();
+    if let Err(e) = ctx.divergence_log.add(report).await {
+        error!("{} Failed to save divergence report: {:?}", env.log_prefix, e);
+    }
+    votee
+}
+
+async fn vote(params: VoteParams, ctx: &VoterContext, env: &ComponentEnv) {
+    // We keep retrying until success.
+    loop {
+        let r = ctx.transport.vote(params.clone()).await;
+        match r {
+            Ok(_) | Err(Error::DuplicatedVote) => {
+                info!("{} Vote sent", env.log_prefix);
+                break;
+            }
+            Err(e) => {
+                warn!("{} An error occurred in vote: {:?}, will retry.", env.log_prefix, e);
+                tokio::time::sleep(Duration::from_secs(3)).await;
+            }
+        }
+    }
+}
+
+/// Wait for the takeover, which is handled by the promoter.  Return
+/// false if the game is not taken over in time.
+async fn wait_takeover(ports: &mut PipelinePorts, env: &ComponentEnv) -> bool {
+    let wait = async {
+        while let Some(frame) = ports.recv().await {
+            if matches!(frame, EventFrame::Takeover { .. } | EventFrame::Shutdown) {
+                return true;
+            }
+        }
+        true
+    };
+    match tokio::time::timeout(TAKEOVER_TIMEOUT, wait).await {
+        Ok(r) => r,
+        Err(_) => {
+            warn!("{} Game is not taken over in time", env.log_prefix);
+            false
+        }
+    }
+}
+
 #[async_trait]
 impl Component<PipelinePorts, VoterContext> for Voter {
     fn name() -> &'static str {
@@ -59,21 +169,26 @@ impl Component<PipelinePorts, VoterContext> for Voter {
                         voter_addr: ctx.server_addr.clone(),
                         votee_addr: votee,
                     };
-                    // We keep retrying until success.
-                    loop {
-                        let r = ctx.transport.vote(params.clone()).await;
-                        match r {
-                            Ok(_) | Err(Error::DuplicatedVote) => {
-                                info!("{} Vote sent", env.log_prefix);
-                                ports.send(EventFrame::Shutdown).await;
-                                break;
-                            }
-                            Err(e) => {
-                                warn!("{} An error occurred in vote: {:?}, will retry.", env.log_prefix, e);
-                                tokio::time::sleep(Duration::from_secs(3)).await;
-                            }
-                        }
+                    vote(params, &ctx, &env).await;
+                    if !wait_takeover(&mut ports, &env).await {
+                        ports.send(EventFrame::Shutdown).await;
+                    }
+                    break;
+                }
+                EventFrame::Divergence { report } => {
+                    let votee = record_divergence(*report, &ctx, &env).await;
+                    warn!("{} State diverged, send vote, votee: {}", env.log_prefix, votee);
+                    let params = VoteParams {
+                        game_addr: ctx.game_addr.clone(),
+                        vote_type: VoteType::ServerVoteTransactorDropOff,
+                        voter_addr: ctx.server_addr.clone(),
+                        votee_addr: votee,
+                    };
+                    vote(params, &ctx, &env).await;
+                    if !wait_takeover(&mut ports, &env).await {
+                        ports.send(EventFrame::Shutdown).await;
                     }
+                    break;
                 }
                 EventFrame::Shutdown => {
                     warn!("{} Shutdown voter", env.log_prefix);
@@ -85,3 +200,97 @@ impl Component<PipelinePorts, VoterContext> for Voter {
         return CloseReason::Complete
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use race_api::event::Event;
+    use race_encryptor::Encryptor;
+    use race_local_db::LocalDbStorage;
+    use race_test::prelude::*;
+
+    fn make_report(game_addr: &str, expected_state_sha: &str) -> DivergenceReport {
+        DivergenceReport {
+            game_addr: game_addr.into(),
+            transactor_addr: "foo".into(),
+            validator_addr: "".into(),
+            access_version: 1,
+            settle_version: 1,
+            event: Event::GameStart,
+            timestamp: 0,
+            expected_state_sha: expected_state_sha.into(),
+            actual_state_sha: "b".into(),
+            signature: None,
+        }
+    }
+
+    async fn vote_on_report(expected_state_sha: &str) -> (Arc<DummyTransport>, Arc<LocalDbStorage>, GameAccount) {
+        let transport = Arc::new(DummyTransport::default());
+        let storage = Arc::new(LocalDbStorage::try_new_mem().unwrap());
+        let divergence_log = Arc::new(DivergenceLog::new(storage.clone()));
+        let mut foo = TestClient::transactor("foo");
+        let mut bar = TestClient::validator("bar");
+        let game_account = TestGameAccountBuilder::default()
+            .set_transactor(&mut foo)
+            .add_validator(&mut bar)
+            .build();
+        let server_account = ServerAccount {
+            addr: "bar".into(),
+            endpoint: "".into(),
+            credentials: vec![],
+        };
+
+        let (voter, ctx) = Voter::init(
+            &game_account,
+            &server_account,
+            transport.clone(),
+            Arc::new(Encryptor::default()),
+            divergence_log.clone(),
+        );
+        let handle = voter.start("", ctx);
+        handle
+            .send_unchecked(EventFrame::Divergence {
+                report: Box::new(make_report(&game_account.addr, expected_state_sha)),
+            })
+            .await;
+        handle.send_unchecked(EventFrame::Shutdown).await;
+        handle.wait().await;
+        (transport, storage, game_account)
+    }
+
+    #[tokio::test]
+    async fn test_vote_on_divergence() {
+        let (transport, storage, game_account) = vote_on_report("a").await;
+
+        assert_eq!(
+            transport.get_votes().clone(),
+            vec![VoteParams {
+                game_addr: game_account.addr.clone(),
+                vote_type: VoteType::ServerVoteTransactorDropOff,
+                voter_addr: "bar".into(),
+                votee_addr: "foo".into(),
+            }]
+        );
+
+        // The report is signed by this server, and kept after a restart
+        let reports = DivergenceLog::new(storage).get(&game_account.addr).await.unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].validator_addr, "bar");
+        assert!(reports[0].signature.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_vote_on_empty_state_sha() {
+        let (transport, _, game_account) = vote_on_report("").await;
+
+        assert_eq!(
+            transport.get_votes().clone(),
+            vec![VoteParams {
+                game_addr: game_account.addr.clone(),
+                vote_type: VoteType::ServerVoteTransactorDropOff,
+                voter_addr: "bar".into(),
+                votee_addr: "foo".into(),
+            }]
+        );
+    }
+}
@@ -1,11 +1,14 @@
-use race_core::{checkpoint::CheckpointOffChain, storage::StorageT, types::{GetCheckpointParams, SaveCheckpointParams}};
+use race_core::{checkpoint::CheckpointOffChain, storage::StorageT, types::{AppendDivergenceLogParams, AppendJournalParams, ChatMember, ConfirmSettleParams, GetBacklogsParams, GetChatMembersParams, GetCheckpointParams, DivergenceReport, GetDivergenceLogParams, GetJournalParams, GetPendingRefundsParams, GetPendingSettlesParams, PruneBacklogsParams, RemovePendingRefundsParams, SaveBacklogParams, SaveChatMemberParams, SaveCheckpointParams, SavePendingRefundsParams, SavePendingSettleParams, SettleParams, TruncateJournalParams, CutJournalParams}};
 use race_env::Config;
 use jsonrpsee::core::async_trait;
+use std::sync::Arc;
 use race_core::error::Result;
 use race_local_db::LocalDbStorage;
 
 pub struct WrappedStorage {
-    pub(crate) inner: Box<dyn StorageT>,
+    pub(crate) inner: Arc<dyn StorageT>,
+    // The chain of the games, prepended to their addresses in the rows.
+    scope: Option<String>,
 }
 
 impl WrappedStorage {
@@ -16,17 +19,171 @@ impl WrappedStorage {
             LocalDbStorage::try_new_mem()?
         };
 
-        Ok(Self { inner: Box::new(storage) })
+        Ok(Self { inner: Arc::new(storage), scope: None })
+    }
+
+    /// A view of the same storage, for the games on a chain.  Their
+    /// rows are keyed by the chain-qualified addresses, e.g.
+    /// `sui:0x1234`, so the same address on two chains doesn't collide.
+    pub fn with_scope(&self, scope: &str) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            scope: Some(scope.to_owned()),
+        }
+    }
+
+    /// Return the address of a game in the rows of this view.
+    pub fn scoped_addr(&self, game_addr: &str) -> String {
+        match self.scope.as_ref() {
+            Some(scope) => format!("{}:{}", scope, game_addr),
+            None => game_addr.to_owned(),
+        }
+    }
+
+    fn unscoped_addr(&self, game_addr: String) -> String {
+        match self.scope.as_ref() {
+            Some(scope) => game_addr
+                .strip_prefix(&format!("{}:", scope))
+                .map(str::to_owned)
+                .unwrap_or(game_addr),
+            None => game_addr,
+        }
     }
 }
 
 #[async_trait]
 impl StorageT for WrappedStorage {
-    async fn save_checkpoint(&self, params: SaveCheckpointParams) -> Result<()> {
+    async fn save_checkpoint(&self, mut params: SaveCheckpointParams) -> Result<()> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
         self.inner.save_checkpoint(params).await
     }
 
-    async fn get_checkpoint(&self, params: GetCheckpointParams) -> Result<Option<CheckpointOffChain>> {
+    async fn get_checkpoint(&self, mut params: GetCheckpointParams) -> Result<Option<CheckpointOffChain>> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
         self.inner.get_checkpoint(params).await
     }
+
+    async fn save_pending_settle(&self, mut params: SavePendingSettleParams) -> Result<()> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
+        self.inner.save_pending_settle(params).await
+    }
+
+    async fn confirm_settle(&self, mut params: ConfirmSettleParams) -> Result<()> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
+        self.inner.confirm_settle(params).await
+    }
+
+    async fn get_pending_settles(&self, mut params: GetPendingSettlesParams) -> Result<Vec<SettleParams>> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
+        self.inner.get_pending_settles(params).await
+    }
+
+    async fn save_pending_refunds(&self, mut params: SavePendingRefundsParams) -> Result<()> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
+        self.inner.save_pending_refunds(params).await
+    }
+
+    async fn remove_pending_refunds(&self, mut params: RemovePendingRefundsParams) -> Result<()> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
+        self.inner.remove_pending_refunds(params).await
+    }
+
+    async fn get_pending_refunds(&self, mut params: GetPendingRefundsParams) -> Result<Vec<u64>> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
+        self.inner.get_pending_refunds(params).await
+    }
+
+    async fn save_backlog(&self, mut params: SaveBacklogParams) -> Result<()> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
+        self.inner.save_backlog(params).await
+    }
+
+    async fn get_backlogs(&self, mut params: GetBacklogsParams) -> Result<Vec<Vec<u8>>> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
+        self.inner.get_backlogs(params).await
+    }
+
+    async fn prune_backlogs(&self, mut params: PruneBacklogsParams) -> Result<()> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
+        self.inner.prune_backlogs(params).await
+    }
+
+    async fn append_journal(&self, mut params: AppendJournalParams) -> Result<()> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
+        self.inner.append_journal(params).await
+    }
+
+    async fn get_journal(&self, mut params: GetJournalParams) -> Result<Vec<Vec<u8>>> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
+        self.inner.get_journal(params).await
+    }
+
+    async fn truncate_journal(&self, mut params: TruncateJournalParams) -> Result<()> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
+        self.inner.truncate_journal(params).await
+    }
+
+    async fn cut_journal(&self, mut params: CutJournalParams) -> Result<()> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
+        self.inner.cut_journal(params).await
+    }
+
+    async fn save_chat_member(&self, mut params: SaveChatMemberParams) -> Result<()> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
+        self.inner.save_chat_member(params).await
+    }
+
+    async fn get_chat_members(&self, mut params: GetChatMembersParams) -> Result<Vec<ChatMember>> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
+        self.inner.get_chat_members(params).await
+    }
+
+    async fn append_divergence_log(&self, mut params: AppendDivergenceLogParams) -> Result<()> {
+        params.report.game_addr = self.scoped_addr(&params.report.game_addr);
+        self.inner.append_divergence_log(params).await
+    }
+
+    async fn get_divergence_log(&self, mut params: GetDivergenceLogParams) -> Result<Vec<DivergenceReport>> {
+        params.game_addr = self.scoped_addr(&params.game_addr);
+        let mut reports = self.inner.get_divergence_log(params).await?;
+        for report in reports.iter_mut() {
+            report.game_addr = self.unscoped_addr(std::mem::take(&mut report.game_addr));
+        }
+        Ok(reports)
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.inner.health_check().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scoped_rows() -> anyhow::Result<()> {
+        let storage = WrappedStorage::try_new(&Config::default()).await?;
+        let sui = storage.with_scope("sui");
+        assert_eq!(storage.scoped_addr("GAME"), "GAME");
+        assert_eq!(sui.scoped_addr("GAME"), "sui:GAME");
+
+        storage
+            .save_pending_refunds(SavePendingRefundsParams {
+                game_addr: "GAME".into(),
+                access_versions: vec![1],
+            })
+            .await?;
+        sui.save_pending_refunds(SavePendingRefundsParams {
+            game_addr: "GAME".into(),
+            access_versions: vec![2],
+        })
+        .await?;
+
+        let get = |addr: &str| GetPendingRefundsParams { game_addr: addr.into() };
+        assert_eq!(storage.get_pending_refunds(get("GAME")).await?, vec![1]);
+        assert_eq!(sui.get_pending_refunds(get("GAME")).await?, vec![2]);
+        assert_eq!(storage.get_pending_refunds(get("sui:GAME")).await?, vec![2]);
+        Ok(())
+    }
 }
@@ -5,7 +5,7 @@ use futures::Stream;
 use jsonrpsee::core::async_trait;
 use race_core::error::{Error, Result};
 use race_core::types::{
-    AddRecipientSlotParams, AssignRecipientParams, CreatePlayerProfileParams, CreateRecipientParams, CreateRegistrationParams, DepositParams, DepositStatus, PublishGameParams, RecipientAccount, RecipientClaimParams, RegisterGameParams, RejectDepositsParams, RejectDepositsResult, ServeParams, SettleResult, UnregisterGameParams, VoteParams
+    AddRecipientSlotParams, AssignRecipientParams, CreatePlayerProfileParams, CreateRecipientParams, CreateRegistrationParams, DepositParams, DepositStatus, PublishGameParams, RecipientAccount, RecipientClaimParams, RegisterGameParams, RejectDepositsParams, RejectDepositsResult, ServeParams, SettleResult, TxState, UnregisterGameParams, VoteParams
 };
 use race_core::{
     transport::TransportT,
@@ -14,17 +14,32 @@ use race_core::{
         PlayerProfile, RegisterServerParams, RegistrationAccount, ServerAccount, SettleParams,
     },
 };
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, Mutex};
 use std::collections::HashMap;
 use std::pin::Pin;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
 use tokio_stream::StreamExt;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 const DEFAULT_RETRY_INTERVAL: u64 = 10;
 const DEFAULT_RESUB_INTERVAL: u64 = 5;
 
+// The maximum interval in seconds between the retries of a failed
+// settlement.
+const SETTLE_MAX_RETRY_INTERVAL: u64 = 60;
+
+// A settlement is reported as stuck after this number of attempts.
+const SETTLE_STUCK_ATTEMPTS: u32 = 5;
+
+/// The state of a settlement being retried, published by
+/// [WrappedTransport::settle_game].
+#[derive(Debug, Clone)]
+pub struct SettleProgress {
+    pub game_addr: String,
+    pub tx_state: TxState,
+}
+
 pub struct BundleCache {
     bundles: Arc<Mutex<HashMap<String, GameBundle>>>,
 }
@@ -50,17 +65,140 @@ pub struct WrappedTransport {
     retry_interval: u64,
     resub_interval: u64,
     bundle_cache: BundleCache,
+    settle_progress_tx: broadcast::Sender<SettleProgress>,
+    // When the settlement of each game started to be retried.
+    settle_retrying_since: StdMutex<HashMap<String, Instant>>,
 }
 
 impl WrappedTransport {
     pub async fn try_new(transport: Box<dyn TransportT>) -> Result<Self> {
-        let bundle_cache = BundleCache::new();
-        Ok(Self {
+        Ok(Self::with_intervals(
+            transport,
+            DEFAULT_RETRY_INTERVAL,
+            DEFAULT_RESUB_INTERVAL,
+        ))
+    }
+
+    fn with_intervals(transport: Box<dyn TransportT>, retry_interval: u64, resub_interval: u64) -> Self {
+        let (settle_progress_tx, _) = broadcast::channel(100);
+        Self {
             inner: transport,
-            bundle_cache,
-            retry_interval: DEFAULT_RETRY_INTERVAL,
-            resub_interval: DEFAULT_RESUB_INTERVAL,
-        })
+            bundle_cache: BundleCache::new(),
+            retry_interval,
+            resub_interval,
+            settle_progress_tx,
+            settle_retrying_since: StdMutex::new(HashMap::new()),
+        }
+    }
+
+    /// Subscribe the states of the settlements being retried.
+    pub fn subscribe_settle_progress(&self) -> broadcast::Receiver<SettleProgress> {
+        self.settle_progress_tx.subscribe()
+    }
+
+    /// Return the games whose settlements have been retried for
+    /// longer than `threshold`, with the duration of the retrying.
+    pub fn get_stuck_settles(&self, threshold: Duration) -> Vec<(String, Duration)> {
+        self.settle_retrying_since
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(addr, since)| (addr.to_owned(), since.elapsed()))
+            .filter(|(_, retrying_for)| *retrying_for > threshold)
+            .collect()
+    }
+
+    /// Send the settlement until it lands.  Retryable errors are
+    /// retried with backoff, and the retries are published as
+    /// [SettleProgress].  Other errors are returned.  A settlement
+    /// found on chain after a failure is treated as succeeded, with an
+    /// empty signature.  With `max_attempts`, the last retryable
+    /// error is returned after that many attempts, for the callers
+    /// which can't wait for an outage to end.
+    pub async fn settle_game_with_attempts(
+        &self,
+        params: SettleParams,
+        max_attempts: Option<u32>,
+    ) -> Result<SettleResult> {
+        let settle_version = params.settle_version;
+        let mut attempts = 0;
+        let mut interval = self.retry_interval;
+
+        let result = loop {
+            attempts += 1;
+            let e = match self.inner.settle_game(params.clone()).await {
+                Ok(rst) => {
+                    info!("Settlement succeed, signature: {}", rst.signature);
+                    break Ok(rst);
+                }
+                Err(e) => e,
+            };
+
+            if let Some(game_account) = self.get_applied_settle(&params).await {
+                info!(
+                    "Settlement of game {} already applied, settle_version = {}",
+                    params.addr, settle_version
+                );
+                break Ok(SettleResult {
+                    signature: "".into(),
+                    game_account,
+                });
+            }
+
+            if !e.is_retryable() || max_attempts.map_or(false, |max| attempts >= max) {
+                error!("Settlement of game {} failed: {}", params.addr, e);
+                break Err(e);
+            }
+
+            warn!(
+                "Settlement of game {} failed: {}, attempts = {}, will retry in {} secs",
+                params.addr, e, attempts, interval
+            );
+            self.settle_retrying_since
+                .lock()
+                .unwrap()
+                .entry(params.addr.clone())
+                .or_insert_with(Instant::now);
+            let tx_state = if attempts >= SETTLE_STUCK_ATTEMPTS {
+                TxState::SettleStuck {
+                    settle_version,
+                    attempts,
+                }
+            } else {
+                TxState::SettleRetrying {
+                    settle_version,
+                    attempts,
+                    error: e.to_string(),
+                }
+            };
+            self.publish_settle_progress(&params.addr, tx_state);
+
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+            interval = (interval * 2).min(SETTLE_MAX_RETRY_INTERVAL);
+        };
+
+        self.settle_retrying_since.lock().unwrap().remove(&params.addr);
+        result
+    }
+
+    /// Return the game account if the settlement has landed, which
+    /// happens when the previous attempt succeeded but the response
+    /// was lost.
+    async fn get_applied_settle(&self, params: &SettleParams) -> Option<GameAccount> {
+        match self.inner.get_game_account(&params.addr).await {
+            Ok(Some(game_account)) if game_account.settle_version >= params.next_settle_version => {
+                Some(game_account)
+            }
+            _ => None,
+        }
+    }
+
+    fn publish_settle_progress(&self, game_addr: &str, tx_state: TxState) {
+        // No receiver is not an error
+        let _ = self.settle_progress_tx.send(SettleProgress {
+            game_addr: game_addr.to_string(),
+            tx_state,
+        });
     }
 }
 
@@ -175,59 +313,9 @@ impl TransportT for WrappedTransport {
         self.inner.publish_game(params).await
     }
 
-    /// `settle_version` is used to identify the settle state,
-    /// Until the `settle_version` is bumped, we keep retrying.
+    /// Send the settlement until it lands, see [Self::settle_game_with_attempts].
     async fn settle_game(&self, params: SettleParams) -> Result<SettleResult> {
-        let mut curr_settle_version: Option<u64> = None;
-        loop {
-            let game_account = self.inner.get_game_account(&params.addr).await;
-            if let Ok(Some(game_account)) = game_account {
-                // We got an old state, which has a smaller settle_version.
-                // It means either the previous settle was failed, or RPC gave a outdated account.
-                // We should retry the query to get the latest account.
-                if game_account.settle_version < params.settle_version {
-                    error!(
-                        "Got invalid settle_version: on-chain version {} != required version {}, will retry in {} secs",
-                        game_account.settle_version, params.settle_version, self.retry_interval
-                    );
-                    tokio::time::sleep(Duration::from_secs(self.retry_interval)).await;
-                    continue;
-                }
-                // We got a settle_version which is greater than the current one.  It means that the
-                // settle_version has been bumped. There's no need to retry.
-                // NOTE: The transaction // may success with error result due to unstable network
-                if curr_settle_version.is_some_and(|v| v < game_account.settle_version) {
-                    return Ok(SettleResult {
-                        signature: "".into(),
-                        game_account,
-                    });
-                }
-                curr_settle_version = Some(game_account.settle_version);
-
-                match self.inner.settle_game(params.clone()).await {
-                    Ok(rst) => {
-                        info!("Settlement succeed, signature: {}", rst.signature);
-                        return Ok(rst);
-                    }
-                    Err(e) => {
-                        error!(
-                            "Error in settlement: {:?}, will retry in {} secs",
-                            e, self.retry_interval
-                        );
-                        tokio::time::sleep(Duration::from_secs(self.retry_interval)).await;
-                        continue;
-                    }
-                }
-            } else {
-                error!(
-                    "Error in settlement due to unable to get game account {}, will retry in {} secs",
-                    params.addr,
-                    self.retry_interval
-                );
-                tokio::time::sleep(Duration::from_secs(self.retry_interval)).await;
-                continue;
-            }
-        }
+        self.settle_game_with_attempts(params, None).await
     }
 
     async fn reject_deposits(&self, params: RejectDepositsParams) -> Result<RejectDepositsResult> {
@@ -249,21 +337,30 @@ impl TransportT for WrappedTransport {
                     continue;
                 }
 
-                // If we see the deposits are marked as rejected/refunded, we should skip
-
-                for d in game_account.deposits.iter() {
-                    if params.reject_deposits.iter().any(|rd| {
-                        *rd == d.access_version
-                            && (d.status == DepositStatus::Rejected
-                                || d.status == DepositStatus::Refunded)
-                    }) {
-                        return Ok(RejectDepositsResult {
-                            signature: "".to_string(),
-                        });
-                    }
+                // Skip the deposits already refunded, and send the rest
+                let reject_deposits: Vec<u64> = params
+                    .reject_deposits
+                    .iter()
+                    .copied()
+                    .filter(|rd| {
+                        !game_account.deposits.iter().any(|d| {
+                            d.access_version == *rd && d.status == DepositStatus::Refunded
+                        })
+                    })
+                    .collect();
+
+                if reject_deposits.is_empty() {
+                    return Ok(RejectDepositsResult {
+                        signature: "".to_string(),
+                    });
                 }
 
-                match self.inner.reject_deposits(params.clone()).await {
+                let to_send = RejectDepositsParams {
+                    addr: params.addr.clone(),
+                    reject_deposits,
+                };
+
+                match self.inner.reject_deposits(to_send).await {
                     Ok(rst) => return Ok(rst),
                     Err(e) => {
                         error!(
@@ -335,6 +432,7 @@ impl TransportT for WrappedTransport {
 #[cfg(test)]
 mod tests {
     use race_core::checkpoint::CheckpointOnChain;
+    use race_core::types::PlayerDeposit;
     use race_test::prelude::{test_game_addr, DummyTransport, TestGameAccountBuilder};
 
     use super::*;
@@ -346,12 +444,7 @@ mod tests {
         let mut ga1 = TestGameAccountBuilder::new().build();
         ga1.settle_version = 1;
         t.simulate_states(vec![ga0, ga1]);
-        let wt = WrappedTransport {
-            inner: Box::new(t),
-            retry_interval: 1,
-            resub_interval: 1,
-            bundle_cache: BundleCache::new(),
-        };
+        let wt = WrappedTransport::with_intervals(Box::new(t), 1, 1);
         let r = wt
             .settle_game(SettleParams {
                 addr: test_game_addr(),
@@ -379,12 +472,7 @@ mod tests {
         let mut ga1 = TestGameAccountBuilder::new().build();
         ga1.settle_version = 1;
         t.simulate_states(vec![ga0, ga1]);
-        let wt = WrappedTransport {
-            inner: Box::new(t),
-            retry_interval: 1,
-            resub_interval: 1,
-            bundle_cache: BundleCache::new(),
-        };
+        let wt = WrappedTransport::with_intervals(Box::new(t), 1, 1);
         let r = wt
             .settle_game(SettleParams {
                 addr: test_game_addr(),
@@ -403,4 +491,157 @@ mod tests {
         assert_eq!(r.unwrap().signature, "".to_string());
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_settle_with_max_attempts() -> anyhow::Result<()> {
+        let mut t = DummyTransport::default();
+        t.fail_next_settle();
+        t.simulate_states(vec![TestGameAccountBuilder::new().build()]);
+        let wt = WrappedTransport::with_intervals(Box::new(t), 1, 1);
+
+        let r = wt
+            .settle_game_with_attempts(make_settle_params(test_game_addr(), 0), Some(1))
+            .await;
+
+        assert_eq!(r.err(), Some(Error::RpcError("Mock failure".into())));
+        assert!(wt.get_stuck_settles(Duration::ZERO).is_empty());
+        Ok(())
+    }
+
+    fn make_deposit(access_version: u64, status: DepositStatus) -> PlayerDeposit {
+        PlayerDeposit {
+            addr: "alice".into(),
+            amount: 100,
+            access_version,
+            settle_version: 1,
+            status,
+        }
+    }
+
+    fn make_settle_params(addr: String, settle_version: u64) -> SettleParams {
+        SettleParams {
+            addr,
+            settles: vec![],
+            transfer: None,
+            checkpoint: CheckpointOnChain::default(),
+            settle_version,
+            access_version: 1,
+            accept_deposits: vec![],
+            awards: vec![],
+            entry_lock: None,
+            next_settle_version: settle_version + 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_settle_retry_progress() -> anyhow::Result<()> {
+        let mut t = DummyTransport::default();
+        t.fail_next_settle();
+        t.simulate_states(vec![TestGameAccountBuilder::new().build()]);
+        let wt = WrappedTransport::with_intervals(Box::new(t), 1, 1);
+        let mut progress = wt.subscribe_settle_progress();
+
+        let r = wt.settle_game(make_settle_params(test_game_addr(), 0)).await;
+
+        assert!(r.is_ok());
+        let SettleProgress { game_addr, tx_state } = progress.try_recv()?;
+        assert_eq!(game_addr, test_game_addr());
+        assert!(matches!(
+            tx_state,
+            TxState::SettleRetrying {
+                settle_version: 0,
+                attempts: 1,
+                ..
+            }
+        ));
+        // The retrying is over
+        assert!(wt.get_stuck_settles(Duration::ZERO).is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_stuck_settles() -> anyhow::Result<()> {
+        let mut t = DummyTransport::default();
+        t.fail_next_settle();
+        t.simulate_states(vec![TestGameAccountBuilder::new().build()]);
+        let wt = Arc::new(WrappedTransport::with_intervals(Box::new(t), 1, 1));
+        let mut progress = wt.subscribe_settle_progress();
+
+        let settle = tokio::spawn({
+            let wt = wt.clone();
+            async move { wt.settle_game(make_settle_params(test_game_addr(), 0)).await }
+        });
+
+        // Stuck while waiting for the retry
+        progress.recv().await?;
+        let stuck = wt.get_stuck_settles(Duration::ZERO);
+        assert_eq!(stuck.len(), 1);
+        assert_eq!(stuck[0].0, test_game_addr());
+        assert!(wt.get_stuck_settles(Duration::from_secs(600)).is_empty());
+
+        settle.await??;
+        assert!(wt.get_stuck_settles(Duration::ZERO).is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_settle_fatal_error() -> anyhow::Result<()> {
+        let t = DummyTransport::default();
+        let wt = WrappedTransport::with_intervals(Box::new(t), 1, 1);
+        let mut progress = wt.subscribe_settle_progress();
+
+        // The mock transport fails with GameAccountNotFound
+        let r = wt.settle_game(make_settle_params("OTHER".into(), 0)).await;
+
+        assert_eq!(r.err(), Some(Error::GameAccountNotFound));
+        assert!(progress.try_recv().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_settle_already_applied() -> anyhow::Result<()> {
+        let mut t = DummyTransport::default();
+        t.fail_next_settle();
+        let mut ga = TestGameAccountBuilder::new().build();
+        ga.settle_version = 1;
+        t.simulate_states(vec![ga]);
+        let wt = WrappedTransport::with_intervals(Box::new(t), 1, 1);
+
+        let r = wt.settle_game(make_settle_params(test_game_addr(), 0)).await?;
+
+        assert_eq!(r.signature, "".to_string());
+        assert_eq!(r.game_account.settle_version, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reject_deposits_skip_refunded() -> anyhow::Result<()> {
+        let t = DummyTransport::default();
+        let mut ga = TestGameAccountBuilder::new().build();
+        ga.access_version = 3;
+        ga.deposits = vec![
+            make_deposit(1, DepositStatus::Refunded),
+            make_deposit(2, DepositStatus::Rejected),
+            make_deposit(3, DepositStatus::Pending),
+        ];
+        t.simulate_states(vec![ga.clone(), ga]);
+        let wt = WrappedTransport::with_intervals(Box::new(t.clone()), 1, 1);
+
+        wt.reject_deposits(RejectDepositsParams {
+            addr: test_game_addr(),
+            reject_deposits: vec![1, 2, 3],
+        })
+        .await?;
+        // All refunded, nothing to send
+        wt.reject_deposits(RejectDepositsParams {
+            addr: test_game_addr(),
+            reject_deposits: vec![1],
+        })
+        .await?;
+
+        let sent = t.get_reject_deposits();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].reject_deposits, vec![2, 3]);
+        Ok(())
+    }
 }
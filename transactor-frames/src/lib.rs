@@ -1,16 +1,18 @@
 use tokio::sync::{mpsc, broadcast};
+use tracing::Span;
 
-use race_api::event::{Event, Message};
+use race_api::event::{ChatMessage, Event};
 use race_api::init_account::InitAccount;
+use race_core::chain::ChainType;
 use race_core::node::Node;
 use race_core::context::{GameContext, SettleDetails};
 use race_core::checkpoint::{ContextCheckpoint, VersionedData};
-use race_core::types::{ClientMode, PlayerDeposit, PlayerJoin, ServerJoin, TxState, VoteType};
+use race_core::types::{ClientMode, DivergenceReport, PlayerDeposit, PlayerJoin, ServerJoin, TxState, VoteType};
 
 #[derive(Debug)]
 pub struct BridgeToParent {
-    pub tx_to_parent: mpsc::Sender<EventFrame>,
-    pub rx_from_parent: broadcast::Receiver<EventFrame>,
+    pub tx_to_parent: mpsc::Sender<TracedFrame>,
+    pub rx_from_parent: broadcast::Receiver<TracedFrame>,
 }
 
 #[derive(Debug)]
@@ -18,15 +20,33 @@ pub enum SignalFrame {
     StartGame {
         game_addr: String,
         mode: ClientMode,
+        chain: ChainType,
     },
+    /// Launch a sub game on `chain`, the chain of its parent.
     LaunchSubGame {
         checkpoint: ContextCheckpoint,
         bridge_to_parent: BridgeToParent,
+        chain: ChainType,
     },
-    #[allow(unused)]
     Shutdown,
     RemoveGame {
         game_addr: String,
+        chain: ChainType,
+    },
+    /// This node becomes the transactor of a game it validates.  The
+    /// game is restored from `checkpoint`, then the events in
+    /// `history` with their timestamps are replayed.
+    Takeover {
+        game_addr: String,
+        chain: ChainType,
+        checkpoint: ContextCheckpoint,
+        history: Vec<(Event, u64)>,
+    },
+    /// The game this node validates is taken over by another
+    /// transactor.  The validator is relaunched to follow it.
+    FollowTransactor {
+        game_addr: String,
+        chain: ChainType,
     },
 }
 
@@ -72,12 +92,56 @@ pub enum EventFrame {
         timestamp: u64,
     },
     SendMessage {
-        message: Message,
+        message: ChatMessage,
     },
     SendServerEvent {
         event: Event,
         timestamp: u64,
     },
+    /// An event broadcast by the transactor, replayed by a validator.
+    /// `state_sha` is the state of the transactor after the event.
+    ReplayEvent {
+        event: Event,
+        timestamp: u64,
+        state_sha: String,
+    },
+    /// Sent by the event loop of a validator after a
+    /// [EventFrame::ReplayEvent] is handled.  `settle_version` is the
+    /// version of the context before the event, so the event comes
+    /// after the checkpoint at this version.
+    EventReplayed {
+        event: Event,
+        timestamp: u64,
+        settle_version: u64,
+    },
+    /// The state of a validator diverged from the transactor's.  The
+    /// report is signed by the voter.
+    Divergence {
+        report: Box<DivergenceReport>,
+    },
+    /// Sent by the synchronizer of a validator when this node becomes
+    /// the transactor at `settle_version`.
+    Takeover {
+        settle_version: u64,
+    },
+    /// Sent by the synchronizer of a validator when the on-chain
+    /// settle version is updated.
+    SettleVersionChanged {
+        settle_version: u64,
+    },
+    /// Sent by the submitter when the settlement to `settle_version`
+    /// lands on chain, so the game is recovered from its checkpoint
+    /// after a crash.
+    SettleConfirmed {
+        settle_version: u64,
+    },
+    /// Sent by the synchronizer when the game is taken over by
+    /// another transactor.  A transactor steps down, and a validator
+    /// follows the new transactor.
+    TransactorChanged {
+        transactor_addr: String,
+        endpoint: String,
+    },
     Checkpoint {
         checkpoint: ContextCheckpoint,
     },
@@ -97,6 +161,14 @@ pub enum EventFrame {
         vote_type: VoteType,
     },
     Shutdown,
+    /// Ask the game to stop gracefully.  The event loop flushes the
+    /// ready settlements and emits a [EventFrame::FinalCheckpoint]
+    /// before sending [EventFrame::Shutdown].
+    GracefulShutdown,
+    /// The latest state of a game which is going to be shutdown.
+    FinalCheckpoint {
+        checkpoint: ContextCheckpoint,
+    },
     /// Represent a event send in current event bus.  `from` is the
     /// source of event, `dest` is the target of the event.  value 0
     /// represent the master game.  When there's an available
@@ -154,6 +226,108 @@ pub enum EventFrame {
     },
 }
 
+/// An [EventFrame] with the span it was sent in.  The components
+/// handling the frame open their spans as children of `span`, so the
+/// spans of one player action are linked across the event bus.
+#[derive(Debug, Clone)]
+pub struct TracedFrame {
+    pub frame: EventFrame,
+    pub span: Span,
+}
+
+impl TracedFrame {
+    /// Wrap the frame with the current span.
+    pub fn new(frame: EventFrame) -> Self {
+        Self {
+            frame,
+            span: Span::current(),
+        }
+    }
+
+    pub fn with_span(frame: EventFrame, span: Span) -> Self {
+        Self { frame, span }
+    }
+}
+
+impl From<EventFrame> for TracedFrame {
+    fn from(frame: EventFrame) -> Self {
+        Self::new(frame)
+    }
+}
+
+impl EventFrame {
+    /// The name of the variant, used in span names.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EventFrame::Empty => "Empty",
+            EventFrame::Sync { .. } => "Sync",
+            EventFrame::SyncWithCredentials { .. } => "SyncWithCredentials",
+            EventFrame::TxState { .. } => "TxState",
+            EventFrame::PlayerLeaving { .. } => "PlayerLeaving",
+            EventFrame::RecoverCheckpoint { .. } => "RecoverCheckpoint",
+            EventFrame::RecoverCheckpointWithCredentials { .. } => "RecoverCheckpointWithCredentials",
+            EventFrame::InitState { .. } => "InitState",
+            EventFrame::SendEvent { .. } => "SendEvent",
+            EventFrame::SendMessage { .. } => "SendMessage",
+            EventFrame::SendServerEvent { .. } => "SendServerEvent",
+            EventFrame::ReplayEvent { .. } => "ReplayEvent",
+            EventFrame::EventReplayed { .. } => "EventReplayed",
+            EventFrame::Divergence { .. } => "Divergence",
+            EventFrame::Takeover { .. } => "Takeover",
+            EventFrame::SettleVersionChanged { .. } => "SettleVersionChanged",
+            EventFrame::SettleConfirmed { .. } => "SettleConfirmed",
+            EventFrame::TransactorChanged { .. } => "TransactorChanged",
+            EventFrame::Checkpoint { .. } => "Checkpoint",
+            EventFrame::Settle { .. } => "Settle",
+            EventFrame::Broadcast { .. } => "Broadcast",
+            EventFrame::ContextUpdated { .. } => "ContextUpdated",
+            EventFrame::Vote { .. } => "Vote",
+            EventFrame::Shutdown => "Shutdown",
+            EventFrame::GracefulShutdown => "GracefulShutdown",
+            EventFrame::FinalCheckpoint { .. } => "FinalCheckpoint",
+            EventFrame::SendBridgeEvent { .. } => "SendBridgeEvent",
+            EventFrame::RecvBridgeEvent { .. } => "RecvBridgeEvent",
+            EventFrame::LaunchSubGame { .. } => "LaunchSubGame",
+            EventFrame::SubSync { .. } => "SubSync",
+            EventFrame::SubGameReady { .. } => "SubGameReady",
+            EventFrame::SubGameLaunched { .. } => "SubGameLaunched",
+            EventFrame::SubGameShutdown { .. } => "SubGameShutdown",
+            EventFrame::RejectDeposits { .. } => "RejectDeposits",
+        }
+    }
+
+    /// The access version and the settle version carried by the
+    /// frame, if any.
+    pub fn versions(&self) -> (Option<u64>, Option<u64>) {
+        match self {
+            EventFrame::Sync { access_version, .. }
+            | EventFrame::SyncWithCredentials { access_version, .. }
+            | EventFrame::SubSync { access_version, .. } => (Some(*access_version), None),
+            EventFrame::InitState {
+                access_version,
+                settle_version,
+                ..
+            } => (Some(*access_version), Some(*settle_version)),
+            EventFrame::RecoverCheckpoint { checkpoint }
+            | EventFrame::RecoverCheckpointWithCredentials { checkpoint }
+            | EventFrame::Checkpoint { checkpoint }
+            | EventFrame::FinalCheckpoint { checkpoint } => {
+                let versions = &checkpoint.root_data.versions;
+                (Some(versions.access_version), Some(versions.settle_version))
+            }
+            EventFrame::Settle { settle_details } => (
+                Some(settle_details.access_version),
+                Some(settle_details.settle_version),
+            ),
+            EventFrame::Takeover { settle_version }
+            | EventFrame::SettleVersionChanged { settle_version }
+            | EventFrame::SettleConfirmed { settle_version }
+            | EventFrame::EventReplayed { settle_version, .. } => (None, Some(*settle_version)),
+            _ => (None, None),
+        }
+    }
+}
+
 impl std::fmt::Display for EventFrame {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -213,12 +387,33 @@ impl std::fmt::Display for EventFrame {
             EventFrame::PlayerLeaving { .. } => write!(f, "PlayerLeaving"),
             EventFrame::SendEvent { event, .. } => write!(f, "SendEvent: {}", event),
             EventFrame::SendServerEvent { event, .. } => write!(f, "SendServerEvent: {}", event),
+            EventFrame::ReplayEvent { event, .. } => write!(f, "ReplayEvent: {}", event),
+            EventFrame::EventReplayed { event, .. } => write!(f, "EventReplayed: {}", event),
+            EventFrame::Takeover { settle_version } => {
+                write!(f, "Takeover, settle_version = {}", settle_version)
+            }
+            EventFrame::SettleVersionChanged { settle_version } => {
+                write!(f, "SettleVersionChanged, settle_version = {}", settle_version)
+            }
+            EventFrame::SettleConfirmed { settle_version } => {
+                write!(f, "SettleConfirmed, settle_version = {}", settle_version)
+            }
+            EventFrame::TransactorChanged { transactor_addr, .. } => {
+                write!(f, "TransactorChanged: {}", transactor_addr)
+            }
+            EventFrame::Divergence { report } => write!(
+                f,
+                "Divergence: expected {}, actual {}",
+                report.expected_state_sha, report.actual_state_sha
+            ),
             EventFrame::Settle { .. } => write!(f, "Settle"),
             EventFrame::Checkpoint { .. } => write!(f, "Checkpoint"),
             EventFrame::Broadcast { event, .. } => write!(f, "Broadcast: {}", event),
             EventFrame::SendMessage { message } => write!(f, "SendMessage: {}", message.sender),
             EventFrame::ContextUpdated { context: _ } => write!(f, "ContextUpdated"),
             EventFrame::Shutdown => write!(f, "Shutdown"),
+            EventFrame::GracefulShutdown => write!(f, "GracefulShutdown"),
+            EventFrame::FinalCheckpoint { .. } => write!(f, "FinalCheckpoint"),
             EventFrame::Vote { votee, vote_type } => {
                 write!(f, "Vote: to {} for {:?}", votee, vote_type)
             }
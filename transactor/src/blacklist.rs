@@ -12,31 +12,38 @@ pub struct Blacklist {
 
 const BLACKLIST_FILE: &str = ".blacklist";
 
-impl Blacklist {
-    pub fn new(persistent: bool) -> Self {
-        if persistent {
-            if let Ok(file) = std::fs::File::open(BLACKLIST_FILE) {
-                let lines = std::io::BufReader::new(file).lines();
-                if let Ok(addrs) = lines
-                    .into_iter()
-                    .collect::<Result<Vec<String>, _>>()
-                {
-                    return Blacklist {
-                        addrs,
-                        persistent: true,
-                    };
-                }
-            }
-            return Blacklist {
-                addrs: Vec::default(),
-                persistent: true,
-            };
+fn load_file() -> Vec<String> {
+    if let Ok(file) = std::fs::File::open(BLACKLIST_FILE) {
+        let lines = std::io::BufReader::new(file).lines();
+        if let Ok(addrs) = lines
+            .into_iter()
+            .collect::<Result<Vec<String>, _>>()
+        {
+            return addrs;
         }
+    }
+    Vec::default()
+}
 
-        Blacklist {
-            addrs: Vec::default(),
-            persistent: false,
+fn append_file(addr: &str) {
+    match OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(BLACKLIST_FILE)
+    {
+        Ok(mut file) => {
+            if let Err(e) = writeln!(file, "{}", addr) {
+                tracing::warn!("Open file .blacklist failed, due to {:?}", e)
+            }
         }
+        Err(e) => tracing::warn!("Open file .blacklist failed, due to {:?}", e),
+    }
+}
+
+impl Blacklist {
+    pub fn new(persistent: bool) -> Self {
+        let addrs = if persistent { load_file() } else { Vec::default() };
+        Blacklist { addrs, persistent }
     }
 
     pub fn add_addr<S: Into<String>>(&mut self, addr: S) {
@@ -44,23 +51,35 @@ impl Blacklist {
         tracing::info!("Save {} to blacklist", addr);
 
         if self.persistent {
-            match OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(BLACKLIST_FILE)
-            {
-                Ok(mut file) => {
-                    if let Err(e) = writeln!(file, "{}", addr) {
-                        tracing::warn!("Open file .blacklist failed, due to {:?}", e)
-                    }
-                }
-                Err(e) => tracing::warn!("Open file .blacklist failed, due to {:?}", e),
-            }
+            append_file(&addr);
         }
 
         self.addrs.push(addr)
     }
 
+    /// Toggle whether new addresses are saved to the file.  When it's
+    /// turned on, the addresses in the file are loaded, and those only
+    /// in memory are saved to the file.
+    pub fn set_persistent(&mut self, persistent: bool) {
+        if self.persistent == persistent {
+            return;
+        }
+        tracing::info!("Set blacklist persistent = {}", persistent);
+        self.persistent = persistent;
+
+        if persistent {
+            let saved = load_file();
+            for addr in self.addrs.iter().filter(|a| !saved.contains(a)) {
+                append_file(addr);
+            }
+            for addr in saved {
+                if !self.contains_addr(&addr) {
+                    self.addrs.push(addr);
+                }
+            }
+        }
+    }
+
     pub fn contains_addr(&self, addr: &str) -> bool {
         self.addrs.iter().any(|a| *a == addr)
     }
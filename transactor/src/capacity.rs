@@ -0,0 +1,139 @@
+//! The capacity of a transactor, to decide whether it can serve more
+//! games, and which ones.
+//!
+//! The limits gate the `serve` transactions sent by the registration
+//! tasks, the games already served are always loaded.  The tasks of
+//! all chains share the limits, see [crate::game_manager::GameManager::try_reserve].  A sub game is
+//! not launched once `max_sub_games` is reached.
+
+use race_core::types::GameAccount;
+use race_env::{CapacityConfig, SelectionPolicy};
+use serde::Serialize;
+
+/// The load of a transactor, advertised in `get_server_load`.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerLoad {
+    pub games: usize,
+    pub sub_games: usize,
+    pub wasm_instances: usize,
+    pub max_games: Option<usize>,
+    pub max_sub_games: Option<usize>,
+    pub max_wasm_instances: Option<usize>,
+}
+
+impl ServerLoad {
+    pub fn with_limits(mut self, config: Option<&CapacityConfig>) -> Self {
+        self.max_games = config.and_then(|c| c.max_games);
+        self.max_sub_games = config.and_then(|c| c.max_sub_games);
+        self.max_wasm_instances = config.and_then(|c| c.max_wasm_instances);
+        self
+    }
+
+    pub fn is_full(&self) -> bool {
+        let reached = |n: usize, max: Option<usize>| max.map(|m| n >= m).unwrap_or(false);
+        reached(self.games, self.max_games)
+            || reached(self.sub_games, self.max_sub_games)
+            || reached(self.wasm_instances, self.max_wasm_instances)
+    }
+}
+
+/// The total of the player balances and the pending deposits.
+pub fn game_stake(game_account: &GameAccount) -> u64 {
+    let balances: u64 = game_account.balances.iter().map(|b| b.balance).sum();
+    let deposits: u64 = game_account.deposits.iter().map(|d| d.amount).sum();
+    balances.saturating_add(deposits)
+}
+
+/// Order the candidate games by the selection policy, the ones not
+/// selectable are removed.
+pub fn select_games(
+    mut candidates: Vec<GameAccount>,
+    config: Option<&CapacityConfig>,
+) -> Vec<GameAccount> {
+    match config.and_then(|c| c.selection).unwrap_or_default() {
+        SelectionPolicy::FirstCome => (),
+        SelectionPolicy::BundleAllowlist => {
+            let allowlist = config
+                .and_then(|c| c.bundle_allowlist.as_ref())
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            candidates.retain(|g| allowlist.contains(&g.bundle_addr));
+        }
+        SelectionPolicy::Stake => {
+            // Stable, so games with equal stakes are first come
+            candidates.sort_by_key(|g| std::cmp::Reverse(game_stake(g)));
+        }
+    }
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use race_api::types::PlayerBalance;
+
+    use super::*;
+
+    fn make_game(addr: &str, bundle_addr: &str, stake: u64) -> GameAccount {
+        GameAccount {
+            addr: addr.into(),
+            bundle_addr: bundle_addr.into(),
+            balances: vec![PlayerBalance {
+                player_id: 1,
+                balance: stake,
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn make_config(selection: SelectionPolicy) -> CapacityConfig {
+        CapacityConfig {
+            max_games: Some(2),
+            max_sub_games: None,
+            max_wasm_instances: Some(1),
+            selection: Some(selection),
+            bundle_allowlist: Some(vec!["bundle_a".into()]),
+        }
+    }
+
+    fn addrs(games: &[GameAccount]) -> Vec<&str> {
+        games.iter().map(|g| g.addr.as_str()).collect()
+    }
+
+    #[test]
+    fn test_select_games() {
+        let candidates = vec![
+            make_game("g1", "bundle_a", 10),
+            make_game("g2", "bundle_b", 30),
+            make_game("g3", "bundle_a", 20),
+        ];
+
+        let selected = select_games(candidates.clone(), None);
+        assert_eq!(addrs(&selected), vec!["g1", "g2", "g3"]);
+
+        let config = make_config(SelectionPolicy::BundleAllowlist);
+        let selected = select_games(candidates.clone(), Some(&config));
+        assert_eq!(addrs(&selected), vec!["g1", "g3"]);
+
+        let config = make_config(SelectionPolicy::Stake);
+        let selected = select_games(candidates, Some(&config));
+        assert_eq!(addrs(&selected), vec!["g2", "g3", "g1"]);
+    }
+
+    #[test]
+    fn test_is_full() {
+        let config = make_config(SelectionPolicy::FirstCome);
+        let mut load = ServerLoad::default().with_limits(Some(&config));
+        assert!(!load.is_full());
+        load.games = 1;
+        assert!(!load.is_full());
+        load.wasm_instances = 1;
+        assert!(load.is_full());
+
+        let load = ServerLoad {
+            games: 100,
+            ..Default::default()
+        };
+        assert!(!load.is_full());
+    }
+}
@@ -0,0 +1,310 @@
+//! The chains served by a transactor.  Each chain has its own
+//! transport, server account, encryptor and registration task, the
+//! bundles, storage and RPC port are shared.
+//!
+//! Games are routed to their chains by the game manager, keyed by
+//! [GameKey].  In the RPC, a game address can be qualified with its
+//! chain, e.g. `sui:0x1234`.
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::Arc;
+
+use race_core::chain::ChainType;
+use race_core::error::{Error, Result};
+use race_core::transport::TransportT;
+use race_core::types::ServerAccount;
+use race_encryptor::{Encryptor, NodePrivateKey};
+use race_env::{ChainConfig, Config};
+use race_transactor_components::{DivergenceLog, WrappedStorage, WrappedTransport};
+use race_transport::TransportBuilder;
+use tracing::info;
+
+use crate::reg::RegHeartbeat;
+
+/// A game served by this transactor, its address on its chain.  The
+/// same address on two chains are two games.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GameKey {
+    pub chain: ChainType,
+    pub addr: String,
+}
+
+impl GameKey {
+    pub fn new<S: Into<String>>(chain: ChainType, addr: S) -> Self {
+        Self { chain, addr: addr.into() }
+    }
+}
+
+impl fmt::Display for GameKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.chain.to_string(), self.addr)
+    }
+}
+
+/// Load the keys of the server's encryptor from `credentials_file`,
+/// the file is created with new keys if it doesn't exist.  It holds
+/// the RSA key and the ECDSA key, one per line.  Without the file, new
+/// keys are generated.
+pub fn load_private_key(credentials_file: Option<&str>) -> Result<NodePrivateKey> {
+    let Some(path) = credentials_file else {
+        return Ok(NodePrivateKey::generate()?);
+    };
+
+    match fs::read_to_string(path) {
+        Ok(content) => {
+            let mut lines = content.lines();
+            let (Some(rsa), Some(ec)) = (lines.next(), lines.next()) else {
+                return Err(Error::IoError(format!("Malformed credentials file: {}", path)));
+            };
+            info!("Load encryptor keys from {}", path);
+            Ok(NodePrivateKey::import(rsa.trim(), ec.trim())?)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let private_key = NodePrivateKey::generate()?;
+            let (rsa, ec) = private_key.export()?;
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let mut file = options.open(path)?;
+            writeln!(file, "{}\n{}", rsa, ec)?;
+            info!("Save new encryptor keys to {}", path);
+            Ok(private_key)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub struct ChainContext {
+    pub chain: ChainType,
+    pub account: ServerAccount,
+    pub transport: Arc<WrappedTransport>,
+    pub encryptor: Arc<Encryptor>,
+    /// The view of the shared storage for the games on this chain.
+    pub storage: Arc<WrappedStorage>,
+    /// The reports made as the validator of the games on this chain.
+    pub divergence_log: Arc<DivergenceLog>,
+    pub reg_heartbeat: RegHeartbeat,
+}
+
+impl ChainContext {
+    async fn try_new(
+        config: &Config,
+        chain: ChainType,
+        chain_config: &ChainConfig,
+        storage: Arc<WrappedStorage>,
+    ) -> Result<Self> {
+        let transport = TransportBuilder::default()
+            .with_chain(chain)
+            .try_with_config(config)?
+            .build()
+            .await?;
+
+        let transport = Arc::new(WrappedTransport::try_new(transport).await?);
+
+        info!("Transactor wallet address on {}: {}", chain_config.chain, chain_config.address);
+
+        let account = transport
+            .get_server_account(&chain_config.address)
+            .await?
+            .ok_or(Error::ServerAccountMissing)?;
+
+        let private_key = load_private_key(chain_config.credentials_file.as_deref())?;
+        let encryptor = Arc::new(Encryptor::try_new(private_key)?);
+
+        Ok(Self {
+            chain,
+            account,
+            transport,
+            encryptor,
+            divergence_log: Arc::new(DivergenceLog::new(storage.clone())),
+            storage,
+            reg_heartbeat: RegHeartbeat::default(),
+        })
+    }
+}
+
+pub struct Chains {
+    // The first one is the chain in `TransactorConfig::chain`
+    chains: Vec<Arc<ChainContext>>,
+}
+
+impl Chains {
+    /// Build the chains, the games on the first chain keep their bare
+    /// addresses in `storage`, so the rows saved before more chains
+    /// were added are still found.
+    pub async fn try_new(config: &Config, storage: Arc<WrappedStorage>) -> Result<Self> {
+        let transactor_config = config
+            .transactor
+            .as_ref()
+            .ok_or(Error::TransactorConfigMissing)?;
+
+        let mut chains: Vec<Arc<ChainContext>> = vec![];
+        for chain_config in transactor_config.chain_configs() {
+            let chain = ChainType::from_name(&chain_config.chain).ok_or(Error::InvalidChainName)?;
+            if chains.iter().any(|c| c.chain == chain) {
+                return Err(Error::DuplicatedChain(chain_config.chain));
+            }
+            let chain_storage = if chains.is_empty() {
+                storage.clone()
+            } else {
+                Arc::new(storage.with_scope(&chain_config.chain))
+            };
+            let chain_context =
+                ChainContext::try_new(config, chain, &chain_config, chain_storage).await?;
+            chains.push(Arc::new(chain_context));
+        }
+
+        Ok(Self { chains })
+    }
+
+    pub fn get(&self, chain: ChainType) -> Result<&Arc<ChainContext>> {
+        self.chains
+            .iter()
+            .find(|c| c.chain == chain)
+            .ok_or_else(|| Error::ChainNotServed(chain.to_string()))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<ChainContext>> {
+        self.chains.iter()
+    }
+
+    /// Resolve a game address in the RPC, which can be qualified with
+    /// its chain.  See [resolve_game_key].
+    pub fn resolve_game_addr<F>(&self, addr: &str, is_known: F) -> Result<GameKey>
+    where
+        F: Fn(&GameKey) -> bool,
+    {
+        let served: Vec<ChainType> = self.chains.iter().map(|c| c.chain).collect();
+        resolve_game_key(&served, addr, is_known)
+    }
+
+    /// Return the address of a game in the shared storage and the
+    /// blacklist, unique across the chains.
+    pub fn scoped_addr(&self, key: &GameKey) -> String {
+        match self.get(key.chain) {
+            Ok(chain) => chain.storage.scoped_addr(&key.addr),
+            Err(_) => key.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Chains {
+    pub fn from_contexts(chains: Vec<Arc<ChainContext>>) -> Self {
+        Self { chains }
+    }
+}
+
+/// Resolve a game address in the RPC to its key.  A chain-qualified
+/// address must be on a `served` chain.  An address without its chain
+/// is on the chain where `is_known` finds it, or the first chain if
+/// it's found nowhere.  Return an error if it's found on more than
+/// one chain.
+fn resolve_game_key<F>(served: &[ChainType], addr: &str, is_known: F) -> Result<GameKey>
+where
+    F: Fn(&GameKey) -> bool,
+{
+    let (chain, game_addr) = parse_game_addr(addr);
+    if let Some(chain) = chain {
+        if !served.contains(&chain) {
+            return Err(Error::ChainNotServed(chain.to_string()));
+        }
+        return Ok(GameKey::new(chain, game_addr));
+    }
+
+    let mut keys = served
+        .iter()
+        .map(|chain| GameKey::new(*chain, game_addr))
+        .filter(|key| is_known(key));
+    match (keys.next(), keys.next()) {
+        (Some(key), None) => Ok(key),
+        (Some(_), Some(_)) => Err(Error::AmbiguousGameAddr(game_addr.to_owned())),
+        (None, _) => served
+            .first()
+            .map(|chain| GameKey::new(*chain, game_addr))
+            .ok_or(Error::GameNotLoaded),
+    }
+}
+
+/// Split a chain-qualified game address, e.g. `sui:0x1234`, into the
+/// chain and the game address.  An address without a known chain as
+/// its prefix is returned as is, e.g. the sub game address `0x1234:1`.
+pub fn parse_game_addr(addr: &str) -> (Option<ChainType>, &str) {
+    if let Some((prefix, game_addr)) = addr.split_once(':') {
+        if let Some(chain) = ChainType::from_name(prefix) {
+            return (Some(chain), game_addr);
+        }
+    }
+    (None, addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_game_addr() {
+        assert_eq!(parse_game_addr("sui:0x1234"), (Some(ChainType::Sui), "0x1234"));
+        assert_eq!(parse_game_addr("solana:GAME:1"), (Some(ChainType::Solana), "GAME:1"));
+        assert_eq!(parse_game_addr("0x1234"), (None, "0x1234"));
+        assert_eq!(parse_game_addr("0x1234:1"), (None, "0x1234:1"));
+    }
+
+    #[test]
+    fn test_game_key() {
+        let key = GameKey::new(ChainType::Sui, "0x1234");
+        assert_eq!(key.to_string(), "sui:0x1234");
+        assert_ne!(key, GameKey::new(ChainType::Solana, "0x1234"));
+    }
+
+    #[test]
+    fn test_resolve_game_key() {
+        let served = [ChainType::Solana, ChainType::Sui];
+        let none = |_: &GameKey| false;
+        assert_eq!(
+            resolve_game_key(&served, "sui:GAME", none).unwrap(),
+            GameKey::new(ChainType::Sui, "GAME")
+        );
+        assert!(matches!(
+            resolve_game_key(&served, "bnb:GAME", none),
+            Err(Error::ChainNotServed(_))
+        ));
+        // Not loaded anywhere, on the first chain
+        assert_eq!(
+            resolve_game_key(&served, "GAME", none).unwrap(),
+            GameKey::new(ChainType::Solana, "GAME")
+        );
+        let on_sui = |key: &GameKey| key.chain == ChainType::Sui;
+        assert_eq!(
+            resolve_game_key(&served, "GAME", on_sui).unwrap(),
+            GameKey::new(ChainType::Sui, "GAME")
+        );
+        let on_both = |_: &GameKey| true;
+        assert!(matches!(
+            resolve_game_key(&served, "GAME", on_both),
+            Err(Error::AmbiguousGameAddr(_))
+        ));
+        assert_eq!(
+            resolve_game_key(&served, "solana:GAME", on_both).unwrap(),
+            GameKey::new(ChainType::Solana, "GAME")
+        );
+    }
+
+    #[test]
+    fn test_load_private_key() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("race-credentials-{}", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+
+        let created = load_private_key(Some(path))?;
+        let loaded = load_private_key(Some(path))?;
+        assert_eq!(created.export()?, loaded.export()?);
+
+        fs::write(path, "malformed")?;
+        assert!(load_private_key(Some(path)).is_err());
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
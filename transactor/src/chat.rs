@@ -0,0 +1,333 @@
+//! Chat moderation.  Every submitted message goes through a chain of
+//! [MessageFilter]s before it's broadcast.  A filter can rewrite the
+//! message or reject it.
+//!
+//! Two filters are built in:
+//!
+//! - [ChatMembers], rejects the messages from the players muted by the
+//!   game owner, and the team messages from the players not in the
+//!   team.
+//! - [ProfanityFilter], masks the configured words with `*`.
+//!
+//! The mutes and the teams are set by the game owner, and saved to
+//! storage.  The subscribers only receive the messages visible to
+//! them, see [ChatModerator::is_visible].
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use race_api::event::{ChatMessage, MessageChannel};
+use race_core::error::{Error, Result};
+use race_core::storage::StorageT;
+use race_core::types::{ChatMember, GetChatMembersParams, SaveChatMemberParams};
+use race_env::ChatConfig;
+
+pub trait MessageFilter: Send + Sync {
+    /// Return the message to broadcast, or an error to reject it.
+    fn filter(&self, game_addr: &str, message: ChatMessage) -> Result<ChatMessage>;
+}
+
+/// The chat settings of the players, by game address.  A game's
+/// settings are loaded from storage when it's first used, and dropped
+/// from memory when the game is unloaded.
+pub struct ChatMembers {
+    storage: Arc<dyn StorageT>,
+    games: Mutex<HashMap<String, HashMap<String, ChatMember>>>,
+}
+
+impl ChatMembers {
+    pub fn new(storage: Arc<dyn StorageT>) -> Self {
+        Self {
+            storage,
+            games: Mutex::new(HashMap::default()),
+        }
+    }
+
+    /// Load the settings of a game, if not loaded yet.
+    pub async fn load_game(&self, game_addr: &str) -> Result<()> {
+        if self.games.lock().unwrap().contains_key(game_addr) {
+            return Ok(());
+        }
+        let members = self
+            .storage
+            .get_chat_members(GetChatMembersParams {
+                game_addr: game_addr.to_owned(),
+            })
+            .await?;
+        self.games
+            .lock()
+            .unwrap()
+            .entry(game_addr.to_owned())
+            .or_insert_with(|| {
+                members
+                    .into_iter()
+                    .map(|m| (m.player_addr.clone(), m))
+                    .collect()
+            });
+        Ok(())
+    }
+
+    /// Drop the settings of an unloaded game from memory.
+    pub fn unload_game(&self, game_addr: &str) {
+        self.games.lock().unwrap().remove(game_addr);
+    }
+
+    async fn update<F>(&self, game_addr: &str, player_addr: &str, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut ChatMember),
+    {
+        self.load_game(game_addr).await?;
+        let mut member = self.get(game_addr, player_addr).unwrap_or_else(|| ChatMember {
+            player_addr: player_addr.to_owned(),
+            ..Default::default()
+        });
+        f(&mut member);
+
+        self.storage
+            .save_chat_member(SaveChatMemberParams {
+                game_addr: game_addr.to_owned(),
+                member: member.clone(),
+            })
+            .await?;
+
+        let mut games = self.games.lock().unwrap();
+        let players = games.entry(game_addr.to_owned()).or_default();
+        if member.muted || member.team.is_some() {
+            players.insert(player_addr.to_owned(), member);
+        } else {
+            players.remove(player_addr);
+        }
+        Ok(())
+    }
+
+    pub async fn set_muted(&self, game_addr: &str, player_addr: &str, muted: bool) -> Result<()> {
+        self.update(game_addr, player_addr, |m| m.muted = muted).await
+    }
+
+    pub async fn set_team(
+        &self,
+        game_addr: &str,
+        player_addr: &str,
+        team: Option<String>,
+    ) -> Result<()> {
+        self.update(game_addr, player_addr, |m| m.team = team).await
+    }
+
+    fn get(&self, game_addr: &str, player_addr: &str) -> Option<ChatMember> {
+        self.games
+            .lock()
+            .unwrap()
+            .get(game_addr)
+            .and_then(|players| players.get(player_addr))
+            .cloned()
+    }
+
+    pub fn is_muted(&self, game_addr: &str, player_addr: &str) -> bool {
+        self.get(game_addr, player_addr).map_or(false, |m| m.muted)
+    }
+
+    pub fn team_of(&self, game_addr: &str, player_addr: &str) -> Option<String> {
+        self.get(game_addr, player_addr).and_then(|m| m.team)
+    }
+}
+
+impl MessageFilter for ChatMembers {
+    fn filter(&self, game_addr: &str, message: ChatMessage) -> Result<ChatMessage> {
+        if self.is_muted(game_addr, &message.sender) {
+            return Err(Error::PlayerMuted);
+        }
+        if let MessageChannel::Team(ref team) = message.channel {
+            if self.team_of(game_addr, &message.sender).as_ref() != Some(team) {
+                return Err(Error::NotTeamMember);
+            }
+        }
+        Ok(message)
+    }
+}
+
+/// Mask the listed words in message content.  Words are matched
+/// case-insensitively on alphanumeric boundaries.
+pub struct ProfanityFilter {
+    words: HashSet<String>,
+}
+
+impl ProfanityFilter {
+    pub fn new<S: AsRef<str>>(words: &[S]) -> Self {
+        Self {
+            words: words.iter().map(|w| w.as_ref().to_lowercase()).collect(),
+        }
+    }
+
+    fn push_word(&self, content: &mut String, word: &str) {
+        if self.words.contains(&word.to_lowercase()) {
+            content.extend(word.chars().map(|_| '*'));
+        } else {
+            content.push_str(word);
+        }
+    }
+
+    fn mask(&self, content: &str) -> String {
+        let mut ret = String::with_capacity(content.len());
+        let mut word = String::new();
+        for c in content.chars() {
+            if c.is_alphanumeric() {
+                word.push(c);
+            } else {
+                self.push_word(&mut ret, &word);
+                word.clear();
+                ret.push(c);
+            }
+        }
+        self.push_word(&mut ret, &word);
+        ret
+    }
+}
+
+impl MessageFilter for ProfanityFilter {
+    fn filter(&self, _game_addr: &str, mut message: ChatMessage) -> Result<ChatMessage> {
+        message.content = self.mask(&message.content);
+        Ok(message)
+    }
+}
+
+pub struct ChatModerator {
+    members: Arc<ChatMembers>,
+    filters: Vec<Arc<dyn MessageFilter>>,
+}
+
+impl ChatModerator {
+    pub fn new(config: Option<&ChatConfig>, storage: Arc<dyn StorageT>) -> Self {
+        let members = Arc::new(ChatMembers::new(storage));
+        let mut filters: Vec<Arc<dyn MessageFilter>> = vec![members.clone()];
+        if let Some(words) = config.and_then(|c| c.profanity_words.as_ref()) {
+            if !words.is_empty() {
+                filters.push(Arc::new(ProfanityFilter::new(words)));
+            }
+        }
+        Self { members, filters }
+    }
+
+    /// Append a filter to the end of the chain.
+    #[allow(unused)]
+    pub fn with_filter(mut self, filter: Arc<dyn MessageFilter>) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    pub fn members(&self) -> &ChatMembers {
+        &self.members
+    }
+
+    /// Run `message` through all filters in order.
+    pub async fn moderate(&self, game_addr: &str, message: ChatMessage) -> Result<ChatMessage> {
+        self.members.load_game(game_addr).await?;
+        self.filters
+            .iter()
+            .try_fold(message, |message, f| f.filter(game_addr, message))
+    }
+
+    /// Return true if `message` can be delivered to `viewer`.  Table
+    /// messages are visible to everyone, team messages to the team
+    /// members, and direct messages to the sender and the receiver.
+    /// An anonymous viewer only sees table messages.  The settings of
+    /// the game must be loaded with [ChatMembers::load_game].
+    pub fn is_visible(&self, game_addr: &str, message: &ChatMessage, viewer: Option<&str>) -> bool {
+        let Some(viewer) = viewer else {
+            return message.channel == MessageChannel::Table;
+        };
+        match message.channel {
+            MessageChannel::Table => true,
+            _ if viewer == message.sender => true,
+            MessageChannel::Team(ref team) => {
+                self.members.team_of(game_addr, viewer).as_ref() == Some(team)
+            }
+            MessageChannel::Direct(ref receiver) => receiver == viewer,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use race_local_db::LocalDbStorage;
+
+    use super::*;
+
+    fn make_message(sender: &str, content: &str, channel: MessageChannel) -> ChatMessage {
+        ChatMessage {
+            sender: sender.into(),
+            content: content.into(),
+            channel,
+        }
+    }
+
+    fn make_moderator(storage: Arc<dyn StorageT>) -> ChatModerator {
+        ChatModerator::new(
+            Some(&ChatConfig {
+                profanity_words: Some(vec!["Darn".into()]),
+            }),
+            storage,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_moderate() {
+        let storage: Arc<dyn StorageT> = Arc::new(LocalDbStorage::try_new_mem().unwrap());
+        let moderator = make_moderator(storage.clone());
+        let table = MessageChannel::Table;
+
+        let message = moderator
+            .moderate("game", make_message("alice", "darn it, DARN! darnit", table.clone()))
+            .await
+            .unwrap();
+        assert_eq!(message.content, "**** it, ****! darnit");
+
+        moderator.members().set_muted("game", "alice", true).await.unwrap();
+        assert_eq!(
+            moderator.moderate("game", make_message("alice", "hi", table.clone())).await,
+            Err(Error::PlayerMuted)
+        );
+        assert!(moderator.moderate("other", make_message("alice", "hi", table.clone())).await.is_ok());
+
+        // The mute is loaded from storage after a restart
+        let moderator = make_moderator(storage);
+        assert_eq!(
+            moderator.moderate("game", make_message("alice", "hi", table.clone())).await,
+            Err(Error::PlayerMuted)
+        );
+
+        moderator.members().set_muted("game", "alice", false).await.unwrap();
+        assert!(moderator.moderate("game", make_message("alice", "hi", table)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_visibility() {
+        let storage: Arc<dyn StorageT> = Arc::new(LocalDbStorage::try_new_mem().unwrap());
+        let moderator = make_moderator(storage);
+        let members = moderator.members();
+        members.set_team("game", "alice", Some("red".into())).await.unwrap();
+        members.set_team("game", "bob", Some("red".into())).await.unwrap();
+        members.set_team("game", "carol", Some("blue".into())).await.unwrap();
+
+        let red = MessageChannel::Team("red".into());
+        assert_eq!(
+            moderator.moderate("game", make_message("carol", "hi", red.clone())).await,
+            Err(Error::NotTeamMember)
+        );
+        let team_message = moderator
+            .moderate("game", make_message("alice", "hi", red))
+            .await
+            .unwrap();
+        assert!(moderator.is_visible("game", &team_message, Some("bob")));
+        assert!(!moderator.is_visible("game", &team_message, Some("carol")));
+        assert!(!moderator.is_visible("game", &team_message, None));
+
+        let direct = make_message("alice", "hi", MessageChannel::Direct("carol".into()));
+        assert!(moderator.is_visible("game", &direct, Some("alice")));
+        assert!(moderator.is_visible("game", &direct, Some("carol")));
+        assert!(!moderator.is_visible("game", &direct, Some("bob")));
+        assert!(!moderator.is_visible("game", &direct, None));
+
+        let table = make_message("alice", "hi", MessageChannel::Table);
+        assert!(moderator.is_visible("game", &table, None));
+    }
+}
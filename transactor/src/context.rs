@@ -1,33 +1,51 @@
 use crate::blacklist::Blacklist;
+use crate::chains::{Chains, GameKey};
+use crate::chat::ChatModerator;
+use crate::capacity::ServerLoad;
 use crate::game_manager::{ServingGame, GameManager};
-use race_api::event::{Event, Message};
+use crate::native::load_native_handlers;
+use crate::rate_limit::RateLimiter;
+use crate::session::SessionManager;
+use crate::spectator::SpectatorRegistry;
+use race_api::event::{ChatMessage, Event};
+use race_core::checkpoint::CheckpointOffChain;
 use race_core::error::{Error, Result};
 use race_core::encryptor::EncryptorT;
 use race_core::transport::TransportT;
-use race_core::types::{BroadcastFrame, ServerAccount, Signature};
-use race_transport::TransportBuilder;
-use race_encryptor::Encryptor;
+use race_core::types::{BroadcastFrame, EventCursor, GameAccount, Signature};
 use race_env::{Config, TransactorConfig};
-use race_transactor_components::{CheckpointBroadcastFrame, CloseReason, WrappedStorage, WrappedTransport};
+use race_handler::{ModuleCache, WasmLimits};
+use race_transactor_components::{CheckpointBroadcastFrame, CloseReason, WrappedStorage};
 use race_transactor_frames::SignalFrame;
-use race_core::chain::ChainType;
+use futures::future::join_all;
+use futures::stream::{FuturesUnordered, StreamExt};
 use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::{broadcast, mpsc, watch, Mutex};
-use tracing::{error, info};
+use tracing::{error, info, warn};
+
+// The default for seconds to wait for games to finish on shutdown.
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 60;
 
 /// Transactor runtime context
 pub struct ApplicationContext {
+    /// The configuration at startup.  Use [ApplicationContext::current_config]
+    /// for the values which can be reloaded.
     pub config: TransactorConfig,
-    #[allow(unused)]
-    pub chain: ChainType,
-    pub account: ServerAccount,
-    pub transport: Arc<WrappedTransport>,
+    config_tx: Arc<watch::Sender<TransactorConfig>>,
+    pub chains: Arc<Chains>,
     pub storage: Arc<WrappedStorage>,
-    pub encryptor: Arc<Encryptor>,
     pub game_manager: Arc<GameManager>,
     pub signal_tx: mpsc::Sender<SignalFrame>,
     pub blacklist: Arc<Mutex<Blacklist>>,
+    pub module_cache: Arc<ModuleCache>,
+    pub rate_limiter: RateLimiter,
+    pub chat: Arc<ChatModerator>,
+    pub sessions: Arc<SessionManager>,
+    pub spectators: Arc<SpectatorRegistry>,
     pub shutdown_rx: watch::Receiver<bool>,
 }
 
@@ -37,36 +55,13 @@ impl ApplicationContext {
 
         let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
-        let chain: &str = &config
-            .transactor
-            .as_ref()
-            .expect("Missing transactor configuration")
-            .chain;
-
-        let transport = TransportBuilder::default()
-            .with_chain_by_name(chain)
-            .try_with_config(&config)?
-            .build()
-            .await?;
-
-        let transport = Arc::new(WrappedTransport::try_new(transport).await?);
-
         let storage = Arc::new(WrappedStorage::try_new(&config).await?);
 
-        let encryptor = Arc::new(Encryptor::default());
+        let chains = Arc::new(Chains::try_new(&config, storage.clone()).await?);
 
         let transactor_config = config.transactor.ok_or(Error::TransactorConfigMissing)?;
 
-        let chain: ChainType = transactor_config.chain.as_str().into();
-
-        info!("Transactor wallet address: {}", transactor_config.address);
-
-        let account = transport
-            .get_server_account(&transactor_config.address)
-            .await?
-            .ok_or(Error::ServerAccountMissing)?;
-
-        let game_manager = Arc::new(GameManager::default());
+        let game_manager = Arc::new(GameManager::new(chains.clone()));
 
         let (signal_tx, signal_rx) = mpsc::channel(3);
 
@@ -74,16 +69,38 @@ impl ApplicationContext {
             transactor_config.disable_blacklist.ne(&(Some(true))),
         )));
 
+        let max_memory_pages = transactor_config
+            .handler
+            .as_ref()
+            .and_then(|c| c.max_memory_pages)
+            .unwrap_or(WasmLimits::default().max_memory_pages);
+        let module_cache = Arc::new(
+            ModuleCache::new(
+                max_memory_pages,
+                transactor_config.bundle_dir.as_ref().map(PathBuf::from),
+            )
+            .with_native_handlers(load_native_handlers(&transactor_config)),
+        );
+
+        let rate_limiter = RateLimiter::new(transactor_config.rate_limit.as_ref());
+
+        let chat = Arc::new(ChatModerator::new(transactor_config.chat.as_ref(), storage.clone()));
+
+        let (config_tx, _) = watch::channel(transactor_config.clone());
+
         let ctx = Self {
             config: transactor_config,
-            chain,
-            account,
-            transport,
+            config_tx: Arc::new(config_tx),
+            chains,
             storage,
-            encryptor,
             game_manager,
             signal_tx,
             blacklist,
+            module_cache,
+            rate_limiter,
+            chat,
+            sessions: Arc::new(SessionManager::default()),
+            spectators: Arc::new(SpectatorRegistry::default()),
             shutdown_rx,
         };
 
@@ -97,132 +114,406 @@ impl ApplicationContext {
         info!("Starting signal loop");
 
         let game_manager_0 = self.game_manager.clone();
-        let transport_0 = self.transport.clone();
-        let storage_0 = self.storage.clone();
-        let encryptor_0 = self.encryptor.clone();
-        let account_0 = self.account.clone();
+        let chains_0 = self.chains.clone();
+        let module_cache_0 = self.module_cache.clone();
         let blacklist_0 = self.blacklist.clone();
         let signal_tx_0 = self.signal_tx.clone();
-        let config_0 = self.config.clone();
+        let chat_0 = self.chat.clone();
+        let config_rx_0 = self.subscribe_config();
 
         tokio::spawn(async move {
-            let mut join_handles: Vec<JoinHandle<CloseReason>> = vec![];
-
-            while let Some(signal) = signal_rx.recv().await {
+            let mut join_handles: Vec<(GameKey, JoinHandle<CloseReason>)> = vec![];
+            let mut replaced: HashSet<GameKey> = HashSet::new();
+            // Takeovers and relaunches run in their own tasks, as they
+            // may retry for seconds.  The value tells if a `RemoveGame`
+            // of the game arrived while the task was running.
+            let mut relaunching: HashMap<GameKey, bool> = HashMap::new();
+            let mut relaunches = FuturesUnordered::new();
+
+            loop {
+                let signal = tokio::select! {
+                    signal = signal_rx.recv() => match signal {
+                        Some(signal) => signal,
+                        None => break,
+                    },
+                    Some(relaunched) = relaunches.next(), if !relaunches.is_empty() => {
+                        let (key, join_handle): (GameKey, Option<JoinHandle<CloseReason>>) = match relaunched {
+                            Ok(r) => r,
+                            Err(e) => {
+                                error!("Error in relaunching game: {}", e);
+                                continue;
+                            }
+                        };
+                        let removed = relaunching.remove(&key).unwrap_or(false);
+                        if let Some(join_handle) = join_handle {
+                            // The validator handle sends RemoveGame
+                            // after it stops, which must not remove
+                            // the new handle.
+                            if removed {
+                                info!("Validator of game {} stopped", key);
+                            } else {
+                                replaced.insert(key.clone());
+                            }
+                            join_handles.push((key, join_handle));
+                        } else if removed {
+                            info!("Unload game {}", key);
+                            game_manager_0.remove_game(&key).await;
+                            chat_0.members().unload_game(&chains_0.scoped_addr(&key));
+                        }
+                        continue;
+                    }
+                };
 
                 let game_manager_1 = game_manager_0.clone();
-                let transport_1 = transport_0.clone();
-                let storage_1 = storage_0.clone();
-                let encryptor_1 = encryptor_0.clone();
-                let account_1 = account_0.clone();
+                let module_cache_1 = module_cache_0.clone();
                 let blacklist_1 = blacklist_0.clone();
                 let signal_tx_1 = signal_tx_0.clone();
+                // New games are launched with the latest configuration
+                let config_1 = config_rx_0.borrow().clone();
 
                 match signal {
-                    SignalFrame::StartGame { game_addr, mode } => {
+                    SignalFrame::StartGame { game_addr, mode, chain } => {
+                        let key = GameKey::new(chain, game_addr);
                         if let Some(join_handle) = game_manager_1
                             .launch_game(
-                                game_addr,
-                                transport_1.clone(),
-                                storage_1.clone(),
-                                encryptor_1.clone(),
-                                &account_1,
+                                key.clone(),
                                 blacklist_1.clone(),
                                 signal_tx_1.clone(),
                                 mode,
-                                &config_0,
+                                module_cache_1.clone(),
+                                &config_1,
                             )
                             .await {
-                                join_handles.push(join_handle);
+                                join_handles.push((key, join_handle));
                             }
                     }
-                    SignalFrame::LaunchSubGame { checkpoint, bridge_to_parent } => {
+                    SignalFrame::LaunchSubGame { checkpoint, bridge_to_parent, chain } => {
+                        let game_spec = &checkpoint.root_data().game_spec;
+                        let key = GameKey::new(chain, format!("{}:{}", game_spec.game_addr, game_spec.game_id));
                         if let Some(join_handle) = game_manager_1
                             .launch_sub_game(
                                 checkpoint,
                                 bridge_to_parent,
-                                &account_1,
-                                transport_1.clone(),
-                                encryptor_1.clone(),
-                                storage_1.clone(),
+                                chain,
                                 signal_tx_1.clone(),
-                                &config_0,
+                                module_cache_1.clone(),
+                                &config_1,
                             )
                             .await {
-                                join_handles.push(join_handle);
+                                join_handles.push((key, join_handle));
                             }
                     }
 
+                    SignalFrame::Takeover { game_addr, chain, checkpoint, history } => {
+                        let key = GameKey::new(chain, game_addr);
+                        info!("Take over game {}", key);
+                        relaunching.insert(key.clone(), false);
+                        relaunches.push(tokio::spawn(async move {
+                            let join_handle = game_manager_1
+                                .takeover_game(
+                                    key.clone(),
+                                    checkpoint,
+                                    history,
+                                    blacklist_1,
+                                    signal_tx_1,
+                                    module_cache_1,
+                                    &config_1,
+                                )
+                                .await;
+                            (key, join_handle)
+                        }));
+                    }
+
+                    SignalFrame::FollowTransactor { game_addr, chain } => {
+                        let key = GameKey::new(chain, game_addr);
+                        info!("Follow the new transactor of game {}", key);
+                        relaunching.insert(key.clone(), false);
+                        relaunches.push(tokio::spawn(async move {
+                            let join_handle = game_manager_1
+                                .follow_transactor(
+                                    key.clone(),
+                                    blacklist_1,
+                                    signal_tx_1,
+                                    module_cache_1,
+                                    &config_1,
+                                )
+                                .await;
+                            (key, join_handle)
+                        }));
+                    }
+
                     SignalFrame::Shutdown => {
+                        info!("Shutdown transactor, stop accepting events");
                         game_manager_1.shutdown().await;
-                        shutdown_tx.send(true).expect("Set shutdown flag");
                         break;
                     }
 
-                    SignalFrame::RemoveGame { game_addr } => {
-                        info!("Unload game {}", game_addr);
-                        game_manager_1.remove_game(&game_addr).await;
+                    SignalFrame::RemoveGame { game_addr, chain } => {
+                        let key = GameKey::new(chain, game_addr);
+                        if replaced.remove(&key) {
+                            info!("Validator of game {} stopped", key);
+                            continue;
+                        }
+                        // Decided when the relaunch finishes
+                        if let Some(removed) = relaunching.get_mut(&key) {
+                            *removed = true;
+                            continue;
+                        }
+                        info!("Unload game {}", key);
+                        game_manager_1.remove_game(&key).await;
+                        chat_0.members().unload_game(&chains_0.scoped_addr(&key));
                     }
                 }
             }
 
-            info!("Waiting game handles to finish...");
+            // The relaunches in progress skip once the game manager
+            // is shutting down, or return a handle to wait for.
+            while let Some(relaunched) = relaunches.next().await {
+                if let Ok((key, Some(join_handle))) = relaunched {
+                    join_handles.push((key, join_handle));
+                }
+            }
+
+            let timeout = config_rx_0.borrow().shutdown_timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT);
+            info!("Waiting {} game handles to finish in {} seconds...", join_handles.len(), timeout);
+            let deadline = Instant::now() + Duration::from_secs(timeout);
 
-            for join_handle in join_handles {
-                if let Err(e) = join_handle.await {
-                    error!("Error in waiting game handles: {}", e);
+            let waits = join_all(join_handles.into_iter().map(|(key, mut join_handle)| async move {
+                match tokio::time::timeout_at(deadline, &mut join_handle).await {
+                    Ok(Ok(CloseReason::Complete)) => None,
+                    Ok(Ok(CloseReason::Fault(e))) => {
+                        warn!("Game {} stopped with error: {}", key, e);
+                        None
+                    }
+                    Ok(Err(e)) => {
+                        error!("Error in waiting game handle {}: {}", key, e);
+                        Some(key)
+                    }
+                    Err(_) => {
+                        join_handle.abort();
+                        Some(key)
+                    }
                 }
+            }));
+            tokio::pin!(waits);
+
+            // Keep receiving signals, so the games are not blocked on
+            // sending `RemoveGame`.
+            let unfinished: Vec<GameKey> = loop {
+                tokio::select! {
+                    r = &mut waits => break r.into_iter().flatten().collect(),
+                    _ = signal_rx.recv() => (),
+                }
+            };
+
+            if unfinished.is_empty() {
+                info!("All game handles stopped");
+            } else {
+                warn!("Games not finished before the deadline: {:?}", unfinished);
+                game_manager_0.force_shutdown(&unfinished).await;
             }
-            info!("All game handles stopped");
+
+            shutdown_tx.send(true).expect("Set shutdown flag");
         })
     }
 
-    pub fn verify(&self, arg: &[u8], signature: &Signature) -> Result<()> {
-        self.encryptor.verify(arg, signature)?;
+    /// Verify a signature with the encryptor of the game's chain,
+    /// where the credentials of its players are loaded.
+    pub fn verify(&self, key: &GameKey, arg: &[u8], signature: &Signature) -> Result<()> {
+        self.chains.get(key.chain)?.encryptor.verify(arg, signature)?;
         Ok(())
     }
 
+    /// Resolve a game address in the RPC, which can be qualified with
+    /// its chain, e.g. `sui:0x1234`.
+    pub fn resolve_game_addr(&self, game_addr: &str) -> Result<GameKey> {
+        self.game_manager.resolve_game_addr(game_addr)
+    }
+
+    /// Return the address of a game in the chat and the blacklist.
+    pub fn scoped_addr(&self, key: &GameKey) -> String {
+        self.chains.scoped_addr(key)
+    }
+
     /// Return if the game is loaded.
     #[allow(unused)]
-    pub async fn is_game_loaded(&self, game_addr: &str) -> bool {
-        self.game_manager.is_game_loaded(game_addr).await
+    pub async fn is_game_loaded(&self, key: &GameKey) -> bool {
+        self.game_manager.is_game_loaded(key).await
     }
 
-    pub async fn eject_player(&self, game_addr: &str, player_addr: &str) -> Result<()> {
-        self.game_manager.eject_player(game_addr, player_addr).await
+    /// Eject a player from a game, an idle game is reloaded first.
+    pub async fn eject_player(&self, key: &GameKey, player_addr: &str) -> Result<()> {
+        self.wake_game(key).await?;
+        self.game_manager.eject_player(key, player_addr).await
     }
 
-    pub async fn send_event(&self, game_addr: &str, event: Event) -> Result<()> {
-        self.game_manager.send_event(game_addr, event).await
+    /// Send an event to a game, an idle game is reloaded first.
+    pub async fn send_event(&self, key: &GameKey, event: Event) -> Result<()> {
+        self.wake_game(key).await?;
+        self.game_manager.send_event(key, event).await
     }
 
-    pub async fn send_message(&self, game_addr: &str, message: Message) -> Result<()> {
-        self.game_manager.send_message(game_addr, message).await
+    /// Send a chat message to a game, an idle game is reloaded first.
+    pub async fn send_message(&self, key: &GameKey, message: ChatMessage) -> Result<()> {
+        self.wake_game(key).await?;
+        self.game_manager.send_message(key, message).await
+    }
+
+    /// Get the checkpoint of a game at `settle_version`, an idle game
+    /// is reloaded first.
+    pub async fn get_checkpoint(
+        &self,
+        key: &GameKey,
+        settle_version: u64,
+    ) -> Result<Option<CheckpointOffChain>> {
+        self.wake_game(key).await?;
+        self.game_manager.get_checkpoint(key, settle_version).await
+    }
+
+    /// Get the latest checkpoint of a game, an idle game is reloaded
+    /// first.
+    pub async fn get_latest_checkpoint(&self, key: &GameKey) -> Result<Option<CheckpointOffChain>> {
+        self.wake_game(key).await?;
+        self.game_manager.get_latest_checkpoint(key).await
+    }
+
+    /// The recent chat messages of a game, of all channels.
+    pub async fn get_chat_history(&self, key: &GameKey) -> Result<Vec<ChatMessage>> {
+        self.game_manager.get_chat_history(key).await
+    }
+
+    /// Check that `signer` owns the game.
+    async fn get_game_account(&self, key: &GameKey) -> Result<GameAccount> {
+        self.chains
+            .get(key.chain)?
+            .transport
+            .get_game_account(&key.addr)
+            .await?
+            .ok_or(Error::GameAccountNotFound)
+    }
+
+    async fn check_game_owner(&self, key: &GameKey, signer: &str) -> Result<()> {
+        let game_account = self.get_game_account(key).await?;
+        if game_account.owner_addr != signer {
+            return Err(Error::NotGameOwner);
+        }
+        Ok(())
+    }
+
+    /// Check that `addr` is a player, a server or the owner of the
+    /// game, who can receive the event stream without delay.
+    pub async fn check_game_participant(&self, key: &GameKey, addr: &str) -> Result<()> {
+        let game_account = self.get_game_account(key).await?;
+        let is_participant = game_account.owner_addr == addr
+            || game_account.players.iter().any(|p| p.addr == addr)
+            || game_account.servers.iter().any(|s| s.addr == addr);
+        if !is_participant {
+            return Err(Error::NotGameParticipant);
+        }
+        Ok(())
+    }
+
+    /// Mute or unmute a player in the chat of a game.  Only the game
+    /// owner is allowed to do this.
+    pub async fn mute_player(
+        &self,
+        key: &GameKey,
+        signer: &str,
+        player_addr: &str,
+        muted: bool,
+    ) -> Result<()> {
+        self.check_game_owner(key, signer).await?;
+        self.chat
+            .members()
+            .set_muted(&self.scoped_addr(key), player_addr, muted)
+            .await
+    }
+
+    /// Put a player in a chat team of a game, or remove it from its
+    /// team.  Only the game owner is allowed to do this.
+    pub async fn set_player_team(
+        &self,
+        key: &GameKey,
+        signer: &str,
+        player_addr: &str,
+        team: Option<String>,
+    ) -> Result<()> {
+        self.check_game_owner(key, signer).await?;
+        self.chat
+            .members()
+            .set_team(&self.scoped_addr(key), player_addr, team)
+            .await
     }
 
     pub async fn get_serving_games(&self) -> Vec<ServingGame> {
-        self.game_manager.get_serving_games().await
+        let mut games = self.game_manager.get_serving_games().await;
+        for game in games.iter_mut() {
+            game.set_spectators(self.spectators.count(&game.key().to_string()));
+        }
+        games
+    }
+
+    /// Return the current load with the limits.
+    pub async fn get_server_load(&self) -> ServerLoad {
+        self.game_manager
+            .get_load()
+            .with_limits(self.current_config().capacity.as_ref())
+    }
+
+    /// Reload a game unloaded for being idle, see [GameManager::wake_game].
+    pub async fn wake_game(&self, key: &GameKey) -> Result<()> {
+        self.game_manager.wake_game(key, &self.signal_tx).await
     }
 
+    /// Get the broadcast channel and backlogs of a game, an idle game
+    /// is reloaded first.
     pub async fn get_broadcast_and_backlogs(
         &self,
-        game_addr: &str,
+        key: &GameKey,
         settle_version: u64,
-    ) -> Result<(broadcast::Receiver<BroadcastFrame>, BroadcastFrame)> {
+        resume: Option<&EventCursor>,
+    ) -> Result<(mpsc::Receiver<BroadcastFrame>, BroadcastFrame)> {
+        self.wake_game(key).await?;
         self.game_manager
-            .get_broadcast_and_backlogs(game_addr, settle_version)
+            .get_broadcast_and_backlogs(key, settle_version, resume)
             .await
     }
 
     pub async fn get_broadcast_and_checkpoint(
         &self,
-        game_addr: &str,
+        key: &GameKey,
     ) -> Result<(broadcast::Receiver<CheckpointBroadcastFrame>, CheckpointBroadcastFrame)> {
         self.game_manager
-            .get_broadcast_and_checkpoint(game_addr)
+            .get_broadcast_and_checkpoint(key)
             .await
     }
 
+    pub async fn get_spectator_channels(
+        &self,
+        key: &GameKey,
+    ) -> Result<(
+        mpsc::Receiver<BroadcastFrame>,
+        broadcast::Receiver<CheckpointBroadcastFrame>,
+        BroadcastFrame,
+    )> {
+        self.game_manager.get_spectator_channels(key).await
+    }
+
+    /// Return the configuration with reloaded values applied.
+    pub fn current_config(&self) -> TransactorConfig {
+        self.config_tx.borrow().clone()
+    }
+
+    pub fn subscribe_config(&self) -> watch::Receiver<TransactorConfig> {
+        self.config_tx.subscribe()
+    }
+
+    /// Return the sender to apply a reloaded configuration.  The
+    /// caller must make sure only the reloadable fields are changed.
+    pub fn get_config_sender(&self) -> Arc<watch::Sender<TransactorConfig>> {
+        self.config_tx.clone()
+    }
+
     pub fn get_signal_sender(&self) -> mpsc::Sender<SignalFrame> {
         self.signal_tx.clone()
     }
@@ -0,0 +1,153 @@
+//! The encodings of RPC arguments and results.
+//!
+//! - [Encoding::Borsh], the default.  Arguments and subscription items
+//!   are base64 strings of borsh bytes, results are borsh bytes.
+//! - [Encoding::Json], used by the methods with `json_` prefix.  All
+//!   values are camelCase JSON.
+//!
+//! A signature is always made on the borsh bytes of the argument, so
+//! in JSON mode the argument is serialized with borsh to be verified.
+//! JSON clients usually sign only `CreateSessionParams`, and
+//! authenticate the other requests by session.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use jsonrpsee::core::error::Error as RpcError;
+use jsonrpsee::types::error::CallError;
+use jsonrpsee::SubscriptionMessage;
+use race_core::types::{Signature, SESSION_PREFIX};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::utils;
+
+fn invalid_params<E: Into<anyhow::Error>>(e: E) -> RpcError {
+    RpcError::Call(CallError::InvalidParams(e.into()))
+}
+
+fn failed<E: Into<anyhow::Error>>(e: E) -> RpcError {
+    RpcError::Call(CallError::Failed(e.into()))
+}
+
+/// How a signed request is authenticated.
+pub enum Credential {
+    Signature(Signature),
+    /// A session token, with the nonce and the proof of the request.
+    Session {
+        token: String,
+        nonce: u64,
+        proof: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Borsh,
+    Json,
+}
+
+impl Encoding {
+    /// Decode an argument.  Return it with its borsh bytes.
+    pub fn decode_arg<T>(self, value: Value) -> Result<(T, Vec<u8>), RpcError>
+    where
+        T: BorshSerialize + BorshDeserialize + DeserializeOwned,
+    {
+        match self {
+            Encoding::Borsh => {
+                let s: String = serde_json::from_value(value).map_err(invalid_params)?;
+                let bs = utils::base64_decode(&s).map_err(invalid_params)?;
+                let arg = T::try_from_slice(&bs).map_err(invalid_params)?;
+                Ok((arg, bs))
+            }
+            Encoding::Json => {
+                let arg: T = serde_json::from_value(value).map_err(invalid_params)?;
+                let bs = borsh::to_vec(&arg).map_err(invalid_params)?;
+                Ok((arg, bs))
+            }
+        }
+    }
+
+    /// Decode a credential.  A session is `session:<token>` in both
+    /// encodings, a signature is encoded like an argument.
+    pub fn decode_credential(self, value: Value) -> Result<Credential, RpcError> {
+        if let Some(s) = value.as_str().and_then(|s| s.strip_prefix(SESSION_PREFIX)) {
+            let mut parts = s.splitn(3, ':');
+            let (Some(token), Some(nonce), Some(proof)) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(invalid_params(anyhow::anyhow!("Malformed session credential")));
+            };
+            return Ok(Credential::Session {
+                token: token.to_owned(),
+                nonce: nonce.parse().map_err(invalid_params)?,
+                proof: proof.to_owned(),
+            });
+        }
+        self.decode_arg(value)
+            .map(|(signature, _)| Credential::Signature(signature))
+    }
+
+    /// Encode a method result.  In borsh mode it's the bytes, not a
+    /// base64 string.
+    pub fn encode_result<T>(self, value: &T) -> Result<Value, RpcError>
+    where
+        T: BorshSerialize + Serialize,
+    {
+        match self {
+            Encoding::Borsh => {
+                let bs = borsh::to_vec(value).map_err(failed)?;
+                serde_json::to_value(bs).map_err(failed)
+            }
+            Encoding::Json => serde_json::to_value(value).map_err(failed),
+        }
+    }
+
+    /// Encode a subscription item.
+    pub fn encode_message<T>(self, value: &T) -> Result<SubscriptionMessage, RpcError>
+    where
+        T: BorshSerialize + Serialize,
+    {
+        match self {
+            Encoding::Borsh => {
+                let bs = borsh::to_vec(value).map_err(failed)?;
+                Ok(SubscriptionMessage::from(&utils::base64_encode(&bs)))
+            }
+            Encoding::Json => SubscriptionMessage::from_json(value).map_err(failed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use race_core::types::ExitGameParams;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_decode_arg() {
+        let (arg, bs): (ExitGameParams, _) = Encoding::Json.decode_arg(json!({})).unwrap();
+        assert_eq!(arg, ExitGameParams {});
+        assert!(bs.is_empty());
+
+        let s = utils::base64_encode(&borsh::to_vec(&ExitGameParams {}).unwrap());
+        let (arg, _): (ExitGameParams, _) = Encoding::Borsh.decode_arg(json!(s)).unwrap();
+        assert_eq!(arg, ExitGameParams {});
+
+        assert!(Encoding::Borsh.decode_arg::<ExitGameParams>(json!({})).is_err());
+    }
+
+    #[test]
+    fn test_decode_credential() {
+        let c = Encoding::Json.decode_credential(json!("session:abc:7:ff")).unwrap();
+        assert!(matches!(
+            c,
+            Credential::Session { token, nonce: 7, proof } if token == "abc" && proof == "ff"
+        ));
+        assert!(Encoding::Json.decode_credential(json!("session:abc")).is_err());
+        assert!(Encoding::Json.decode_credential(json!("session:abc:x:ff")).is_err());
+
+        let c = Encoding::Json
+            .decode_credential(json!({"signer": "alice", "timestamp": 1, "signature": [1, 2]}))
+            .unwrap();
+        assert!(matches!(c, Credential::Signature(s) if s.signer == "alice"));
+    }
+}
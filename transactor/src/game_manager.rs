@@ -1,62 +1,140 @@
-use race_api::event::{Event, Message};
+use race_api::event::{ChatMessage, Event};
 use race_core::checkpoint::CheckpointOffChain;
 use race_core::error::{Error, Result};
-use race_core::types::{BroadcastFrame, ClientMode, ServerAccount};
+use race_core::chain::ChainType;
+use race_core::types::{BroadcastFrame, ClientMode, DivergenceReport, EventCursor};
 use race_core::checkpoint::ContextCheckpoint;
-use race_encryptor::Encryptor;
-use race_env::TransactorConfig;
+use race_env::{CapacityConfig, TransactorConfig};
+use race_handler::ModuleCache;
 use race_transactor_frames::BridgeToParent;
-use race_transactor_components::{CheckpointBroadcastFrame, CloseReason, WrappedStorage, WrappedTransport};
+use race_transactor_components::{CheckpointBroadcastFrame, CloseReason, SubscriberStats};
 use race_transactor_frames::{EventFrame, SignalFrame};
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
 use tokio::sync::{broadcast, mpsc, Mutex, RwLock};
 use tokio::task::JoinHandle;
+use tokio::time::Instant;
 use tracing::{error, info, warn};
 use serde::Serialize;
 
 use crate::blacklist::Blacklist;
+use crate::capacity::ServerLoad;
+use crate::chains::{ChainContext, Chains, GameKey};
 use crate::handle::Handle;
 use crate::utils::current_timestamp;
 
+// The attempts to relaunch a validator after the game is taken over
+const FOLLOW_ATTEMPTS: u32 = 3;
+const FOLLOW_DELAY: Duration = Duration::from_secs(2);
+// Seconds to wait for an idle game to be reloaded.
+const WAKE_TIMEOUT: u64 = 10;
+
+/// A game counted against the capacity.
+#[derive(Clone, Copy)]
+struct Slot {
+    is_native: bool,
+    is_subgame: bool,
+}
+
+fn load_of(slots: &HashMap<GameKey, Slot>) -> ServerLoad {
+    let mut load = ServerLoad::default();
+    for slot in slots.values() {
+        if slot.is_subgame {
+            load.sub_games += 1;
+        } else {
+            load.games += 1;
+        }
+        if !slot.is_native {
+            load.wasm_instances += 1;
+        }
+    }
+    load
+}
+
 #[derive(Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ServingGame {
     addr: String,
+    chain: String,
     bundle_addr: String,
+    spectators: usize,
+    subscribers: SubscriberStats,
+    #[serde(skip)]
+    key: GameKey,
 }
 
 impl ServingGame {
-    pub fn new(addr: String, bundle_addr: String) -> Self {
-        Self { addr, bundle_addr }
+    pub fn new(key: GameKey, bundle_addr: String, subscribers: SubscriberStats) -> Self {
+        Self {
+            addr: key.addr.clone(),
+            chain: key.chain.to_string(),
+            bundle_addr,
+            spectators: 0,
+            subscribers,
+            key,
+        }
+    }
+
+    pub fn key(&self) -> &GameKey {
+        &self.key
+    }
+
+    pub fn set_spectators(&mut self, spectators: usize) {
+        self.spectators = spectators;
     }
 }
 
 pub struct GameManager {
-    games: Arc<RwLock<HashMap<String, Handle>>>,
+    games: Arc<RwLock<HashMap<GameKey, Handle>>>,
+    chains: Arc<Chains>,
+    // The loaded games, readable without waiting for the lock of
+    // `games`.
+    loaded: StdMutex<HashSet<GameKey>>,
+    // The games unloaded for being idle, reloaded on demand, with
+    // their access versions when they were unloaded.  A greater one
+    // on chain means new joins or deposits.
+    idle_games: StdMutex<HashMap<GameKey, u64>>,
+    // The games loaded or about to be loaded, counted against the
+    // capacity shared by the registration tasks of all chains.
+    slots: StdMutex<HashMap<GameKey, Slot>>,
+    // Set when the transactor is shutting down, no more events are accepted.
+    shutting_down: AtomicBool,
 }
 
-impl Default for GameManager {
-    fn default() -> Self {
+impl GameManager {
+    pub fn new(chains: Arc<Chains>) -> Self {
         Self {
             games: Arc::new(RwLock::new(HashMap::default())),
+            chains,
+            loaded: StdMutex::new(HashSet::default()),
+            idle_games: StdMutex::new(HashMap::default()),
+            slots: StdMutex::new(HashMap::default()),
+            shutting_down: AtomicBool::new(false),
         }
     }
-}
 
+    /// Resolve a game address in the RPC, see [Chains::resolve_game_addr].
+    /// An address without its chain is looked up among the loaded and
+    /// the idle games.
+    pub fn resolve_game_addr(&self, addr: &str) -> Result<GameKey> {
+        self.chains.resolve_game_addr(addr, |key| {
+            self.loaded.lock().unwrap().contains(key)
+                || self.idle_games.lock().unwrap().contains_key(key)
+        })
+    }
 
-impl GameManager {
-    /// Load a child game
+    /// Load a child game, on the chain of its parent.
     pub async fn launch_sub_game(
         &self,
         checkpoint: ContextCheckpoint,
         bridge_to_parent: BridgeToParent,
-        server_account: &ServerAccount,
-        transport: Arc<WrappedTransport>,
-        encryptor: Arc<Encryptor>,
-        storage: Arc<WrappedStorage>,
+        chain: ChainType,
         signal_tx: mpsc::Sender<SignalFrame>,
+        module_cache: Arc<ModuleCache>,
         config: &TransactorConfig,
     ) -> Option<JoinHandle<CloseReason>> {
         let game_addr = checkpoint.root_data().game_spec.game_addr.clone();
@@ -64,23 +142,43 @@ impl GameManager {
 
         info!("Launch sub game, bundle = {}", checkpoint.root_data().game_spec.bundle_addr);
 
+        if !self.loaded.lock().unwrap().contains(&GameKey::new(chain, game_addr.as_str())) {
+            warn!("Parent game {} not loaded, skip loading child game {}", game_addr, game_id);
+            return None;
+        }
+        let chain_context = self.chains.get(chain).ok()?;
+
+        let key = GameKey::new(chain, format!("{}:{}", game_addr, game_id));
+        {
+            let mut slots = self.slots.lock().unwrap();
+            if let Some(max) = config.capacity.as_ref().and_then(|c| c.max_sub_games) {
+                if slots.values().filter(|s| s.is_subgame).count() >= max {
+                    warn!(
+                        "Max sub games {} reached, skip loading child game {} of {}",
+                        max, game_id, game_addr
+                    );
+                    return None;
+                }
+            }
+            let is_native = module_cache.is_native(&checkpoint.root_data().game_spec.bundle_addr);
+            slots.insert(key.clone(), Slot { is_native, is_subgame: true });
+        }
+
         match Handle::try_new_sub_game(
             checkpoint,
             bridge_to_parent,
-            transport,
-            encryptor,
-            storage,
-            server_account,
+            chain_context,
+            module_cache,
             config,
         )
         .await
         {
             Ok(mut handle) => {
                 let mut games = self.games.write().await;
-                let addr = format!("{}:{}", game_addr, game_id);
-                info!("Launch child game {}", addr);
-                let join_handle = handle.wait(signal_tx);
-                games.insert(addr.clone(), handle);
+                info!("Launch child game {}", key);
+                let join_handle = handle.wait(chain_context, signal_tx, None);
+                self.loaded.lock().unwrap().insert(key.clone());
+                games.insert(key, handle);
                 Some(join_handle)
             }
             Err(e) => {
@@ -89,43 +187,53 @@ impl GameManager {
                     game_id,
                     e.to_string()
                 );
+                self.release(&key);
                 None
             }
         }
     }
 
-    /// Load game by its address.  This operation is idempotent.
+    /// Load game by its address on a chain.  This operation is
+    /// idempotent.
     pub async fn launch_game(
         &self,
-        game_addr: String,
-        transport: Arc<WrappedTransport>,
-        storage: Arc<WrappedStorage>,
-        encryptor: Arc<Encryptor>,
-        server_account: &ServerAccount,
+        key: GameKey,
         blacklist: Arc<Mutex<Blacklist>>,
         signal_tx: mpsc::Sender<SignalFrame>,
         mode: ClientMode,
+        module_cache: Arc<ModuleCache>,
         config: &TransactorConfig,
     ) -> Option<JoinHandle<CloseReason>> {
+        if self.is_shutting_down() {
+            warn!("Transactor is shutting down, skip loading game: {}", key);
+            self.release(&key);
+            return None;
+        }
+
+        let chain_context = match self.chains.get(key.chain) {
+            Ok(chain_context) => chain_context,
+            Err(e) => {
+                warn!("Failed to load game {}: {}", key, e);
+                self.release(&key);
+                return None;
+            }
+        };
+
         let handle = if mode == ClientMode::Transactor {
             Handle::try_new_transactor(
-                game_addr.clone(),
-                transport,
-                storage,
-                encryptor,
-                server_account,
+                key.addr.clone(),
+                chain_context,
                 signal_tx.clone(),
+                module_cache.clone(),
                 &config,
             )
                 .await
         } else {
             Handle::try_new_validator(
-                game_addr.clone(),
-                transport,
-                storage,
-                encryptor,
-                server_account,
+                key.addr.clone(),
+                chain_context,
                 signal_tx.clone(),
+                module_cache.clone(),
                 config,
             )
                 .await
@@ -138,68 +246,352 @@ impl GameManager {
             }
             Err(err) => {
                 warn!("Error loading game: {}", err.to_string());
-                warn!("Failed to load game: {}", game_addr);
-                blacklist.lock().await.add_addr(&game_addr);
+                warn!("Failed to load game: {}", key);
+                blacklist.lock().await.add_addr(chain_context.storage.scoped_addr(&key.addr));
+                self.release(&key);
                 return None
             }
         };
 
         let mut games = self.games.write().await;
-        if let Entry::Vacant(e) = games.entry(game_addr.clone()) {
-            let join_handle = handle.wait(signal_tx);
+        if let Entry::Vacant(e) = games.entry(key.clone()) {
+            let join_handle = handle.wait(chain_context, signal_tx, Some(blacklist));
+            self.reserve(&key, module_cache.is_native(&handle.bundle_addr()));
+            self.loaded.lock().unwrap().insert(key);
             e.insert(handle);
             Some(join_handle)
         } else {
-            error!("Game already loaded: {}", game_addr);
+            error!("Game already loaded: {}", key);
             None
         }
     }
 
+    /// Relaunch a game served in validator mode as its transactor,
+    /// on the same chain.  The validator handle is replaced in place.
+    pub async fn takeover_game(
+        &self,
+        key: GameKey,
+        checkpoint: ContextCheckpoint,
+        history: Vec<(Event, u64)>,
+        blacklist: Arc<Mutex<Blacklist>>,
+        signal_tx: mpsc::Sender<SignalFrame>,
+        module_cache: Arc<ModuleCache>,
+        config: &TransactorConfig,
+    ) -> Option<JoinHandle<CloseReason>> {
+        if self.is_shutting_down() {
+            warn!("Transactor is shutting down, skip taking over game: {}", key);
+            return None;
+        }
+
+        let Some(chain_context) = self.get_loaded_chain(&key) else {
+            warn!("Validator of game {} not loaded, skip taking over", key);
+            return None;
+        };
+
+        let mut handle = match Handle::try_new_takeover(
+            key.addr.clone(),
+            chain_context,
+            signal_tx.clone(),
+            module_cache,
+            config,
+            checkpoint,
+            history,
+        )
+        .await
+        {
+            Ok(handle) => {
+                info!("Game taken over: {}", handle.addr());
+                handle
+            }
+            Err(err) => {
+                warn!("Failed to take over game {}: {}", key, err.to_string());
+                blacklist.lock().await.add_addr(chain_context.storage.scoped_addr(&key.addr));
+                return None;
+            }
+        };
+
+        let join_handle = handle.wait(chain_context, signal_tx, Some(blacklist));
+        self.games.write().await.insert(key, handle);
+        Some(join_handle)
+    }
+
+    /// Relaunch a game served in validator mode, to follow the
+    /// transactor which took it over.  The validator handle is
+    /// replaced in place.  The new transactor may not serve the game
+    /// yet, so the launch is retried.
+    pub async fn follow_transactor(
+        &self,
+        key: GameKey,
+        blacklist: Arc<Mutex<Blacklist>>,
+        signal_tx: mpsc::Sender<SignalFrame>,
+        module_cache: Arc<ModuleCache>,
+        config: &TransactorConfig,
+    ) -> Option<JoinHandle<CloseReason>> {
+        if self.is_shutting_down() {
+            warn!("Transactor is shutting down, skip relaunching game: {}", key);
+            return None;
+        }
+
+        let Some(chain_context) = self.get_loaded_chain(&key) else {
+            warn!("Validator of game {} not loaded, skip relaunching", key);
+            return None;
+        };
+
+        for attempt in 1..=FOLLOW_ATTEMPTS {
+            match Handle::try_new_validator(
+                key.addr.clone(),
+                chain_context,
+                signal_tx.clone(),
+                module_cache.clone(),
+                config,
+            )
+            .await
+            {
+                Ok(mut handle) => {
+                    info!("Game relaunched to follow the new transactor: {}", handle.addr());
+                    let join_handle = handle.wait(chain_context, signal_tx, Some(blacklist));
+                    self.games.write().await.insert(key, handle);
+                    return Some(join_handle);
+                }
+                Err(err) => {
+                    warn!(
+                        "Failed to relaunch game {}, attempt {}/{}: {}",
+                        key, attempt, FOLLOW_ATTEMPTS, err
+                    );
+                    tokio::time::sleep(FOLLOW_DELAY).await;
+                }
+            }
+        }
+        None
+    }
+
+    /// Return the chain of a loaded game.
+    fn get_loaded_chain(&self, key: &GameKey) -> Option<&Arc<ChainContext>> {
+        if !self.loaded.lock().unwrap().contains(key) {
+            return None;
+        }
+        self.chains.get(key.chain).ok()
+    }
+
     pub async fn get_serving_games(&self) -> Vec<ServingGame> {
         let games = self.games.read().await;
 
-        games.iter().map(|(addr, handle)| ServingGame::new(addr.to_owned(), handle.bundle_addr())).collect()
+        games
+            .iter()
+            .map(|(key, handle)| {
+                let subscribers = handle
+                    .broadcaster()
+                    .map(|b| b.subscriber_stats())
+                    .unwrap_or_default();
+                ServingGame::new(key.clone(), handle.bundle_addr(), subscribers)
+            })
+            .collect()
+    }
+
+    /// Unload the games on a chain which have been idle for longer
+    /// than `timeout`.  The games are shut down gracefully, so they
+    /// are settled and checkpointed before they are removed.  Sub
+    /// games are unloaded with their parents, so a game with loaded
+    /// sub games is kept.  So is a game with validators, see
+    /// [race_transactor_components::Broadcaster::idle_for].
+    pub async fn unload_idle_games(&self, chain: ChainType, timeout: Duration) -> Vec<GameKey> {
+        if self.is_shutting_down() {
+            return vec![];
+        }
+        let games = self.games.read().await;
+        let mut unloaded = vec![];
+
+        for (key, handle) in games.iter() {
+            if handle.is_subgame() || key.chain != chain || self.is_idle(key) {
+                continue;
+            }
+            // Only the games served as transactor have broadcasters
+            let Ok(broadcaster) = handle.broadcaster() else {
+                continue;
+            };
+            if !broadcaster.idle_for().map_or(false, |idle_for| idle_for > timeout) {
+                continue;
+            }
+            let sub_game_prefix = format!("{}:", key.addr);
+            if games
+                .keys()
+                .any(|k| k.chain == chain && k.addr.starts_with(&sub_game_prefix))
+            {
+                continue;
+            }
+
+            info!("Unload idle game {}", key);
+            self.idle_games
+                .lock()
+                .unwrap()
+                .insert(key.clone(), broadcaster.access_version());
+            handle.event_bus().send(EventFrame::GracefulShutdown).await;
+            unloaded.push(key.clone());
+        }
+        unloaded
+    }
+
+    /// Return true if the game is unloaded, or being unloaded, for
+    /// being idle.
+    pub fn is_idle(&self, key: &GameKey) -> bool {
+        self.idle_games.lock().unwrap().contains_key(key)
+    }
+
+    /// Return the idle games on a chain, with their access versions
+    /// when they were unloaded.
+    pub fn get_idle_games(&self, chain: ChainType) -> Vec<(GameKey, u64)> {
+        self.idle_games
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(key, _)| key.chain == chain)
+            .map(|(key, access_version)| (key.clone(), *access_version))
+            .collect()
+    }
+
+    /// Remove a game from the idle games before reloading it.  Return
+    /// false if it's not idle or already being reloaded.
+    pub fn take_idle_game(&self, key: &GameKey) -> bool {
+        self.idle_games.lock().unwrap().remove(key).is_some()
+    }
+
+    /// Reload a game unloaded for being idle with a `StartGame`
+    /// signal, and wait until it's loaded.  Do nothing if the game is
+    /// not idle.
+    pub async fn wake_game(&self, key: &GameKey, signal_tx: &mpsc::Sender<SignalFrame>) -> Result<()> {
+        if !self.is_idle(key) {
+            return Ok(());
+        }
+        if self.is_shutting_down() {
+            return Err(Error::TransactorShuttingDown);
+        }
+        let deadline = Instant::now() + Duration::from_secs(WAKE_TIMEOUT);
+
+        // The game can be still shutting down
+        while self.is_game_loaded(key).await {
+            if Instant::now() > deadline {
+                return Err(Error::GameNotLoaded);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        // Reloaded by whoever takes it first, the others just wait
+        if self.take_idle_game(key) {
+            info!("Reload idle game {}", key);
+            signal_tx
+                .send(SignalFrame::StartGame {
+                    game_addr: key.addr.clone(),
+                    mode: ClientMode::Transactor,
+                    chain: key.chain,
+                })
+                .await
+                .map_err(|e| Error::InternalError(e.to_string()))?;
+        }
+
+        while !self.is_game_loaded(key).await {
+            if Instant::now() > deadline {
+                warn!("Idle game {} not reloaded in {} seconds", key, WAKE_TIMEOUT);
+                return Err(Error::GameNotLoaded);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        Ok(())
+    }
+
+    /// Count the loaded games.  Games with native handlers don't take
+    /// WASM instances.
+    /// Reserve the capacity for a game about to be served, so the
+    /// registration tasks of all chains can't exceed the limits
+    /// together.  Return false if the capacity is reached.  The slot
+    /// is released when the game fails to load or is removed.
+    pub fn try_reserve(&self, key: &GameKey, is_native: bool, capacity: Option<&CapacityConfig>) -> bool {
+        let mut slots = self.slots.lock().unwrap();
+        if slots.contains_key(key) {
+            return true;
+        }
+        if load_of(&slots).with_limits(capacity).is_full() {
+            return false;
+        }
+        slots.insert(key.clone(), Slot { is_native, is_subgame: false });
+        true
+    }
+
+    /// Reserve the capacity for a game regardless of the limits, for
+    /// the games already served.
+    pub fn reserve(&self, key: &GameKey, is_native: bool) {
+        self.slots
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_insert(Slot { is_native, is_subgame: false });
+    }
+
+    pub fn release(&self, key: &GameKey) {
+        self.slots.lock().unwrap().remove(key);
+    }
+
+    /// The games loaded or reserved.
+    pub fn get_load(&self) -> ServerLoad {
+        load_of(&self.slots.lock().unwrap())
+    }
+
+    /// Get the divergence reports made as the validator of a game.
+    pub async fn get_divergence_reports(&self, key: &GameKey) -> Result<Vec<DivergenceReport>> {
+        self.chains.get(key.chain)?.divergence_log.get(&key.addr).await
     }
 
-    pub async fn is_game_loaded(&self, game_addr: &str) -> bool {
+    pub async fn is_game_loaded(&self, key: &GameKey) -> bool {
         let games = self.games.read().await;
-        games.contains_key(game_addr)
+        games.contains_key(key)
     }
 
-    pub async fn send_event(&self, game_addr: &str, event: Event) -> Result<()> {
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    pub async fn send_event(&self, key: &GameKey, event: Event) -> Result<()> {
+        if self.is_shutting_down() {
+            return Err(Error::TransactorShuttingDown);
+        }
         let games = self.games.read().await;
-        if let Some(handle) = games.get(game_addr) {
+        if let Some(handle) = games.get(key) {
             let timestamp = current_timestamp();
             let event_frame = EventFrame::SendEvent { event, timestamp };
             handle.event_bus().send(event_frame).await;
             Ok(())
         } else {
-            warn!("Game {} not loaded, discard event: {:?}", game_addr, event);
+            warn!("Game {} not loaded, discard event: {:?}", key, event);
             Err(Error::GameNotLoaded)
         }
     }
 
-    pub async fn send_message(&self, game_addr: &str, message: Message) -> Result<()> {
+    pub async fn send_message(&self, key: &GameKey, message: ChatMessage) -> Result<()> {
+        if self.is_shutting_down() {
+            return Err(Error::TransactorShuttingDown);
+        }
         let games = self.games.read().await;
-        if let Some(handle) = games.get(game_addr) {
+        if let Some(handle) = games.get(key) {
             let event_frame = EventFrame::SendMessage { message };
             handle.event_bus().send(event_frame).await;
             Ok(())
         } else {
             warn!(
                 "Game {} not loaded, discard message: {:?}",
-                game_addr, message
+                key, message
             );
             Err(Error::GameNotLoaded)
         }
     }
 
-    pub async fn eject_player(&self, game_addr: &str, player_addr: &str) -> Result<()> {
+    pub async fn eject_player(&self, key: &GameKey, player_addr: &str) -> Result<()> {
+        if self.is_shutting_down() {
+            return Err(Error::TransactorShuttingDown);
+        }
         let games = self.games.read().await;
-        if let Some(handle) = games.get(game_addr) {
+        if let Some(handle) = games.get(key) {
             info!(
-                "Receive leaving request from {:?} for game {:?}",
-                player_addr, game_addr
+                "Receive leaving request from {} for game {}",
+                player_addr, key
             );
             let event_frame = EventFrame::PlayerLeaving {
                 player_addr: player_addr.to_owned(),
@@ -214,11 +606,11 @@ impl GameManager {
 
     pub async fn get_checkpoint(
         &self,
-        game_addr: &str,
+        key: &GameKey,
         settle_version: u64,
     ) -> Result<Option<CheckpointOffChain>> {
         let games = self.games.read().await;
-        let handle = games.get(game_addr).ok_or(Error::GameNotLoaded)?;
+        let handle = games.get(key).ok_or(Error::GameNotLoaded)?;
         let broadcaster = handle.broadcaster()?;
         let checkpoint = broadcaster.get_checkpoint(settle_version).await;
         Ok(checkpoint)
@@ -226,36 +618,72 @@ impl GameManager {
 
     pub async fn get_latest_checkpoint(
         &self,
-        game_addr: &str,
+        key: &GameKey,
     ) -> Result<Option<CheckpointOffChain>> {
         let games = self.games.read().await;
-        let handle = games.get(game_addr).ok_or(Error::GameNotLoaded)?;
+        let handle = games.get(key).ok_or(Error::GameNotLoaded)?;
         let broadcaster = handle.broadcaster()?;
         let checkpoint = broadcaster.get_latest_checkpoint().await;
         Ok(checkpoint)
     }
 
-    /// Get the broadcast channel of game, and its event histories
+    /// Get the broadcast channel of game and the first frame to send.
+    /// With a `resume` cursor, it's the frames missed after the cursor,
+    /// or the backlogs from `settle_version` if the cursor has aged out.
     pub async fn get_broadcast_and_backlogs(
         &self,
-        game_addr: &str,
+        key: &GameKey,
         settle_version: u64,
-    ) -> Result<(broadcast::Receiver<BroadcastFrame>, BroadcastFrame)> {
+        resume: Option<&EventCursor>,
+    ) -> Result<(mpsc::Receiver<BroadcastFrame>, BroadcastFrame)> {
         let games = self.games.read().await;
-        let handle = games.get(game_addr).ok_or(Error::GameNotLoaded)?;
+        let handle = games.get(key).ok_or(Error::GameNotLoaded)?;
         let broadcaster = handle.broadcaster()?;
-        let receiver = broadcaster.get_broadcast_rx();
+        let receiver = broadcaster.subscribe();
+        if let Some(cursor) = resume {
+            if let Some(frame) = broadcaster.get_resume(cursor).await {
+                return Ok((receiver, frame));
+            }
+            info!("Cursor {:?} aged out, resync game {}", cursor, key);
+        }
         let backlogs = broadcaster.get_backlogs(settle_version).await;
         Ok((receiver, backlogs))
     }
 
+    /// Get the recent chat messages of game, of all channels.
+    pub async fn get_chat_history(&self, key: &GameKey) -> Result<Vec<ChatMessage>> {
+        let games = self.games.read().await;
+        let handle = games.get(key).ok_or(Error::GameNotLoaded)?;
+        let broadcaster = handle.broadcaster()?;
+        Ok(broadcaster.get_messages().await)
+    }
+
+    /// Get the broadcast and checkpoint channels of game, and the
+    /// backlogs from the latest checkpoint, for a spectator.
+    pub async fn get_spectator_channels(
+        &self,
+        key: &GameKey,
+    ) -> Result<(
+        mpsc::Receiver<BroadcastFrame>,
+        broadcast::Receiver<CheckpointBroadcastFrame>,
+        BroadcastFrame,
+    )> {
+        let games = self.games.read().await;
+        let handle = games.get(key).ok_or(Error::GameNotLoaded)?;
+        let broadcaster = handle.broadcaster()?;
+        let receiver = broadcaster.subscribe();
+        let checkpoint_rx = broadcaster.get_checkpoint_rx();
+        let backlogs = broadcaster.get_backlogs(0).await;
+        Ok((receiver, checkpoint_rx, backlogs))
+    }
+
     /// Get the checkopint channel of game and its latest checkpoint
     pub async fn get_broadcast_and_checkpoint(
         &self,
-        game_addr: &str,
+        key: &GameKey,
     ) -> Result<(broadcast::Receiver<CheckpointBroadcastFrame>, CheckpointBroadcastFrame)> {
         let games = self.games.read().await;
-        let handle = games.get(game_addr).ok_or(Error::GameNotLoaded)?;
+        let handle = games.get(key).ok_or(Error::GameNotLoaded)?;
         let broadcaster = handle.broadcaster()?;
         let receiver = broadcaster.get_checkpoint_rx();
         let Some(frame) = broadcaster.get_latest_checkpoint_broadcast_frame().await else {
@@ -265,21 +693,249 @@ impl GameManager {
         Ok((receiver, frame))
     }
 
-    pub async fn remove_game(&self, addr: &str) {
+    pub async fn remove_game(&self, key: &GameKey) {
         let mut games = self.games.write().await;
-        games.remove(&addr.to_string());
+        games.remove(key);
+        self.loaded.lock().unwrap().remove(key);
+        self.release(key);
     }
 
-    /// Shutdown all games, and drop their handles.
+    /// Stop accepting events, and ask all games to shutdown
+    /// gracefully.  Sub games are shutted down by their parents.
     pub async fn shutdown(&self) {
-        let mut games = self.games.write().await;
-        for (addr, game) in games.iter() {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        let games = self.games.read().await;
+        for (key, game) in games.iter() {
             if !game.is_subgame() {
-                info!("Shutdown game {}", addr);
-                game.event_bus().send(EventFrame::Shutdown).await;
+                info!("Shutdown game {}", key);
+                game.event_bus().send(EventFrame::GracefulShutdown).await;
             }
         }
+    }
 
+    /// Force the games which didn't finish in time to stop, and drop
+    /// all handles.
+    pub async fn force_shutdown(&self, keys: &[GameKey]) {
+        let mut games = self.games.write().await;
+        for key in keys.iter() {
+            if let Some(game) = games.get(key) {
+                warn!("Force shutdown game {}", key);
+                game.event_bus().send(EventFrame::Shutdown).await;
+            }
+        }
         games.clear();
+        self.loaded.lock().unwrap().clear();
+        self.slots.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reg::RegHeartbeat;
+    use race_api::prelude::{
+        BorshDeserialize, BorshSerialize, Effect, GameHandler, HandleResult, InitAccount, PlayerBalance,
+    };
+    use race_core::types::ServerAccount;
+    use race_encryptor::Encryptor;
+    use race_env::Config;
+    use race_handler::NativeHandlers;
+    use race_test::prelude::{DummyTransport, GameAccount, TestClient, TestGameAccountBuilder};
+    use race_transactor_components::{DivergenceLog, WrappedStorage, WrappedTransport};
+
+    #[derive(Default, BorshSerialize, BorshDeserialize)]
+    struct Noop {
+        events: u64,
+    }
+
+    impl GameHandler for Noop {
+        fn init_state(_effect: &mut Effect, _init_account: InitAccount) -> HandleResult<Self> {
+            Ok(Self::default())
+        }
+
+        fn handle_event(&mut self, _effect: &mut Effect, _event: Event) -> HandleResult<()> {
+            self.events += 1;
+            Ok(())
+        }
+
+        fn balances(&self) -> Vec<PlayerBalance> {
+            vec![]
+        }
+    }
+
+    /// Serve the game of `account` as its transactor on a dummy
+    /// chain, with the signals handled like in the signal loop of the
+    /// application context.  Return when the game has its first
+    /// checkpoint.
+    async fn serve_game(
+        account: GameAccount,
+    ) -> anyhow::Result<(Arc<GameManager>, GameKey, mpsc::Sender<SignalFrame>)> {
+        let transport = DummyTransport::default();
+        // The account is fetched on every launch and subscription
+        transport.simulate_states(vec![account.clone(); 100]);
+        let storage = Arc::new(WrappedStorage::try_new(&Config::default()).await?);
+        let chain = ChainContext {
+            chain: ChainType::Facade,
+            account: ServerAccount {
+                addr: "server".into(),
+                endpoint: "".into(),
+                credentials: vec![],
+            },
+            transport: Arc::new(WrappedTransport::try_new(Box::new(transport)).await?),
+            encryptor: Arc::new(Encryptor::default()),
+            divergence_log: Arc::new(DivergenceLog::new(storage.clone())),
+            storage,
+            reg_heartbeat: RegHeartbeat::default(),
+        };
+        let chains = Chains::from_contexts(vec![Arc::new(chain)]);
+        let game_manager = Arc::new(GameManager::new(Arc::new(chains)));
+
+        let mut native_handlers = NativeHandlers::default();
+        native_handlers.register::<Noop>(account.bundle_addr.clone());
+        let module_cache = Arc::new(ModuleCache::new(0, None).with_native_handlers(native_handlers));
+        let blacklist = Arc::new(Mutex::new(Blacklist::new(false)));
+        let (signal_tx, mut signal_rx) = mpsc::channel(3);
+
+        let game_manager_0 = game_manager.clone();
+        let signal_tx_0 = signal_tx.clone();
+        tokio::spawn(async move {
+            while let Some(signal) = signal_rx.recv().await {
+                match signal {
+                    SignalFrame::StartGame { game_addr, mode, chain } => {
+                        game_manager_0
+                            .launch_game(
+                                GameKey::new(chain, game_addr),
+                                blacklist.clone(),
+                                signal_tx_0.clone(),
+                                mode,
+                                module_cache.clone(),
+                                &TransactorConfig::default(),
+                            )
+                            .await;
+                    }
+                    SignalFrame::RemoveGame { game_addr, chain } => {
+                        game_manager_0.remove_game(&GameKey::new(chain, game_addr)).await;
+                    }
+                    _ => (),
+                }
+            }
+        });
+
+        let key = GameKey::new(ChainType::Facade, account.addr.as_str());
+        signal_tx
+            .send(SignalFrame::StartGame {
+                game_addr: account.addr.clone(),
+                mode: ClientMode::Transactor,
+                chain: ChainType::Facade,
+            })
+            .await
+            .expect("Send StartGame");
+        for _ in 0..100 {
+            if let Ok(Some(_)) = game_manager.get_latest_checkpoint(&key).await {
+                return Ok((game_manager, key, signal_tx));
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("Game {} not loaded", key);
+    }
+
+    #[test]
+    fn test_reserve_capacity_across_chains() {
+        let game_manager = GameManager::new(Arc::new(Chains::from_contexts(vec![])));
+        let capacity = CapacityConfig {
+            max_games: Some(2),
+            max_sub_games: None,
+            max_wasm_instances: Some(1),
+            selection: None,
+            bundle_allowlist: None,
+        };
+        let solana = |addr: &str| GameKey::new(ChainType::Solana, addr);
+        let sui = |addr: &str| GameKey::new(ChainType::Sui, addr);
+
+        assert!(game_manager.try_reserve(&solana("g1"), false, Some(&capacity)));
+        // Reserving the same game again takes no more capacity
+        assert!(game_manager.try_reserve(&solana("g1"), false, Some(&capacity)));
+        assert!(game_manager.try_reserve(&sui("g2"), true, Some(&capacity)));
+        assert!(!game_manager.try_reserve(&sui("g3"), true, Some(&capacity)));
+        assert_eq!(game_manager.get_load().games, 2);
+        assert_eq!(game_manager.get_load().wasm_instances, 1);
+
+        // Released when the game fails to load or is removed
+        game_manager.release(&solana("g1"));
+        assert!(game_manager.try_reserve(&sui("g3"), true, Some(&capacity)));
+        assert!(!game_manager.try_reserve(&solana("g4"), true, Some(&capacity)));
+    }
+
+    #[tokio::test]
+    async fn test_unload_and_wake_idle_game() -> anyhow::Result<()> {
+        let mut server = TestClient::transactor("server");
+        let account = TestGameAccountBuilder::new()
+            .set_transactor(&mut server)
+            .build();
+        let (game_manager, key, signal_tx) = serve_game(account).await?;
+
+        let unloaded = game_manager
+            .unload_idle_games(ChainType::Facade, Duration::ZERO)
+            .await;
+        assert_eq!(unloaded, vec![key.clone()]);
+        assert!(game_manager.is_idle(&key));
+
+        // Waits for the shutdown to finish, then reloads the game
+        game_manager.wake_game(&key, &signal_tx).await?;
+        assert!(!game_manager.is_idle(&key));
+        assert!(game_manager.is_game_loaded(&key).await);
+        assert!(game_manager.get_latest_checkpoint(&key).await.is_ok());
+
+        // Not idle any more, so waking it again does nothing
+        game_manager.wake_game(&key, &signal_tx).await?;
+        assert!(game_manager.is_game_loaded(&key).await);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_flushes_final_checkpoint() -> anyhow::Result<()> {
+        let mut server = TestClient::transactor("server");
+        let account = TestGameAccountBuilder::new()
+            .set_transactor(&mut server)
+            .build();
+        let (game_manager, key, _signal_tx) = serve_game(account).await?;
+        let (mut checkpoint_rx, latest) = game_manager.get_broadcast_and_checkpoint(&key).await?;
+
+        game_manager.shutdown().await;
+        assert_eq!(
+            game_manager.send_event(&key, Event::GameStart).await,
+            Err(Error::TransactorShuttingDown)
+        );
+
+        // The final checkpoint is flushed before the deadline
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let frame = tokio::time::timeout_at(deadline, checkpoint_rx.recv()).await??;
+        assert_eq!(frame.data, latest.data);
+
+        // Then the game stops and is removed
+        while game_manager.is_game_loaded(&key).await {
+            assert!(Instant::now() < deadline, "Game {} not removed", key);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_keep_idle_game_with_validators() -> anyhow::Result<()> {
+        let mut server = TestClient::transactor("server");
+        let mut validator = TestClient::validator("validator");
+        let account = TestGameAccountBuilder::new()
+            .set_transactor(&mut server)
+            .add_validator(&mut validator)
+            .build();
+        let (game_manager, key, _signal_tx) = serve_game(account).await?;
+
+        let unloaded = game_manager
+            .unload_idle_games(ChainType::Facade, Duration::ZERO)
+            .await;
+        assert!(unloaded.is_empty());
+        assert!(!game_manager.is_idle(&key));
+        assert!(game_manager.is_game_loaded(&key).await);
+        Ok(())
     }
 }
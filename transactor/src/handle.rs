@@ -4,19 +4,20 @@ mod validator;
 
 use std::sync::Arc;
 
+use race_api::event::Event;
 use race_transactor_frames::{BridgeToParent, SignalFrame};
-use race_transactor_components::{
-    Broadcaster, CloseReason, EventBus, WrappedStorage, WrappedTransport,
-};
+use race_transactor_components::{Broadcaster, CloseReason, EventBus};
 use race_core::error::{Error, Result};
-use race_core::types::ServerAccount;
 use race_core::checkpoint::ContextCheckpoint;
-use race_encryptor::Encryptor;
 use race_env::TransactorConfig;
+use race_handler::ModuleCache;
+
+use crate::blacklist::Blacklist;
+use crate::chains::ChainContext;
 use subgame::SubGameHandle;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, Mutex};
 use tokio::task::JoinHandle;
-use tracing::error;
+use tracing::{error, warn};
 use transactor::TransactorHandle;
 use validator::ValidatorHandle;
 
@@ -29,22 +30,51 @@ pub enum Handle {
 impl Handle {
     pub async fn try_new_transactor(
         game_addr: String,
-        transport: Arc<WrappedTransport>,
-        storage: Arc<WrappedStorage>,
-        encryptor: Arc<Encryptor>,
-        server_account: &ServerAccount,
+        chain: &ChainContext,
         signal_tx: mpsc::Sender<SignalFrame>,
+        module_cache: Arc<ModuleCache>,
         config: &TransactorConfig,
     ) -> Result<Self> {
         Ok(Self::Transactor(
             TransactorHandle::try_new(
                 game_addr,
-                server_account,
-                encryptor,
-                transport,
-                storage,
+                &chain.account,
+                chain.encryptor.clone(),
+                chain.transport.clone(),
+                chain.chain,
+                chain.storage.clone(),
                 signal_tx,
+                module_cache,
                 config,
+                None,
+            )
+            .await?,
+        ))
+    }
+
+    /// Relaunch a game in transactor mode, with the checkpoint and
+    /// the events after it from the validator.
+    pub async fn try_new_takeover(
+        game_addr: String,
+        chain: &ChainContext,
+        signal_tx: mpsc::Sender<SignalFrame>,
+        module_cache: Arc<ModuleCache>,
+        config: &TransactorConfig,
+        checkpoint: ContextCheckpoint,
+        history: Vec<(Event, u64)>,
+    ) -> Result<Self> {
+        Ok(Self::Transactor(
+            TransactorHandle::try_new(
+                game_addr,
+                &chain.account,
+                chain.encryptor.clone(),
+                chain.transport.clone(),
+                chain.chain,
+                chain.storage.clone(),
+                signal_tx,
+                module_cache,
+                config,
+                Some((checkpoint, history)),
             )
             .await?,
         ))
@@ -52,22 +82,22 @@ impl Handle {
 
     pub async fn try_new_validator(
         game_addr: String,
-        transport: Arc<WrappedTransport>,
-        storage: Arc<WrappedStorage>,
-        encryptor: Arc<Encryptor>,
-        server_account: &ServerAccount,
+        chain: &ChainContext,
         signal_tx: mpsc::Sender<SignalFrame>,
+        module_cache: Arc<ModuleCache>,
         config: &TransactorConfig,
     ) -> Result<Self> {
         Ok(Self::Validator(
             ValidatorHandle::try_new(
                 game_addr,
-                server_account,
-                encryptor,
-                transport,
-                storage,
+                &chain.account,
+                chain.encryptor.clone(),
+                chain.transport.clone(),
+                chain.chain,
                 signal_tx,
+                module_cache,
                 config,
+                chain.divergence_log.clone(),
             )
             .await?,
         ))
@@ -76,20 +106,20 @@ impl Handle {
     pub async fn try_new_sub_game(
         checkpoint: ContextCheckpoint,
         bridge_to_parent: BridgeToParent,
-        transport: Arc<WrappedTransport>,
-        encryptor: Arc<Encryptor>,
-        storage: Arc<WrappedStorage>,
-        server_account: &ServerAccount,
+        chain: &ChainContext,
+        module_cache: Arc<ModuleCache>,
         config: &TransactorConfig,
     ) -> Result<Self> {
         Ok(Self::SubGame(
             SubGameHandle::try_new(
                 checkpoint,
                 bridge_to_parent,
-                transport,
-                encryptor,
-                storage,
-                server_account,
+                chain.transport.clone(),
+                chain.encryptor.clone(),
+                chain.chain,
+                chain.storage.clone(),
+                &chain.account,
+                module_cache,
                 config,
             )
             .await?,
@@ -134,7 +164,14 @@ impl Handle {
 
     /// Wait handle until it's shutted down.  A
     /// [SignalFrame::RemoveGame] will be sent through `signal_tx`.
-    pub fn wait(&mut self, signal_tx: mpsc::Sender<SignalFrame>) -> JoinHandle<CloseReason> {
+    /// The game is added to `blacklist` if its handler exceeded the
+    /// execution limits.
+    pub fn wait(
+        &mut self,
+        chain: &ChainContext,
+        signal_tx: mpsc::Sender<SignalFrame>,
+        blacklist: Option<Arc<Mutex<Blacklist>>>,
+    ) -> JoinHandle<CloseReason> {
         let handles = match self {
             Handle::Transactor(ref mut x) => &mut x.handles,
             Handle::Validator(ref mut x) => &mut x.handles,
@@ -145,6 +182,8 @@ impl Handle {
         }
         let handles = std::mem::take(handles);
         let addr = self.addr();
+        let scoped_addr = chain.storage.scoped_addr(&addr);
+        let chain = chain.chain;
         tokio::spawn(async move {
             let mut close_reason = CloseReason::Complete;
             for h in handles.into_iter() {
@@ -153,8 +192,14 @@ impl Handle {
                     close_reason = cr
                 }
             }
+            if let (CloseReason::Fault(e), Some(blacklist)) = (&close_reason, blacklist) {
+                if e.is_wasm_limit_exceeded() {
+                    warn!("Game {} exceeded the handler limits: {}", addr, e);
+                    blacklist.lock().await.add_addr(scoped_addr);
+                }
+            }
             if let Err(e) = signal_tx
-                .send(SignalFrame::RemoveGame { game_addr: addr })
+                .send(SignalFrame::RemoveGame { game_addr: addr, chain })
                 .await
             {
                 error!("Failed to send RemoveGame signal due to {}", e);
@@ -1,9 +1,12 @@
 use std::sync::Arc;
 
 use race_transactor_frames::{EventFrame, BridgeToParent};
+use race_handler::ModuleCache;
 use race_transactor_components::{
-    Broadcaster, Component, EventBridgeChild, EventBus, EventLoop, LocalConnection, PortsHandle, WrappedClient,
+    Broadcaster, Component, EventBridgeChild, EventBus, EventLoop, LocalConnection, PortsHandle, Recorder, WrappedClient,
 };
+use race_core::chain::ChainType;
+use race_core::entry_type::EntryType;
 use race_core::error::Result;
 use race_core::storage::StorageT;
 use race_core::transport::TransportT;
@@ -28,15 +31,22 @@ impl SubGameHandle {
         bridge_to_parent: BridgeToParent,
         transport: Arc<dyn TransportT + Send + Sync>,
         encryptor: Arc<Encryptor>,
-        _storage: Arc<dyn StorageT + Send + Sync>,
+        chain: ChainType,
+        storage: Arc<dyn StorageT + Send + Sync>,
         server_account: &ServerAccount,
-        _config: &TransactorConfig,
+        module_cache: Arc<ModuleCache>,
+        config: &TransactorConfig,
     ) -> Result<Self> {
         let game_spec = &checkpoint.root_data().game_spec;
         let addr = format!("{}:{}", game_spec.game_addr, game_spec.game_id);
         let event_bus = EventBus::new(addr.to_string());
 
-        let (broadcaster, broadcaster_ctx) = Broadcaster::init(addr.clone(), game_spec.game_id);
+        let (broadcaster, broadcaster_ctx) = Broadcaster::init(
+            addr.clone(),
+            game_spec.game_id,
+            Some(storage as Arc<dyn StorageT>),
+            config.backlog.as_ref(),
+        );
         let mut broadcaster_handle = broadcaster.start(&addr, broadcaster_ctx);
 
         let (bridge, bridge_ctx) = EventBridgeChild::init(game_spec.game_id, bridge_to_parent);
@@ -47,12 +57,30 @@ impl SubGameHandle {
                 game_spec.clone(),
                 encryptor.clone(),
                 transport.clone(),
+                module_cache,
                 ClientMode::Transactor,
-                GameMode::Sub
+                GameMode::Sub,
+                config.handler.as_ref(),
             );
 
         let mut event_loop_handle = event_loop.start(&addr, event_loop_ctx);
 
+        // Sub games are replayed from the checkpoint, which is the
+        // first record.
+        let recorder_config = config.recorder.as_ref().filter(|c| Recorder::is_enabled(c));
+        let mut recorder_handle = if let Some(recorder_config) = recorder_config {
+            let (recorder, recorder_ctx) = Recorder::init(
+                game_spec.clone(),
+                vec![],
+                EntryType::default(),
+                chain,
+                recorder_config,
+            );
+            Some(recorder.start(&addr, recorder_ctx))
+        } else {
+            None
+        };
+
         let mut connection = LocalConnection::new(encryptor.clone());
 
         event_bus.attach(&mut connection).await;
@@ -70,6 +98,9 @@ impl SubGameHandle {
         event_bus.attach(&mut bridge_handle).await;
         event_bus.attach(&mut broadcaster_handle).await;
         event_bus.attach(&mut event_loop_handle).await;
+        if let Some(recorder_handle) = recorder_handle.as_mut() {
+            event_bus.attach(recorder_handle).await;
+        }
 
         let init_frame = EventFrame::RecoverCheckpointWithCredentials {
             checkpoint: checkpoint.clone()
@@ -77,11 +108,14 @@ impl SubGameHandle {
 
         event_bus.send(init_frame).await;
 
+        let mut handles = vec![broadcaster_handle, bridge_handle, event_loop_handle];
+        handles.extend(recorder_handle);
+
         Ok(Self {
             addr: format!("{}:{}", game_spec.game_addr, game_spec.game_id),
             bundle_addr: checkpoint.root_data().game_spec.bundle_addr.to_owned(),
             event_bus,
-            handles: vec![broadcaster_handle, bridge_handle, event_loop_handle],
+            handles,
             broadcaster,
             bridge_child: bridge,
         })
@@ -1,11 +1,15 @@
 use std::sync::Arc;
 
+use race_api::event::Event;
+use race_handler::ModuleCache;
 use race_transactor_components::{
-    Broadcaster, Component, EventBridgeParent, EventBus, EventLoop, GameSynchronizer, LocalConnection, PortsHandle, Refunder, Submitter, WrappedClient, CredentialConsolidator,
+    cut_at_own_secrets, load_journal, replay_pending_settles, Broadcaster, Component, EventBridgeParent, EventBus, EventLoop, GameSynchronizer, HistoryReplayer, Journal, LocalConnection, PortsHandle, Recorder, Refunder, Submitter, WrappedClient, WrappedTransport, CredentialConsolidator,
 };
+use race_core::chain::ChainType;
+use race_core::checkpoint::ContextCheckpoint;
 use race_transactor_frames::{EventFrame, SignalFrame};
 use race_core::error::{Error, Result};
-use race_core::types::GetCheckpointParams;
+use race_core::types::{CutJournalParams, GetCheckpointParams};
 use race_core::storage::StorageT;
 use race_core::transport::TransportT;
 use race_core::types::{ClientMode, GameMode, ServerAccount};
@@ -13,7 +17,7 @@ use race_core::node::Node;
 use race_encryptor::Encryptor;
 use race_env::TransactorConfig;
 use tokio::sync::mpsc;
-use tracing::info;
+use tracing::{error, info};
 
 #[allow(dead_code)]
 pub struct TransactorHandle {
@@ -78,16 +82,21 @@ impl TransactorHandle {
         game_addr: String,
         server_account: &ServerAccount,
         encryptor: Arc<Encryptor>,
-        transport: Arc<dyn TransportT + Send + Sync>,
+        transport: Arc<WrappedTransport>,
+        chain: ChainType,
         storage: Arc<dyn StorageT + Send + Sync>,
         signal_tx: mpsc::Sender<SignalFrame>,
+        module_cache: Arc<ModuleCache>,
         config: &TransactorConfig,
+        takeover: Option<(ContextCheckpoint, Vec<(Event, u64)>)>,
     ) -> Result<Self> {
         info!(
             "Start game handle for {} with Transactor mode",
             game_addr
         );
 
+        replay_pending_settles(&game_addr, transport.as_ref(), storage.as_ref()).await;
+
         let Some(game_account) = transport.get_game_account(&game_addr).await? else {
             return Err(Error::GameAccountNotFound);
         };
@@ -107,8 +116,17 @@ impl TransactorHandle {
         let init_account = game_account.derive_init_account();
 
         let mut checkpoint_access_version = 0;
+        let mut history = vec![];
 
-        let init_frame = if game_account.settle_version == 0 {
+        let init_frame = if let Some((mut checkpoint, events)) = takeover {
+            info!("Take over game {} from a validator, settle_version = {}.", game_addr, game_account.settle_version);
+            // The checkpoint is from the validator, with the previous
+            // transactor in the nodes.
+            checkpoint.set_transactor(&server_account.addr);
+            checkpoint_access_version = checkpoint.root_data().versions.access_version;
+            history = events;
+            EventFrame::RecoverCheckpoint { checkpoint }
+        } else if game_account.settle_version == 0 {
             info!("Initialize game {} for the first time.", game_addr);
             // The game is not initialized, create an InitState frame.
             let nodes = game_account.servers.iter()
@@ -133,6 +151,34 @@ impl TransactorHandle {
             };
 
             checkpoint_access_version = checkpoint.root_data.versions.access_version;
+            // The events handled after the checkpoint before a crash
+            history = load_journal(&game_addr, game_account.settle_version, storage.as_ref()).await?;
+            let server_id = game_account
+                .servers
+                .iter()
+                .find(|s| s.addr == server_account.addr)
+                .map(|s| s.access_version);
+            if let Some(server_id) = server_id {
+                let cut = cut_at_own_secrets(&mut history, server_id);
+                if cut > 0 {
+                    error!(
+                        "Drop {} events from the journal of game {}, the secrets of its randomness are lost.",
+                        cut, game_addr
+                    );
+                    // The dropped entries are replaced as the game goes on,
+                    // remove them so they aren't replayed after another crash
+                    storage
+                        .cut_journal(CutJournalParams {
+                            game_addr: game_addr.clone(),
+                            settle_version: game_account.settle_version,
+                            seq: history.len() as u64,
+                        })
+                        .await?;
+                }
+            }
+            if !history.is_empty() {
+                info!("Recover {} events from the journal of game {}.", history.len(), game_addr);
+            }
             // The game is already initialized, create a RecoverCheckpoint frame.
             EventFrame::RecoverCheckpoint {
                 checkpoint: checkpoint.to_context_checkpoint(),
@@ -141,10 +187,15 @@ impl TransactorHandle {
 
         let event_bus = EventBus::new(game_addr.clone());
 
-        let (broadcaster, broadcaster_ctx) = Broadcaster::init(game_addr.clone(), 0);
+        let (broadcaster, broadcaster_ctx) = Broadcaster::init(
+            game_addr.clone(),
+            0,
+            Some(storage.clone() as Arc<dyn StorageT>),
+            config.backlog.as_ref(),
+        );
         let mut broadcaster_handle = broadcaster.start(&game_addr, broadcaster_ctx);
 
-        let (bridge, bridge_ctx) = EventBridgeParent::init(signal_tx);
+        let (bridge, bridge_ctx) = EventBridgeParent::init(signal_tx, chain);
         let mut bridge_handle = bridge.start(&game_addr, bridge_ctx);
 
         let (credential_consolidator, credential_consolidator_ctx) = CredentialConsolidator::init(
@@ -158,25 +209,60 @@ impl TransactorHandle {
         );
 
         let (event_loop, event_loop_ctx) = EventLoop::init(
-            game_spec,
+            game_spec.clone(),
             encryptor.clone(),
             transport.clone(),
+            module_cache,
             ClientMode::Transactor,
             GameMode::Main,
+            config.handler.as_ref(),
         );
         let mut event_loop_handle = event_loop.start(&game_account.addr, event_loop_ctx);
 
         let (submitter, submitter_ctx) =
-            Submitter::init(&game_account, transport.clone(), storage.clone(), config.submitter.as_ref());
+            Submitter::init(
+                &game_account,
+                transport.clone(),
+                storage.clone(),
+                Some(transport.subscribe_settle_progress()),
+                config.submitter.as_ref(),
+            );
         let mut submitter_handle = submitter.start(&game_account.addr, submitter_ctx);
 
         let (synchronizer, synchronizer_ctx) =
-            GameSynchronizer::init(transport.clone(), &game_account.addr, checkpoint_access_version);
+            GameSynchronizer::init(
+                transport.clone(),
+                &game_account.addr,
+                &server_account.addr,
+                &server_account.addr,
+                ClientMode::Transactor,
+                checkpoint_access_version,
+            );
 
         let (refunder, refunder_ctx) =
-            Refunder::init(&game_account, transport.clone());
+            Refunder::init(&game_account, transport.clone(), storage.clone());
         let mut refunder_handle = refunder.start(&game_account.addr, refunder_ctx);
 
+        let (journal, journal_ctx) = Journal::init(game_addr.clone(), storage.clone());
+        let mut journal_handle = journal.start(&game_account.addr, journal_ctx);
+
+        let (history_replayer, history_replayer_ctx) = HistoryReplayer::init(history);
+        let mut history_replayer_handle = history_replayer.start(&game_account.addr, history_replayer_ctx);
+
+        let recorder_config = config.recorder.as_ref().filter(|c| Recorder::is_enabled(c));
+        let mut recorder_handle = if let Some(recorder_config) = recorder_config {
+            let (recorder, recorder_ctx) = Recorder::init(
+                game_spec.clone(),
+                game_account.data.clone(),
+                game_account.entry_type.clone(),
+                chain,
+                recorder_config,
+            );
+            Some(recorder.start(&game_account.addr, recorder_ctx))
+        } else {
+            None
+        };
+
         let mut connection = LocalConnection::new(encryptor.clone());
 
         event_bus.attach(&mut connection).await;
@@ -197,7 +283,12 @@ impl TransactorHandle {
         event_bus.attach(&mut client_handle).await;
         event_bus.attach(&mut refunder_handle).await;
         event_bus.attach(&mut credential_consolidator_handle).await;
-        // event_bus.attach(&mut recorder_handle).await;
+        event_bus.attach(&mut history_replayer_handle).await;
+        event_bus.attach(&mut journal_handle).await;
+        // Attached before the init frame to record the initial checkpoint
+        if let Some(recorder_handle) = recorder_handle.as_mut() {
+            event_bus.attach(recorder_handle).await;
+        }
         event_bus.send(init_frame).await;
 
         // XXX in both cases, whether the game is initialized from InitState or from RecoverCheckpoint,
@@ -209,18 +300,22 @@ impl TransactorHandle {
         let mut synchronizer_handle = synchronizer.start(&game_account.addr, synchronizer_ctx);
         event_bus.attach(&mut synchronizer_handle).await;
 
+        let mut handles = vec![
+            broadcaster_handle,
+            submitter_handle,
+            event_loop_handle,
+            client_handle,
+            synchronizer_handle,
+            credential_consolidator_handle,
+            journal_handle,
+        ];
+        handles.extend(recorder_handle);
+
         Ok(Self {
             addr: game_account.addr.clone(),
             bundle_addr: game_account.bundle_addr.clone(),
             event_bus,
-            handles: vec![
-                broadcaster_handle,
-                submitter_handle,
-                event_loop_handle,
-                client_handle,
-                synchronizer_handle,
-                credential_consolidator_handle,
-            ],
+            handles,
             broadcaster,
             bridge_parent: bridge,
         })
@@ -1,12 +1,13 @@
 use std::sync::Arc;
 
+use race_handler::ModuleCache;
 use race_transactor_components::{
-    Component, EventBridgeParent, EventBus, EventLoop, PortsHandle, RemoteConnection, Subscriber,
-    Voter, WrappedClient,
+    Component, DivergenceLog, EventBridgeParent, EventBus, EventLoop, GameSynchronizer, PortsHandle,
+    Promoter, RemoteConnection, Subscriber, Voter, WrappedClient,
 };
 use race_transactor_frames::{EventFrame, SignalFrame};
+use race_core::chain::ChainType;
 use race_core::error::{Error, Result};
-use race_core::storage::StorageT;
 use race_core::transport::TransportT;
 use race_core::types::{CheckpointParams, ClientMode, GameMode, ServerAccount};
 use race_encryptor::Encryptor;
@@ -29,9 +30,11 @@ impl ValidatorHandle {
         server_account: &ServerAccount,
         encryptor: Arc<Encryptor>,
         transport: Arc<dyn TransportT + Send + Sync>,
-        _storage: Arc<dyn StorageT + Send + Sync>,
+        chain: ChainType,
         signal_tx: mpsc::Sender<SignalFrame>,
-        _config: &TransactorConfig,
+        module_cache: Arc<ModuleCache>,
+        config: &TransactorConfig,
+        divergence_log: Arc<DivergenceLog>,
     ) -> Result<Self> {
         info!("Start game handle for {} with Validator mode", game_addr,);
         let Some(game_account) = transport.get_game_account(&game_addr).await? else {
@@ -74,7 +77,23 @@ impl ValidatorHandle {
         info!("Creating components");
         let event_bus = EventBus::new(game_account.addr.clone());
 
-        let (bridge, bridge_ctx) = EventBridgeParent::init(signal_tx);
+        // Watch the game account, to take over the game when this
+        // server becomes the transactor, or to follow the new
+        // transactor
+        let (promoter, promoter_ctx) =
+            Promoter::init(&game_account.addr, chain, &server_account.addr, signal_tx.clone());
+        let mut promoter_handle = promoter.start(&game_account.addr, promoter_ctx);
+
+        let (synchronizer, synchronizer_ctx) = GameSynchronizer::init(
+            transport.clone(),
+            &game_account.addr,
+            &server_account.addr,
+            transactor_addr,
+            ClientMode::Validator,
+            0,
+        );
+
+        let (bridge, bridge_ctx) = EventBridgeParent::init(signal_tx, chain);
         let mut bridge_handle = bridge.start(&game_account.addr, bridge_ctx);
 
         let (event_loop, event_loop_ctx) =
@@ -82,8 +101,10 @@ impl ValidatorHandle {
                 game_spec,
                 encryptor.clone(),
                 transport.clone(),
+                module_cache,
                 ClientMode::Validator,
-                GameMode::Main
+                GameMode::Main,
+                config.handler.as_ref(),
             );
         let mut event_loop_handle = event_loop.start(&game_account.addr, event_loop_ctx);
 
@@ -96,18 +117,25 @@ impl ValidatorHandle {
             game_account.addr.clone(),
             ClientMode::Validator,
             transport.clone(),
-            encryptor,
+            encryptor.clone(),
             connection,
         );
         let mut client_handle = client.start(&game_account.addr, client_ctx);
 
-        let (voter, voter_ctx) = Voter::init(&game_account, server_account, transport.clone());
+        let (voter, voter_ctx) = Voter::init(
+            &game_account,
+            server_account,
+            transport.clone(),
+            encryptor,
+            divergence_log,
+        );
         let mut voter_handle = voter.start(&game_account.addr, voter_ctx);
 
         event_bus.attach(&mut bridge_handle).await;
         event_bus.attach(&mut event_loop_handle).await;
         event_bus.attach(&mut voter_handle).await;
         event_bus.attach(&mut client_handle).await;
+        event_bus.attach(&mut promoter_handle).await;
 
         // Dispatch init state
         event_bus
@@ -117,6 +145,10 @@ impl ValidatorHandle {
             .await;
 
         event_bus.attach(&mut subscriber_handle).await;
+
+        let mut synchronizer_handle = synchronizer.start(&game_account.addr, synchronizer_ctx);
+        event_bus.attach(&mut synchronizer_handle).await;
+
         Ok(Self {
             addr: game_account.addr.clone(),
             bundle_addr: game_account.bundle_addr.clone(),
@@ -126,6 +158,8 @@ impl ValidatorHandle {
                 client_handle,
                 event_loop_handle,
                 voter_handle,
+                promoter_handle,
+                synchronizer_handle,
             ],
             bridge_parent: bridge,
         })
@@ -0,0 +1,356 @@
+//! The HTTP endpoints for the orchestrator, served on the same port
+//! as the JSON-RPC server.
+//!
+//! - `GET /healthz`, always 200 as long as the server responds.
+//! - `GET /readyz`, 200 when all subsystems are ready, otherwise 503.
+//!   The body is a JSON breakdown per subsystem, the transport and the
+//!   registration task are checked for each chain.
+
+use std::collections::BTreeMap;
+use std::error::Error as StdError;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::future::join_all;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use race_core::storage::StorageT;
+use race_core::transport::TransportT;
+use race_env::{HealthConfig, TransactorConfig};
+use race_transactor_components::WrappedStorage;
+use serde::Serialize;
+use tokio::sync::watch;
+use tower::{Layer, Service};
+
+use crate::chains::{ChainContext, Chains};
+use crate::context::ApplicationContext;
+use crate::utils::current_timestamp;
+
+// The default for seconds to wait for each subsystem.
+const DEFAULT_CHECK_TIMEOUT: u64 = 5;
+// The default for seconds without a finished scan of the reg task.
+const DEFAULT_REG_TIMEOUT: u64 = 60;
+// The default for seconds a settlement can be retried.
+const DEFAULT_SETTLE_STUCK_THRESHOLD: u64 = 600;
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SubsystemStatus {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SubsystemStatus {
+    fn from_result(r: Result<(), String>) -> Self {
+        match r {
+            Ok(()) => Self { ok: true, error: None },
+            Err(e) => Self { ok: false, error: Some(e) },
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RegTaskStatus {
+    pub ok: bool,
+    /// The timestamp of the last finished scan.
+    pub last_scan: Option<u64>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StuckGame {
+    pub addr: String,
+    /// Seconds the settlement has been retried.
+    pub retrying_secs: u64,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SettlementStatus {
+    pub ok: bool,
+    pub stuck_games: Vec<StuckGame>,
+}
+
+/// The result of `/readyz`.  The transport and the registration task
+/// are keyed by chain.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Readiness {
+    pub ready: bool,
+    pub transport: BTreeMap<String, SubsystemStatus>,
+    pub storage: SubsystemStatus,
+    pub reg_task: BTreeMap<String, RegTaskStatus>,
+    pub settlements: SettlementStatus,
+}
+
+impl Readiness {
+    fn new(
+        transport: BTreeMap<String, SubsystemStatus>,
+        storage: SubsystemStatus,
+        reg_task: BTreeMap<String, RegTaskStatus>,
+        settlements: SettlementStatus,
+    ) -> Self {
+        let ready = transport.values().all(|s| s.ok)
+            && storage.ok
+            && reg_task.values().all(|s| s.ok)
+            && settlements.ok;
+        Self {
+            ready,
+            transport,
+            storage,
+            reg_task,
+            settlements,
+        }
+    }
+}
+
+fn check_reg_task(last_scan: Option<u64>, now: u64, reg_timeout: u64) -> RegTaskStatus {
+    let ok = last_scan
+        .map(|ts| now.saturating_sub(ts) <= reg_timeout * 1000)
+        .unwrap_or(false);
+    RegTaskStatus { ok, last_scan }
+}
+
+fn settlement_status(stuck: impl Iterator<Item = (String, Duration)>) -> SettlementStatus {
+    let mut stuck_games: Vec<StuckGame> = stuck
+        .map(|(addr, retrying_for)| StuckGame {
+            addr,
+            retrying_secs: retrying_for.as_secs(),
+        })
+        .collect();
+    stuck_games.sort_by(|a, b| a.addr.cmp(&b.addr));
+    SettlementStatus {
+        ok: stuck_games.is_empty(),
+        stuck_games,
+    }
+}
+
+/// Run the readiness checks against the subsystems of the transactor.
+pub struct HealthChecker {
+    chains: Arc<Chains>,
+    storage: Arc<WrappedStorage>,
+    config_rx: watch::Receiver<TransactorConfig>,
+}
+
+impl HealthChecker {
+    pub fn new(context: &ApplicationContext) -> Self {
+        Self {
+            chains: context.chains.clone(),
+            storage: context.storage.clone(),
+            config_rx: context.subscribe_config(),
+        }
+    }
+
+    async fn check_transport(chain: &ChainContext, timeout: Duration) -> SubsystemStatus {
+        let server_addr = &chain.account.addr;
+        let r = match tokio::time::timeout(timeout, chain.transport.get_server_account(server_addr)).await {
+            Ok(Ok(Some(_))) => Ok(()),
+            Ok(Ok(None)) => Err(format!("Server account {} not found", server_addr)),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err("Timeout".to_string()),
+        };
+        SubsystemStatus::from_result(r)
+    }
+
+    async fn check_storage(&self, timeout: Duration) -> SubsystemStatus {
+        let r = match tokio::time::timeout(timeout, self.storage.health_check()).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err("Timeout".to_string()),
+        };
+        SubsystemStatus::from_result(r)
+    }
+
+    /// The settlements are retried by the transport of each chain.
+    async fn check_settlements(&self, threshold: Duration) -> SettlementStatus {
+        settlement_status(
+            self.chains
+                .iter()
+                .flat_map(|c| c.transport.get_stuck_settles(threshold)),
+        )
+    }
+
+    pub async fn check(&self) -> Readiness {
+        let config: Option<HealthConfig> = self.config_rx.borrow().health.clone();
+        let check_timeout = config
+            .as_ref()
+            .and_then(|c| c.check_timeout)
+            .unwrap_or(DEFAULT_CHECK_TIMEOUT);
+        let reg_timeout = config
+            .as_ref()
+            .and_then(|c| c.reg_timeout)
+            .unwrap_or(DEFAULT_REG_TIMEOUT);
+        let settle_stuck_threshold = config
+            .as_ref()
+            .and_then(|c| c.settle_stuck_threshold)
+            .unwrap_or(DEFAULT_SETTLE_STUCK_THRESHOLD);
+
+        let timeout = Duration::from_secs(check_timeout);
+        let (transports, storage, settlements) = tokio::join!(
+            join_all(self.chains.iter().map(|c| Self::check_transport(c, timeout))),
+            self.check_storage(timeout),
+            self.check_settlements(Duration::from_secs(settle_stuck_threshold)),
+        );
+        let transport = self
+            .chains
+            .iter()
+            .map(|c| c.chain.to_string())
+            .zip(transports)
+            .collect();
+
+        let now = current_timestamp();
+        let reg_task = self
+            .chains
+            .iter()
+            .map(|c| {
+                let status = check_reg_task(c.reg_heartbeat.last_scan(), now, reg_timeout);
+                (c.chain.to_string(), status)
+            })
+            .collect();
+
+        Readiness::new(transport, storage, reg_task, settlements)
+    }
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    let body = serde_json::to_vec(body).unwrap_or_default();
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .expect("Build health response")
+}
+
+/// A middleware to serve `/healthz` and `/readyz` in front of the
+/// JSON-RPC server.
+#[derive(Clone)]
+pub struct HealthLayer {
+    checker: Arc<HealthChecker>,
+}
+
+impl HealthLayer {
+    pub fn new(checker: HealthChecker) -> Self {
+        Self {
+            checker: Arc::new(checker),
+        }
+    }
+}
+
+impl<S> Layer<S> for HealthLayer {
+    type Service = HealthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HealthService {
+            inner,
+            checker: self.checker.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HealthService<S> {
+    inner: S,
+    checker: Arc<HealthChecker>,
+}
+
+impl<S> Service<Request<Body>> for HealthService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Error: Into<Box<dyn StdError + Send + Sync>> + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = Box<dyn StdError + Send + Sync + 'static>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if req.method() == Method::GET {
+            match req.uri().path() {
+                "/healthz" => {
+                    return Box::pin(async {
+                        Ok(json_response(StatusCode::OK, &serde_json::json!({ "status": "ok" })))
+                    });
+                }
+                "/readyz" => {
+                    let checker = self.checker.clone();
+                    return Box::pin(async move {
+                        let readiness = checker.check().await;
+                        let status = if readiness.ready {
+                            StatusCode::OK
+                        } else {
+                            StatusCode::SERVICE_UNAVAILABLE
+                        };
+                        Ok(json_response(status, &readiness))
+                    });
+                }
+                _ => (),
+            }
+        }
+
+        let fut = self.inner.call(req);
+        Box::pin(async move { fut.await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_reg_task() {
+        assert!(!check_reg_task(None, 100_000, 60).ok);
+        assert!(check_reg_task(Some(50_000), 100_000, 60).ok);
+        assert!(!check_reg_task(Some(30_000), 100_000, 60).ok);
+    }
+
+    #[test]
+    fn test_settlement_status() {
+        assert!(settlement_status(std::iter::empty()).ok);
+
+        let status = settlement_status(
+            vec![
+                ("game2".to_string(), Duration::from_secs(700)),
+                ("game1".to_string(), Duration::from_millis(900_500)),
+            ]
+            .into_iter(),
+        );
+        assert!(!status.ok);
+        assert_eq!(
+            status.stuck_games,
+            vec![
+                StuckGame { addr: "game1".into(), retrying_secs: 900 },
+                StuckGame { addr: "game2".into(), retrying_secs: 700 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_readiness_breakdown() {
+        let readiness = Readiness::new(
+            BTreeMap::from([
+                ("solana".to_string(), SubsystemStatus::from_result(Ok(()))),
+                ("sui".to_string(), SubsystemStatus::from_result(Ok(()))),
+            ]),
+            SubsystemStatus::from_result(Err("disk I/O error".into())),
+            BTreeMap::from([
+                ("solana".to_string(), RegTaskStatus { ok: true, last_scan: Some(1000) }),
+            ]),
+            SettlementStatus { ok: true, stuck_games: vec![] },
+        );
+        assert!(!readiness.ready);
+
+        let json = serde_json::to_value(&readiness).unwrap();
+        assert_eq!(json["transport"]["sui"], serde_json::json!({ "ok": true }));
+        assert_eq!(json["storage"]["error"], "disk I/O error");
+        assert_eq!(json["regTask"]["solana"]["lastScan"], 1000);
+        assert_eq!(json["settlements"]["stuckGames"], serde_json::json!([]));
+    }
+}
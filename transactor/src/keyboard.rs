@@ -1,19 +1,52 @@
-///! Keyboard handling
+///! Keyboard and process signal handling
 
 use tokio::{signal, task::JoinHandle};
+use tracing::{error, info, warn};
 
 use crate::context::ApplicationContext;
 use race_transactor_frames::SignalFrame;
 
-#[allow(unused)]
-pub fn setup_keyboard_handler(context: &mut ApplicationContext) -> JoinHandle<()> {
+/// Wait for SIGTERM, which is sent by process managers, e.g. Kubernetes.
+#[cfg(unix)]
+async fn terminate() {
+    match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+        Ok(mut sigterm) => {
+            sigterm.recv().await;
+        }
+        Err(e) => {
+            error!("Failed to listen for SIGTERM: {:?}", e);
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn terminate() {
+    std::future::pending::<()>().await;
+}
+
+/// Start a graceful shutdown on the first Ctrl+C or SIGTERM.  A
+/// second one will exit the process immediately.
+pub fn setup_keyboard_handler(context: &ApplicationContext) -> JoinHandle<()> {
 
     let signal_tx = context.get_signal_sender();
 
-    let ctrl_c_handler = tokio::spawn(async move {
-        signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
-        signal_tx.send(SignalFrame::Shutdown).await.expect("Failed to send shutdown signal");
-    });
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = signal::ctrl_c() => info!("Received Ctrl+C"),
+            _ = terminate() => info!("Received SIGTERM"),
+        }
+
+        info!("Start graceful shutdown, press Ctrl+C again to exit immediately");
+        if let Err(e) = signal_tx.send(SignalFrame::Shutdown).await {
+            error!("Failed to send shutdown signal: {:?}", e);
+        }
 
-    ctrl_c_handler
+        tokio::select! {
+            _ = signal::ctrl_c() => (),
+            _ = terminate() => (),
+        }
+        warn!("Exit without waiting games to finish");
+        std::process::exit(1);
+    })
 }
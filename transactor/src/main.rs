@@ -6,16 +6,30 @@ mod reg;
 mod blacklist;
 mod server;
 mod keyboard;
+mod reload;
+mod native;
+mod rate_limit;
+mod chat;
+mod session;
+mod encoding;
+mod spectator;
+mod capacity;
+mod telemetry;
+mod health;
+mod chains;
 
+use std::path::PathBuf;
 use tracing::error;
 use crate::server::run_server;
 use clap::{arg, Command};
 use context::ApplicationContext;
+use keyboard::setup_keyboard_handler;
+use reload::setup_config_reloader;
 use race_env::Config;
 use reg::{register_server, start_reg_task};
 use tokio::try_join;
-use tracing::info;
-use tracing_subscriber::{fmt, prelude::__tracing_subscriber_SubscriberExt, Layer, EnvFilter};
+use tracing::{info, Level};
+use tracing_subscriber::{fmt, filter::Targets, prelude::__tracing_subscriber_SubscriberExt, Layer, EnvFilter};
 
 fn cli() -> Command {
     Command::new("transactor")
@@ -44,27 +58,54 @@ fn setup_logger(config: &Config) {
         .with_ansi(true)
         .without_time()
         .with_filter(EnvFilter::from_default_env());
+    // A bad telemetry config is logged once the logger is ready, the
+    // transactor runs without telemetry
+    let telemetry = config
+        .transactor
+        .as_ref()
+        .and_then(|c| c.telemetry.as_ref())
+        .map_or(Ok(None), telemetry::init_tracer);
+    let (tracer, telemetry_error) = match telemetry {
+        Ok(tracer) => (tracer, None),
+        Err(e) => (None, Some(e)),
+    };
+    // The spans of the race crates, regardless of the log filter
+    let telemetry_layer = tracer
+        .map(|tracer| {
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer)
+                .with_filter(Targets::new().with_target("race", Level::INFO))
+        });
     let subscriber = tracing_subscriber::registry()
         .with(console_layer)
-        .with(file_layer);
+        .with(file_layer)
+        .with(telemetry_layer);
 
     tracing::subscriber::set_global_default(subscriber).expect("Failed to configure logger");
+
+    if let Some(e) = telemetry_error {
+        error!("Failed to configure telemetry, continue without it: {}", e);
+    }
 }
 
 #[tokio::main]
 pub async fn main() {
 
     let matches = cli().get_matches();
-    let config = Config::from_path(&matches.get_one::<String>("config").unwrap().into()).await;
+    let config_path: PathBuf = matches.get_one::<String>("config").unwrap().into();
+    let config = Config::from_path(&config_path).await;
 
     setup_logger(&config);
 
     match matches.subcommand() {
         Some(("run", _)) => {
             info!("Starting transactor.");
-            let (context, signal_loop) = ApplicationContext::try_new_and_start_signal_loop(config)
-                .await
-                .expect("Failed to initalize");
+            let (context, signal_loop) =
+                ApplicationContext::try_new_and_start_signal_loop(config.clone())
+                    .await
+                    .expect("Failed to initalize");
+            setup_keyboard_handler(&context);
+            setup_config_reloader(&context, config_path, config);
             let reg_task = start_reg_task(&context).await;
             let server_handle = run_server(context).await.expect("Unexpected error occured");
             if let Err(e) = try_join!(signal_loop, reg_task, server_handle) {
@@ -80,4 +121,6 @@ pub async fn main() {
         }
         _ => unreachable!(),
     }
+
+    telemetry::shutdown();
 }
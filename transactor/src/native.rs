@@ -0,0 +1,51 @@
+//! Native game handlers for local development.
+//!
+//! With `debug_mode` enabled, the bundles listed in `native_handlers`
+//! are served by game handlers built into the transactor, instead of
+//! their WASM bundles.  So a game can be debugged with gdb or lldb.
+//!
+//! To build a game into the transactor, add it as an optional
+//! dependency and a match arm in [register_by_name].  The example
+//! games are available with feature `native-examples`.
+
+use race_env::TransactorConfig;
+use race_handler::NativeHandlers;
+use tracing::{info, warn};
+
+#[cfg(feature = "native-examples")]
+fn register_by_name(handlers: &mut NativeHandlers, bundle_addr: &str, name: &str) -> bool {
+    match name {
+        "minimal" => handlers.register::<race_example_minimal::Minimal>(bundle_addr),
+        "raffle" => handlers.register::<race_example_raffle::Raffle>(bundle_addr),
+        _ => return false,
+    }
+    true
+}
+
+#[cfg(not(feature = "native-examples"))]
+fn register_by_name(_handlers: &mut NativeHandlers, _bundle_addr: &str, _name: &str) -> bool {
+    false
+}
+
+pub fn load_native_handlers(config: &TransactorConfig) -> NativeHandlers {
+    let mut handlers = NativeHandlers::default();
+
+    let Some(ref native_handlers) = config.native_handlers else {
+        return handlers;
+    };
+
+    if config.debug_mode.ne(&Some(true)) {
+        warn!("Native handlers are ignored, they require debug mode");
+        return handlers;
+    }
+
+    for (bundle_addr, name) in native_handlers.iter() {
+        if register_by_name(&mut handlers, bundle_addr, name) {
+            info!("Serve bundle {} with native handler {}", bundle_addr, name);
+        } else {
+            warn!("Native handler {} is not built into this transactor", name);
+        }
+    }
+
+    handlers
+}
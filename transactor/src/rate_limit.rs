@@ -0,0 +1,300 @@
+//! Rate limiting for the events and messages submitted by clients.
+//!
+//! Each signer and each game has a token bucket, a request takes one
+//! token from both, and none when either is empty.  The limits are
+//! checked after the signature of a request is verified, so a forged
+//! request can't spend the tokens of the signer it claims.  Only the
+//! payload size is checked before, with no violation counted, so an
+//! oversized request costs no verification.  A signer with too many
+//! rejections in a minute is banned for a while, all its requests are
+//! rejected during the ban.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use race_api::event::Event;
+use race_core::error::{Error, Result};
+use race_env::RateLimitConfig;
+use tokio::time::{Duration, Instant};
+use tracing::warn;
+
+const DEFAULT_SIGNER_RATE: u32 = 10;
+const DEFAULT_SIGNER_BURST: u32 = 20;
+const DEFAULT_GAME_RATE: u32 = 200;
+const DEFAULT_GAME_BURST: u32 = 400;
+const DEFAULT_MAX_EVENT_SIZE: usize = 4096;
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024;
+const DEFAULT_BAN_THRESHOLD: u32 = 50;
+const DEFAULT_BAN_DURATION: u64 = 300;
+
+// The window to count rejections for bans.
+const VIOLATION_WINDOW: Duration = Duration::from_secs(60);
+// Buckets idle for this long are dropped.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+// Drop idle buckets every this many checks.
+const PRUNE_INTERVAL: u64 = 10_000;
+
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(burst: u32, now: Instant) -> Self {
+        Self {
+            tokens: burst as f64,
+            updated_at: now,
+        }
+    }
+
+    /// Refill the bucket, return whether it has a token to take.
+    fn refill(&mut self, rate: u32, burst: u32, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate as f64).min(burst as f64);
+        self.updated_at = now;
+        self.tokens >= 1.0
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+fn event_size(event: &Event) -> usize {
+    match event {
+        Event::Custom { raw, .. } => raw.len(),
+        _ => 0,
+    }
+}
+
+fn check_size(size: usize, max_size: usize) -> Result<()> {
+    if size > max_size {
+        Err(Error::PayloadTooLarge(size))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Violations {
+    count: u32,
+    window_start: Option<Instant>,
+    banned_until: Option<Instant>,
+}
+
+#[derive(Default)]
+struct State {
+    signers: HashMap<String, TokenBucket>,
+    games: HashMap<String, TokenBucket>,
+    violations: HashMap<String, Violations>,
+    checks: u64,
+}
+
+pub struct RateLimiter {
+    signer_rate: u32,
+    signer_burst: u32,
+    game_rate: u32,
+    game_burst: u32,
+    max_event_size: usize,
+    max_message_size: usize,
+    ban_threshold: u32,
+    ban_duration: Duration,
+    state: Mutex<State>,
+}
+
+impl RateLimiter {
+    pub fn new(config: Option<&RateLimitConfig>) -> Self {
+        Self {
+            signer_rate: config.and_then(|c| c.signer_rate).unwrap_or(DEFAULT_SIGNER_RATE),
+            signer_burst: config.and_then(|c| c.signer_burst).unwrap_or(DEFAULT_SIGNER_BURST),
+            game_rate: config.and_then(|c| c.game_rate).unwrap_or(DEFAULT_GAME_RATE),
+            game_burst: config.and_then(|c| c.game_burst).unwrap_or(DEFAULT_GAME_BURST),
+            max_event_size: config
+                .and_then(|c| c.max_event_size)
+                .unwrap_or(DEFAULT_MAX_EVENT_SIZE),
+            max_message_size: config
+                .and_then(|c| c.max_message_size)
+                .unwrap_or(DEFAULT_MAX_MESSAGE_SIZE),
+            ban_threshold: config.and_then(|c| c.ban_threshold).unwrap_or(DEFAULT_BAN_THRESHOLD),
+            ban_duration: Duration::from_secs(
+                config.and_then(|c| c.ban_duration).unwrap_or(DEFAULT_BAN_DURATION),
+            ),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Check the size of an event before its signer is verified.
+    pub fn check_event_size(&self, event: &Event) -> Result<()> {
+        check_size(event_size(event), self.max_event_size)
+    }
+
+    /// Check the size of a message before its signer is verified.
+    pub fn check_message_size(&self, content: &str) -> Result<()> {
+        check_size(content.len(), self.max_message_size)
+    }
+
+    pub fn check_event(&self, signer: &str, game_addr: &str, event: &Event) -> Result<()> {
+        self.check(signer, game_addr, event_size(event), self.max_event_size, Instant::now())
+    }
+
+    pub fn check_message(&self, signer: &str, game_addr: &str, content: &str) -> Result<()> {
+        let size = content.len();
+        self.check(signer, game_addr, size, self.max_message_size, Instant::now())
+    }
+
+    fn check(
+        &self,
+        signer: &str,
+        game_addr: &str,
+        size: usize,
+        max_size: usize,
+        now: Instant,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        state.checks += 1;
+        if state.checks % PRUNE_INTERVAL == 0 {
+            Self::prune(&mut state, now);
+        }
+
+        if let Some(banned_until) = state.violations.get(signer).and_then(|v| v.banned_until) {
+            if now < banned_until {
+                return Err(Error::SenderBanned);
+            }
+        }
+
+        if size > max_size {
+            self.add_violation(&mut state, signer, now);
+            return Err(Error::PayloadTooLarge(size));
+        }
+
+        let State { signers, games, .. } = &mut *state;
+        let signer_bucket = signers
+            .entry(signer.to_owned())
+            .or_insert_with(|| TokenBucket::new(self.signer_burst, now));
+        if !signer_bucket.refill(self.signer_rate, self.signer_burst, now) {
+            self.add_violation(&mut state, signer, now);
+            return Err(Error::RateLimited);
+        }
+        let game_bucket = games
+            .entry(game_addr.to_owned())
+            .or_insert_with(|| TokenBucket::new(self.game_burst, now));
+        if !game_bucket.refill(self.game_rate, self.game_burst, now) {
+            // Don't count it as a violation, the signer may not be
+            // the one flooding the game.
+            return Err(Error::RateLimited);
+        }
+
+        game_bucket.take();
+        signer_bucket.take();
+        Ok(())
+    }
+
+    fn add_violation(&self, state: &mut State, signer: &str, now: Instant) {
+        let v = state.violations.entry(signer.to_owned()).or_default();
+        match v.window_start {
+            Some(start) if now.saturating_duration_since(start) < VIOLATION_WINDOW => {
+                v.count += 1;
+            }
+            _ => {
+                v.window_start = Some(now);
+                v.count = 1;
+            }
+        }
+        if v.count >= self.ban_threshold {
+            warn!("Ban {} for {} seconds", signer, self.ban_duration.as_secs());
+            v.banned_until = Some(now + self.ban_duration);
+            v.window_start = None;
+            v.count = 0;
+        }
+    }
+
+    fn prune(state: &mut State, now: Instant) {
+        let is_active = |t: Instant| now.saturating_duration_since(t) < IDLE_TIMEOUT;
+        state.signers.retain(|_, b| is_active(b.updated_at));
+        state.games.retain(|_, b| is_active(b.updated_at));
+        state.violations.retain(|_, v| {
+            v.banned_until.map_or(false, |t| now < t) || v.window_start.map_or(false, is_active)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_limiter() -> RateLimiter {
+        RateLimiter::new(Some(&RateLimitConfig {
+            signer_rate: Some(1),
+            signer_burst: Some(2),
+            game_rate: Some(100),
+            game_burst: Some(100),
+            max_event_size: Some(10),
+            max_message_size: Some(10),
+            ban_threshold: Some(3),
+            ban_duration: Some(60),
+        }))
+    }
+
+    #[test]
+    fn test_signer_bucket() {
+        let limiter = make_limiter();
+        let now = Instant::now();
+        assert_eq!(limiter.check("alice", "game", 0, 10, now), Ok(()));
+        assert_eq!(limiter.check("alice", "game", 0, 10, now), Ok(()));
+        assert_eq!(limiter.check("alice", "game", 0, 10, now), Err(Error::RateLimited));
+        assert_eq!(limiter.check("bob", "game", 0, 10, now), Ok(()));
+        let now = now + Duration::from_secs(1);
+        assert_eq!(limiter.check("alice", "game", 0, 10, now), Ok(()));
+    }
+
+    #[test]
+    fn test_game_bucket_keeps_signer_token() {
+        let limiter = RateLimiter::new(Some(&RateLimitConfig {
+            signer_rate: Some(1),
+            signer_burst: Some(1),
+            game_rate: Some(1),
+            game_burst: Some(1),
+            ..Default::default()
+        }));
+        let now = Instant::now();
+        assert_eq!(limiter.check("alice", "game", 0, 10, now), Ok(()));
+        // Rejected by the game bucket, bob's token is not taken
+        assert_eq!(limiter.check("bob", "game", 0, 10, now), Err(Error::RateLimited));
+        assert_eq!(limiter.check("bob", "other", 0, 10, now), Ok(()));
+    }
+
+    #[test]
+    fn test_payload_size() {
+        let limiter = make_limiter();
+        let now = Instant::now();
+        assert_eq!(limiter.check("alice", "game", 11, 10, now), Err(Error::PayloadTooLarge(11)));
+        assert_eq!(limiter.check("alice", "game", 10, 10, now), Ok(()));
+    }
+
+    #[test]
+    fn test_size_check_counts_no_violation() {
+        let limiter = make_limiter();
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(
+                limiter.check_message_size("01234567890"),
+                Err(Error::PayloadTooLarge(11))
+            );
+        }
+        assert_eq!(limiter.check_message_size("0123456789"), Ok(()));
+        assert_eq!(limiter.check("alice", "game", 0, 10, now), Ok(()));
+    }
+
+    #[test]
+    fn test_ban() {
+        let limiter = make_limiter();
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check("alice", "game", 11, 10, now).is_err());
+        }
+        assert_eq!(limiter.check("alice", "game", 0, 10, now), Err(Error::SenderBanned));
+        let now = now + Duration::from_secs(61);
+        assert_eq!(limiter.check("alice", "game", 0, 10, now), Ok(()));
+    }
+}
@@ -1,68 +1,208 @@
 //! Register current transactor into on-chain transactor list
-//! Find available games and serve them.
+//! Find available games and serve them, within the capacity.
+//! Each chain is scanned by its own task, with its own registrations.
+//!
+//! With `idle_timeout`, the scan also unloads the idle games, and
+//! reloads them once new players join on chain.
 
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
+use futures::future::join_all;
 use race_core::error::{Error, Result};
 use race_core::types::ClientMode;
 use race_core::{
     transport::TransportT,
     types::{RegisterServerParams, ServeParams},
 };
-use race_encryptor::generate_credentials;
-use race_env::Config;
+use race_encryptor::encrypt_credentials;
+use race_env::{Config, TransactorConfig};
 use race_transport::TransportBuilder;
+use race_transactor_components::WrappedTransport;
 use tokio::select;
+use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
 use tracing::{error, info, warn};
 
 use race_transactor_frames::SignalFrame;
+use crate::capacity::select_games;
+use crate::chains::{load_private_key, ChainContext, GameKey};
 use crate::context::ApplicationContext;
+use crate::game_manager::GameManager;
+use crate::utils::current_timestamp;
 
-/// Register current server.
+/// The time of the last scan finished by the registration task, used
+/// to tell if the task is alive.
+#[derive(Default)]
+pub struct RegHeartbeat {
+    last_scan: AtomicU64,
+}
+
+impl RegHeartbeat {
+    fn beat(&self) {
+        self.last_scan.store(current_timestamp(), Ordering::SeqCst);
+    }
+
+    /// Return the timestamp of the last scan, None if no scan is
+    /// finished yet.
+    pub fn last_scan(&self) -> Option<u64> {
+        match self.last_scan.load(Ordering::SeqCst) {
+            0 => None,
+            ts => Some(ts),
+        }
+    }
+}
+
+/// Register current server on all chains, each with the credentials
+/// of its encryptor keys.
 pub async fn register_server(config: &Config) -> Result<()> {
     let transactor_conf = config
         .transactor
         .as_ref()
         .ok_or(Error::TransactorConfigMissing)?;
-    let transport: Box<dyn TransportT> = TransportBuilder::default()
-        .with_chain_by_name(transactor_conf.chain.as_str())
-        .try_with_config(config)?
-        .build()
-        .await?;
-
-    let secret = transport.generate_secret().await?;
-    let credentials = generate_credentials(secret)?;
-    let credentials = borsh::to_vec(&credentials)?;
-
-    info!("Transport built successfully");
-    transport
-        .register_server(RegisterServerParams {
-            endpoint: transactor_conf.endpoint.to_owned(),
-            credentials,
-        })
-        .await?;
-    info!("Server account created");
+
+    for chain_conf in transactor_conf.chain_configs() {
+        let transport: Box<dyn TransportT> = TransportBuilder::default()
+            .with_chain_by_name(chain_conf.chain.as_str())
+            .try_with_config(config)?
+            .build()
+            .await?;
+
+        let secret = transport.generate_secret().await?;
+        let private_key = load_private_key(chain_conf.credentials_file.as_deref())?;
+        let credentials = encrypt_credentials(&private_key, secret)?;
+        let credentials = borsh::to_vec(&credentials)?;
+
+        info!("Transport for {} built successfully", chain_conf.chain);
+        transport
+            .register_server(RegisterServerParams {
+                endpoint: transactor_conf.endpoint.to_owned(),
+                credentials,
+            })
+            .await?;
+        info!("Server account created on {}", chain_conf.chain);
+    }
     Ok(())
 }
 
-/// Start the registration task.
-/// This task will scan the games in registration account, find unserved games and join.
+/// Return the registration addresses of a chain.
+fn reg_addresses_of(config: &TransactorConfig, chain: &str) -> Vec<String> {
+    config
+        .chain_configs()
+        .into_iter()
+        .find(|c| c.chain == chain)
+        .map(|c| c.reg_addresses)
+        .unwrap_or_default()
+}
+
+/// Launch a game served by this transactor, as the transactor or a
+/// validator.  Return false if the game can't be launched for now.
+async fn start_game(
+    transport: &WrappedTransport,
+    signal_tx: &mpsc::Sender<SignalFrame>,
+    chain: &ChainContext,
+    server_addr: &str,
+    game_addr: &str,
+) -> bool {
+    let Ok(Some(game_account)) = transport.get_game_account(game_addr).await else {
+        error!("Failed to fetch game account after sending a serve transaction");
+        return false;
+    };
+
+    let Some(transactor_addr) = game_account.transactor_addr.as_ref() else {
+        error!("Failed to find transactor addr after sending a serve transaction");
+        return false;
+    };
+
+    let mode = if transactor_addr.eq(server_addr) {
+        ClientMode::Transactor
+    } else {
+        ClientMode::Validator
+    };
+    let signal_result = signal_tx
+        .send(SignalFrame::StartGame {
+            game_addr: game_account.addr.clone(),
+            mode,
+            chain: chain.chain,
+        })
+        .await;
+
+    if let Err(e) = signal_result {
+        error!("Failed to send StartGame for [{}] signal due to {:?}", game_addr, e);
+    }
+    true
+}
+
+/// Reload the idle games on a chain whose access versions have grown
+/// on chain, which means new players joined or deposited.  The games
+/// still being unloaded are checked in the next scan.
+async fn reload_idle_games(
+    transport: &WrappedTransport,
+    signal_tx: &mpsc::Sender<SignalFrame>,
+    chain: &ChainContext,
+    server_addr: &str,
+    game_manager: &GameManager,
+) {
+    for (key, access_version) in game_manager.get_idle_games(chain.chain) {
+        if game_manager.is_game_loaded(&key).await {
+            continue;
+        }
+        match transport.get_game_account(&key.addr).await {
+            Ok(Some(game_account)) if game_account.access_version > access_version => {
+                if game_manager.take_idle_game(&key) {
+                    info!("New joins in idle game {}, reload it", key);
+                    start_game(transport, signal_tx, chain, server_addr, &key.addr).await;
+                }
+            }
+            Ok(Some(_)) => (),
+            Ok(None) => {
+                warn!("Idle game account not found: {}", key);
+                game_manager.take_idle_game(&key);
+            }
+            Err(e) => {
+                error!("Failed to fetch idle game account due to {:?}", e);
+            }
+        }
+    }
+}
+
+/// Start the registration tasks, one for each chain.  The returned
+/// handle finishes when all of them stop.
 pub async fn start_reg_task(context: &ApplicationContext) -> JoinHandle<()> {
+    let tasks: Vec<JoinHandle<()>> = context
+        .chains
+        .iter()
+        .map(|chain| start_chain_reg_task(context, chain.clone()))
+        .collect();
+
+    tokio::spawn(async move {
+        join_all(tasks).await;
+    })
+}
+
+/// Start the registration task of a chain.
+/// This task will scan the games in registration account, find unserved games and join.
+/// New games are served until the capacity is reached, in the order of the selection policy.
+fn start_chain_reg_task(context: &ApplicationContext, chain: Arc<ChainContext>) -> JoinHandle<()> {
     let blacklist = context.blacklist();
     let mut shutdown_rx = context.get_shutdown_receiver();
 
-    let (reg_addresses, transport, server_addr, signal_tx) = {
+    let (config_rx, transport, server_addr, signal_tx, game_manager, module_cache) = {
         (
-            context.config.reg_addresses.clone(),
-            context.transport.clone(),
-            context.account.addr.clone(),
+            context.subscribe_config(),
+            chain.transport.clone(),
+            chain.account.addr.clone(),
             context.get_signal_sender(),
+            context.game_manager.clone(),
+            context.module_cache.clone(),
         )
     };
-    info!("Server address: {}", server_addr);
-    info!("Registraion addresses: {:?}", reg_addresses);
+    let chain_name = chain.chain.to_string();
+    let mut reg_addresses = reg_addresses_of(&config_rx.borrow(), &chain_name);
+    info!("Server address on {}: {}", chain_name, server_addr);
+    info!("Registraion addresses on {}: {:?}", chain_name, reg_addresses);
 
     tokio::spawn(async move {
         let mut not_found_counts = HashMap::<String, usize>::new();
@@ -77,11 +217,21 @@ pub async fn start_reg_task(context: &ApplicationContext) -> JoinHandle<()> {
         let mut loaded_game_addrs: HashSet<String> = Default::default();
 
         loop {
+            // Pick up the registration addresses from reloaded configuration
+            let latest_reg_addresses = reg_addresses_of(&config_rx.borrow(), &chain_name);
+            if latest_reg_addresses != reg_addresses {
+                info!("Registraion addresses on {} updated: {:?}", chain_name, latest_reg_addresses);
+                reg_addresses = latest_reg_addresses;
+            }
+            let capacity = config_rx.borrow().capacity.clone();
+            // The games not served by us yet
+            let mut candidates = vec![];
+
             // We search for accounts every 10 seconds
             for addr in reg_addresses.iter() {
                 if let Ok(Some(reg)) = transport.get_registration(addr).await {
                     for game_reg in reg.games.into_iter() {
-                        if blacklist.lock().await.contains_addr(&game_reg.addr) {
+                        if blacklist.lock().await.contains_addr(&chain.storage.scoped_addr(&game_reg.addr)) {
                             continue;
                         }
                         if loaded_game_addrs.contains(&game_reg.addr) {
@@ -89,46 +239,19 @@ pub async fn start_reg_task(context: &ApplicationContext) -> JoinHandle<()> {
                         }
                         match transport.get_game_account(&game_reg.addr).await {
                             Ok(Some(game_account)) => {
-
                                 // Check if we are registered
                                 if !game_account.servers.iter().any(|s| s.addr.eq(&server_addr)) {
-                                    // Register to game
-                                    let register_result = transport.serve(ServeParams {
-                                        game_addr: game_account.addr.clone(),
-                                    }).await;
-
-                                    if let Err(e) = register_result {
-                                        error!("Failed to register to game account at [{}] due to {:?}", game_reg.addr, e);
-                                    }
-
-                                }
-
-                                let Ok(Some(game_account)) = transport.get_game_account(&game_account.addr).await else {
-                                    error!("Failed to fetch game account after sending a serve transaction");
-                                    continue;
-                                };
-
-                                let Some(transactor_addr) = game_account.transactor_addr.as_ref() else {
-                                    error!("Failed to find transactor addr after sending a serve transaction");
+                                    candidates.push(game_account);
                                     continue;
-                                };
-
-                                loaded_game_addrs.insert(game_account.addr.clone());
+                                }
 
-                                let mode = if transactor_addr.eq(&server_addr) {
-                                    ClientMode::Transactor
+                                // We are committed to the game, so it's loaded regardless of the capacity
+                                let key = GameKey::new(chain.chain, game_account.addr.as_str());
+                                game_manager.reserve(&key, module_cache.is_native(&game_account.bundle_addr));
+                                if start_game(&transport, &signal_tx, &chain, &server_addr, &game_account.addr).await {
+                                    loaded_game_addrs.insert(game_account.addr.clone());
                                 } else {
-                                    ClientMode::Validator
-                                };
-                                let signal_result = signal_tx
-                                    .send(SignalFrame::StartGame {
-                                        game_addr: game_account.addr.clone(),
-                                        mode,
-                                    })
-                                    .await;
-
-                                if let Err(e) = signal_result {
-                                    error!("Failed to send StartGame for [{}] signal due to {:?}", game_reg.addr, e);
+                                    game_manager.release(&key);
                                 }
                             }
                             Ok(None) => {
@@ -137,7 +260,7 @@ pub async fn start_reg_task(context: &ApplicationContext) -> JoinHandle<()> {
                                     std::collections::hash_map::Entry::Occupied(mut cnt) => {
                                         *cnt.get_mut() += 1;
                                         if *cnt.get() == 2 {
-                                            blacklist.lock().await.add_addr(&game_reg.addr);
+                                            blacklist.lock().await.add_addr(&chain.storage.scoped_addr(&game_reg.addr));
                                         }
                                     }
                                     std::collections::hash_map::Entry::Vacant(cnt) => {
@@ -156,9 +279,43 @@ pub async fn start_reg_task(context: &ApplicationContext) -> JoinHandle<()> {
                 }
             }
 
+            for game_account in select_games(candidates, capacity.as_ref()) {
+                // The capacity is shared with the tasks of other chains
+                let key = GameKey::new(chain.chain, game_account.addr.as_str());
+                let is_native = module_cache.is_native(&game_account.bundle_addr);
+                if !game_manager.try_reserve(&key, is_native, capacity.as_ref()) {
+                    info!("Capacity reached, stop serving new games: {:?}", game_manager.get_load());
+                    break;
+                }
+
+                // Register to game
+                let register_result = transport.serve(ServeParams {
+                    game_addr: game_account.addr.clone(),
+                }).await;
+
+                if let Err(e) = register_result {
+                    error!("Failed to register to game account at [{}] due to {:?}", game_account.addr, e);
+                }
+
+                if start_game(&transport, &signal_tx, &chain, &server_addr, &game_account.addr).await {
+                    loaded_game_addrs.insert(game_account.addr.clone());
+                } else {
+                    game_manager.release(&key);
+                }
+            }
+            let idle_timeout = config_rx.borrow().idle_timeout;
+            if let Some(idle_timeout) = idle_timeout {
+                game_manager
+                    .unload_idle_games(chain.chain, Duration::from_secs(idle_timeout))
+                    .await;
+            }
+            reload_idle_games(&transport, &signal_tx, &chain, &server_addr, &game_manager).await;
+
+            chain.reg_heartbeat.beat();
+
             select! {
                 _ = shutdown_rx.changed() => {
-                    info!("Stop discovering games");
+                    info!("Stop discovering games on {}", chain_name);
                     break;
                 },
                 _ = tokio::time::sleep(Duration::from_secs(10)) => { continue; }
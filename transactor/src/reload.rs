@@ -0,0 +1,371 @@
+//! Reload the configuration file on SIGHUP.  Only a part of the
+//! transactor configuration can be applied to a running transactor:
+//!
+//! - `reg_addresses`, also those in `chains`, picked up by the
+//!   registration tasks in their next scans.
+//! - `submitter` and `handler`, applied to the games launched afterwards.
+//! - `disable_blacklist`
+//! - `shutdown_timeout`
+//! - `session_ttl`, applied to the sessions created afterwards.
+//! - `spectator`, applied to the spectators and event subscribers
+//!   joined afterwards.
+//! - `capacity`, applied to the games served afterwards.
+//! - `backlog`, applied to the games launched afterwards.
+//! - `recorder`, applied to the games launched afterwards.
+//! - `health`, applied to the next readiness check.
+//! - `idle_timeout`, applied in the next scans of the registration tasks.
+//!
+//! A reload with any other change is rejected, a restart is required.
+//! Every field of [TransactorConfig] is classified in [LIVE_FIELDS] or
+//! [RESTART_REQUIRED_FIELDS], a field in neither requires a restart.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use race_env::{Config, TransactorConfig};
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::blacklist::Blacklist;
+use crate::context::ApplicationContext;
+
+/// The fields applied to a running transactor.  A change to the
+/// server accounts in `chains`, or to `handler.max_memory_pages`,
+/// still requires a restart.
+const LIVE_FIELDS: &[&str] = &[
+    "reg_addresses",
+    "chains",
+    "submitter",
+    "handler",
+    "disable_blacklist",
+    "shutdown_timeout",
+    "session_ttl",
+    "spectator",
+    "capacity",
+    "backlog",
+    "recorder",
+    "health",
+    "idle_timeout",
+];
+
+const RESTART_REQUIRED_FIELDS: &[&str] = &[
+    "port",
+    "endpoint",
+    "chain",
+    "address",
+    "credentials_file",
+    "log_dir",
+    "bundle_dir",
+    "debug_mode",
+    "native_handlers",
+    "rate_limit",
+    "chat",
+    "telemetry",
+];
+
+macro_rules! transactor_fields {
+    ($($field:ident),* $(,)?) => {
+        /// The names of all fields in [TransactorConfig].
+        #[cfg(test)]
+        const TRANSACTOR_FIELDS: &[&str] = &[$(stringify!($field)),*];
+
+        /// Return the changed fields, with their names qualified by
+        /// `transactor.`.  The destructuring is exhaustive, so a new
+        /// field doesn't compile until it's listed here.
+        fn changed_fields(
+            current: &TransactorConfig,
+            new: &TransactorConfig,
+        ) -> Vec<(&'static str, &'static str)> {
+            let TransactorConfig { $($field),* } = current;
+            let mut changes = vec![];
+            $(
+                if *$field != new.$field {
+                    changes.push((stringify!($field), concat!("transactor.", stringify!($field))));
+                }
+            )*
+            changes
+        }
+    };
+}
+
+transactor_fields!(
+    port,
+    endpoint,
+    chain,
+    address,
+    reg_addresses,
+    credentials_file,
+    chains,
+    disable_blacklist,
+    debug_mode,
+    log_dir,
+    bundle_dir,
+    submitter,
+    handler,
+    native_handlers,
+    rate_limit,
+    chat,
+    session_ttl,
+    spectator,
+    capacity,
+    backlog,
+    recorder,
+    telemetry,
+    health,
+    idle_timeout,
+    shutdown_timeout,
+);
+
+/// Return the names of the changed fields which can't be applied
+/// without a restart.
+fn restart_required_changes(current: &Config, new: &Config) -> Vec<&'static str> {
+    let mut changes = vec![];
+
+    let Config {
+        transactor,
+        replayer,
+        storage,
+        facade,
+        solana,
+        bnb,
+        sui,
+    } = current;
+
+    match (transactor.as_ref(), new.transactor.as_ref()) {
+        (Some(c), Some(n)) => {
+            for (field, qualified) in changed_fields(c, n) {
+                if RESTART_REQUIRED_FIELDS.contains(&field) {
+                    changes.push(qualified);
+                } else if !LIVE_FIELDS.contains(&field) {
+                    warn!("Field {} is not classified, a restart is required", qualified);
+                    changes.push(qualified);
+                }
+            }
+            // Each chain has its own transport, server account and
+            // encryptor keys
+            let chain_accounts = |t: &TransactorConfig| -> Vec<(String, String, Option<String>)> {
+                t.chains
+                    .iter()
+                    .flatten()
+                    .map(|c| (c.chain.clone(), c.address.clone(), c.credentials_file.clone()))
+                    .collect()
+            };
+            if chain_accounts(c) != chain_accounts(n) {
+                changes.push("transactor.chains");
+            }
+            // The memory limit is built into the shared engine
+            if c.handler.as_ref().and_then(|h| h.max_memory_pages)
+                != n.handler.as_ref().and_then(|h| h.max_memory_pages)
+            {
+                changes.push("transactor.handler.max_memory_pages");
+            }
+        }
+        _ => changes.push("transactor"),
+    }
+
+    if *replayer != new.replayer {
+        changes.push("replayer");
+    }
+    if *storage != new.storage {
+        changes.push("storage");
+    }
+    if *facade != new.facade {
+        changes.push("facade");
+    }
+    if *solana != new.solana {
+        changes.push("solana");
+    }
+    if *bnb != new.bnb {
+        changes.push("bnb");
+    }
+    if *sui != new.sui {
+        changes.push("sui");
+    }
+
+    changes
+}
+
+/// Return the names of the changed fields which are applied live.
+fn live_changes(current: &TransactorConfig, new: &TransactorConfig) -> Vec<&'static str> {
+    changed_fields(current, new)
+        .into_iter()
+        .map(|(field, _)| field)
+        .filter(|field| LIVE_FIELDS.contains(field))
+        .collect()
+}
+
+async fn reload(
+    path: &PathBuf,
+    current: &mut Config,
+    config_tx: &watch::Sender<TransactorConfig>,
+    blacklist: &Arc<Mutex<Blacklist>>,
+) {
+    let new = match Config::try_from_path(path) {
+        Ok(new) => new,
+        Err(e) => {
+            error!("Failed to reload configuration: {}", e);
+            return;
+        }
+    };
+
+    let restart_required = restart_required_changes(current, &new);
+    if !restart_required.is_empty() {
+        warn!(
+            "Configuration reload rejected, changes to {:?} require a restart",
+            restart_required
+        );
+        return;
+    }
+
+    let Some(transactor_config) = new.transactor.clone() else {
+        return;
+    };
+
+    let changes = config_tx.borrow().clone();
+    let changes = live_changes(&changes, &transactor_config);
+    if changes.is_empty() {
+        info!("Configuration reloaded, nothing changed");
+        return;
+    }
+
+    blacklist
+        .lock()
+        .await
+        .set_persistent(transactor_config.disable_blacklist.ne(&Some(true)));
+    config_tx.send_replace(transactor_config);
+    *current = new;
+    info!("Configuration reloaded, applied changes to {:?}", changes);
+}
+
+/// Start a task to reload the configuration from `path` on SIGHUP.
+/// `config` is the configuration loaded at startup.
+#[cfg(unix)]
+pub fn setup_config_reloader(
+    context: &ApplicationContext,
+    path: PathBuf,
+    config: Config,
+) -> JoinHandle<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let config_tx = context.get_config_sender();
+    let blacklist = context.blacklist();
+    let mut shutdown_rx = context.get_shutdown_receiver();
+
+    tokio::spawn(async move {
+        let mut current = config;
+        let mut sighup = match signal(SignalKind::hangup()) {
+            Ok(sighup) => sighup,
+            Err(e) => {
+                error!("Failed to listen for SIGHUP: {:?}", e);
+                return;
+            }
+        };
+
+        loop {
+            tokio::select! {
+                _ = shutdown_rx.changed() => break,
+                _ = sighup.recv() => {
+                    info!("Received SIGHUP");
+                    reload(&path, &mut current, &config_tx, &blacklist).await;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(not(unix))]
+pub fn setup_config_reloader(
+    _context: &ApplicationContext,
+    _path: PathBuf,
+    _config: Config,
+) -> JoinHandle<()> {
+    tokio::spawn(async {})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use race_env::{ChainConfig, ReplayerConfig};
+
+    fn make_config() -> Config {
+        Config {
+            transactor: Some(TransactorConfig {
+                port: 12003,
+                endpoint: "ws://localhost:12003".into(),
+                chain: "facade".into(),
+                address: "Server 1".into(),
+                reg_addresses: vec!["REG".into()],
+                disable_blacklist: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_all_fields_classified() {
+        for field in TRANSACTOR_FIELDS {
+            let live = LIVE_FIELDS.contains(field);
+            let restart_required = RESTART_REQUIRED_FIELDS.contains(field);
+            assert!(live != restart_required, "Field {} must be classified once", field);
+        }
+        for field in LIVE_FIELDS.iter().chain(RESTART_REQUIRED_FIELDS) {
+            assert!(TRANSACTOR_FIELDS.contains(field), "Unknown field {}", field);
+        }
+    }
+
+    #[test]
+    fn test_reload_replayer() {
+        let current = make_config();
+        let mut new = make_config();
+        new.replayer = Some(ReplayerConfig { port: 12005 });
+        assert_eq!(restart_required_changes(&current, &new), vec!["replayer"]);
+    }
+
+    #[test]
+    fn test_reload_changes() {
+        let current = make_config();
+        let mut new = make_config();
+        let t = new.transactor.as_mut().unwrap();
+        t.reg_addresses.push("REG2".into());
+        t.disable_blacklist = None;
+        assert!(restart_required_changes(&current, &new).is_empty());
+        assert_eq!(
+            live_changes(current.transactor.as_ref().unwrap(), new.transactor.as_ref().unwrap()),
+            vec!["reg_addresses", "disable_blacklist"]
+        );
+
+        new.transactor.as_mut().unwrap().port = 12004;
+        assert_eq!(restart_required_changes(&current, &new), vec!["transactor.port"]);
+    }
+
+    #[test]
+    fn test_reload_chains() {
+        let mut current = make_config();
+        current.transactor.as_mut().unwrap().chains = Some(vec![ChainConfig {
+            chain: "sui".into(),
+            address: "Server 1 on Sui".into(),
+            reg_addresses: vec!["SUI REG".into()],
+            credentials_file: None,
+        }]);
+
+        let mut new = current.clone();
+        let chains = new.transactor.as_mut().unwrap().chains.as_mut().unwrap();
+        chains[0].reg_addresses.push("SUI REG2".into());
+        assert!(restart_required_changes(&current, &new).is_empty());
+        assert_eq!(
+            live_changes(current.transactor.as_ref().unwrap(), new.transactor.as_ref().unwrap()),
+            vec!["chains"]
+        );
+
+        let chains = new.transactor.as_mut().unwrap().chains.as_mut().unwrap();
+        chains[0].address = "Server 2 on Sui".into();
+        assert_eq!(restart_required_changes(&current, &new), vec!["transactor.chains"]);
+
+        let mut new = current.clone();
+        let chains = new.transactor.as_mut().unwrap().chains.as_mut().unwrap();
+        chains[0].credentials_file = Some("sui.credentials".into());
+        assert_eq!(restart_required_changes(&current, &new), vec!["transactor.chains"]);
+    }
+}
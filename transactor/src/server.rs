@@ -1,9 +1,15 @@
 use std::{net::SocketAddr, sync::Arc};
 
+use crate::chains::GameKey;
 use crate::context::ApplicationContext;
 use crate::utils;
+use crate::capacity::ServerLoad;
 use crate::game_manager::ServingGame;
-use borsh::BorshDeserialize;
+use crate::health::{HealthChecker, HealthLayer};
+use crate::encoding::{Credential, Encoding};
+use crate::session::NewSession;
+use crate::spectator::{DelayQueue, SpectatorDelay};
+use borsh::{BorshDeserialize, BorshSerialize};
 use hyper::Method;
 use jsonrpsee::core::error::Error as RpcError;
 use jsonrpsee::core::StringError;
@@ -11,63 +17,201 @@ use jsonrpsee::server::AllowHosts;
 use jsonrpsee::types::error::CallError;
 use jsonrpsee::types::ErrorObjectOwned;
 use jsonrpsee::{server::ServerBuilder, types::Params, RpcModule};
-use jsonrpsee::{PendingSubscriptionSink, SubscriptionMessage, TrySendError};
-use race_api::event::Message;
+use jsonrpsee::{PendingSubscriptionSink, TrySendError};
+use race_api::event::{ChatMessage, MessageChannel};
 use race_core::checkpoint::CheckpointOffChain;
-use race_core::types::SubmitMessageParams;
+use race_core::types::{
+    BroadcastFrame, MutePlayerParams, SetPlayerTeamParams, SubmitChatMessageParams,
+    SubmitMessageParams,
+};
 use race_core::types::{
     CheckpointParams, LatestCheckpointParams, ExitGameParams, Signature, SubmitEventParams,
-    SubscribeEventParams, SubscribeCheckpointParams
+    SubscribeEventParams, SubscribeEventParamsV2, SubscribeCheckpointParams, CreateSessionParams, SessionInfo, CREATE_SESSION_PURPOSE,
+    SubscribeSpectateParams, GetDivergenceReportsParams, SESSION_ERROR_CODE,
 };
-use tokio_stream::wrappers::BroadcastStream;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{sleep_until, Duration, Instant};
+use tokio_stream::wrappers::{BroadcastStream, ReceiverStream};
 use tokio_stream::StreamExt;
 use tower::ServiceBuilder;
 use tower_http::cors::Any;
 use tower_http::cors::CorsLayer;
-use tracing::{error, info, warn};
+use tracing::{error, info, info_span, warn, Instrument};
+
+/// The error code for the requests rejected by the rate limiter.
+const RATE_LIMITED_ERROR_CODE: i32 = -32029;
+
+// The default for seconds a session lasts.
+const DEFAULT_SESSION_TTL: u64 = 3600;
+// The signature to create a session must be signed in this many
+// milliseconds, so a leaked one can't be replayed later.
+const SESSION_SIGNATURE_MAX_AGE: u64 = 60_000;
+
+fn rate_limited_error(e: race_core::error::Error) -> RpcError {
+    RpcError::Call(CallError::Custom(ErrorObjectOwned::owned(
+        RATE_LIMITED_ERROR_CODE,
+        e.to_string(),
+        None::<()>,
+    )))
+}
 
-fn base64_decode(data: &str) -> Result<Vec<u8>, RpcError> {
-    utils::base64_decode(data).map_err(|e| RpcError::Call(CallError::InvalidParams(e.into())))
+fn session_error(e: race_core::error::Error) -> RpcError {
+    RpcError::Call(CallError::Custom(ErrorObjectOwned::owned(
+        SESSION_ERROR_CODE,
+        e.to_string(),
+        None::<()>,
+    )))
 }
 
-fn parse_params_no_sig<T: BorshDeserialize>(params: Params<'_>) -> Result<(String, T), RpcError> {
-    let (game_addr, arg_base64) = params.parse::<(String, String)>()?;
+/// Return the signer of a session credential.
+fn authenticate_session(
+    game_addr: &GameKey,
+    arg_vec: &[u8],
+    (token, nonce, proof): (&str, u64, &str),
+    context: &ApplicationContext,
+) -> Result<String, RpcError> {
+    context
+        .sessions
+        .authenticate(token, &game_addr.to_string(), nonce, proof, arg_vec)
+        .map_err(|e| {
+            warn!("Session authentication failed: {:?}", e);
+            session_error(e)
+        })
+}
+
+fn verify_signature(
+    game_addr: &GameKey,
+    arg_vec: &[u8],
+    signature: &Signature,
+    context: &ApplicationContext,
+) -> Result<(), RpcError> {
+    context
+        .verify(game_addr, arg_vec, signature)
+        .map_err(|e| {
+            warn!("Signature verification failed: {:?}", e);
+            RpcError::Call(CallError::InvalidParams(e.into()))
+        })
+}
 
-    let arg_vec = base64_decode(&arg_base64)?;
+/// Return the chain and the address of a game, the address can be
+/// qualified with its chain, e.g. `sui:0x1234`.
+fn resolve_game_addr(game_addr: &str, context: &ApplicationContext) -> Result<GameKey, RpcError> {
+    context
+        .resolve_game_addr(game_addr)
+        .map_err(|e| RpcError::Call(CallError::InvalidParams(e.into())))
+}
 
-    let arg = T::try_from_slice(&arg_vec)
-        .map_err(|e| RpcError::Call(CallError::InvalidParams(e.into())))?;
+fn parse_params_no_sig<T>(
+    params: Params<'_>,
+    context: &ApplicationContext,
+    encoding: Encoding,
+) -> Result<(GameKey, T), RpcError>
+where
+    T: BorshSerialize + BorshDeserialize + DeserializeOwned,
+{
+    let (game_addr, arg) = params.parse::<(String, Value)>()?;
+    let game_addr = resolve_game_addr(&game_addr, context)?;
+    let (arg, _) = encoding.decode_arg(arg)?;
     Ok((game_addr, arg))
 }
 
-fn parse_params<T: BorshDeserialize>(
+/// Parse the parameters of a subscription, with an optional
+/// credential as the third parameter to identify the viewer.  Return
+/// the game address, the argument and the viewer, None for an
+/// anonymous viewer.
+fn parse_params_with_viewer<T>(
     params: Params<'_>,
     context: &ApplicationContext,
-) -> Result<(String, T, Signature), RpcError> {
-    let (game_addr, arg_base64, sig_base64) = params.parse::<(String, String, String)>()?;
-    let arg_vec = base64_decode(&arg_base64)?;
-    let sig_vec = base64_decode(&sig_base64)?;
+    encoding: Encoding,
+) -> Result<(GameKey, T, Option<String>), RpcError>
+where
+    T: BorshSerialize + BorshDeserialize + DeserializeOwned,
+{
+    let mut seq = params.sequence();
+    let game_addr: String = seq.next()?;
+    let arg: Value = seq.next()?;
+    let credential: Option<Value> = seq.optional_next()?;
+    let game_addr = resolve_game_addr(&game_addr, context)?;
+    let (arg, arg_vec) = encoding.decode_arg(arg)?;
+
+    let viewer = match credential.map(|c| encoding.decode_credential(c)).transpose()? {
+        None => None,
+        Some(Credential::Session { token, nonce, proof }) => Some(authenticate_session(
+            &game_addr,
+            &arg_vec,
+            (&token, nonce, &proof),
+            context,
+        )?),
+        Some(Credential::Signature(signature)) => {
+            verify_signature(&game_addr, &arg_vec, &signature, context)?;
+            // A subscription signature can't be replayed later
+            if utils::current_timestamp().abs_diff(signature.timestamp) > SESSION_SIGNATURE_MAX_AGE {
+                return Err(RpcError::Call(CallError::InvalidParams(anyhow::anyhow!(
+                    "Stale signature"
+                ))));
+            }
+            Some(signature.signer)
+        }
+    };
 
-    let signature = Signature::try_from_slice(&sig_vec)
-        .map_err(|e| {
-            warn!("Signature deserialization failed: {:?}", e);
-            RpcError::Call(CallError::InvalidParams(e.into()))
-        })?;
+    Ok((game_addr, arg, viewer))
+}
 
-    context
-        .verify(&arg_vec, &signature)
-        .map_err(|e| {
-            warn!("Signature verification failed: {:?}", e);
-            RpcError::Call(CallError::InvalidParams(e.into()))
-        })?;
+/// Parse the parameters of a request authenticated by either a
+/// signature or a session.  Return the game address, the argument
+/// and the signer.
+fn parse_params<T>(
+    params: Params<'_>,
+    context: &ApplicationContext,
+    encoding: Encoding,
+) -> Result<(GameKey, T, String), RpcError>
+where
+    T: BorshSerialize + BorshDeserialize + DeserializeOwned,
+{
+    parse_params_limited(params, context, encoding, |_| Ok(()), |_, _, _| Ok(()))
+}
 
-    let arg = T::try_from_slice(&arg_vec)
-        .map_err(|e| {
-            warn!("Argument deserialization failed: {:?}", e);
-            RpcError::Call(CallError::InvalidParams(e.into()))
-        })?;
+/// Like [parse_params], with the rate limits checked.  `precheck` is
+/// called with the argument before the signature is verified, so an
+/// oversized request costs no verification.  `check` is called with
+/// the game address, the authenticated signer and the argument.
+fn parse_params_limited<T, P, F>(
+    params: Params<'_>,
+    context: &ApplicationContext,
+    encoding: Encoding,
+    precheck: P,
+    check: F,
+) -> Result<(GameKey, T, String), RpcError>
+where
+    T: BorshSerialize + BorshDeserialize + DeserializeOwned,
+    P: FnOnce(&T) -> race_core::error::Result<()>,
+    F: FnOnce(&GameKey, &str, &T) -> race_core::error::Result<()>,
+{
+    let (game_addr, arg, credential) = params.parse::<(String, Value, Value)>()?;
+    let game_addr = resolve_game_addr(&game_addr, context)?;
+
+    let (arg, arg_vec) = encoding.decode_arg(arg).map_err(|e| {
+        warn!("Argument deserialization failed: {:?}", e);
+        e
+    })?;
+
+    let signer = match encoding.decode_credential(credential)? {
+        Credential::Session { token, nonce, proof } => {
+            let signer = authenticate_session(&game_addr, &arg_vec, (&token, nonce, &proof), context)?;
+            check(&game_addr, &signer, &arg).map_err(rate_limited_error)?;
+            signer
+        }
+        Credential::Signature(signature) => {
+            precheck(&arg).map_err(rate_limited_error)?;
+            verify_signature(&game_addr, &arg_vec, &signature, context)?;
+            check(&game_addr, &signature.signer, &arg).map_err(rate_limited_error)?;
+            signature.signer
+        }
+    };
 
-    Ok((game_addr, arg, signature))
+    Ok((game_addr, arg, signer))
 }
 
 fn ping(_: Params<'_>, _: &ApplicationContext) -> Result<String, RpcError> {
@@ -78,18 +222,96 @@ async fn get_serving_games(_: Params<'_>, context: Arc<ApplicationContext>) -> R
     Ok(context.get_serving_games().await)
 }
 
+async fn get_server_load(_: Params<'_>, context: Arc<ApplicationContext>) -> Result<ServerLoad, RpcError> {
+    Ok(context.get_server_load().await)
+}
+
+/// Moderate a chat message and send it to the game.
+async fn send_chat_message(
+    game_addr: GameKey,
+    message: ChatMessage,
+    context: Arc<ApplicationContext>,
+) -> Result<(), RpcError> {
+    info!("Player message, {}: {} bytes", message.sender, message.content.len());
+
+    let message = context
+        .chat
+        .moderate(&context.scoped_addr(&game_addr), message)
+        .await
+        .map_err(|e| RpcError::Call(CallError::Failed(e.into())))?;
+
+    let span = info_span!("submit_message", game_addr = %game_addr, sender = %message.sender);
+    context
+        .send_message(&game_addr, message)
+        .instrument(span)
+        .await
+        .map_err(|e| RpcError::Call(CallError::Failed(e.into())))
+}
+
 async fn submit_message(
     params: Params<'_>,
     context: Arc<ApplicationContext>,
+    encoding: Encoding,
+) -> Result<(), RpcError> {
+    let (game_addr, SubmitMessageParams { content }, sender) = parse_params_limited(
+        params,
+        &context,
+        encoding,
+        |params: &SubmitMessageParams| context.rate_limiter.check_message_size(&params.content),
+        |game_addr, signer, params| {
+            context.rate_limiter.check_message(signer, &game_addr.to_string(), &params.content)
+        },
+    )?;
+
+    let message = ChatMessage { sender, content, channel: MessageChannel::Table };
+    send_chat_message(game_addr, message, context).await
+}
+
+async fn submit_chat_message(
+    params: Params<'_>,
+    context: Arc<ApplicationContext>,
+    encoding: Encoding,
 ) -> Result<(), RpcError> {
-    let (game_addr, SubmitMessageParams { content }, sig) = parse_params(params, &context)?;
+    let (game_addr, SubmitChatMessageParams { content, channel }, sender) = parse_params_limited(
+        params,
+        &context,
+        encoding,
+        |params: &SubmitChatMessageParams| context.rate_limiter.check_message_size(&params.content),
+        |game_addr, signer, params| {
+            context.rate_limiter.check_message(signer, &game_addr.to_string(), &params.content)
+        },
+    )?;
+
+    let message = ChatMessage { sender, content, channel };
+    send_chat_message(game_addr, message, context).await
+}
 
-    let sender = sig.signer;
-    info!("Player message, {}: {}", sender, content);
-    let message = Message { content, sender };
+async fn mute_player(
+    params: Params<'_>,
+    context: Arc<ApplicationContext>,
+    encoding: Encoding,
+) -> Result<(), RpcError> {
+    let (game_addr, MutePlayerParams { player_addr, muted }, signer) = parse_params(params, &context, encoding)?;
+
+    info!("Mute player, game_addr: {}, player: {}, muted: {}", game_addr, player_addr, muted);
 
     context
-        .send_message(&game_addr, message)
+        .mute_player(&game_addr, &signer, &player_addr, muted)
+        .await
+        .map_err(|e| RpcError::Call(CallError::Failed(e.into())))
+}
+
+async fn set_player_team(
+    params: Params<'_>,
+    context: Arc<ApplicationContext>,
+    encoding: Encoding,
+) -> Result<(), RpcError> {
+    let (game_addr, SetPlayerTeamParams { player_addr, team }, signer) = parse_params(params, &context, encoding)?;
+
+    info!("Set player team, game_addr: {}, player: {}, team: {:?}", game_addr, player_addr, team);
+
+    context
+        .set_player_team(&game_addr, &signer, &player_addr, team)
         .await
         .map_err(|e| RpcError::Call(CallError::Failed(e.into())))
 }
@@ -97,19 +319,25 @@ async fn submit_message(
 async fn submit_event(
     params: Params<'_>,
     context: Arc<ApplicationContext>,
+    encoding: Encoding,
 ) -> Result<(), RpcError> {
-    let (game_addr, SubmitEventParams { event }, _sig) = match parse_params(params, &context) {
-        Ok(x) => x,
-        Err(e) => {
-            // warn!("Invalid event from client: {:?}", e);
-            return Err(e);
-        }
-    };
+    let (game_addr, SubmitEventParams { event }, signer) = parse_params_limited(
+        params,
+        &context,
+        encoding,
+        |params: &SubmitEventParams| context.rate_limiter.check_event_size(&params.event),
+        |game_addr, signer, params| {
+            context.rate_limiter.check_event(signer, &game_addr.to_string(), &params.event)
+        },
+    )?;
 
     info!("Submit event, game_addr: {}, event: {}", game_addr, event);
 
+    // The root span of the frames sent for this event
+    let span = info_span!("submit_event", game_addr = %game_addr, signer = %signer, event = %event);
     context
         .send_event(&game_addr, event)
+        .instrument(span)
         .await
         .map_err(|e| RpcError::Call(CallError::Failed(e.into())))
 }
@@ -117,75 +345,218 @@ async fn submit_event(
 async fn get_checkpoint(
     params: Params<'_>,
     context: Arc<ApplicationContext>,
-) -> Result<Option<Vec<u8>>, RpcError> {
-    let (game_addr, CheckpointParams { settle_version }) = parse_params_no_sig(params)?;
+    encoding: Encoding,
+) -> Result<Value, RpcError> {
+    let (game_addr, CheckpointParams { settle_version }, viewer) =
+        parse_params_with_viewer(params, &context, encoding)?;
 
-    info!("Get checkpoint, game_addr: {}", game_addr);
+    info!("Get checkpoint, game_addr: {}, viewer: {:?}", game_addr, viewer);
 
     let checkpoint: Option<CheckpointOffChain> = context
-        .game_manager
         .get_checkpoint(&game_addr, settle_version)
         .await
         .map_err(|e| RpcError::Call(CallError::Failed(e.into())))?;
 
-    let bs = checkpoint
-        .map(|c| borsh::to_vec(&c).map_err(|e| RpcError::Call(CallError::Failed(e.into()))))
-        .transpose()?;
+    let ret = checkpoint
+        .map(|c| encoding.encode_result(&c))
+        .transpose()?
+        .unwrap_or(Value::Null);
 
-    Ok(bs)
+    Ok(ret)
 }
 
-async fn get_latest_checkpoint(params: Params<'_>, context: Arc<ApplicationContext>) -> Result<Vec<u8>, RpcError> {
-    let (game_addr, LatestCheckpointParams {}) = parse_params_no_sig(params)?;
+async fn get_latest_checkpoint(
+    params: Params<'_>,
+    context: Arc<ApplicationContext>,
+    encoding: Encoding,
+) -> Result<Value, RpcError> {
+    let (game_addr, LatestCheckpointParams {}) = parse_params_no_sig(params, &context, encoding)?;
 
     let checkpoint: Option<CheckpointOffChain> = context
-        .game_manager
         .get_latest_checkpoint(&game_addr)
         .await
         .ok()
         .flatten();
 
-    let bs = borsh::to_vec(&checkpoint).map_err(|e| RpcError::Call(CallError::Failed(e.into())))?;
-
-    Ok(bs)
-
+    encoding.encode_result(&checkpoint)
 }
 
-async fn get_latest_checkpoints(params: Params<'_>, context: Arc<ApplicationContext>) -> Result<Vec<u8>, RpcError> {
+async fn get_latest_checkpoints(
+    params: Params<'_>,
+    context: Arc<ApplicationContext>,
+    encoding: Encoding,
+) -> Result<Value, RpcError> {
     let game_addrs = params.parse::<Vec<String>>()?;
     let mut result = Vec::with_capacity(game_addrs.len());
 
     for addr in game_addrs {
+        let Ok(key) = context.resolve_game_addr(&addr) else {
+            result.push(None);
+            continue;
+        };
         let checkpoint: Option<CheckpointOffChain> = context
             .game_manager
-            .get_latest_checkpoint(&addr)
+            .get_latest_checkpoint(&key)
             .await
             .ok()
             .flatten();
         result.push(checkpoint);
     }
 
-    let bs = borsh::to_vec(&result).map_err(|e| RpcError::Call(CallError::Failed(e.into())))?;
-    Ok(bs)
+    encoding.encode_result(&result)
 }
 
-async fn exit_game(params: Params<'_>, context: Arc<ApplicationContext>) -> Result<(), RpcError> {
-    let (game_addr, ExitGameParams {}, sig) = parse_params(params, &context)?;
+async fn get_divergence_reports(
+    params: Params<'_>,
+    context: Arc<ApplicationContext>,
+    encoding: Encoding,
+) -> Result<Value, RpcError> {
+    let (game_addr, GetDivergenceReportsParams {}) = parse_params_no_sig(params, &context, encoding)?;
+
+    info!("Get divergence reports, game_addr: {}", game_addr);
+
+    let reports = context
+        .game_manager
+        .get_divergence_reports(&game_addr)
+        .await
+        .map_err(|e| RpcError::Call(CallError::Failed(e.into())))?;
+    encoding.encode_result(&reports)
+}
+
+async fn exit_game(
+    params: Params<'_>,
+    context: Arc<ApplicationContext>,
+    encoding: Encoding,
+) -> Result<(), RpcError> {
+    let (game_addr, ExitGameParams {}, signer) = parse_params(params, &context, encoding)?;
     info!("Exit game");
 
     context
-        .eject_player(&game_addr, &sig.signer)
+        .eject_player(&game_addr, &signer)
         .await
         .map_err(|e| RpcError::Call(CallError::Failed(e.into())))
 }
 
+/// Create a session with a signed request.  The session token is sent
+/// as the first item, and the session is revoked when the subscription
+/// is closed.
+async fn subscribe_session(
+    params: Params<'_>,
+    pending: PendingSubscriptionSink,
+    context: Arc<ApplicationContext>,
+    encoding: Encoding,
+) -> Result<(), StringError> {
+    let parsed = params
+        .parse::<(String, Value, Value)>()
+        .map_err(RpcError::from)
+        .and_then(|(game_addr, arg, credential)| {
+            let game_addr = resolve_game_addr(&game_addr, &context)?;
+            let (CreateSessionParams { purpose, game_addr: session_game_addr }, arg_vec) =
+                encoding.decode_arg(arg)?;
+            // The signature is only valid for creating a session in this game
+            if purpose != CREATE_SESSION_PURPOSE || resolve_game_addr(&session_game_addr, &context)? != game_addr {
+                return Err(RpcError::Call(CallError::InvalidParams(anyhow::anyhow!(
+                    "Invalid params to create session"
+                ))));
+            }
+            let Credential::Signature(signature) = encoding.decode_credential(credential)? else {
+                return Err(RpcError::Call(CallError::InvalidParams(anyhow::anyhow!(
+                    "A signature is required to create session"
+                ))));
+            };
+            verify_signature(&game_addr, &arg_vec, &signature, &context)?;
+            Ok((game_addr, signature))
+        });
+
+    let (game_addr, signature) = match parsed {
+        Ok(p) => p,
+        Err(e) => {
+            let _ = pending.reject(ErrorObjectOwned::from(e)).await;
+            return Ok(());
+        }
+    };
+
+    let now = utils::current_timestamp();
+    if now.abs_diff(signature.timestamp) > SESSION_SIGNATURE_MAX_AGE {
+        warn!("Stale signature to create session, signer: {}", signature.signer);
+        let _ = pending
+            .reject(CallError::InvalidParams(anyhow::anyhow!("Stale signature")))
+            .await;
+        return Ok(());
+    }
+
+    let ttl = context.current_config().session_ttl.unwrap_or(DEFAULT_SESSION_TTL);
+    let sessions = context.sessions.clone();
+    drop(context);
+
+    let NewSession { token, key, expires_at } =
+        sessions.create(&signature.signer, &game_addr.to_string(), Duration::from_secs(ttl));
+    info!("Create session, game: {}, signer: {}", game_addr, signature.signer);
+
+    let session_info = SessionInfo { token: token.clone(), key, ttl };
+    let msg = encoding.encode_message(&session_info).unwrap();
+
+    let ret = match pending.accept().await {
+        Ok(sink) => {
+            if sink.send(msg).await.is_ok() {
+                tokio::select! {
+                    _ = sink.closed() => (),
+                    _ = tokio::time::sleep_until(expires_at) => (),
+                }
+            }
+            Ok(())
+        }
+        Err(e) => Err(e),
+    };
+
+    sessions.revoke(&token);
+    info!("Session closed, game: {}, signer: {}", game_addr, signature.signer);
+    ret?;
+    Ok(())
+}
+
+/// Subscribe the event stream with [SubscribeEventParams].  The
+/// frames added after it, `ChatMessage` and `ChatHistory`, are not
+/// sent, the existing clients can't decode them.  It's open to
+/// anyone, unless `participants_only` is set in the spectator config.
 async fn subscribe_event(
     params: Params<'_>,
     pending: PendingSubscriptionSink,
     context: Arc<ApplicationContext>,
+    encoding: Encoding,
+) -> Result<(), StringError> {
+    let participants_only = context
+        .current_config()
+        .spectator
+        .and_then(|c| c.participants_only)
+        .unwrap_or(false);
+    let parsed = parse_params_with_viewer::<SubscribeEventParams>(params, &context, encoding)
+        .map(|(game_addr, params, viewer)| (game_addr, params.into(), viewer));
+    serve_event_stream(parsed, pending, context, encoding, false, participants_only).await
+}
+
+/// Subscribe the event stream with [SubscribeEventParamsV2], which
+/// can resume the stream and filter the frames.  It's only open to
+/// the participants of the game.
+async fn subscribe_event_v2(
+    params: Params<'_>,
+    pending: PendingSubscriptionSink,
+    context: Arc<ApplicationContext>,
+    encoding: Encoding,
 ) -> Result<(), StringError> {
+    let parsed = parse_params_with_viewer(params, &context, encoding);
+    serve_event_stream(parsed, pending, context, encoding, true, true).await
+}
 
-    let (game_addr, SubscribeEventParams { settle_version }) = match parse_params_no_sig(params) {
+async fn serve_event_stream(
+    parsed: Result<(String, SubscribeEventParamsV2, Option<String>), RpcError>,
+    pending: PendingSubscriptionSink,
+    context: Arc<ApplicationContext>,
+    encoding: Encoding,
+    chat_frames: bool,
+    participants_only: bool,
+) -> Result<(), StringError> {
+    let (game_addr, SubscribeEventParamsV2 { settle_version, resume, filter }, viewer) = match parsed {
         Ok(p) => p,
         Err(e) => {
             let _ = pending.reject(ErrorObjectOwned::from(e)).await;
@@ -193,8 +564,26 @@ async fn subscribe_event(
         }
     };
 
-    let (receiver, backlogs_frame) =
-        match context.get_broadcast_and_backlogs(&game_addr, settle_version).await {
+    // The stream is real-time, so it's only for the participants.
+    // Others watch the delayed stream of `subscribe_spectate`.
+    if participants_only {
+        let Some(ref viewer) = viewer else {
+            let _ = pending
+                .reject(CallError::InvalidParams(anyhow::anyhow!(
+                    "A credential is required, use subscribe_spectate to watch the game"
+                )))
+                .await;
+            return Ok(());
+        };
+        if let Err(e) = context.check_game_participant(&game_addr, viewer).await {
+            warn!("Reject event subscriber {} of game {}: {}", viewer, game_addr, e);
+            let _ = pending.reject(CallError::Failed(e.into())).await;
+            return Ok(());
+        }
+    }
+
+    let (receiver, mut backlogs_frame) =
+        match context.get_broadcast_and_backlogs(&game_addr, settle_version, resume.as_ref()).await {
             Ok(x) => x,
             Err(e) => {
                 warn!("Game not found: {}", game_addr);
@@ -203,17 +592,39 @@ async fn subscribe_event(
             }
         };
 
+    let filter = filter.unwrap_or_default();
+    let chat = context.chat.clone();
+    let chat_addr = context.scoped_addr(&game_addr);
+    let chat_history = if chat_frames && filter.messages {
+        let loaded = chat.members().load_game(&chat_addr).await;
+        let history = context.get_chat_history(&game_addr).await;
+        match (loaded, history) {
+            (Ok(()), Ok(messages)) => Some(
+                messages
+                    .into_iter()
+                    .filter(|m| chat.is_visible(&chat_addr, m, viewer.as_deref()))
+                    .collect(),
+            ),
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("Failed to load chat of game {}: {}", game_addr, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
     drop(context);
     info!(
-        "Subscribe event stream, game: {:?}, settle version: {}",
-        game_addr, settle_version,
+        "Subscribe event stream, game: {:?}, settle version: {}, resume: {:?}, viewer: {:?}",
+        game_addr, settle_version, resume, viewer,
     );
 
     let mut sink = pending.accept().await?;
 
-    let v = borsh::to_vec(&backlogs_frame).unwrap();
-    let s = utils::base64_encode(&v);
-    sink.send(SubscriptionMessage::from(&s))
+    filter.apply_to_backlogs(&mut backlogs_frame);
+
+    sink.send(encoding.encode_message(&backlogs_frame).unwrap())
         .await
         .map_err(|e| {
             error!("Error occurred when broadcasting historical frame: {:?}", e);
@@ -221,31 +632,39 @@ async fn subscribe_event(
         })
         .unwrap();
 
-    let rx = BroadcastStream::new(receiver);
-    let mut serialized_rx = rx.map(|f| match f {
-        Ok(x) => {
-            let v = borsh::to_vec(&x).unwrap();
-            let s = utils::base64_encode(&v);
-            Ok(s)
+    if let Some(messages) = chat_history {
+        let frame = BroadcastFrame::ChatHistory { messages };
+        if sink.send(encoding.encode_message(&frame).unwrap()).await.is_err() {
+            return Ok(());
         }
-        Err(e) => Err(e),
+    }
+
+    // The broadcaster resyncs a slow subscriber with a `Backlogs`
+    // frame, or closes the stream if it stays slow.
+    let rx = ReceiverStream::new(receiver).filter_map(move |mut frame| {
+        filter.apply_to_backlogs(&mut frame);
+        let accepted = match frame {
+            BroadcastFrame::ChatMessage { ref message } => {
+                chat_frames && filter.messages && chat.is_visible(&chat_addr, message, viewer.as_deref())
+            }
+            ref frame => filter.accepts(frame),
+        };
+        accepted.then_some(frame)
     });
+    let mut serialized_rx = rx.map(|x| encoding.encode_message(&x).ok());
 
     loop {
         tokio::select! {
             _ = sink.closed() => break Err(anyhow::anyhow!("Subscription was closed")),
             maybe_item = serialized_rx.next() => {
-                let item = match maybe_item {
-                    Some(Ok(item)) => item,
+                let msg = match maybe_item {
+                    Some(Some(msg)) => msg,
                     _ => break Err(anyhow::anyhow!("Event stream ended")),
                 };
-                let msg = SubscriptionMessage::from(&item);
-                match sink.try_send(msg) {
-                    Ok(_) => (),
-                    Err(TrySendError::Closed(_)) => break Err(anyhow::anyhow!("Client disconnected, subscription closed")),
-                    Err(TrySendError::Full(_)) => {
-                        warn!("TrySendError::Full");
-                    }
+                // Wait for the client, frames are queued in the
+                // broadcaster meanwhile.
+                if sink.send(msg).await.is_err() {
+                    break Err(anyhow::anyhow!("Client disconnected, subscription closed"));
                 }
             },
         }
@@ -257,8 +676,9 @@ async fn subscribe_checkpoint(
     params: Params<'_>,
     pending: PendingSubscriptionSink,
     context: Arc<ApplicationContext>,
+    encoding: Encoding,
 ) -> Result<(), StringError> {
-    let (game_addr, SubscribeCheckpointParams { }) = match parse_params_no_sig(params) {
+    let (game_addr, SubscribeCheckpointParams { }) = match parse_params_no_sig(params, &context, encoding) {
         Ok(p) => p,
         Err(e) => {
             let _ = pending.reject(ErrorObjectOwned::from(e)).await;
@@ -284,9 +704,7 @@ async fn subscribe_checkpoint(
 
     let mut sink = pending.accept().await?;
 
-    let v = borsh::to_vec(&frame).unwrap();
-    let s = utils::base64_encode(&v);
-    sink.send(SubscriptionMessage::from(&s))
+    sink.send(encoding.encode_message(&frame).unwrap())
         .await
         .map_err(|e| {
             error!("Error occurred when broadcasting current checkpoint: {:?}", e);
@@ -294,25 +712,18 @@ async fn subscribe_checkpoint(
         })
         .unwrap();
 
-    let rx = BroadcastStream::new(receiver);
-    let mut serialized_rx = rx.map(|f| match f {
-        Ok(x) => {
-            let v = borsh::to_vec(&x).unwrap();
-            let s = utils::base64_encode(&v);
-            Ok(s)
-        }
-        Err(e) => Err(e),
-    });
+    // A lagged subscriber skips to the next checkpoint
+    let rx = BroadcastStream::new(receiver).filter_map(|f| f.ok());
+    let mut serialized_rx = rx.map(|x| encoding.encode_message(&x).ok());
 
     loop {
         tokio::select! {
             _ = sink.closed() => break Err(anyhow::anyhow!("Subscription was closed")),
             maybe_item = serialized_rx.next() => {
-                let item = match maybe_item {
-                    Some(Ok(item)) => item,
+                let msg = match maybe_item {
+                    Some(Some(msg)) => msg,
                     _ => break Err(anyhow::anyhow!("Event stream ended")),
                 };
-                let msg = SubscriptionMessage::from(&item);
                 match sink.try_send(msg) {
                     Ok(_) => (),
                     Err(TrySendError::Closed(_)) => break Err(anyhow::anyhow!("Client disconnected, subscription closed")),
@@ -326,6 +737,112 @@ async fn subscribe_checkpoint(
     Ok(())
 }
 
+/// Subscribe a delayed event stream as a spectator.  The frames are
+/// held back by [DelayQueue], the latest backlogs are sent without the
+/// events not old enough.
+async fn subscribe_spectate(
+    params: Params<'_>,
+    pending: PendingSubscriptionSink,
+    context: Arc<ApplicationContext>,
+    encoding: Encoding,
+) -> Result<(), StringError> {
+    let (game_addr, SubscribeSpectateParams {}) = match parse_params_no_sig(params, &context, encoding) {
+        Ok(p) => p,
+        Err(e) => {
+            let _ = pending.reject(ErrorObjectOwned::from(e)).await;
+            return Ok(());
+        }
+    };
+
+    let config = context.current_config();
+    let _guard = match context.spectators.try_join(&game_addr.to_string(), config.spectator.as_ref()) {
+        Ok(guard) => guard,
+        Err(e) => {
+            warn!("Reject spectator of game {}: {}", game_addr, e);
+            let _ = pending.reject(CallError::Failed(e.into())).await;
+            return Ok(());
+        }
+    };
+
+    let (mut receiver, mut checkpoint_rx, backlogs_frame) =
+        match context.get_spectator_channels(&game_addr).await {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Game not found: {}", game_addr);
+                let _ = pending.reject(CallError::Failed(e.into())).await;
+                return Ok(());
+            }
+        };
+
+    drop(context);
+    let delay = SpectatorDelay::from_config(config.spectator.as_ref());
+    info!("Subscribe spectate, game: {:?}, delay: {:?}", game_addr, delay);
+    let mut queue = DelayQueue::new(delay);
+
+    let mut sink = pending.accept().await?;
+
+    let backlogs_frame = queue.split_backlogs(backlogs_frame);
+    sink.send(encoding.encode_message(&backlogs_frame).unwrap())
+        .await
+        .map_err(|e| {
+            error!("Error occurred when sending spectator backlogs: {:?}", e);
+            e
+        })
+        .unwrap();
+
+    let mut receiver_closed = false;
+    let mut checkpoint_closed = false;
+
+    loop {
+        let next_release = queue.next_release();
+        let frames = tokio::select! {
+            _ = sink.closed() => break,
+            r = receiver.recv(), if !receiver_closed => {
+                match r {
+                    // Spectators are anonymous, only table messages are delivered
+                    Some(BroadcastFrame::ChatMessage { .. }) => (),
+                    // The spectator lagged, it has to subscribe again
+                    Some(BroadcastFrame::Backlogs { .. }) => {
+                        warn!("Spectator of game {} lagged", game_addr);
+                        break;
+                    }
+                    Some(frame) => queue.push(frame),
+                    None => receiver_closed = true,
+                }
+                vec![]
+            }
+            r = checkpoint_rx.recv(), if queue.is_until_checkpoint() && !checkpoint_closed => {
+                if let Err(RecvError::Closed) = r {
+                    checkpoint_closed = true;
+                }
+                queue.release_all()
+            }
+            _ = sleep_until(next_release.unwrap_or_else(Instant::now)), if next_release.is_some() => {
+                queue.pop_due(Instant::now())
+            }
+        };
+
+        for frame in frames.iter() {
+            let msg = match encoding.encode_message(frame) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    error!("Failed to encode spectator frame: {:?}", e);
+                    continue;
+                }
+            };
+            if sink.send(msg).await.is_err() {
+                info!("Spectator disconnected, game: {}", game_addr);
+                return Ok(());
+            }
+        }
+
+        if receiver_closed && queue.is_empty() {
+            break;
+        }
+    }
+    Ok(())
+}
+
 pub async fn run_server(
     context: ApplicationContext,
 ) -> anyhow::Result<tokio::task::JoinHandle<()>> {
@@ -334,7 +851,9 @@ pub async fn run_server(
         .allow_origin(Any)
         .allow_headers([hyper::header::CONTENT_TYPE]);
 
-    let middleware = ServiceBuilder::new().layer(cors);
+    let middleware = ServiceBuilder::new()
+        .layer(cors)
+        .layer(HealthLayer::new(HealthChecker::new(&context)));
 
     let host = {
         let port = context.config.port;
@@ -354,24 +873,91 @@ pub async fn run_server(
     let mut module = RpcModule::new(context);
 
     module.register_method("ping", ping)?;
-    module.register_async_method("get_checkpoint", get_checkpoint)?;
-    module.register_async_method("get_latest_checkpoints", get_latest_checkpoints)?;
-    module.register_async_method("get_latest_checkpoint", get_latest_checkpoint)?;
     module.register_async_method("get_serving_games", get_serving_games)?;
-    module.register_async_method("submit_event", submit_event)?;
-    module.register_async_method("submit_message", submit_message)?;
-    module.register_async_method("exit_game", exit_game)?;
+    module.register_async_method("get_server_load", get_server_load)?;
+
+    // Base64 of borsh bytes
+    module.register_async_method("get_checkpoint", |p, c| get_checkpoint(p, c, Encoding::Borsh))?;
+    module.register_async_method("get_latest_checkpoints", |p, c| get_latest_checkpoints(p, c, Encoding::Borsh))?;
+    module.register_async_method("get_latest_checkpoint", |p, c| get_latest_checkpoint(p, c, Encoding::Borsh))?;
+    module.register_async_method("get_divergence_reports", |p, c| get_divergence_reports(p, c, Encoding::Borsh))?;
+    module.register_async_method("submit_event", |p, c| submit_event(p, c, Encoding::Borsh))?;
+    module.register_async_method("submit_message", |p, c| submit_message(p, c, Encoding::Borsh))?;
+    module.register_async_method("submit_chat_message", |p, c| submit_chat_message(p, c, Encoding::Borsh))?;
+    module.register_async_method("mute_player", |p, c| mute_player(p, c, Encoding::Borsh))?;
+    module.register_async_method("set_player_team", |p, c| set_player_team(p, c, Encoding::Borsh))?;
+    module.register_async_method("exit_game", |p, c| exit_game(p, c, Encoding::Borsh))?;
     module.register_subscription(
         "subscribe_event",
         "s_event",
         "unsubscribe_event",
-        subscribe_event,
+        |p, s, c| subscribe_event(p, s, c, Encoding::Borsh),
+    )?;
+    module.register_subscription(
+        "subscribe_event_v2",
+        "s_event_v2",
+        "unsubscribe_event_v2",
+        |p, s, c| subscribe_event_v2(p, s, c, Encoding::Borsh),
+    )?;
+    module.register_subscription(
+        "subscribe_session",
+        "s_session",
+        "unsubscribe_session",
+        |p, s, c| subscribe_session(p, s, c, Encoding::Borsh),
     )?;
     module.register_subscription(
         "subscribe_checkpoint",
         "s_checkpoint",
         "unsubscribe_checkpoint",
-        subscribe_checkpoint,
+        |p, s, c| subscribe_checkpoint(p, s, c, Encoding::Borsh),
+    )?;
+    module.register_subscription(
+        "subscribe_spectate",
+        "s_spectate",
+        "unsubscribe_spectate",
+        |p, s, c| subscribe_spectate(p, s, c, Encoding::Borsh),
+    )?;
+
+    // camelCase JSON
+    module.register_async_method("json_get_checkpoint", |p, c| get_checkpoint(p, c, Encoding::Json))?;
+    module.register_async_method("json_get_latest_checkpoints", |p, c| get_latest_checkpoints(p, c, Encoding::Json))?;
+    module.register_async_method("json_get_latest_checkpoint", |p, c| get_latest_checkpoint(p, c, Encoding::Json))?;
+    module.register_async_method("json_get_divergence_reports", |p, c| get_divergence_reports(p, c, Encoding::Json))?;
+    module.register_async_method("json_submit_event", |p, c| submit_event(p, c, Encoding::Json))?;
+    module.register_async_method("json_submit_message", |p, c| submit_message(p, c, Encoding::Json))?;
+    module.register_async_method("json_submit_chat_message", |p, c| submit_chat_message(p, c, Encoding::Json))?;
+    module.register_async_method("json_mute_player", |p, c| mute_player(p, c, Encoding::Json))?;
+    module.register_async_method("json_set_player_team", |p, c| set_player_team(p, c, Encoding::Json))?;
+    module.register_async_method("json_exit_game", |p, c| exit_game(p, c, Encoding::Json))?;
+    module.register_subscription(
+        "json_subscribe_event",
+        "json_s_event",
+        "json_unsubscribe_event",
+        |p, s, c| subscribe_event(p, s, c, Encoding::Json),
+    )?;
+    module.register_subscription(
+        "json_subscribe_event_v2",
+        "json_s_event_v2",
+        "json_unsubscribe_event_v2",
+        |p, s, c| subscribe_event_v2(p, s, c, Encoding::Json),
+    )?;
+    module.register_subscription(
+        "json_subscribe_session",
+        "json_s_session",
+        "json_unsubscribe_session",
+        |p, s, c| subscribe_session(p, s, c, Encoding::Json),
+    )?;
+    module.register_subscription(
+        "json_subscribe_checkpoint",
+        "json_s_checkpoint",
+        "json_unsubscribe_checkpoint",
+        |p, s, c| subscribe_checkpoint(p, s, c, Encoding::Json),
+    )?;
+    module.register_subscription(
+        "json_subscribe_spectate",
+        "json_s_spectate",
+        "json_unsubscribe_spectate",
+        |p, s, c| subscribe_spectate(p, s, c, Encoding::Json),
     )?;
     let handle = server.start(module)?;
     info!("Server started at {:?}", host);
@@ -396,11 +982,11 @@ mod tests {
     #[test]
     fn test() {
         let data = "ABUAAABIVWlXWm1zRm00SXhWLVpndmVCR1EJAAAAAGQAAAAAAAAA";
-        let v = base64_decode(data).unwrap();
+        let v = utils::base64_decode(data).unwrap();
         let p = SubmitEventParams::try_from_slice(&v);
         println!("Params: {:?}", p);
         let sig = "FQAAAEhVaVdabXNGbTRJeFYtWmd2ZUJHUTutKUmIAQAAQAAAALUql7fxjNhbQtNq2M5xKe9SnAz5ZEchVxTcxfAEDpg9Dx4RlFTr7tx+M5BhUw3fddmVsmiWzJXmi/4mr5SgJss=";
-        let v = base64_decode(sig).unwrap();
+        let v = utils::base64_decode(sig).unwrap();
         let s = Signature::try_from_slice(&v);
         println!("Signature: {:?}", s);
     }
@@ -0,0 +1,158 @@
+//! Client sessions.  A client proves the ownership of its wallet once
+//! by signing a `subscribe_session` request, then authenticates the
+//! following requests with the issued token instead of a signature.
+//!
+//! A session is bound to a game and to the subscriber it's created
+//! for: the session key is only sent through that subscription, and
+//! every request carries a proof made with the key and a fresh nonce.
+//! A leaked credential can't be replayed, nor used for another
+//! request.  The session is revoked when the subscription is closed
+//! or the session expires.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+
+use race_core::error::{Error, Result};
+use race_core::types::verify_session_proof;
+use tokio::time::{Duration, Instant};
+
+/// The nonces are accepted out of order, within this many of the
+/// greatest one seen, because concurrent requests may overtake each
+/// other.
+const NONCE_WINDOW: u64 = 64;
+
+struct Session {
+    signer: String,
+    game_addr: String,
+    key: String,
+    expires_at: Instant,
+    // The nonces used within the window
+    used_nonces: BTreeSet<u64>,
+}
+
+impl Session {
+    fn use_nonce(&mut self, nonce: u64) -> bool {
+        let max = self.used_nonces.iter().next_back().copied().unwrap_or(0);
+        if nonce + NONCE_WINDOW <= max || !self.used_nonces.insert(nonce) {
+            return false;
+        }
+        while self.used_nonces.len() as u64 > NONCE_WINDOW {
+            let first = *self.used_nonces.iter().next().unwrap();
+            self.used_nonces.remove(&first);
+        }
+        true
+    }
+}
+
+/// A newly created session.
+pub struct NewSession {
+    pub token: String,
+    pub key: String,
+    pub expires_at: Instant,
+}
+
+#[derive(Default)]
+pub struct SessionManager {
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl SessionManager {
+    /// Create a session for `signer` in game `game_addr`.
+    pub fn create(&self, signer: &str, game_addr: &str, ttl: Duration) -> NewSession {
+        let now = Instant::now();
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        let key = uuid::Uuid::new_v4().simple().to_string();
+        let expires_at = now + ttl;
+
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, s| now < s.expires_at);
+        sessions.insert(
+            token.clone(),
+            Session {
+                signer: signer.to_owned(),
+                game_addr: game_addr.to_owned(),
+                key: key.clone(),
+                expires_at,
+                used_nonces: BTreeSet::default(),
+            },
+        );
+        NewSession { token, key, expires_at }
+    }
+
+    /// Return the signer of the session `token`, if it's valid for
+    /// game `game_addr`, and `proof` is made for `arg` with an unused
+    /// `nonce`.
+    pub fn authenticate(
+        &self,
+        token: &str,
+        game_addr: &str,
+        nonce: u64,
+        proof: &str,
+        arg: &[u8],
+    ) -> Result<String> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(token).ok_or(Error::InvalidSession)?;
+        if session.game_addr != game_addr {
+            return Err(Error::InvalidSession);
+        }
+        if Instant::now() >= session.expires_at {
+            return Err(Error::SessionExpired);
+        }
+        if !verify_session_proof(&session.key, nonce, arg, proof) || !session.use_nonce(nonce) {
+            return Err(Error::InvalidSession);
+        }
+        Ok(session.signer.clone())
+    }
+
+    pub fn revoke(&self, token: &str) {
+        self.sessions.lock().unwrap().remove(token);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use race_core::types::session_proof;
+
+    use super::*;
+
+    #[test]
+    fn test_session() {
+        let manager = SessionManager::default();
+        let s = manager.create("alice", "game", Duration::from_secs(60));
+        let proof = |nonce, arg: &[u8]| session_proof(&s.key, nonce, arg);
+
+        assert_eq!(manager.authenticate(&s.token, "game", 1, &proof(1, b"a"), b"a"), Ok("alice".to_string()));
+        assert_eq!(manager.authenticate(&s.token, "other", 2, &proof(2, b"a"), b"a"), Err(Error::InvalidSession));
+        assert_eq!(manager.authenticate("bad", "game", 2, &proof(2, b"a"), b"a"), Err(Error::InvalidSession));
+
+        let expired = manager.create("bob", "game", Duration::ZERO);
+        let expired_proof = session_proof(&expired.key, 1, b"a");
+        assert_eq!(manager.authenticate(&expired.token, "game", 1, &expired_proof, b"a"), Err(Error::SessionExpired));
+
+        manager.revoke(&s.token);
+        assert_eq!(manager.authenticate(&s.token, "game", 3, &proof(3, b"a"), b"a"), Err(Error::InvalidSession));
+    }
+
+    #[test]
+    fn test_session_proof() {
+        let manager = SessionManager::default();
+        let s = manager.create("alice", "game", Duration::from_secs(60));
+        let proof = |nonce, arg: &[u8]| session_proof(&s.key, nonce, arg);
+
+        // The proof is bound to the argument and the key
+        assert_eq!(manager.authenticate(&s.token, "game", 1, &proof(1, b"a"), b"b"), Err(Error::InvalidSession));
+        let forged = session_proof("guess", 1, b"a");
+        assert_eq!(manager.authenticate(&s.token, "game", 1, &forged, b"a"), Err(Error::InvalidSession));
+        assert_eq!(manager.authenticate(&s.token, "game", 1, "not hex", b"a"), Err(Error::InvalidSession));
+
+        // A credential can't be replayed
+        assert!(manager.authenticate(&s.token, "game", 1, &proof(1, b"a"), b"a").is_ok());
+        assert_eq!(manager.authenticate(&s.token, "game", 1, &proof(1, b"a"), b"a"), Err(Error::InvalidSession));
+
+        // Nonces can arrive out of order within the window
+        assert!(manager.authenticate(&s.token, "game", 10, &proof(10, b"a"), b"a").is_ok());
+        assert!(manager.authenticate(&s.token, "game", 5, &proof(5, b"a"), b"a").is_ok());
+        assert!(manager.authenticate(&s.token, "game", 100, &proof(100, b"a"), b"a").is_ok());
+        assert_eq!(manager.authenticate(&s.token, "game", 20, &proof(20, b"a"), b"a"), Err(Error::InvalidSession));
+    }
+}
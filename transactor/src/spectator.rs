@@ -0,0 +1,308 @@
+//! Spectators watch a game through a delayed event stream, so they
+//! can't pass hidden information to the players while it matters.
+//!
+//! Frames are held for a fixed duration, or until the next checkpoint
+//! which is usually the end of a hand.  The real-time stream of
+//! `subscribe_event_v2` is only open to the participants of the game.
+//! Spectators are counted per game, separately from the nodes, with
+//! their own limits.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use race_core::error::{Error, Result};
+use race_core::types::BroadcastFrame;
+use race_env::SpectatorConfig;
+use tokio::time::{Duration, Instant};
+
+const DEFAULT_DELAY: u64 = 120;
+const DEFAULT_MAX_SPECTATORS_PER_GAME: usize = 100;
+const DEFAULT_MAX_SPECTATORS: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpectatorDelay {
+    Duration(Duration),
+    UntilCheckpoint,
+}
+
+impl SpectatorDelay {
+    pub fn from_config(config: Option<&SpectatorConfig>) -> Self {
+        if config.and_then(|c| c.delay_until_checkpoint) == Some(true) {
+            SpectatorDelay::UntilCheckpoint
+        } else {
+            SpectatorDelay::Duration(Duration::from_secs(
+                config.and_then(|c| c.delay).unwrap_or(DEFAULT_DELAY),
+            ))
+        }
+    }
+}
+
+/// The number of spectators by game.
+#[derive(Default)]
+pub struct SpectatorRegistry {
+    counts: Mutex<HashMap<String, usize>>,
+}
+
+/// Leave the game when dropped.
+pub struct SpectatorGuard {
+    registry: Arc<SpectatorRegistry>,
+    game_addr: String,
+}
+
+impl Drop for SpectatorGuard {
+    fn drop(&mut self) {
+        let mut counts = self.registry.counts.lock().unwrap();
+        if let Some(n) = counts.get_mut(&self.game_addr) {
+            *n -= 1;
+            if *n == 0 {
+                counts.remove(&self.game_addr);
+            }
+        }
+    }
+}
+
+impl SpectatorRegistry {
+    /// Join a game as a spectator, if the limits allow.
+    pub fn try_join(
+        self: &Arc<Self>,
+        game_addr: &str,
+        config: Option<&SpectatorConfig>,
+    ) -> Result<SpectatorGuard> {
+        let max_per_game = config
+            .and_then(|c| c.max_spectators_per_game)
+            .unwrap_or(DEFAULT_MAX_SPECTATORS_PER_GAME);
+        let max_total = config
+            .and_then(|c| c.max_spectators)
+            .unwrap_or(DEFAULT_MAX_SPECTATORS);
+
+        let mut counts = self.counts.lock().unwrap();
+        let total: usize = counts.values().sum();
+        let n = counts.entry(game_addr.to_owned()).or_default();
+        if *n >= max_per_game || total >= max_total {
+            if *n == 0 {
+                counts.remove(game_addr);
+            }
+            return Err(Error::TooManySpectators);
+        }
+        *n += 1;
+
+        Ok(SpectatorGuard {
+            registry: self.clone(),
+            game_addr: game_addr.to_owned(),
+        })
+    }
+
+    pub fn count(&self, game_addr: &str) -> usize {
+        self.counts
+            .lock()
+            .unwrap()
+            .get(game_addr)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// The frames held back from a spectator, in order.  A frame without
+/// a release time waits for the next checkpoint.
+pub struct DelayQueue {
+    delay: SpectatorDelay,
+    frames: VecDeque<(Option<Instant>, BroadcastFrame)>,
+}
+
+impl DelayQueue {
+    pub fn new(delay: SpectatorDelay) -> Self {
+        Self {
+            delay,
+            frames: VecDeque::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn is_until_checkpoint(&self) -> bool {
+        self.delay == SpectatorDelay::UntilCheckpoint
+    }
+
+    /// Split a `Backlogs` frame into the part to send now, and hold
+    /// the rest.
+    ///
+    /// With a fixed delay, the checkpoint can be newer than the delay,
+    /// and its time is unknown, so the whole frame is held for the
+    /// delay, and a `Backlogs` without checkpoint and events is sent
+    /// now.  Until checkpoint, the checkpoint is the state of the last
+    /// hand, it's sent now with the frames before the first event.
+    pub fn split_backlogs(&mut self, frame: BroadcastFrame) -> BroadcastFrame {
+        let (checkpoint_off_chain, backlogs, state_sha) = match frame {
+            BroadcastFrame::Backlogs {
+                checkpoint_off_chain,
+                backlogs,
+                state_sha,
+            } => (checkpoint_off_chain, backlogs, state_sha),
+            _ => return frame,
+        };
+
+        match self.delay {
+            SpectatorDelay::Duration(_) => {
+                self.push(BroadcastFrame::Backlogs {
+                    checkpoint_off_chain,
+                    backlogs,
+                    state_sha,
+                });
+                BroadcastFrame::Backlogs {
+                    checkpoint_off_chain: None,
+                    backlogs: Box::new(vec![]),
+                    state_sha: "".into(),
+                }
+            }
+            SpectatorDelay::UntilCheckpoint => {
+                let mut sent = vec![];
+                let mut held = false;
+                for f in backlogs.into_iter() {
+                    held = held || matches!(f, BroadcastFrame::Event { .. });
+                    if held {
+                        self.frames.push_back((None, f));
+                    } else {
+                        sent.push(f);
+                    }
+                }
+                BroadcastFrame::Backlogs {
+                    checkpoint_off_chain,
+                    backlogs: Box::new(sent),
+                    state_sha,
+                }
+            }
+        }
+    }
+
+    /// Hold a frame received now.
+    pub fn push(&mut self, frame: BroadcastFrame) {
+        let at = match self.delay {
+            SpectatorDelay::Duration(delay) => Some(Instant::now() + delay),
+            SpectatorDelay::UntilCheckpoint => None,
+        };
+        self.frames.push_back((at, frame));
+    }
+
+    /// The time to release the next frame, None if it waits for a
+    /// checkpoint or there's no frame.
+    pub fn next_release(&self) -> Option<Instant> {
+        self.frames.front().and_then(|(at, _)| *at)
+    }
+
+    /// Take the frames due at `now`.
+    pub fn pop_due(&mut self, now: Instant) -> Vec<BroadcastFrame> {
+        let mut ret = vec![];
+        while let Some((Some(at), _)) = self.frames.front() {
+            if *at > now {
+                break;
+            }
+            if let Some((_, frame)) = self.frames.pop_front() {
+                ret.push(frame);
+            }
+        }
+        ret
+    }
+
+    /// Take all frames, on a checkpoint.
+    pub fn release_all(&mut self) -> Vec<BroadcastFrame> {
+        self.frames.drain(..).map(|(_, frame)| frame).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use race_api::event::Event;
+    use race_core::checkpoint::CheckpointOffChain;
+    use race_core::types::BroadcastSync;
+
+    use super::*;
+
+    fn make_event(timestamp: u64) -> BroadcastFrame {
+        BroadcastFrame::Event {
+            event: Event::GameStart,
+            timestamp,
+            state_sha: "".into(),
+        }
+    }
+
+    fn make_backlogs() -> BroadcastFrame {
+        BroadcastFrame::Backlogs {
+            checkpoint_off_chain: Some(CheckpointOffChain::default()),
+            backlogs: Box::new(vec![
+                BroadcastFrame::Sync {
+                    sync: BroadcastSync::new(0),
+                },
+                make_event(1_000),
+                make_event(50_000),
+                make_event(60_000),
+            ]),
+            state_sha: "".into(),
+        }
+    }
+
+    fn backlogs_len(frame: &BroadcastFrame) -> usize {
+        match frame {
+            BroadcastFrame::Backlogs { backlogs, .. } => backlogs.len(),
+            _ => panic!("Unexpected frame"),
+        }
+    }
+
+    #[test]
+    fn test_split_backlogs_by_duration() {
+        let mut queue = DelayQueue::new(SpectatorDelay::Duration(Duration::from_secs(30)));
+        let frame = queue.split_backlogs(make_backlogs());
+        // Neither the checkpoint nor the events are sent before the delay
+        assert_eq!(backlogs_len(&frame), 0);
+        assert!(matches!(
+            frame,
+            BroadcastFrame::Backlogs { checkpoint_off_chain: None, .. }
+        ));
+        queue.push(make_event(70_000));
+        assert!(queue.pop_due(Instant::now()).is_empty());
+
+        let released = queue.pop_due(Instant::now() + Duration::from_secs(31));
+        assert_eq!(released.len(), 2);
+        assert_eq!(backlogs_len(&released[0]), 4);
+        assert!(matches!(
+            released[0],
+            BroadcastFrame::Backlogs { checkpoint_off_chain: Some(_), .. }
+        ));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_split_backlogs_until_checkpoint() {
+        let mut queue = DelayQueue::new(SpectatorDelay::UntilCheckpoint);
+        let frame = queue.split_backlogs(make_backlogs());
+        assert_eq!(backlogs_len(&frame), 1);
+        queue.push(make_event(70_000));
+        assert_eq!(queue.next_release(), None);
+        assert_eq!(queue.release_all().len(), 4);
+    }
+
+    #[test]
+    fn test_spectator_limits() {
+        let registry = Arc::new(SpectatorRegistry::default());
+        let config = SpectatorConfig {
+            delay: None,
+            delay_until_checkpoint: None,
+            max_spectators_per_game: Some(1),
+            max_spectators: Some(2),
+            participants_only: None,
+        };
+        let g1 = registry.try_join("game1", Some(&config)).unwrap();
+        assert_eq!(
+            registry.try_join("game1", Some(&config)).err(),
+            Some(Error::TooManySpectators)
+        );
+        let _g2 = registry.try_join("game2", Some(&config)).unwrap();
+        assert!(registry.try_join("game3", Some(&config)).is_err());
+        assert_eq!(registry.count("game3"), 0);
+
+        drop(g1);
+        assert_eq!(registry.count("game1"), 0);
+        assert!(registry.try_join("game1", Some(&config)).is_ok());
+    }
+}
@@ -0,0 +1,70 @@
+//! Export the spans of the games with OpenTelemetry.  The frames on
+//! the event bus carry the span they are sent in, so the spans of one
+//! player action, from `submit_event` to `settle_game`, are in one
+//! trace.
+
+use std::fs::OpenOptions;
+
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{self, Sampler, Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use race_env::TelemetryConfig;
+
+const DEFAULT_SERVICE_NAME: &str = "race-transactor";
+
+/// Build the tracer and install its provider globally.  Return None
+/// when no exporter is configured.
+pub fn init_tracer(config: &TelemetryConfig) -> Result<Option<Tracer>, TraceError> {
+    if config.otlp_endpoint.is_none() && config.file.is_none() {
+        return Ok(None);
+    }
+
+    let service_name = config
+        .service_name
+        .clone()
+        .unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string());
+    // Follow the decision of the parent, so a trace is never cut
+    let sampler = match config.sample_ratio {
+        Some(ratio) => Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio))),
+        None => Sampler::AlwaysOn,
+    };
+    let mut builder = TracerProvider::builder().with_config(
+        trace::config()
+            .with_sampler(sampler)
+            .with_resource(Resource::new(vec![KeyValue::new("service.name", service_name)])),
+    );
+
+    if let Some(endpoint) = config.otlp_endpoint.as_ref() {
+        let exporter = opentelemetry_otlp::SpanExporterBuilder::from(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .build_span_exporter()?;
+        builder = builder.with_batch_exporter(exporter, runtime::Tokio);
+    }
+
+    if let Some(path) = config.file.as_ref() {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| TraceError::Other(Box::new(e)))?;
+        let exporter = opentelemetry_stdout::SpanExporter::builder()
+            .with_writer(file)
+            .build();
+        builder = builder.with_batch_exporter(exporter, runtime::Tokio);
+    }
+
+    let provider = builder.build();
+    let tracer = provider.tracer(DEFAULT_SERVICE_NAME);
+    opentelemetry::global::set_tracer_provider(provider);
+    Ok(Some(tracer))
+}
+
+/// Export the spans not exported yet.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
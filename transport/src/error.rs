@@ -121,6 +121,12 @@ pub enum TransportError {
     #[error("Transaction is not confirmed")]
     TransactionNotConfirmed,
 
+    #[error("Transaction expired")]
+    TransactionExpired,
+
+    #[error("Transaction failed: {0}")]
+    TransactionFailed(String),
+
     #[error("Network error: {0}")]
     NetworkError(String),
 
@@ -196,9 +202,26 @@ pub enum TransportError {
 
 pub type TransportResult<T> = std::result::Result<T, TransportError>;
 
+/// Keep the errors which may go away on a retry apart from the fatal
+/// ones, see [race_core::error::Error::is_retryable].
 impl From<TransportError> for race_core::error::Error {
     fn from(value: TransportError) -> Self {
-        Self::TransportError(value.to_string())
+        match value {
+            TransportError::TransactionExpired | TransportError::GetBlockhashFailed => {
+                Self::TransactionExpired
+            }
+            TransportError::NetworkError(_)
+            | TransportError::ClientSendTransactionFailed(_)
+            | TransportError::TransactionNotConfirmed
+            | TransportError::ClientGetDataFailed
+            | TransportError::GetAccountError(_)
+            | TransportError::SubscriptionError(_)
+            | TransportError::GetBalanceError(_)
+            | TransportError::GetGasPriceError(_)
+            | TransportError::GetVersionError(_)
+            | TransportError::GetObjectError(_) => Self::RpcError(value.to_string()),
+            _ => Self::TransportError(value.to_string()),
+        }
     }
 }
 
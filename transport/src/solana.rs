@@ -40,7 +40,7 @@ use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::Keypair;
 use solana_sdk::signer::Signer;
 use solana_sdk::system_instruction::{self, create_account_with_seed};
-use solana_sdk::transaction::Transaction;
+use solana_sdk::transaction::{Transaction, TransactionError};
 use solana_sdk::{commitment_config::CommitmentConfig, program_pack::Pack};
 use solana_sdk::{
     hash::Hash,
@@ -1635,7 +1635,13 @@ impl SolanaTransport {
             .map_err(|e| {
                 if let Some(e) = e.get_transaction_error() {
                     error!("Transactior error: {}", e);
-                    TransportError::ClientSendTransactionFailed(e.to_string())
+                    // The blockhash expired, the transaction can be sent
+                    // again.  The others are rejected by the program.
+                    if e == TransactionError::BlockhashNotFound {
+                        TransportError::TransactionExpired
+                    } else {
+                        TransportError::TransactionFailed(e.to_string())
+                    }
                 } else {
                     TransportError::ClientSendTransactionFailed(e.to_string())
                 }
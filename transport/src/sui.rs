@@ -823,7 +823,7 @@ impl TransportT for SuiTransport {
         let signature = response.digest.to_string();
         let status = self.confirm_settle_status(digest.clone())
             .await
-            .map_err(|e| Error::TransportError(e.to_string()))?;
+            .map_err(|e| Error::RpcError(e.to_string()))?;
 
         if status  {
             let updated_game = self.get_move_object::<GameObject>(game_id).await?;
@@ -1303,7 +1303,7 @@ impl SuiTransport {
                 query,
                 None,           // cursor
                 Some(1)         // limit
-            ).await.map_err(|e| Error::TransportError(e.to_string()))?
+            ).await.map_err(|e| Error::RpcError(e.to_string()))?
             .data;
 
         println!("Got reponses data {:?}", data[0]);